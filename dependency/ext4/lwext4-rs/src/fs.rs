@@ -5,6 +5,7 @@ use crate::file::{raw_metadata, OpenOptions};
 use crate::types::{FileType, Metadata, Permissions};
 use crate::{BlockDeviceInterface, FileTimes, MetaDataExt, MountHandle, Time};
 use alloc::string::{String, ToString};
+use alloc::vec;
 use alloc::vec::Vec;
 use core::ptr::null_mut;
 use log::info;
@@ -40,6 +41,12 @@ impl<T: BlockDeviceInterface> FileSystem<T> {
         &self.mp
     }
 
+    /// Write the dirty blocks of the cache back to the device
+    pub fn flush(&self) -> Result<()> {
+        unsafe { errno_to_result(ext4_cache_flush(self.mp.mount_point.as_ptr()))? }
+        Ok(())
+    }
+
     /// Create a OpenOptions builder for opening a file
     pub fn file_builder(&self) -> OpenOptions {
         OpenOptions::new()
@@ -173,19 +180,27 @@ impl<T: BlockDeviceInterface> FileSystem<T> {
     /// Get the extended attribute of a file
     pub fn get_xattr<P: AsRef<str>, Q: AsRef<str>>(&self, path: P, name: Q) -> Result<Vec<u8>> {
         let path = CName::new(path.as_ref().to_string())?;
-        let mut buf = [0u8; 255];
+        let mut buf = vec![0u8; 255];
         let mut read = 0usize;
-        unsafe {
-            errno_to_result(ext4_getxattr(
-                path.as_ptr(),
-                name.as_ref().as_ptr() as _,
-                name.as_ref().len(),
-                buf.as_mut_ptr() as _,
-                buf.len(),
-                &mut read as _,
-            ))?;
+        loop {
+            unsafe {
+                errno_to_result(ext4_getxattr(
+                    path.as_ptr(),
+                    name.as_ref().as_ptr() as _,
+                    name.as_ref().len(),
+                    buf.as_mut_ptr() as _,
+                    buf.len(),
+                    &mut read as _,
+                ))?;
+            }
+            // `read` is the size of the whole value, even if it didn't fit
+            if read <= buf.len() {
+                break;
+            }
+            buf.resize(read, 0);
         }
-        Ok(buf[..read].to_vec())
+        buf.truncate(read);
+        Ok(buf)
     }
 
     /// Set an extended attribute of a file
@@ -238,10 +253,7 @@ impl<T: BlockDeviceInterface> FileSystem<T> {
     }
 
     /// Remove an extended attribute.
-    ///
-    /// Warning: This function is not available
-    #[allow(unused)]
-    fn remove_xattr<P: AsRef<str>, Q: AsRef<str>>(&self, path: P, name: Q) -> Result<()> {
+    pub fn remove_xattr<P: AsRef<str>, Q: AsRef<str>>(&self, path: P, name: Q) -> Result<()> {
         let path = CName::new(path.as_ref().to_string())?;
         unsafe {
             errno_to_result(ext4_removexattr(
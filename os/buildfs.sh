@@ -50,13 +50,26 @@ fi
 # "$SUDO" mkdir -p ${U_FAT32_DIR}/fs/final
 # "$SUDO" mkdir -p ${U_FAT32_DIR}/fs/pre
 
+# FAT32 has no symbolic links, the kernel takes a file with the system attribute
+# whose content is "!<symlink>" followed by the target as one (see `SYMLINK_MAGIC` in src/fs/fat32/vfs.rs).
+# usage: fat_symlink <target> <link>
+fat_symlink(){
+    printf '!<symlink>%s' "$1" | "$SUDO" tee "$2" > /dev/null
+    "$SUDO" fatattr +s "$2"
+}
+
 try_copy(){
     if [ -d $1 ]
     then
         echo copying $1 ';'
         for programname in $(ls -A $1)
         do
-            "$SUDO" cp -fr "$1"/"$programname" $2
+            if [ -L "$1"/"$programname" ]
+            then
+                fat_symlink "$(readlink "$1"/"$programname")" $2/"$programname"
+            else
+                "$SUDO" cp -fr "$1"/"$programname" $2
+            fi
         done
     else
         echo "$1" "doesn""'""t exist, skipped."
@@ -8,3 +8,5 @@ pub const BLOCK_SZ: usize = 2048;
 // warning: 不能移除“ + HIGH_BASE_EIGHT”，会导致开发板上地址错误
 pub const UART_BASE: usize = 0x1FE2_0000 + HIGH_BASE_EIGHT;
 pub const ACPI_BASE: usize = 0x1FE2_7000 + HIGH_BASE_EIGHT;
+/// The cores of the 2K1000, QEMU emulates as many
+pub const CPU_NUM: usize = 2;
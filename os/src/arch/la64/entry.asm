@@ -25,6 +25,28 @@ _start:
     la.global $sp, boot_stack_top
     bl          rust_main
 
+    .globl _start_secondary
+_start_secondary:
+# 其余的核由boot核通过IPI唤醒，从mailbox 0跳到这里，同样先切换窗口
+    pcaddi      $t0,    0x0
+    srli.d      $t0,    $t0,    0x30
+    slli.d      $t0,    $t0,    0x30
+    addi.d      $t0,    $t0,    0x11
+    csrwr       $t0,    0x181
+    sub.d       $t0,    $t0,    $t0
+    addi.d      $t0,    $t0,    0x11
+    csrwr       $t0,    0x180
+    pcaddi      $t0,    0x0
+    slli.d      $t0,    $t0,    0x10
+    srli.d      $t0,    $t0,    0x10
+    jirl        $t0,    $t0,    0x10
+    sub.d       $t0,    $t0,    $t0
+    csrwr       $t0,    0x181
+    # The stack boot_secondary_cpus() allocated for this core
+    la.global   $t0,    SECONDARY_BOOT_SP
+    ld.d        $sp,    $t0,    0
+    bl          rust_main_secondary
+
     .section .bss.stack
     .globl boot_stack
 boot_stack:
@@ -125,9 +125,17 @@ impl LAFlexPageTableEntry {
     pub fn executable(&self) -> bool {
         !self.flags().contains(LAPTEFlagBits::NX)
     }
-    /// LA hasn't had access bit so far. So this function is left empty.
+    /// LA hasn't had access bit so far, the D bit stands in for it.
+    /// A page which is cleaned faults on the next store and gets D back, so stores are noticed, loads are not.
     #[inline(always)]
-    pub fn clear_access(&mut self) {}
+    pub fn is_accessed(&self) -> bool {
+        self.is_dirty()
+    }
+    /// See `is_accessed()`.
+    #[inline(always)]
+    pub fn clear_access(&mut self) {
+        self.clear_dirty();
+    }
 
     #[inline(always)]
     pub fn clear_dirty(&mut self) {
@@ -482,6 +490,9 @@ impl PageTable for LAFlexPageTable {
             self.find_pte(vpn).map(|pte| pte.is_dirty())
         }
     }
+    fn is_accessed(&self, vpn: VirtPageNum) -> Option<bool> {
+        self.find_pte(vpn).map(|pte| pte.is_accessed())
+    }
     fn readable(&self, vpn: VirtPageNum) -> Option<bool> {
         self.find_pte(vpn).map(|pte| pte.readable())
     }
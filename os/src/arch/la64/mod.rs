@@ -33,7 +33,9 @@ pub use register::*;
 mod kern_stack;
 mod la_libc_import;
 mod register;
+mod smp;
 mod tlb;
+pub use smp::{boot_secondary_cpus, cpu_id, kick_cpu, online_cpus, tlb_shootdown};
 extern "C" {
     pub fn srfill();
 }
@@ -59,8 +61,17 @@ pub fn machine_init() {
     println!("{:?}", Misc::read());
     println!("{:?}", RVACfg::read());
     println!("[machine_init] MMAP_BASE: {:#x}", MMAP_BASE);
+    smp::init();
     trap::enable_timer_interrupt();
 }
+/// The boot of a secondary core, which shares the memory and devices the boot core set up.
+pub fn secondary_init() {
+    init_core();
+    trap::init();
+    smp::init();
+    trap::enable_timer_interrupt();
+    smp::set_online();
+}
 pub fn pre_start_init() {
     EEntry::empty().set_exception_entry(strampoline as usize);
 }
@@ -68,6 +79,13 @@ pub fn bootstrap_init() {
     if CPUId::read().get_core_id() != 0 {
          loop {}
     };
+    init_core();
+
+    println!("[kernel] UART address: {:#x}", UART_BASE);
+    println!("[bootstrap_init] {:?}", PRCfg1::read());
+}
+/// The setup of the registers every core has a copy of
+fn init_core() {
     ECfg::empty()
         .set_line_based_interrupt_vector(LineBasedInterrupt::TIMER)
         .write();
@@ -115,7 +133,4 @@ pub fn bootstrap_init() {
         .set_dir4_base(0)
         .set_dir4_width(0)
         .write();
-
-    println!("[kernel] UART address: {:#x}", UART_BASE);
-    println!("[bootstrap_init] {:?}", PRCfg1::read());
 }
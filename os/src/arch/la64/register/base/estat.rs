@@ -96,6 +96,8 @@ or ADdress error Exception for Memory access instructions (ADEM) will be trigger
     InstructionNonDefined = 0xD,       //指令不合规
     InstructionPrivilegeIllegal = 0xE, //特权指令不合规
     FloatingPointUnavailable = 0xF,
+    /// An instruction fetch or memory access watchpoint fired, told apart by `EsubCode`.
+    Watch = 0x13,
 }
 
 // 中断类型
@@ -0,0 +1,13 @@
+use bit_field::BitField;
+impl_define_csr!(
+    FWPC,
+    "Fetch Watchpoint Config \n\
+    Tells how many instruction fetch watchpoints the core implements."
+);
+impl_read_csr!(0x380, FWPC);
+impl FWPC {
+    /// The number of instruction fetch watchpoints, none on some cores.
+    pub fn get_num(&self) -> usize {
+        self.bits.get_bits(0..=5)
+    }
+}
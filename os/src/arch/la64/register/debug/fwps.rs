@@ -0,0 +1,23 @@
+use bit_field::BitField;
+impl_define_csr!(
+    FWPS,
+    "Fetch Watchpoint Status \n\
+    Records which instruction fetch watchpoints fired, \
+    bit `n` for watchpoint `n`, software clears them by writing 1."
+);
+impl_read_csr!(0x381, FWPS);
+impl_write_csr!(0x381, FWPS);
+impl FWPS {
+    pub fn get_status(&self) -> usize {
+        self.bits.get_bits(0..=13)
+    }
+    pub fn set_status(&mut self, status: usize) -> &mut Self {
+        self.bits.set_bits(0..=13, status);
+        self
+    }
+    /// Let the first instruction fetched after `ERTN` through, even if a watchpoint matches it.
+    pub fn set_skip(&mut self, skip: bool) -> &mut Self {
+        self.bits.set_bit(16, skip);
+        self
+    }
+}
@@ -0,0 +1,44 @@
+use bit_field::BitField;
+impl_define_csr!(
+    IB0ADDR,
+    "Instruction Breakpoint 0 Address \n\
+    The address instruction fetch watchpoint 0 compares fetches with."
+);
+impl_read_csr!(0x390, IB0ADDR);
+impl_write_csr!(0x390, IB0ADDR);
+impl IB0ADDR {
+    pub fn set_addr(&mut self, addr: usize) -> &mut Self {
+        self.bits = addr;
+        self
+    }
+}
+
+impl_define_csr!(
+    IB0MASK,
+    "Instruction Breakpoint 0 Mask \n\
+    The bits of the address which watchpoint 0 doesn't compare."
+);
+impl_read_csr!(0x391, IB0MASK);
+impl_write_csr!(0x391, IB0MASK);
+impl IB0MASK {
+    pub fn set_mask(&mut self, mask: usize) -> &mut Self {
+        self.bits = mask;
+        self
+    }
+}
+
+impl_define_csr!(
+    IB0CTRL,
+    "Instruction Breakpoint 0 Control \n\
+    The privilege levels at which watchpoint 0 fires."
+);
+impl_read_csr!(0x392, IB0CTRL);
+impl_write_csr!(0x392, IB0CTRL);
+impl IB0CTRL {
+    /// Fire on fetches at privilege level `plv`.
+    pub fn set_plv_enable(&mut self, plv: usize, enable: bool) -> &mut Self {
+        debug_assert!(plv < 4);
+        self.bits.set_bit(1 + plv, enable);
+        self
+    }
+}
@@ -0,0 +1,4 @@
+#![allow(unused)]
+pub mod fwpc;
+pub mod fwps;
+pub mod ib0;
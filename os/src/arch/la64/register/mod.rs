@@ -1,6 +1,7 @@
 #[macro_use]
 mod csr_macros;
 mod base;
+mod debug;
 mod mmu;
 mod ras;
 mod timer;
@@ -11,6 +12,7 @@ pub use base::{
     // llbctl::*,
     misc::*, prcfg::*, prmd::*, rvacfg::*,
 };
+pub use debug::{fwpc::*, fwps::*, ib0::*};
 pub use mmu::{
     asid::*, dmw::*, pgd::*, pwch::*, pwcl::*, stlbps::*, tlbehi::*, tlbelo::*, tlbidx::*,
     tlbrbadv::*, tlbrehi::*, tlbrelo::*, tlbrentry::*, tlbrera::*, tlbrprmd::*, 
@@ -0,0 +1,196 @@
+//! Multiprocessor support: waking the other cores up, interrupting them and shooting down their TLB entries.
+//!
+//! The firmware parks every core but the boot one until it gets an IPI,
+//! then jumps to the address in its mailbox 0, `_start_secondary` in `entry.asm` here.
+//! Without the `smp` feature the other cores stay parked.
+//!
+//! # Locks and interrupts
+//! Interrupts are only taken in user mode, the kernel always runs with them off (see `trap_return()`),
+//! so no interrupt handler ever spins on a `spin::Mutex` held by the code it interrupted,
+//! and none of the global locks has to turn interrupts off, on one core or many.
+//! A core spinning on a lock just waits for the core holding it.
+//! The locks of the scheduler nest in this order: `TASK_MANAGER`,
+//! then the inner of a task or the `Processor` of a core, then the `sched` of a task.
+use alloc::vec;
+use core::arch::asm;
+use core::hint::spin_loop;
+use core::sync::atomic::{fence, AtomicUsize, Ordering};
+
+use super::board::CPU_NUM;
+use super::tlb::tlb_invalidate;
+use super::CPUId;
+use crate::timer::get_time_ms;
+
+const IOCSR_IPI_STATUS: usize = 0x1000;
+const IOCSR_IPI_EN: usize = 0x1004;
+const IOCSR_IPI_CLEAR: usize = 0x100c;
+const IOCSR_IPI_SEND: usize = 0x1040;
+const IOCSR_MBUF_SEND: usize = 0x1048;
+
+const IPI_SEND_BLOCKING: u32 = 1 << 31;
+const IPI_SEND_CPU_SHIFT: u32 = 16;
+const MBUF_SEND_BLOCKING: u64 = 1 << 31;
+const MBUF_SEND_BOX_SHIFT: u64 = 2;
+const MBUF_SEND_CPU_SHIFT: u64 = 16;
+const MBUF_SEND_BUF_SHIFT: u64 = 32;
+
+/// What an IPI asks for, bit numbers of `IOCSR_IPI_STATUS`
+const ACTION_BOOT_CPU: u32 = 0;
+const ACTION_TLB_SHOOTDOWN: u32 = 1;
+const ACTION_KICK: u32 = 2;
+
+/// Size of the stack a core boots on, its idle loop keeps running on it
+const BOOT_STACK_SIZE: usize = 4096 * 16;
+/// How long the boot core waits for another one to come up
+const BOOT_TIMEOUT_MS: usize = 1000;
+
+#[allow(clippy::declare_interior_mutable_const)]
+const ZERO: AtomicUsize = AtomicUsize::new(0);
+
+/// Bit `cpu` is set once core `cpu` runs the kernel
+static ONLINE: AtomicUsize = AtomicUsize::new(1);
+/// Top of the stack `_start_secondary` switches to
+#[no_mangle]
+static SECONDARY_BOOT_SP: AtomicUsize = ZERO;
+/// Odd while a core runs user code, bumped on every trap and every return to user mode
+static USER_EPOCH: [AtomicUsize; CPU_NUM] = [ZERO; CPU_NUM];
+/// The address space a core runs in user mode, valid while its epoch is odd
+static USER_TOKEN: [AtomicUsize; CPU_NUM] = [ZERO; CPU_NUM];
+
+extern "C" {
+    fn _start_secondary();
+}
+
+pub fn cpu_id() -> usize {
+    CPUId::read().get_core_id()
+}
+
+/// The cores running the kernel, bit `cpu` for core `cpu`
+pub fn online_cpus() -> usize {
+    ONLINE.load(Ordering::Acquire)
+}
+
+fn iocsr_read32(reg: usize) -> u32 {
+    let value: u32;
+    unsafe {
+        asm!("iocsrrd.w {}, {}", out(reg) value, in(reg) reg);
+    }
+    value
+}
+
+fn iocsr_write32(value: u32, reg: usize) {
+    unsafe {
+        asm!("iocsrwr.w {}, {}", in(reg) value, in(reg) reg);
+    }
+}
+
+fn iocsr_write64(value: u64, reg: usize) {
+    unsafe {
+        asm!("iocsrwr.d {}, {}", in(reg) value, in(reg) reg);
+    }
+}
+
+/// Write `data` to mailbox `mailbox` of `cpu`, 32 bits at a time, the high half first like Linux
+fn mail_send(cpu: usize, mailbox: u64, data: u64) {
+    let base = MBUF_SEND_BLOCKING | (cpu as u64) << MBUF_SEND_CPU_SHIFT;
+    let high = ((mailbox << 1) + 1) << MBUF_SEND_BOX_SHIFT | (data & 0xffff_ffff_0000_0000);
+    iocsr_write64(base | high, IOCSR_MBUF_SEND);
+    let low = (mailbox << 1) << MBUF_SEND_BOX_SHIFT | data << MBUF_SEND_BUF_SHIFT;
+    iocsr_write64(base | low, IOCSR_MBUF_SEND);
+}
+
+fn send_ipi(cpu: usize, action: u32) {
+    iocsr_write32(
+        IPI_SEND_BLOCKING | (cpu as u32) << IPI_SEND_CPU_SHIFT | action,
+        IOCSR_IPI_SEND,
+    );
+}
+
+/// Let IPIs in, on every core.
+pub fn init() {
+    iocsr_write32(u32::MAX, IOCSR_IPI_EN);
+}
+
+/// Wake the other cores up one after the other, each on a stack of its own.
+/// A core which doesn't come up in time and those after it stay parked.
+pub fn boot_secondary_cpus() {
+    if !cfg!(feature = "smp") {
+        return;
+    }
+    for cpu in 1..CPU_NUM {
+        let stack = vec![0u128; BOOT_STACK_SIZE / 16].leak();
+        SECONDARY_BOOT_SP.store(stack.as_ptr() as usize + BOOT_STACK_SIZE, Ordering::Release);
+        mail_send(cpu, 0, _start_secondary as usize as u64);
+        send_ipi(cpu, ACTION_BOOT_CPU);
+        let deadline = get_time_ms() + BOOT_TIMEOUT_MS;
+        while online_cpus() & 1 << cpu == 0 {
+            if get_time_ms() > deadline {
+                println!("[smp] CPU {} didn't come up", cpu);
+                return;
+            }
+            spin_loop();
+        }
+        println!("[smp] CPU {} online", cpu);
+    }
+}
+
+/// Tell the boot core this one is up, the last step of `secondary_init()`.
+pub fn set_online() {
+    ONLINE.fetch_or(1 << cpu_id(), Ordering::AcqRel);
+}
+
+/// Clear the IPIs pending on this core. There is nothing else to do,
+/// the TLB was flushed on the way into the kernel and signals are handled on the way out.
+pub fn handle_ipi() {
+    let status = iocsr_read32(IOCSR_IPI_STATUS);
+    iocsr_write32(status, IOCSR_IPI_CLEAR);
+}
+
+/// Interrupt `cpu` if it runs user code, so that its task sees the signals just sent to it.
+pub fn kick_cpu(cpu: usize) {
+    if cpu != cpu_id() && online_cpus() & 1 << cpu != 0 {
+        send_ipi(cpu, ACTION_KICK);
+    }
+}
+
+/// Called first thing on a trap from user mode, after `__alltraps` dropped the TLB entries of user space.
+pub fn enter_kernel() {
+    USER_EPOCH[cpu_id()].fetch_add(1, Ordering::SeqCst);
+}
+
+/// Called right before returning to user mode in the address space `token`.
+pub fn leave_kernel(token: usize) {
+    let cpu = cpu_id();
+    USER_TOKEN[cpu].store(token, Ordering::Relaxed);
+    USER_EPOCH[cpu].fetch_add(1, Ordering::SeqCst);
+}
+
+/// Drop the TLB entries of user space after page tables changed, on this core
+/// and on the others running `token`, or any address space with `None`, before returning.
+/// A core drops them on every trap, so only those in user mode are interrupted,
+/// and waited for until they took the trap.
+pub fn tlb_shootdown(token: Option<usize>) {
+    tlb_invalidate();
+    // the page tables are written before the epochs are read, and read after a core bumped its epoch
+    fence(Ordering::SeqCst);
+    let this = cpu_id();
+    let online = online_cpus();
+    let mut epochs = [None; CPU_NUM];
+    for cpu in (0..CPU_NUM).filter(|cpu| *cpu != this && online & 1 << cpu != 0) {
+        let epoch = USER_EPOCH[cpu].load(Ordering::SeqCst);
+        let in_space = token.map_or(true, |token| {
+            USER_TOKEN[cpu].load(Ordering::Relaxed) == token
+        });
+        if epoch % 2 == 1 && in_space {
+            send_ipi(cpu, ACTION_TLB_SHOOTDOWN);
+            epochs[cpu] = Some(epoch);
+        }
+    }
+    for (cpu, epoch) in epochs.iter().enumerate() {
+        if let Some(epoch) = epoch {
+            while USER_EPOCH[cpu].load(Ordering::SeqCst) == *epoch {
+                spin_loop();
+            }
+        }
+    }
+}
@@ -1,11 +1,25 @@
+pub const SYSCALL_SETXATTR: usize = 5;
+pub const SYSCALL_LSETXATTR: usize = 6;
+pub const SYSCALL_FSETXATTR: usize = 7;
+pub const SYSCALL_GETXATTR: usize = 8;
+pub const SYSCALL_LGETXATTR: usize = 9;
+pub const SYSCALL_FGETXATTR: usize = 10;
+pub const SYSCALL_LISTXATTR: usize = 11;
+pub const SYSCALL_LLISTXATTR: usize = 12;
+pub const SYSCALL_FLISTXATTR: usize = 13;
+pub const SYSCALL_REMOVEXATTR: usize = 14;
+pub const SYSCALL_LREMOVEXATTR: usize = 15;
+pub const SYSCALL_FREMOVEXATTR: usize = 16;
 pub const SYSCALL_GETCWD: usize = 17;
 pub const SYSCALL_DUP3: usize = 20;
 pub const SYSCALL_DUP: usize = 23;
 pub const SYSCALL_DUP2: usize = 24;
 pub const SYSCALL_FCNTL: usize = 25;
 pub const SYSCALL_IOCTL: usize = 29;
+pub const SYSCALL_FLOCK: usize = 32;
 pub const SYSCALL_MKDIRAT: usize = 34;
 pub const SYSCALL_UNLINKAT: usize = 35;
+pub const SYSCALL_SYMLINKAT: usize = 36;
 pub const SYSCALL_LINKAT: usize = 37;
 pub const SYSCALL_UMOUNT2: usize = 39;
 pub const SYSCALL_MOUNT: usize = 40;
@@ -13,7 +27,10 @@ pub const SYSCALL_STATFS: usize = 43;
 pub const SYSCALL_FTRUNCATE: usize = 46;
 pub const SYSCALL_FACCESSAT: usize = 48;
 pub const SYSCALL_CHDIR: usize = 49;
+pub const SYSCALL_FCHMOD: usize = 52;
 pub const SYSCALL_FCHMODAT: usize = 53;
+pub const SYSCALL_FCHOWNAT: usize = 54;
+pub const SYSCALL_FCHOWN: usize = 55;
 pub const SYSCALL_OPENAT: usize = 56;
 pub const SYSCALL_CLOSE: usize = 57;
 pub const SYSCALL_PIPE2: usize = 59;
@@ -33,7 +50,9 @@ pub const SYSCALL_FSTATAT: usize = 79;
 pub const SYSCALL_FSTAT: usize = 80;
 pub const SYSCALL_SYNC: usize = 81;
 pub const SYSCALL_FSYNC: usize = 82;
+pub const SYSCALL_FDATASYNC: usize = 83;
 pub const SYSCALL_UTIMENSAT: usize = 88;
+pub const SYSCALL_PERSONALITY: usize = 92;
 pub const SYSCALL_EXIT: usize = 93;
 pub const SYSCALL_EXIT_GROUP: usize = 94;
 pub const SYSCALL_SET_TID_ADDRESS: usize = 96;
@@ -46,7 +65,17 @@ pub const SYSCALL_SETITIMER: usize = 103;
 pub const SYSCALL_CLOCK_GETTIME: usize = 113;
 pub const SYSCALL_CLOCK_NANOSLEEP: usize = 115;
 pub const SYSCALL_SYSLOG: usize = 116;
+pub const SYSCALL_PTRACE: usize = 117;
+pub const SYSCALL_SCHED_SETPARAM: usize = 118;
+pub const SYSCALL_SCHED_SETSCHEDULER: usize = 119;
+pub const SYSCALL_SCHED_GETSCHEDULER: usize = 120;
+pub const SYSCALL_SCHED_GETPARAM: usize = 121;
+pub const SYSCALL_SCHED_SETAFFINITY: usize = 122;
+pub const SYSCALL_SCHED_GETAFFINITY: usize = 123;
 pub const SYSCALL_YIELD: usize = 124;
+pub const SYSCALL_SCHED_GET_PRIORITY_MAX: usize = 125;
+pub const SYSCALL_SCHED_GET_PRIORITY_MIN: usize = 126;
+pub const SYSCALL_SCHED_RR_GET_INTERVAL: usize = 127;
 pub const SYSCALL_KILL: usize = 129;
 pub const SYSCALL_TKILL: usize = 130;
 pub const SYSCALL_TGKILL: usize = 131;
@@ -54,10 +83,23 @@ pub const SYSCALL_SIGACTION: usize = 134;
 pub const SYSCALL_SIGPROCMASK: usize = 135;
 pub const SYSCALL_SIGTIMEDWAIT: usize = 137;
 pub const SYSCALL_SIGRETURN: usize = 139;
+pub const SYSCALL_SETPRIORITY: usize = 140;
+pub const SYSCALL_GETPRIORITY: usize = 141;
+pub const SYSCALL_SETREGID: usize = 143;
+pub const SYSCALL_SETGID: usize = 144;
+pub const SYSCALL_SETREUID: usize = 145;
+pub const SYSCALL_SETUID: usize = 146;
+pub const SYSCALL_SETRESUID: usize = 147;
+pub const SYSCALL_GETRESUID: usize = 148;
+pub const SYSCALL_SETRESGID: usize = 149;
+pub const SYSCALL_GETRESGID: usize = 150;
 pub const SYSCALL_TIMES: usize = 153;
 pub const SYSCALL_SETPGID: usize = 154;
 pub const SYSCALL_GETPGID: usize = 155;
+pub const SYSCALL_GETSID: usize = 156;
 pub const SYSCALL_SETSID: usize = 157;
+pub const SYSCALL_GETGROUPS: usize = 158;
+pub const SYSCALL_SETGROUPS: usize = 159;
 pub const SYSCALL_UNAME: usize = 160;
 pub const SYSCALL_GETRUSAGE: usize = 165;
 pub const SYSCALL_UMASK: usize = 166;
@@ -70,6 +112,14 @@ pub const SYSCALL_GETGID: usize = 176;
 pub const SYSCALL_GETEGID: usize = 177;
 pub const SYSCALL_GETTID: usize = 178;
 pub const SYSCALL_SYSINFO: usize = 179;
+pub const SYSCALL_MSGGET: usize = 186;
+pub const SYSCALL_MSGCTL: usize = 187;
+pub const SYSCALL_MSGRCV: usize = 188;
+pub const SYSCALL_MSGSND: usize = 189;
+pub const SYSCALL_SEMGET: usize = 190;
+pub const SYSCALL_SEMCTL: usize = 191;
+pub const SYSCALL_SEMTIMEDOP: usize = 192;
+pub const SYSCALL_SEMOP: usize = 193;
 pub const SYSCALL_SHMGET: usize = 194;
 pub const SYSCALL_SHMCTL: usize = 195;
 pub const SYSCALL_SHMAT: usize = 196;
@@ -90,16 +140,23 @@ pub const SYSCALL_SOCK_SHUTDOWN: usize = 210;
 pub const SYSCALL_SBRK: usize = 213;
 pub const SYSCALL_BRK: usize = 214;
 pub const SYSCALL_MUNMAP: usize = 215;
+pub const SYSCALL_MREMAP: usize = 216;
 // Warning, we don't implement clone, we implement fork instead.
 pub const SYSCALL_CLONE: usize = 220; // fork is implemented as clone(SIGCHLD, 0) in lib.
 pub const SYSCALL_EXECVE: usize = 221;
 pub const SYSCALL_MMAP: usize = 222;
+pub const SYSCALL_SWAPON: usize = 224;
+pub const SYSCALL_SWAPOFF: usize = 225;
 pub const SYSCALL_MPROTECT: usize = 226;
 pub const SYSCALL_MSYNC: usize = 227;
+pub const SYSCALL_MINCORE: usize = 232;
+pub const SYSCALL_MADVISE: usize = 233;
 pub const SYSCALL_WAIT4: usize = 260; // wait is implemented as wait4(pid, status, options, 0) in pub lib.
 pub const SYSCALL_PRLIMIT: usize = 261;
+pub const SYSCALL_SYNCFS: usize = 267;
 pub const SYSCALL_RENAMEAT2: usize = 276;
 pub const SYSCALL_GETRANDOM: usize = 278;
+pub const SYSCALL_MEMFD_CREATE: usize = 279;
 pub const SYSCALL_MEMBARRIER: usize = 283;
 pub const SYSCALL_COPY_FILE_RANGE: usize = 285;
 pub const SYSCALL_STATX: usize = 291;
@@ -132,6 +132,18 @@ impl UserContext {
     pub const PADDING_SIZE: usize = 128;
 }
 
+/// The registers a tracer reads and writes, `struct user_pt_regs` of Linux
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct UserRegs {
+    /// `$r0` to `$r31`, `$r0` reads as 0
+    pub regs: [usize; 32],
+    pub orig_a0: usize,
+    pub csr_era: usize,
+    pub csr_badv: usize,
+    pub reserved: [usize; 10],
+}
+
 #[repr(C)]
 #[derive(Clone, Copy)]
 /// The trap cotext containing the user context and the supervisor level
@@ -188,4 +200,26 @@ impl TrapContext {
         cx.set_sp(sp);
         cx
     }
+    /// The registers as `PTRACE_GETREGS` shows them, `GeneralRegs` keeps `$r{n}` at index `n`.
+    pub fn user_regs(&self) -> UserRegs {
+        let mut regs = [0; 32];
+        for (n, reg) in regs.iter_mut().enumerate() {
+            *reg = self.gp[n];
+        }
+        UserRegs {
+            regs,
+            orig_a0: self.origin_a0,
+            csr_era: self.gp.pc,
+            csr_badv: 0,
+            reserved: [0; 10],
+        }
+    }
+    /// Load the registers `PTRACE_SETREGS` gives, `$r0` is left alone.
+    pub fn set_user_regs(&mut self, regs: &UserRegs) {
+        for n in 1..32 {
+            self.gp[n] = regs.regs[n];
+        }
+        self.origin_a0 = regs.orig_a0;
+        self.gp.pc = regs.csr_era;
+    }
 }
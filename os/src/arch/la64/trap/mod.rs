@@ -2,22 +2,30 @@ mod context;
 mod mem_access;
 use self::context::GeneralRegs;
 
+use super::config::VA_MASK;
 use super::register::{self, Exception, Interrupt, Trap, ERA};
+use super::smp::{cpu_id, enter_kernel, handle_ipi, leave_kernel};
 use super::{pre_start_init, MErrEntry};
 use crate::arch::la64::laflex::LAFlexPageTable;
-use crate::arch::la64::register::{CrMd, ECfg, LineBasedInterrupt, PrMd, TCfg, TIClr};
+use crate::arch::la64::register::{
+    CrMd, ECfg, LineBasedInterrupt, PrMd, TCfg, TIClr, FWPC, FWPS, IB0ADDR, IB0CTRL, IB0MASK,
+};
 use crate::arch::la64::trap::mem_access::Instruction;
 use crate::arch::{get_clock_freq, TICKS_PER_SEC};
+use crate::fs::dev::tty::TTY;
 use crate::mm::{copy_from_user, copy_to_user, frame_reserve, MemoryError, PageTable, VirtAddr};
+#[cfg(feature = "oom_handler")]
+use crate::mm::out_of_memory;
 use crate::syscall::syscall;
+use crate::task::ptrace::{is_single_stepping, syscall_stop};
 use crate::task::{
     current_task, current_trap_cx, current_user_token, do_signal, do_wake_expired,
-    suspend_current_and_run_next, Signals,
+    preempt_current_and_run_next, scheduler_tick, Signals,
 };
 use core::arch::{asm, global_asm};
 use core::ptr::{addr_of, addr_of_mut};
 
-pub use context::{MachineContext, TrapContext, UserContext};
+pub use context::{MachineContext, TrapContext, UserContext, UserRegs};
 use register::{
     BadV, EStat, TLBRBadV, TLBREHi, TLBRELo0, TLBRELo1, TLBRPrMd, PGD, PGDH, PGDL, PWCH, PWCL,
     TLBRERA,
@@ -152,15 +160,31 @@ pub fn enable_timer_interrupt() {
         .set_init_val(timer_freq / TICKS_PER_SEC)
         .write();
     ECfg::empty()
-        .set_line_based_interrupt_vector(LineBasedInterrupt::TIMER)
+        .set_line_based_interrupt_vector(LineBasedInterrupt::TIMER | LineBasedInterrupt::IPI)
         .write();
 }
+
+/// Whether `PTRACE_SINGLESTEP` works, it takes an instruction fetch watchpoint.
+pub fn single_step_supported() -> bool {
+    FWPC::read().get_num() != 0
+}
+
+/// Trap after one user instruction, as Linux does: watchpoint 0 matches every fetch in user mode,
+/// but the one at `pc` is let through, so the next raises a watch exception.
+/// It only fires if the trap context sets `PRMD.PWE` too.
+fn arm_single_step(pc: usize) {
+    IB0ADDR::empty().set_addr(pc).write();
+    IB0MASK::empty().set_mask(VA_MASK).write();
+    IB0CTRL::empty().set_plv_enable(3, true).write();
+    FWPS::empty().set_skip(true).write();
+}
 #[link_section = ".text.trap_handler"]
 #[no_mangle]
 pub fn trap_handler() -> ! {
     if PrMd::read().get_pplv() == 0 {
         panic!();
     }
+    enter_kernel();
     set_kernel_trap_entry();
 
     {
@@ -168,6 +192,12 @@ pub fn trap_handler() -> ! {
         let mut inner = task.acquire_inner_lock();
         inner.update_process_times_enter_trap();
     }
+    // no lock is held yet, the OOM killer may wait here for its victim to exit
+    #[cfg(feature = "oom_handler")]
+    if !out_of_memory() {
+        // the current process was killed, it exits in `do_signal()`
+        trap_return();
+    }
 
     let cause = get_exception_cause();
     let stval = get_bad_addr();
@@ -179,6 +209,9 @@ pub fn trap_handler() -> ! {
             let mut cx = current_trap_cx();
             ERA::read().next_ins().write();
             cx.gp.pc += 4;
+            // a tracer may change the arguments while the task is stopped
+            syscall_stop();
+            cx = current_trap_cx();
             // get system call return value
             let result = syscall(
                 cx.gp.a7,
@@ -187,6 +220,7 @@ pub fn trap_handler() -> ! {
             // cx is changed during sys_exec, so we have to call it again
             cx = current_trap_cx();
             cx.gp.a0 = result as usize;
+            syscall_stop();
         }
         Trap::Exception(Exception::PagePrivilegeIllegal)
         | Trap::Exception(Exception::PageInvalidFetch)
@@ -243,12 +277,30 @@ pub fn trap_handler() -> ! {
         }
         Trap::Interrupt(Interrupt::Timer) => {
             do_wake_expired();
+            // the housekeeping is done by the boot core alone
+            if cpu_id() == 0 {
+                TTY.poll();
+            }
             TIClr::read().clear_timer().write();
             enable_timer_interrupt();
-            suspend_current_and_run_next();
+            if scheduler_tick() {
+                preempt_current_and_run_next();
+            }
+        }
+        Trap::Interrupt(Interrupt::IPI) => {
+            handle_ipi();
         }
+        // a `break` in user code, most likely one a debugger put there
         Trap::Exception(Exception::Breakpoint) => {
-            read_bp();
+            let task = current_task().unwrap();
+            task.acquire_inner_lock().add_signal(Signals::SIGTRAP);
+        }
+        // the instruction was stepped, see `arm_single_step()`
+        Trap::Exception(Exception::Watch) => {
+            let status = FWPS::read().get_status();
+            FWPS::empty().set_status(status).write();
+            let task = current_task().unwrap();
+            task.acquire_inner_lock().add_signal(Signals::SIGTRAP);
         }
         Trap::Exception(Exception::AddressNotAligned) => {
             let cx = current_trap_cx();
@@ -312,11 +364,7 @@ pub fn trap_handler() -> ! {
                 );
             }
         }
-        Trap::Interrupt(Interrupt::IPI)
-        | Trap::MachineError(_)
-        | Trap::Unknown
-        | Trap::Exception(Exception::AddressError)
-        | _ => {
+        Trap::MachineError(_) | Trap::Unknown | Trap::Exception(Exception::AddressError) | _ => {
             panic!(
                 "Unsupported trap {:?}, stval = {:#x}, BadI = {:#x}!",
                 cause, stval, badi
@@ -331,37 +379,6 @@ pub fn trap_handler() -> ! {
     trap_return();
 }
 
-fn read_bp() {
-    println!(
-        "[trap_handler] {:?}\n\
-                 [trap_handler] {:?}\n\
-                 [trap_handler] {:?}\n\
-                 [trap_handler] {:?}\n\
-                 [trap_handler] {:?}\n\
-                 [trap_handler] {:?}\n\
-                 [trap_handler] {:?}\n\
-                 [trap_handler] {:?}\n\
-                 [trap_handler] {:?}\n\
-                 [trap_handler] {:?}",
-        PrMd::read(),
-        TLBRERA::read(),
-        TLBRBadV::read(),
-        TLBRPrMd::read(),
-        TLBREHi::read(),
-        TLBRELo0::read(),
-        TLBRELo1::read(),
-        PGD::read(),
-        PWCL::read(),
-        PWCH::read()
-    );
-    let cause = get_exception_cause();
-    let stval = get_bad_addr();
-    let badi = get_bad_instruction();
-    panic!(
-        "[trap_handler] {:?}, stval = {:#x}, BadI = {:#x}!",
-        cause, stval, badi
-    );
-}
 #[no_mangle]
 pub fn trap_return() -> ! {
     do_signal();
@@ -369,13 +386,22 @@ pub fn trap_return() -> ! {
     let task = current_task().unwrap();
     let trap_cx = task.acquire_inner_lock().get_trap_cx();
     let trap_cx_ptr = trap_cx as *const TrapContext as usize;
-    trap_cx.sstatus.set_pplv(3).set_pie(true);
+    let single_step = is_single_stepping(&task);
+    trap_cx
+        .sstatus
+        .set_pplv(3)
+        .set_pie(true)
+        .set_pwe(single_step);
+    if single_step {
+        arm_single_step(trap_cx.gp.pc);
+    }
     //log::debug!("[trap_return] trap_cx:{:?}", trap_cx);
     let user_satp = task.get_user_token();
     //log::debug!("[trap_return] trap_cx_ptr:{:#x}, user_satp:{:#x}", trap_cx_ptr, user_satp);
     drop(task);
     let restore_va = __restore as usize - __alltraps as usize + strampoline as usize;
     pre_start_init();
+    leave_kernel(user_satp);
     unsafe {
         asm!(
             "ibar 0",
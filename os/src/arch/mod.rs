@@ -4,16 +4,17 @@ mod la64;
 pub use la64::{
     board,
     board::MMIO,
-    bootstrap_init, config,
+    boot_secondary_cpus, bootstrap_init, config,
     config::BUFFER_CACHE_NUM,
-    console_flush, console_getchar, console_putchar, machine_init, shutdown,
+    console_flush, console_getchar, console_putchar, cpu_id, kick_cpu, machine_init,
+    online_cpus, secondary_init, shutdown,
     time::{get_clock_freq, get_time, TICKS_PER_SEC},
     KernelPageTableImpl, PageTableImpl, __switch, syscall_id, 
     // tlb_global_invalidate,
-    tlb_invalidate,
+    tlb_invalidate, tlb_shootdown,
     trap::{
-        get_bad_addr, get_bad_instruction, get_exception_cause, trap_handler, trap_return,
-        MachineContext, TrapContext, TrapImpl, UserContext,
+        get_bad_addr, get_bad_instruction, get_exception_cause, single_step_supported,
+        trap_handler, trap_return, MachineContext, TrapContext, TrapImpl, UserContext, UserRegs,
     },
     trap_cx_bottom_from_tid, ustack_bottom_from_tid, KernelStack, BLOCK_SZ,
 };
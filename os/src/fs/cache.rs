@@ -3,6 +3,8 @@ use crate::arch::BLOCK_SZ;
 use crate::arch::BUFFER_CACHE_NUM;
 use crate::config::{MEMORY_HIGH_BASE, PAGE_SIZE, PAGE_SIZE_BITS};
 use crate::mm::{frame_alloc, FrameTracker, KERNEL_SPACE};
+use crate::timer::get_time_ms;
+use alloc::collections::BTreeMap;
 use alloc::sync::Arc;
 use alloc::vec::Vec;
 use spin::Mutex;
@@ -120,6 +122,16 @@ impl BlockCacheManager {
             }
         }
     }
+    /// Write every dirty buffer back, they stay cached.
+    pub fn sync(&self, block_device: &Arc<dyn BlockDevice>) {
+        for buffer_cache in &self.cache_pool {
+            let mut locked = buffer_cache.lock();
+            if locked.dirty && locked.block_id != usize::MAX {
+                block_device.write_block(locked.block_id, locked.buffer.as_ref());
+                locked.dirty = false;
+            }
+        }
+    }
     fn alloc_buffer_cache(&self, block_device: &Arc<dyn BlockDevice>) -> Arc<Mutex<BufferCache>> {
         loop {
             for buffer_cache in &self.cache_pool {
@@ -196,6 +208,9 @@ pub struct PageCache {
     priority: usize,
     page_ptr: &'static mut [u8; PAGE_SIZE],
     tracker: Arc<FrameTracker>,
+    /// When the page was first modified after it was last written back, in milliseconds.
+    /// Stores through a shared mapping don't go through `modify()`, see `mark_dirty()`.
+    dirty_since: Option<usize>,
 }
 
 impl Cache for PageCache {
@@ -212,6 +227,9 @@ impl Cache for PageCache {
     }
 
     fn modify<T, V>(&mut self, offset: usize, f: impl FnOnce(&mut T) -> V) -> V {
+        if self.dirty_since.is_none() {
+            self.dirty_since = Some(get_time_ms());
+        }
         debug_assert!(offset.saturating_add(core::mem::size_of::<T>()) <= PAGE_SIZE);
         f(unsafe {
             self.page_ptr
@@ -224,16 +242,9 @@ impl Cache for PageCache {
     }
 
     fn sync(&self, block_ids: Vec<usize>, block_device: &Arc<dyn BlockDevice>) {
-        let lock = KERNEL_SPACE.try_lock();
-        match lock {
-            Some(lock) => {
-                if !lock.is_dirty(self.tracker.ppn).unwrap() {
-                    return;
-                }
-            }
-            None => {}
+        if self.is_dirty() {
+            self.write_back(block_ids, block_device)
         }
-        self.write_back(block_ids, block_device)
     }
 }
 
@@ -246,12 +257,24 @@ impl PageCache {
             priority: 0,
             page_ptr,
             tracker,
+            dirty_since: None,
         }
     }
 
     pub fn get_tracker(&self) -> Arc<FrameTracker> {
         self.tracker.clone()
     }
+    /// Whether the page holds data the disk has not seen.
+    /// It counts as dirty if the D bit can't be read because `KERNEL_SPACE` is locked.
+    pub fn is_dirty(&self) -> bool {
+        if self.dirty_since.is_some() {
+            return true;
+        }
+        match KERNEL_SPACE.try_lock() {
+            Some(lock) => lock.is_dirty(self.tracker.ppn).unwrap(),
+            None => true,
+        }
+    }
     pub fn read_in(&mut self, block_ids: Vec<usize>, block_device: &Arc<dyn BlockDevice>) {
         if block_ids.is_empty() {
             return;
@@ -294,6 +317,48 @@ impl PageCache {
             .unwrap();
     }
 
+    /// Write the page back if it has been dirty for at least `expire` milliseconds,
+    /// or at all if `expire` is `None`. Returns whether it was written.
+    /// `neighbor` gives the blocks of the page, it is only called for a dirty page.
+    pub fn flush<FUNC>(
+        &mut self,
+        neighbor: FUNC,
+        block_device: &Arc<dyn BlockDevice>,
+        expire: Option<usize>,
+    ) -> bool
+    where
+        FUNC: FnOnce() -> Vec<usize>,
+    {
+        if !self.take_dirty(expire) {
+            return false;
+        }
+        self.write_back(neighbor(), block_device);
+        true
+    }
+
+    /// Note stores made through a shared mapping of the page.
+    pub fn mark_dirty(&mut self) {
+        if self.dirty_since.is_none() {
+            self.dirty_since = Some(get_time_ms());
+        }
+    }
+
+    /// Whether the page is due to be written back, see `flush()` for `expire`.
+    /// The page counts as clean afterwards, the caller has to write it.
+    pub fn take_dirty(&mut self, expire: Option<usize>) -> bool {
+        let dirty_since = match self.dirty_since {
+            Some(dirty_since) => dirty_since,
+            None => return false,
+        };
+        if let Some(expire) = expire {
+            if get_time_ms().saturating_sub(dirty_since) < expire {
+                return false;
+            }
+        }
+        self.dirty_since = None;
+        true
+    }
+
     pub fn write_back(&self, block_ids: Vec<usize>, block_device: &Arc<dyn BlockDevice>) {
         if block_ids.is_empty() {
             return;
@@ -396,7 +461,15 @@ impl PageCacheManager {
         page_cache
     }
 
-    pub fn oom<FUNC>(&self, neighbor: FUNC, block_device: &Arc<dyn BlockDevice>) -> usize
+    /// Drop the pages nobody else holds which were not used since the last call.
+    /// Without `write_back` the clean pages are dropped and the used ones aged,
+    /// with it the dirty pages are written back and dropped, the aging is left to the other pass.
+    pub fn oom<FUNC>(
+        &self,
+        neighbor: FUNC,
+        block_device: &Arc<dyn BlockDevice>,
+        write_back: bool,
+    ) -> usize
     where
         FUNC: Fn(usize) -> Vec<usize>,
     {
@@ -415,7 +488,11 @@ impl PageCacheManager {
             if Arc::strong_count(&inner_lock.tracker) > 1 {
                 new_allocated_cache.push(inner_cache_id);
             } else if inner_lock.priority > 0 {
-                inner_lock.priority -= 1;
+                if !write_back {
+                    inner_lock.priority -= 1;
+                }
+                new_allocated_cache.push(inner_cache_id);
+            } else if inner_lock.is_dirty() != write_back {
                 new_allocated_cache.push(inner_cache_id);
             } else {
                 let block_ids = neighbor(inner_cache_id);
@@ -429,6 +506,30 @@ impl PageCacheManager {
         dropped
     }
 
+    /// Write back the dirty pages, see `PageCache::flush()` for `expire`.
+    /// Returns the number of pages written.
+    pub fn sync<FUNC>(
+        &self,
+        neighbor: FUNC,
+        block_device: &Arc<dyn BlockDevice>,
+        expire: Option<usize>,
+    ) -> usize
+    where
+        FUNC: Fn(usize) -> Vec<usize>,
+    {
+        let lock = self.cache_pool.lock();
+        let mut written = 0;
+        for inner_cache_id in self.allocated_cache.lock().iter() {
+            if let Some(inner) = &lock[*inner_cache_id] {
+                let mut inner_lock = inner.lock();
+                if inner_lock.flush(|| neighbor(*inner_cache_id), block_device, expire) {
+                    written += 1;
+                }
+            }
+        }
+        written
+    }
+
     pub fn notify_new_size(&self, new_size: usize) {
         let mut lock = self.cache_pool.lock();
         let new_pages = (new_size + PAGE_SIZE - 1) / PAGE_SIZE;
@@ -446,3 +547,116 @@ impl PageCacheManager {
             .retain(|cache_id| *cache_id < new_pages);
     }
 }
+
+/// Copies of file pages handed out to `mmap()` by a backend without a page cache of its own, e.g. ext4.
+/// Writes go into them as well, stores through shared mappings reach the file in `writeback()`.
+pub struct MappedPages(Mutex<BTreeMap<usize, Arc<Mutex<PageCache>>>>);
+
+impl MappedPages {
+    pub fn new() -> Self {
+        Self(Mutex::new(BTreeMap::new()))
+    }
+    /// The page `inner_cache_id`, filled by `read` on first use, which returns the bytes it read.
+    pub fn get<FUNC>(&self, inner_cache_id: usize, read: FUNC) -> Arc<Mutex<PageCache>>
+    where
+        FUNC: FnOnce(&mut [u8; PAGE_SIZE]) -> usize,
+    {
+        if let Some(page_cache) = self.0.lock().get(&inner_cache_id) {
+            return page_cache.clone();
+        }
+        crate::mm::frame_reserve(1);
+        let mut page_cache = PageCache::new();
+        page_cache.modify(0, |page: &mut [u8; PAGE_SIZE]| {
+            let len = read(page);
+            page[len..].fill(0);
+        });
+        // it was just read in, there is nothing to write back
+        page_cache.take_dirty(None);
+        let page_cache = Arc::new(Mutex::new(page_cache));
+        self.0
+            .lock()
+            .entry(inner_cache_id)
+            .or_insert(page_cache)
+            .clone()
+    }
+    pub fn get_all(&self) -> Vec<Arc<Mutex<PageCache>>> {
+        self.0.lock().values().cloned().collect()
+    }
+    /// Drop every page, when the file is truncated or unlinked.
+    pub fn clear(&self) {
+        self.0.lock().clear();
+    }
+    /// Copy a write into the pages it covers, they may be mapped shared.
+    pub fn update(&self, offset: usize, buf: &[u8]) {
+        let end = offset + buf.len();
+        let lock = self.0.lock();
+        for (inner_cache_id, page_cache) in
+            lock.range(offset / PAGE_SIZE..(end + PAGE_SIZE - 1) / PAGE_SIZE)
+        {
+            let page_start = inner_cache_id * PAGE_SIZE;
+            let from = offset.max(page_start);
+            let to = end.min(page_start + PAGE_SIZE);
+            page_cache.lock().modify(0, |page: &mut [u8; PAGE_SIZE]| {
+                page[from - page_start..to - page_start]
+                    .copy_from_slice(&buf[from - offset..to - offset]);
+            });
+        }
+    }
+    /// Hand the modified pages within the first `size` bytes of the file to `write`, with their offset.
+    /// See `PageCache::flush()` for `expire`. Returns the number of written pages.
+    pub fn writeback<FUNC>(&self, size: usize, expire: Option<usize>, write: FUNC) -> usize
+    where
+        FUNC: Fn(usize, &[u8]),
+    {
+        let page_caches: Vec<(usize, Arc<Mutex<PageCache>>)> = self
+            .0
+            .lock()
+            .iter()
+            .map(|(inner_cache_id, page_cache)| (*inner_cache_id, page_cache.clone()))
+            .collect();
+        let mut written = 0;
+        for (inner_cache_id, page_cache) in page_caches {
+            let mut page_cache = page_cache.lock();
+            if !page_cache.take_dirty(expire) {
+                continue;
+            }
+            // the tail of the last page lies beyond EOF
+            let page_start = inner_cache_id * PAGE_SIZE;
+            if page_start >= size {
+                continue;
+            }
+            let len = (size - page_start).min(PAGE_SIZE);
+            page_cache.read(0, |page: &[u8; PAGE_SIZE]| write(page_start, &page[..len]));
+            written += 1;
+        }
+        written
+    }
+    /// Drop the pages nobody maps, once they are written back like by `writeback()`.
+    pub fn oom<FUNC>(&self, size: usize, write: FUNC) -> usize
+    where
+        FUNC: Fn(usize, &[u8]),
+    {
+        self.writeback(size, None, write);
+        let mut lock = self.0.lock();
+        let before = lock.len();
+        // `get_tracker()` hands out one more reference of its own
+        lock.retain(|_, page_cache| {
+            Arc::strong_count(page_cache) > 1
+                || Arc::strong_count(&page_cache.lock().get_tracker()) > 2
+        });
+        before - lock.len()
+    }
+    /// Drop the pages nobody maps and that hold nothing unwritten.
+    pub fn shrink_clean(&self) -> usize {
+        let mut lock = self.0.lock();
+        let before = lock.len();
+        lock.retain(|_, page_cache| {
+            if Arc::strong_count(page_cache) > 1 {
+                return true;
+            }
+            let page_cache = page_cache.lock();
+            page_cache.is_dirty() || Arc::strong_count(&page_cache.get_tracker()) > 2
+        });
+        before - lock.len()
+    }
+}
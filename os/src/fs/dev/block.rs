@@ -0,0 +1,309 @@
+use crate::fs::DiskInodeType;
+use alloc::{
+    string::String,
+    sync::{Arc, Weak},
+    vec::Vec,
+};
+use spin::Mutex;
+
+use crate::{
+    arch::BLOCK_SZ,
+    drivers::block::BlockDevice,
+    fs::{
+        directory_tree::DirectoryTreeNode,
+        file_trait::File,
+        layout::{Dirent, OpenFlags, Stat},
+        PageCache, SeekWhence, StatMode,
+    },
+    mm::UserBuffer,
+    syscall::errno::{EINVAL, ENOTDIR},
+};
+
+/// A range of blocks of the disk, read and written around every cache.
+/// The disk carries no partition table, `/dev/sda1` is the root filesystem
+/// and `/dev/sda2` whatever follows it, e.g. a swap area made by `mkswap`.
+pub struct BlockFile {
+    readable: bool,
+    writable: bool,
+    device: Arc<dyn BlockDevice>,
+    /// The first block
+    start: usize,
+    /// Number of blocks, `None` if the range reaches the end of the disk, whose size is unknown
+    blocks: Option<usize>,
+    rdev: u64,
+    offset: Mutex<usize>,
+    dirnode_ptr: Arc<Mutex<Weak<DirectoryTreeNode>>>,
+}
+
+impl BlockFile {
+    pub fn new(
+        device: Arc<dyn BlockDevice>,
+        start: usize,
+        blocks: Option<usize>,
+        rdev: u64,
+    ) -> Arc<Self> {
+        Arc::new(Self {
+            readable: true,
+            writable: true,
+            device,
+            start,
+            blocks,
+            rdev,
+            offset: Mutex::new(0),
+            dirnode_ptr: Arc::new(Mutex::new(Weak::new())),
+        })
+    }
+    /// The device, the first block and the number of blocks, for `swapon()`
+    pub fn extent(&self) -> (Arc<dyn BlockDevice>, usize, Option<usize>) {
+        (self.device.clone(), self.start, self.blocks)
+    }
+    /// The end of an access of `len` bytes at `offset`, which stops at the end of the range
+    fn end(&self, offset: usize, len: usize) -> usize {
+        match self.blocks {
+            Some(blocks) => (offset + len).min(blocks * BLOCK_SZ).max(offset),
+            None => offset + len,
+        }
+    }
+    fn read_at(&self, offset: usize, buf: &mut [u8]) -> usize {
+        let end = self.end(offset, buf.len());
+        let mut block = [0u8; BLOCK_SZ];
+        let mut pos = offset;
+        while pos < end {
+            let block_offset = pos % BLOCK_SZ;
+            let len = (BLOCK_SZ - block_offset).min(end - pos);
+            self.device
+                .read_block(self.start + pos / BLOCK_SZ, &mut block);
+            buf[pos - offset..pos - offset + len]
+                .copy_from_slice(&block[block_offset..block_offset + len]);
+            pos += len;
+        }
+        end - offset
+    }
+    fn write_at(&self, offset: usize, buf: &[u8]) -> usize {
+        let end = self.end(offset, buf.len());
+        let mut block = [0u8; BLOCK_SZ];
+        let mut pos = offset;
+        while pos < end {
+            let block_id = self.start + pos / BLOCK_SZ;
+            let block_offset = pos % BLOCK_SZ;
+            let len = (BLOCK_SZ - block_offset).min(end - pos);
+            // the rest of a partly written block is kept
+            if len < BLOCK_SZ {
+                self.device.read_block(block_id, &mut block);
+            }
+            block[block_offset..block_offset + len]
+                .copy_from_slice(&buf[pos - offset..pos - offset + len]);
+            self.device.write_block(block_id, &block);
+            pos += len;
+        }
+        end - offset
+    }
+}
+
+#[allow(unused)]
+impl File for BlockFile {
+    fn deep_clone(&self) -> Arc<dyn File> {
+        Arc::new(Self {
+            readable: self.readable,
+            writable: self.writable,
+            device: self.device.clone(),
+            start: self.start,
+            blocks: self.blocks,
+            rdev: self.rdev,
+            offset: Mutex::new(*self.offset.lock()),
+            dirnode_ptr: self.dirnode_ptr.clone(),
+        })
+    }
+
+    fn readable(&self) -> bool {
+        self.readable
+    }
+
+    fn writable(&self) -> bool {
+        self.writable
+    }
+
+    fn read(&self, offset: Option<&mut usize>, buf: &mut [u8]) -> usize {
+        match offset {
+            Some(offset) => {
+                let read_size = self.read_at(*offset, buf);
+                *offset += read_size;
+                read_size
+            }
+            None => {
+                let mut offset = self.offset.lock();
+                let read_size = self.read_at(*offset, buf);
+                *offset += read_size;
+                read_size
+            }
+        }
+    }
+
+    fn write(&self, offset: Option<&mut usize>, buf: &[u8]) -> usize {
+        match offset {
+            Some(offset) => {
+                let write_size = self.write_at(*offset, buf);
+                *offset += write_size;
+                write_size
+            }
+            None => {
+                let mut offset = self.offset.lock();
+                let write_size = self.write_at(*offset, buf);
+                *offset += write_size;
+                write_size
+            }
+        }
+    }
+
+    fn r_ready(&self) -> bool {
+        true
+    }
+
+    fn w_ready(&self) -> bool {
+        true
+    }
+
+    fn read_user(&self, offset: Option<usize>, mut buf: UserBuffer) -> usize {
+        let mut offset = offset;
+        let mut total_read_size = 0usize;
+        for slice in buf.buffers.iter_mut() {
+            let read_size = self.read(offset.as_mut(), *slice);
+            total_read_size += read_size;
+            if read_size < slice.len() {
+                break;
+            }
+        }
+        total_read_size
+    }
+
+    fn write_user(&self, offset: Option<usize>, buf: UserBuffer) -> usize {
+        let mut offset = offset;
+        let mut total_write_size = 0usize;
+        for slice in buf.buffers.iter() {
+            let write_size = self.write(offset.as_mut(), *slice);
+            total_write_size += write_size;
+            if write_size < slice.len() {
+                break;
+            }
+        }
+        total_write_size
+    }
+
+    fn get_size(&self) -> usize {
+        self.blocks.map_or(0, |blocks| blocks * BLOCK_SZ)
+    }
+
+    fn get_stat(&self) -> Stat {
+        Stat::new(
+            crate::makedev!(0, 5),
+            1,
+            StatMode::S_IFBLK.bits() | 0o660,
+            1,
+            self.rdev,
+            self.get_size() as i64,
+            0,
+            0,
+            0,
+        )
+    }
+
+    fn get_file_type(&self) -> DiskInodeType {
+        DiskInodeType::File
+    }
+
+    fn info_dirtree_node(&self, dirnode_ptr: Weak<DirectoryTreeNode>) {
+        *self.dirnode_ptr.lock() = dirnode_ptr;
+    }
+
+    fn get_dirtree_node(&self) -> Option<Arc<DirectoryTreeNode>> {
+        self.dirnode_ptr.lock().upgrade()
+    }
+
+    fn open(&self, flags: OpenFlags, special_use: bool) -> Arc<dyn File> {
+        Arc::new(Self {
+            readable: flags.contains(OpenFlags::O_RDONLY) || flags.contains(OpenFlags::O_RDWR),
+            writable: flags.contains(OpenFlags::O_WRONLY) || flags.contains(OpenFlags::O_RDWR),
+            device: self.device.clone(),
+            start: self.start,
+            blocks: self.blocks,
+            rdev: self.rdev,
+            offset: Mutex::new(0),
+            dirnode_ptr: self.dirnode_ptr.clone(),
+        })
+    }
+
+    fn open_subfile(&self) -> Result<Vec<(String, Arc<dyn File>)>, isize> {
+        Err(ENOTDIR)
+    }
+
+    fn create(&self, name: &str, file_type: DiskInodeType) -> Result<Arc<dyn File>, isize> {
+        Err(ENOTDIR)
+    }
+
+    fn link_child(&self, name: &str, child: &Self) -> Result<(), isize>
+    where
+        Self: Sized,
+    {
+        Err(ENOTDIR)
+    }
+
+    fn unlink(&self, delete: bool) -> Result<(), isize> {
+        Ok(())
+    }
+
+    fn get_dirent(&self, count: usize) -> Vec<Dirent> {
+        Vec::new()
+    }
+
+    fn lseek(&self, offset: isize, whence: SeekWhence) -> Result<usize, isize> {
+        let mut current = self.offset.lock();
+        let new_offset = match whence {
+            SeekWhence::SEEK_SET => offset,
+            SeekWhence::SEEK_CUR => *current as isize + offset,
+            SeekWhence::SEEK_END => self.get_size() as isize + offset,
+            // whence is duplicated
+            _ => return Err(EINVAL),
+        };
+        let new_offset = match new_offset < 0 {
+            true => return Err(EINVAL),
+            false => new_offset as usize,
+        };
+        *current = new_offset;
+        Ok(new_offset)
+    }
+
+    fn modify_size(&self, diff: isize) -> Result<(), isize> {
+        Err(EINVAL)
+    }
+
+    fn truncate_size(&self, new_size: usize) -> Result<(), isize> {
+        Err(EINVAL)
+    }
+
+    fn set_timestamp(&self, ctime: Option<usize>, atime: Option<usize>, mtime: Option<usize>) {}
+
+    /// Nothing is cached, the blocks are on the disk already
+    fn fsync(&self) -> Result<(), isize> {
+        Ok(())
+    }
+
+    fn get_single_cache(&self, offset: usize) -> Result<Arc<Mutex<PageCache>>, ()> {
+        Err(())
+    }
+
+    fn get_all_caches(&self) -> Result<Vec<Arc<Mutex<PageCache>>>, ()> {
+        Err(())
+    }
+
+    fn oom(&self) -> usize {
+        0
+    }
+
+    fn hang_up(&self) -> bool {
+        false
+    }
+
+    fn fcntl(&self, cmd: u32, arg: u32) -> isize {
+        0
+    }
+}
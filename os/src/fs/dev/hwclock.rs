@@ -64,7 +64,7 @@ impl File for Hwclock {
     fn get_dirtree_node(
         &self,
     ) -> Option<alloc::sync::Arc<crate::fs::directory_tree::DirectoryTreeNode>> {
-        todo!()
+        None
     }
 
     fn open(&self, flags: crate::fs::OpenFlags, special_use: bool) -> alloc::sync::Arc<dyn File> {
@@ -1,3 +1,4 @@
+pub mod block;
 pub mod hwclock;
 pub mod null;
 pub mod pipe;
@@ -66,7 +66,7 @@ impl File for Socket {
     }
 
     fn get_dirtree_node(&self) -> Option<Arc<crate::fs::directory_tree::DirectoryTreeNode>> {
-        todo!()
+        None
     }
 
     fn open(&self, flags: crate::fs::OpenFlags, special_use: bool) -> Arc<dyn File> {
@@ -7,7 +7,12 @@ use crate::fs::StatMode;
 use crate::mm::{copy_from_user, copy_to_user};
 use crate::mm::{translated_ref, translated_refmut, UserBuffer};
 use crate::syscall::errno::*;
+use crate::task::{
+    current_cred, current_task, is_orphaned_pgrp, kill_pgrp, pgrp_in_session,
+    suspend_current_and_run_next, Signals,
+};
 
+use alloc::collections::VecDeque;
 use alloc::sync::Arc;
 use lazy_static::lazy_static;
 use log::{info, warn};
@@ -38,8 +43,21 @@ impl Default for WinSize {
     }
 }
 
+/// Characters kept until they are read, like `N_TTY_BUF_SIZE` of Linux
+const MAX_INPUT: usize = 4096;
+
+/// Indexes of `Termios::cc`
+const VINTR: usize = 0;
+const VQUIT: usize = 1;
+const VSUSP: usize = 10;
+
 pub struct TeletypeInner {
+    #[cfg(feature = "board_k210")]
     last_char: u8,
+    /// Received but not read yet, the characters which send a signal never get here
+    input: VecDeque<u8>,
+    /// The session this is the controlling terminal of
+    session: Option<usize>,
     foreground_pgid: u32,
     winsize: WinSize,
     termios: Termios,
@@ -48,7 +66,10 @@ pub struct TeletypeInner {
 impl Default for TeletypeInner {
     fn default() -> Self {
         Self {
+            #[cfg(feature = "board_k210")]
             last_char: 255,
+            input: VecDeque::new(),
+            session: None,
             foreground_pgid: Default::default(),
             winsize: WinSize::default(),
             termios: Termios::default(),
@@ -56,6 +77,93 @@ impl Default for TeletypeInner {
     }
 }
 
+impl TeletypeInner {
+    /// Take what the console has received.
+    /// With `ISIG`, the interrupt, quit and suspend characters signal the foreground process group instead.
+    fn receive(&mut self) {
+        loop {
+            let ch = console_getchar() as u8;
+            if ch == 255 {
+                break;
+            }
+            let lflag = LocalModes::from_bits_truncate(self.termios.lflag);
+            if lflag.contains(LocalModes::ISIG) {
+                if let Some(signal) = self.signal_of(ch) {
+                    if lflag.contains(LocalModes::ECHO | LocalModes::ECHOCTL) {
+                        print!("^{}", (ch ^ 0x40) as char);
+                    }
+                    if !lflag.contains(LocalModes::NOFLSH) {
+                        self.input.clear();
+                    }
+                    if self.foreground_pgid != 0 {
+                        kill_pgrp(self.foreground_pgid as usize, signal);
+                    }
+                    continue;
+                }
+            }
+            if self.input.len() >= MAX_INPUT {
+                continue;
+            }
+            if lflag.contains(LocalModes::ECHO) {
+                if ch == '\r' as u8 {
+                    print!("\n");
+                } else {
+                    print!("{}", ch as char);
+                }
+            }
+            self.input.push_back(ch);
+        }
+    }
+    /// The signal `ch` sends, a special character of 0 is disabled
+    fn signal_of(&self, ch: u8) -> Option<Signals> {
+        [
+            (VINTR, Signals::SIGINT),
+            (VQUIT, Signals::SIGQUIT),
+            (VSUSP, Signals::SIGTSTP),
+        ]
+        .iter()
+        .find(|(index, _)| self.termios.cc[*index] != 0 && self.termios.cc[*index] == ch)
+        .map(|(_, signal)| *signal)
+    }
+    /// Job control, like `tty_check_change()` of Linux.
+    /// A process of the session in a background group gets `signal` and its call is restarted once it is continued.
+    /// If it blocks or ignores the signal, it goes ahead, but a read fails with `EIO`.
+    fn check_background(&self, signal: Signals) -> Result<(), isize> {
+        let task = current_task().unwrap();
+        let (pgid, sid, blocked) = {
+            let inner = task.acquire_inner_lock();
+            (inner.pgid, inner.sid, inner.sigmask.contains(signal))
+        };
+        if self.session != Some(sid) || pgid == self.foreground_pgid as usize {
+            return Ok(());
+        }
+        let ignored = task.sighand.lock()[signal.to_signum().unwrap() - 1]
+            .as_ref()
+            .map_or(false, |act| act.ignored());
+        drop(task);
+        if blocked || ignored {
+            return match signal {
+                Signals::SIGTTIN => Err(EIO),
+                _ => Ok(()),
+            };
+        }
+        if is_orphaned_pgrp(pgid) {
+            return Err(EIO);
+        }
+        kill_pgrp(pgid, signal);
+        Err(ERESTART)
+    }
+    /// Stop being the controlling terminal, the foreground process group is hung up.
+    fn release_session(&mut self) {
+        if self.foreground_pgid != 0 {
+            kill_pgrp(self.foreground_pgid as usize, Signals::SIGHUP);
+            kill_pgrp(self.foreground_pgid as usize, Signals::SIGCONT);
+        }
+        self.session = None;
+        self.foreground_pgid = 0;
+    }
+}
+
 #[derive(Default)]
 pub struct Teletype {
     inner: Mutex<TeletypeInner>,
@@ -65,6 +173,24 @@ impl Teletype {
     pub fn new() -> Self {
         Default::default()
     }
+    /// Become the controlling terminal of session `sid`, with group `pgid` in the foreground.
+    pub fn set_controlling(&self, sid: usize, pgid: usize) {
+        let mut inner = self.inner.lock();
+        inner.session = Some(sid);
+        inner.foreground_pgid = pgid as u32;
+    }
+    /// Called when the leader of session `sid` exits, like `disassociate_ctty()` of Linux
+    pub fn disassociate(&self, sid: usize) {
+        let mut inner = self.inner.lock();
+        if inner.session == Some(sid) {
+            inner.release_session();
+        }
+    }
+    /// Take what the console has received, so that the signal characters work while nobody reads.
+    /// Called from the idle loop and from timer interrupts.
+    pub fn poll(&self) {
+        self.inner.lock().receive();
+    }
 }
 
 // TODO: independ of rust sbi
@@ -115,14 +241,8 @@ impl File for Teletype {
     #[cfg(not(any(feature = "board_k210")))]
     fn r_ready(&self) -> bool {
         let mut inner = self.inner.lock();
-        // buffer has valid data
-        if inner.last_char != 255 {
-            true
-        // peek next char
-        } else {
-            inner.last_char = console_getchar() as u8;
-            inner.last_char != 255
-        }
+        inner.receive();
+        !inner.input.is_empty()
     }
 
     fn w_ready(&self) -> bool {
@@ -160,45 +280,50 @@ impl File for Teletype {
         if offset.is_some() {
             return ESPIPE as usize;
         }
-        let mut inner = self.inner.lock();
-        // todo: check foreground pgid
-        let mut count = 0;
-        for ptr in buf {
-            loop {
-                //we have read a legal char
-                if inner.last_char != 255 {
-                    break;
-                }
-                //if we have read some chars, we can return
-                if count > 0 {
-                    return count;
-                }
-                //we read no char, suspend the procedure
-                crate::task::suspend_current_and_run_next;
-                inner.last_char = console_getchar() as u8;
+        loop {
+            let mut inner = self.inner.lock();
+            if let Err(errno) = inner.check_background(Signals::SIGTTIN) {
+                return errno as usize;
             }
-            //we can guarantee last_char isn't a illegal char
-            unsafe {
-                ptr.write_volatile(inner.last_char);
-            }
-            if inner.termios.lflag & LocalModes::ECHO.bits() != 0 {
-                if inner.last_char == '\r' as u8 {
-                    print!("\n");
-                } else {
-                    print!("{}", inner.last_char as char);
+            inner.receive();
+            if !inner.input.is_empty() {
+                let mut count = 0;
+                for ptr in buf {
+                    match inner.input.pop_front() {
+                        Some(ch) => unsafe { ptr.write_volatile(ch) },
+                        None => break,
+                    }
+                    count += 1;
                 }
+                return count;
             }
-            inner.last_char = console_getchar() as u8;
-            count += 1;
+            drop(inner);
+            // a signal, such as the one sent by the interrupt character, interrupts the wait
+            let task = current_task().unwrap();
+            let task_inner = task.acquire_inner_lock();
+            if !task_inner
+                .sigpending
+                .difference(task_inner.sigmask)
+                .is_empty()
+            {
+                return ERESTART as usize;
+            }
+            drop(task_inner);
+            drop(task);
+            suspend_current_and_run_next();
         }
-        count
     }
 
     fn write_user(&self, offset: Option<usize>, user_buffer: UserBuffer) -> usize {
         if offset.is_some() {
             return ESPIPE as usize;
         }
-        let _inner = self.inner.lock();
+        let inner = self.inner.lock();
+        if inner.termios.lflag & LocalModes::TOSTOP.bits() != 0 {
+            if let Err(errno) = inner.check_background(Signals::SIGTTOU) {
+                return errno as usize;
+            }
+        }
         for buffer in user_buffer.buffers.iter() {
             match core::str::from_utf8(*buffer) {
                 Ok(content) => print!("{}", content),
@@ -237,7 +362,7 @@ impl File for Teletype {
     }
 
     fn get_dirtree_node(&self) -> Option<Arc<DirectoryTreeNode>> {
-        todo!()
+        None
     }
 
     fn open(&self, flags: crate::fs::layout::OpenFlags, special_use: bool) -> Arc<dyn File> {
@@ -309,6 +434,11 @@ impl File for Teletype {
         );
         let mut inner = self.inner.lock();
         let token = crate::task::current_user_token();
+        let (tgid, pgid, sid) = {
+            let task = current_task().unwrap();
+            let task_inner = task.acquire_inner_lock();
+            (task.tgid, task_inner.pgid, task_inner.sid)
+        };
         match TeletypeCommand::from_primitive(cmd) {
             TeletypeCommand::TCGETS | TeletypeCommand::TCGETA => {
                 copy_to_user(token, &inner.termios, argp as *mut Termios);
@@ -318,6 +448,8 @@ impl File for Teletype {
                 copy_from_user(token, argp as *const Termios, &mut inner.termios);
                 SUCCESS
             }
+            // only the session of the terminal sees its foreground process group
+            TeletypeCommand::TIOCGPGRP if inner.session != Some(sid) => ENOTTY,
             TeletypeCommand::TIOCGPGRP => match translated_refmut(token, argp as *mut u32) {
                 Ok(word) => {
                     *word = inner.foreground_pgid;
@@ -325,12 +457,55 @@ impl File for Teletype {
                 }
                 Err(errno) => errno,
             },
-            TeletypeCommand::TIOCSPGRP => match translated_ref(token, argp as *const u32) {
-                Ok(word) => {
-                    inner.foreground_pgid = *word;
-                    SUCCESS
+            TeletypeCommand::TIOCSPGRP if inner.session != Some(sid) => ENOTTY,
+            TeletypeCommand::TIOCSPGRP => {
+                if let Err(errno) = inner.check_background(Signals::SIGTTOU) {
+                    return errno;
                 }
-                Err(errno) => errno,
+                let pgrp = match translated_ref(token, argp as *const i32) {
+                    Ok(word) => *word,
+                    Err(errno) => return errno,
+                };
+                if pgrp < 0 {
+                    return EINVAL;
+                }
+                if !pgrp_in_session(pgrp as usize, sid) {
+                    return EPERM;
+                }
+                inner.foreground_pgid = pgrp as u32;
+                SUCCESS
+            }
+            TeletypeCommand::TIOCSCTTY => {
+                if sid != tgid {
+                    return EPERM;
+                }
+                if inner.session == Some(sid) {
+                    return SUCCESS;
+                }
+                // stealing the terminal of another session takes root and an argument of 1
+                if inner.session.is_some() && !(argp == 1 && current_cred().is_root()) {
+                    return EPERM;
+                }
+                inner.session = Some(sid);
+                inner.foreground_pgid = pgid as u32;
+                SUCCESS
+            }
+            TeletypeCommand::TIOCNOTTY if inner.session != Some(sid) => ENOTTY,
+            TeletypeCommand::TIOCNOTTY => {
+                if sid == tgid {
+                    inner.release_session();
+                }
+                SUCCESS
+            }
+            TeletypeCommand::TIOCGSID => match inner.session {
+                Some(session) => match translated_refmut(token, argp as *mut u32) {
+                    Ok(word) => {
+                        *word = session as u32;
+                        SUCCESS
+                    }
+                    Err(errno) => errno,
+                },
+                None => ENOTTY,
             },
             TeletypeCommand::TIOCGWINSZ => {
                 copy_to_user(token, &inner.winsize, argp as *mut WinSize);
@@ -378,6 +553,13 @@ pub enum TeletypeCommand {
     /// Set the foreground process group ID of this terminal.
     TIOCSPGRP = 0x5410,
 
+    /// Make this the controlling terminal of the session of the calling process.
+    TIOCSCTTY = 0x540E,
+    /// Give up this controlling terminal.
+    TIOCNOTTY = 0x5422,
+    /// Get the session ID this is the controlling terminal of.
+    TIOCGSID = 0x5429,
+
     /// Get window size.
     TIOCGWINSZ = 0x5413,
     /// Set window size.
@@ -1,8 +1,10 @@
 use super::fat32::{DiskInodeType, EasyFileSystem};
 use alloc::{
     collections::BTreeMap,
+    format,
     string::{String, ToString},
     sync::{Arc, Weak},
+    vec,
     vec::Vec,
 };
 use lazy_static::*;
@@ -10,22 +12,27 @@ use spin::{Mutex, MutexGuard, RwLock, RwLockWriteGuard};
 
 use super::{
     cache::BlockCacheManager,
-    dev::{null::Null, tty::Teletype, zero::Zero, urandom::Urandom},
+    dev::{block::BlockFile, null::Null, tty::Teletype, zero::Zero, urandom::Urandom},
     file_trait::File,
     filesystem::FileSystem,
-    layout::OpenFlags,
+    layout::{MountFlags, OpenFlags, StatMode, UmountFlags},
     Hwclock,
 };
 use crate::{
     drivers::BLOCK_DEVICE,
     fs::{
-        fat32::inode::{InodeImpl, OSInode},
+        fat32::inode::{Fat32Options, InodeImpl, OSInode},
         filesystem::FS,
+        tmpfs::{self, TmpFileSystem, TmpfsInode, TmpfsOSInode, TmpfsOptions},
     },
+    task::{all_tasks, current_cred, Access, Credentials},
 };
-
-#[cfg(feature = "oom_handler")]
-use crate::mm::tlb_invalidate;
+#[cfg(feature = "lwext4")]
+use crate::fs::ext4::{Ext4FileSystem, Ext4Inode, Ext4OSInode};
+#[cfg(feature = "ext4rs")]
+use crate::fs::ext4rs::{Ext4RsFileSystem, Ext4RsInode, Ext4RsOSInode};
+#[cfg(any(feature = "lwext4", feature = "ext4rs"))]
+use crate::{arch::BLOCK_SZ, fs::filesystem::probe_ext4};
 
 use crate::syscall::errno::*;
 
@@ -35,10 +42,11 @@ lazy_static! {
         Arc::new(Mutex::new(BlockCacheManager::new()))
     );
     pub static ref ROOT: Arc<DirectoryTreeNode> = {
+        let (filesystem, root_file) = root_filesystem();
         let inode = DirectoryTreeNode::new(
             "".to_string(),
-            Arc::new(FileSystem::new(FS::Fat32)),
-            OSInode::new(InodeImpl::root_inode(&FILE_SYSTEM)),
+            filesystem,
+            root_file,
             Weak::new(),
         );
         inode.add_special_use();
@@ -48,6 +56,171 @@ lazy_static! {
         Mutex::new((Vec::new(), 0));
     static ref PATH_CACHE: Mutex<(String, Weak<DirectoryTreeNode>)> =
         Mutex::new(("".to_string(), Weak::new()));
+    static ref MOUNT_TABLE: Mutex<Vec<Mount>> = Mutex::new(vec![Mount {
+        source: "/dev/root".to_string(),
+        fstype: fstype_name(&ROOT.filesystem.fs_type).to_string(),
+        root: ROOT.clone(),
+        covered: None,
+    }]);
+}
+
+/// The longest chain of symbolic links followed in one path walk, the same as Linux
+const MAXSYMLINKS: usize = 40;
+
+/// Pick the backend of the root filesystem.
+/// FAT32 is the fallback when no other backend recognizes `BLOCK_DEVICE`.
+fn root_filesystem() -> (Arc<FileSystem>, Arc<dyn File>) {
+    #[cfg(feature = "lwext4")]
+    if let Some(geometry) = probe_ext4(&BLOCK_DEVICE) {
+        match Ext4FileSystem::open(BLOCK_DEVICE.clone(), geometry, false) {
+            Ok(efs) => {
+                return (
+                    Arc::new(FileSystem::new(FS::Ext4)),
+                    Ext4OSInode::new(Ext4Inode::root_inode(&efs)),
+                )
+            }
+            Err(errno) => log::error!("[root_filesystem] failed to mount ext4: {}", errno),
+        }
+    }
+    #[cfg(feature = "ext4rs")]
+    if let Some(geometry) = probe_ext4(&BLOCK_DEVICE) {
+        match Ext4RsFileSystem::open(BLOCK_DEVICE.clone(), geometry) {
+            Ok(efs) => {
+                return (
+                    Arc::new(FileSystem::new(FS::Ext4Rs)),
+                    Ext4RsOSInode::new(Ext4RsInode::root_inode(&efs)),
+                )
+            }
+            Err(errno) => log::error!("[root_filesystem] failed to mount ext4 with ext4-rs: {}", errno),
+        }
+    }
+    (
+        Arc::new(FileSystem::new(FS::Fat32)),
+        OSInode::new(InodeImpl::root_inode(&FILE_SYSTEM), Fat32Options::default()),
+    )
+}
+
+/// `FILE_SYSTEM` is only valid when the root filesystem is FAT32,
+/// e.g. the boot-time swap area is carved out of it.
+pub fn root_is_fat32() -> bool {
+    match ROOT.filesystem.fs_type {
+        FS::Fat32 => true,
+        _ => false,
+    }
+}
+
+/// Number of blocks of `BLOCK_DEVICE` taken by the root filesystem
+fn root_volume_blocks() -> usize {
+    match ROOT.filesystem.fs_type {
+        #[cfg(feature = "lwext4")]
+        FS::Ext4 => probe_ext4(&BLOCK_DEVICE).map_or(0, |geometry| {
+            (geometry.volume_size() as usize + BLOCK_SZ - 1) / BLOCK_SZ
+        }),
+        #[cfg(feature = "ext4rs")]
+        FS::Ext4Rs => probe_ext4(&BLOCK_DEVICE).map_or(0, |geometry| {
+            (geometry.volume_size() as usize + BLOCK_SZ - 1) / BLOCK_SZ
+        }),
+        FS::Fat32 => FILE_SYSTEM.tot_sec as usize,
+        _ => 0,
+    }
+}
+
+fn fstype_name(fs_type: &FS) -> &'static str {
+    match fs_type {
+        FS::Fat32 => "vfat",
+        #[cfg(feature = "lwext4")]
+        FS::Ext4 => "ext4",
+        #[cfg(feature = "ext4rs")]
+        FS::Ext4Rs => "ext4",
+        FS::Proc => "proc",
+        FS::Tmpfs => "tmpfs",
+        FS::Null => "none",
+    }
+}
+
+/// Instantiate a filesystem of type `fstype` for `mount()`, `data` holds its options.
+/// There is only one block device and the root filesystem lives on it, so it is busy:
+/// a second tree over the volume would cache its files apart from the first one.
+/// Its options are changed with `MS_REMOUNT` instead.
+fn new_filesystem(fstype: &str, data: &str) -> Result<(FS, Arc<dyn File>), isize> {
+    match (fstype, &ROOT.filesystem.fs_type) {
+        ("vfat" | "fat32" | "msdos", FS::Fat32) => Err(EBUSY),
+        #[cfg(feature = "lwext4")]
+        ("ext4" | "ext3" | "ext2", FS::Ext4) => Err(EBUSY),
+        #[cfg(feature = "ext4rs")]
+        ("ext4" | "ext3" | "ext2", FS::Ext4Rs) => Err(EBUSY),
+        ("proc", _) => Ok((FS::Proc, super::procfs::root())),
+        ("tmpfs", _) => {
+            let options = TmpfsOptions::parse(data)?;
+            Ok((
+                FS::Tmpfs,
+                TmpfsOSInode::new(TmpfsInode::root_inode(&TmpFileSystem::new(&options))),
+            ))
+        }
+        // the device doesn't hold a filesystem of this type
+        ("vfat" | "fat32" | "msdos" | "ext4" | "ext3" | "ext2", _) => Err(EINVAL),
+        _ => Err(ENODEV),
+    }
+}
+
+/// Flags that stick to a mount, the others only affect a single `mount()` call.
+fn per_mount_flags(flags: MountFlags) -> MountFlags {
+    flags
+        & (MountFlags::MS_RDONLY
+            | MountFlags::MS_NOSUID
+            | MountFlags::MS_NODEV
+            | MountFlags::MS_NOEXEC
+            | MountFlags::MS_SYNCHRONOUS
+            | MountFlags::MS_NOATIME
+            | MountFlags::MS_NODIRATIME
+            | MountFlags::MS_RELATIME)
+}
+
+/// An entry of the mount table
+struct Mount {
+    source: String,
+    fstype: String,
+    root: Arc<DirectoryTreeNode>,
+    /// The directory hidden by `root`, `None` for the root filesystem
+    covered: Option<Arc<DirectoryTreeNode>>,
+}
+
+/// The content of `/proc/mounts`
+pub fn mounts_info() -> String {
+    const OPTIONS: [(MountFlags, &str); 7] = [
+        (MountFlags::MS_NOSUID, "nosuid"),
+        (MountFlags::MS_NODEV, "nodev"),
+        (MountFlags::MS_NOEXEC, "noexec"),
+        (MountFlags::MS_SYNCHRONOUS, "sync"),
+        (MountFlags::MS_NOATIME, "noatime"),
+        (MountFlags::MS_NODIRATIME, "nodiratime"),
+        (MountFlags::MS_RELATIME, "relatime"),
+    ];
+    MOUNT_TABLE
+        .lock()
+        .iter()
+        .map(|mount| {
+            let flags = mount.root.filesystem.mount_flags();
+            let mut options = String::from(if flags.contains(MountFlags::MS_RDONLY) {
+                "ro"
+            } else {
+                "rw"
+            });
+            for (flag, name) in OPTIONS.iter() {
+                if flags.contains(*flag) {
+                    options.push(',');
+                    options.push_str(name);
+                }
+            }
+            format!(
+                "{} {} {} {} 0 0\n",
+                mount.source,
+                mount.root.get_cwd(),
+                mount.fstype,
+                options
+            )
+        })
+        .collect()
 }
 
 fn insert_directory_vec(inode: Weak<DirectoryTreeNode>) {
@@ -77,6 +250,7 @@ pub struct DirectoryTreeNode {
     /// 3. root node
     /// If this is a file
     /// 1. executed by some processes
+    /// 2. swapped on
     /// This parameter will add 1 when opening
     spe_usage: Mutex<usize>,
     name: String,
@@ -85,6 +259,9 @@ pub struct DirectoryTreeNode {
     selfptr: Mutex<Weak<Self>>,
     father: Mutex<Weak<Self>>,
     children: RwLock<Option<BTreeMap<String, Arc<Self>>>>,
+    /// Root of the filesystem mounted on this directory.
+    /// It takes the place of this node in every path walk.
+    mounted: Mutex<Option<Arc<Self>>>,
 }
 
 impl Drop for DirectoryTreeNode {
@@ -108,6 +285,7 @@ impl DirectoryTreeNode {
             selfptr: Mutex::new(Weak::new()),
             father: Mutex::new(father),
             children: RwLock::new(None),
+            mounted: Mutex::new(None),
         });
         *node.selfptr.lock() = Arc::downgrade(&node);
         node.file.info_dirtree_node(Arc::downgrade(&node));
@@ -144,6 +322,118 @@ impl DirectoryTreeNode {
     fn get_arc(&self) -> Arc<Self> {
         self.selfptr.lock().upgrade().unwrap().clone()
     }
+    pub fn mount_flags(&self) -> MountFlags {
+        self.filesystem.mount_flags()
+    }
+    fn check_writable(&self) -> Result<(), isize> {
+        if self.filesystem.is_read_only() {
+            return Err(EROFS);
+        }
+        Ok(())
+    }
+    /// `ETXTBSY` if this is a file being executed or swapped on, which must not be written.
+    pub fn deny_write(&self) -> Result<(), isize> {
+        if self.file.is_file() && *self.spe_usage.lock() > 0 {
+            return Err(ETXTBSY);
+        }
+        Ok(())
+    }
+    /// Check `access` on this node for `cred`, `EACCES` if denied.
+    pub fn check_access(&self, cred: &Credentials, access: Access) -> Result<(), isize> {
+        // spare the stat, root is only refused executing a file without any x bit
+        if cred.is_root() && (!access.contains(Access::EXEC) || self.file.is_dir()) {
+            return Ok(());
+        }
+        if cred.may_access(&self.file.get_stat(), access) {
+            Ok(())
+        } else {
+            Err(EACCES)
+        }
+    }
+    /// In a directory with the sticky bit, like `/tmp`,
+    /// only the owner of `child` or of the directory may remove or rename it.
+    fn check_sticky(&self, cred: &Credentials, child: &Self) -> Result<(), isize> {
+        if cred.is_root() {
+            return Ok(());
+        }
+        let stat = self.file.get_stat();
+        if stat.get_mode() & StatMode::S_ISVTX.bits() == 0
+            || stat.get_uid() == cred.euid
+            || child.file.get_stat().get_uid() == cred.euid
+        {
+            Ok(())
+        } else {
+            Err(EPERM)
+        }
+    }
+    /// Give `file`, just created in this directory, to `cred` with the permission bits `mode`.
+    /// A set-group-ID directory passes on its group, and the bit itself to subdirectories.
+    fn init_new_file(&self, file: &Arc<dyn File>, cred: &Credentials, mode: u32) {
+        let mut gid = cred.egid;
+        let mut mode = mode & 0o7777;
+        let stat = self.file.get_stat();
+        if stat.get_mode() & StatMode::S_ISGID.bits() != 0 {
+            gid = stat.get_gid();
+            if file.is_dir() {
+                mode |= StatMode::S_ISGID.bits();
+            }
+        }
+        // filesystems without owners, such as FAT32, refuse and keep their mount options
+        file.chown(Some(cred.euid), Some(gid)).ok();
+        if !file.is_symlink() {
+            file.chmod(mode).ok();
+        }
+    }
+    /// Step onto the root of whatever is mounted here, the topmost one if mounts are stacked.
+    fn follow_mount(self: Arc<Self>) -> Arc<Self> {
+        let mut inode = self;
+        loop {
+            let mounted = inode.mounted.lock().clone();
+            match mounted {
+                Some(root) => inode = root,
+                None => return inode,
+            }
+        }
+    }
+    fn is_within(&self, ancestor: &Arc<Self>) -> bool {
+        let mut current_inode = self.get_arc();
+        loop {
+            if Arc::ptr_eq(&current_inode, ancestor) {
+                return true;
+            }
+            let par_inode = current_inode.father.lock().upgrade();
+            match par_inode {
+                Some(par_inode) => current_inode = par_inode,
+                None => return false,
+            }
+        }
+    }
+    /// Whether a loaded node below this one is a cwd, being executed or a mount point.
+    /// # Warning
+    /// Opened files are not tracked by the directory tree, see `has_open_descendant()`.
+    fn has_busy_descendant(&self) -> bool {
+        let lock = self.children.read();
+        match lock.as_ref() {
+            Some(children) => children.values().any(|child| {
+                *child.spe_usage.lock() > 0
+                    || child.mounted.lock().is_some()
+                    || child.has_busy_descendant()
+            }),
+            None => false,
+        }
+    }
+    /// Whether a process has this node or one below it open
+    fn has_open_descendant(&self) -> bool {
+        let ancestor = self.get_arc();
+        all_tasks().iter().any(|task| {
+            task.files.lock().iter().flatten().any(|file_descriptor| {
+                file_descriptor
+                    .file
+                    .get_dirtree_node()
+                    .map_or(false, |inode| inode.is_within(&ancestor))
+            })
+        })
+    }
     fn parse_dir_path(path: &str) -> Vec<&str> {
         path.split('/').fold(Vec::with_capacity(8), |mut v, s| {
             match s {
@@ -166,7 +456,7 @@ impl DirectoryTreeNode {
         &self,
         lock: &mut RwLockWriteGuard<Option<BTreeMap<String, Arc<Self>>>>,
     ) -> Result<(), isize> {
-        if lock.is_some() {
+        if lock.is_some() && !self.filesystem.is_volatile() {
             return Ok(());
         }
         if !self.file.is_dir() {
@@ -200,13 +490,30 @@ impl DirectoryTreeNode {
             Err(errno) => return Err(errno),
         };
         match lock.as_ref().unwrap().get(&name.to_string()) {
-            Some(child) => Ok(child.clone()),
+            Some(child) => Ok(child.clone().follow_mount()),
             None => Err(ENOENT),
         }
     }
     pub fn cd_comp(&self, components: &Vec<&str>) -> Result<Arc<Self>, isize> {
+        self.walk(components, true, &mut 0)
+    }
+    /// Walk down `components`. Symbolic links are followed, except for the last component
+    /// if `follow_last` is false. `depth` counts the links followed so far.
+    fn walk(
+        &self,
+        components: &[&str],
+        follow_last: bool,
+        depth: &mut usize,
+    ) -> Result<Arc<Self>, isize> {
+        let cred = current_cred();
         let mut current_inode = self.get_arc();
-        for component in components {
+        for (idx, component) in components.iter().enumerate() {
+            // looking up a name, ".." included, needs search permission on the directory
+            if current_inode.file.is_dir() {
+                if let Err(errno) = current_inode.check_access(&cred, Access::EXEC) {
+                    return Err(errno);
+                }
+            }
             if *component == ".." {
                 let lock = current_inode.father.lock();
                 let par_inode = lock.upgrade();
@@ -220,17 +527,45 @@ impl DirectoryTreeNode {
                 continue;
             }
             let mut lock = current_inode.children.write();
-            match current_inode.try_to_open_subfile(component, &mut lock) {
-                Ok(child_inode) => {
-                    let child_inode = child_inode.clone();
-                    drop(lock);
-                    current_inode = child_inode.clone()
-                }
+            let child_inode = match current_inode.try_to_open_subfile(component, &mut lock) {
+                Ok(child_inode) => child_inode,
                 Err(errno) => return Err(errno),
-            }
+            };
+            drop(lock);
+            current_inode = if child_inode.file.is_symlink()
+                && (follow_last || idx + 1 < components.len())
+            {
+                match child_inode.follow_link(&current_inode, depth) {
+                    Ok(inode) => inode,
+                    Err(errno) => return Err(errno),
+                }
+            } else {
+                child_inode
+            };
         }
         Ok(current_inode)
     }
+    /// Resolve this symbolic link, `parent` is the directory it was found in.
+    fn follow_link(&self, parent: &Arc<Self>, depth: &mut usize) -> Result<Arc<Self>, isize> {
+        *depth += 1;
+        if *depth > MAXSYMLINKS {
+            return Err(ELOOP);
+        }
+        let target = match self.file.read_link() {
+            Ok(target) => target,
+            Err(errno) => return Err(errno),
+        };
+        if target.is_empty() {
+            return Err(ENOENT);
+        }
+        let components = Self::parse_dir_path(&target);
+        let inode: &Self = if target.starts_with('/') {
+            &**ROOT
+        } else {
+            &**parent
+        };
+        inode.walk(&components, true, depth)
+    }
     pub fn cd_path(&self, path: &str) -> Result<Arc<Self>, isize> {
         let components = Self::parse_dir_path(path);
         let inode = if path.starts_with("/") {
@@ -246,10 +581,13 @@ impl DirectoryTreeNode {
         // }
         self.file.create(name, file_type)
     }
+    /// Open the file at `path`. `mode` holds the permission bits of a file created by `O_CREAT`,
+    /// with the umask already applied.
     pub fn open(
         &self,
         path: &str,
         flags: OpenFlags,
+        mode: u32,
         special_use: bool,
     ) -> Result<Arc<dyn File>, isize> {
         log::debug!("[open]: cwd: {}, path: {}", self.get_cwd(), path);
@@ -283,8 +621,15 @@ impl DirectoryTreeNode {
             &self
         };
 
+        let cred = current_cred();
         let mut path_cache_lock = PATH_CACHE.lock();
-        let inode = if path.starts_with('/')
+        // the number of symbolic links followed, only paths without any are cached
+        let mut depth = 0;
+        // a file created just now may be opened whatever its mode says
+        let mut created = false;
+        // the cache skips the permission checks of the walk, which always pass for root
+        let inode = if cred.is_root()
+            && path.starts_with('/')
             && path == path_cache_lock.0
             && path_cache_lock.1.upgrade().is_some()
         {
@@ -292,27 +637,53 @@ impl DirectoryTreeNode {
         } else {
             let mut components = Self::parse_dir_path(path);
             let last_comp = components.pop();
-            let inode = match inode.cd_comp(&components) {
+            let inode = match inode.walk(&components, true, &mut depth) {
                 Ok(inode) => inode,
                 Err(errno) => return Err(errno),
             };
             if let Some(last_comp) = last_comp {
+                if inode.file.is_dir() {
+                    if let Err(errno) = inode.check_access(&cred, Access::EXEC) {
+                        return Err(errno);
+                    }
+                }
                 let mut lock = inode.children.write();
                 match inode.try_to_open_subfile(last_comp, &mut lock) {
-                    Ok(inode) => {
+                    Ok(child_inode) => {
                         if flags.contains(OpenFlags::O_CREAT | OpenFlags::O_EXCL) {
                             return Err(EEXIST);
                         }
-                        inode
+                        drop(lock);
+                        if !child_inode.file.is_symlink() {
+                            child_inode
+                        } else if !flags.contains(OpenFlags::O_NOFOLLOW) {
+                            match child_inode.follow_link(&inode, &mut depth) {
+                                Ok(inode) => inode,
+                                Err(errno) => return Err(errno),
+                            }
+                        } else if flags.contains(OpenFlags::O_PATH) {
+                            // the link itself, used by lstat() and readlink()
+                            child_inode
+                        } else {
+                            return Err(ELOOP);
+                        }
                     }
                     Err(ENOENT) => {
                         if !flags.contains(OpenFlags::O_CREAT) {
                             return Err(ENOENT);
                         }
+                        if let Err(errno) = inode.check_writable() {
+                            return Err(errno);
+                        }
+                        if let Err(errno) = inode.check_access(&cred, Access::WRITE) {
+                            return Err(errno);
+                        }
                         let new_file = match inode.create(last_comp, DiskInodeType::File) {
                             Ok(file) => file,
                             Err(errno) => return Err(errno),
                         };
+                        inode.init_new_file(&new_file, &cred, mode);
+                        created = true;
                         let key = (*last_comp).to_string();
                         let value = Self::new(
                             key.clone(),
@@ -333,6 +704,32 @@ impl DirectoryTreeNode {
             }
         };
 
+        if !created && !flags.contains(OpenFlags::O_PATH) {
+            let mut access = match flags.bits() & 0o3 {
+                0 => Access::READ,
+                1 => Access::WRITE,
+                _ => Access::READ | Access::WRITE,
+            };
+            if flags.contains(OpenFlags::O_TRUNC) {
+                access |= Access::WRITE;
+            }
+            if let Err(errno) = inode.check_access(&cred, access) {
+                return Err(errno);
+            }
+        }
+
+        if flags.intersects(OpenFlags::O_WRONLY | OpenFlags::O_RDWR | OpenFlags::O_TRUNC) {
+            if let Err(errno) = inode.check_writable() {
+                return Err(errno);
+            }
+        }
+
+        if flags.intersects(OpenFlags::O_WRONLY | OpenFlags::O_RDWR | OpenFlags::O_TRUNC) {
+            if let Err(errno) = inode.deny_write() {
+                return Err(errno);
+            }
+        }
+
         if flags.contains(OpenFlags::O_TRUNC) {
             match inode.file.truncate_size(0) {
                 Ok(_) => {}
@@ -340,13 +737,6 @@ impl DirectoryTreeNode {
             }
         }
 
-        if inode.file.is_file()
-            && *inode.spe_usage.lock() > 0
-            && (flags.contains(OpenFlags::O_WRONLY) || flags.contains(OpenFlags::O_RDWR))
-        {
-            return Err(ETXTBSY);
-        }
-
         if inode.file.is_dir()
             && (flags.contains(OpenFlags::O_WRONLY) || flags.contains(OpenFlags::O_RDWR))
         {
@@ -361,14 +751,22 @@ impl DirectoryTreeNode {
             *inode.spe_usage.lock() += 1;
         }
 
-        if path.starts_with('/') && path != path_cache_lock.0 {
+        if path.starts_with('/')
+            && path != path_cache_lock.0
+            && !inode.filesystem.is_volatile()
+            && depth == 0
+            && !inode.file.is_symlink()
+        {
             *path_cache_lock = (path.to_string(), Arc::downgrade(&inode.get_arc()));
         }
+        // procfs generates the content on open, which may walk the mount table
+        drop(path_cache_lock);
 
         Ok(inode.file.open(flags, special_use))
     }
 
-    pub fn mkdir(&self, path: &str) -> Result<(), isize> {
+    /// Create a directory at `path`, `mode` has the umask already applied.
+    pub fn mkdir(&self, path: &str, mode: u32) -> Result<(), isize> {
         let inode = if path.starts_with("/") {
             &**ROOT
         } else {
@@ -383,16 +781,29 @@ impl DirectoryTreeNode {
         };
 
         if let Some(last_comp) = last_comp {
+            let cred = current_cred();
+            if inode.file.is_dir() {
+                if let Err(errno) = inode.check_access(&cred, Access::EXEC) {
+                    return Err(errno);
+                }
+            }
             let mut lock = inode.children.write();
             match inode.try_to_open_subfile(last_comp, &mut lock) {
                 Ok(_) => {
                     return Err(EEXIST);
                 }
                 Err(ENOENT) => {
+                    if let Err(errno) = inode.check_writable() {
+                        return Err(errno);
+                    }
+                    if let Err(errno) = inode.check_access(&cred, Access::WRITE) {
+                        return Err(errno);
+                    }
                     let new_file = match inode.create(last_comp, DiskInodeType::Directory) {
                         Ok(file) => file,
                         Err(errno) => return Err(errno),
                     };
+                    inode.init_new_file(&new_file, &cred, mode);
                     let key = (*last_comp).to_string();
                     let value = Self::new(
                         key.clone(),
@@ -415,6 +826,62 @@ impl DirectoryTreeNode {
         Ok(())
     }
 
+    /// Create a symbolic link at `path` which points to `target`.
+    /// `target` is stored as is, it doesn't have to exist.
+    pub fn symlink(&self, target: &str, path: &str) -> Result<(), isize> {
+        if target.is_empty() {
+            return Err(ENOENT);
+        }
+        let inode = if path.starts_with("/") {
+            &**ROOT
+        } else {
+            &self
+        };
+
+        let mut components = Self::parse_dir_path(path);
+        let last_comp = match components.pop() {
+            Some(last_comp) => last_comp,
+            None => return Err(EEXIST),
+        };
+        let inode = match inode.cd_comp(&components) {
+            Ok(inode) => inode,
+            Err(errno) => return Err(errno),
+        };
+
+        let cred = current_cred();
+        if inode.file.is_dir() {
+            if let Err(errno) = inode.check_access(&cred, Access::EXEC) {
+                return Err(errno);
+            }
+        }
+        let mut lock = inode.children.write();
+        match inode.try_to_open_subfile(last_comp, &mut lock) {
+            Ok(_) => return Err(EEXIST),
+            Err(ENOENT) => {}
+            Err(errno) => return Err(errno),
+        }
+        if let Err(errno) = inode.check_writable() {
+            return Err(errno);
+        }
+        if let Err(errno) = inode.check_access(&cred, Access::WRITE) {
+            return Err(errno);
+        }
+        let new_file = match inode.file.symlink(last_comp, target) {
+            Ok(file) => file,
+            Err(errno) => return Err(errno),
+        };
+        inode.init_new_file(&new_file, &cred, 0o777);
+        let key = last_comp.to_string();
+        let value = Self::new(
+            key.clone(),
+            inode.filesystem.clone(),
+            new_file,
+            Arc::downgrade(&inode.get_arc()),
+        );
+        lock.as_mut().unwrap().insert(key, value);
+        Ok(())
+    }
+
     pub fn delete(&self, path: &str, delete_directory: bool) -> Result<(), isize> {
         if path.split('/').last().map_or(true, |x| x == ".") {
             return Err(EINVAL);
@@ -428,12 +895,18 @@ impl DirectoryTreeNode {
 
         let components = Self::parse_dir_path(path);
         let last_comp = *components.last().unwrap();
-        let inode = match inode.cd_comp(&components) {
+        // remove the link, not what it points to
+        let inode = match inode.walk(&components, false, &mut 0) {
             Ok(inode) => inode,
             Err(errno) => return Err(errno),
         };
 
         if *inode.spe_usage.lock() > 0 {
+            // an active swap file stays, like one being written to
+            #[cfg(feature = "swap")]
+            if super::swap::is_swap_file(&inode) {
+                return Err(ETXTBSY);
+            }
             return Err(EBUSY);
         }
 
@@ -445,8 +918,19 @@ impl DirectoryTreeNode {
             return Err(ENOTDIR);
         }
 
+        if let Err(errno) = inode.check_writable() {
+            return Err(errno);
+        }
+
         match inode.father.lock().upgrade() {
             Some(par_inode) => {
+                let cred = current_cred();
+                if let Err(errno) = par_inode.check_access(&cred, Access::WRITE | Access::EXEC) {
+                    return Err(errno);
+                }
+                if let Err(errno) = par_inode.check_sticky(&cred, &inode) {
+                    return Err(errno);
+                }
                 let mut lock = par_inode.children.write();
                 match inode.file.unlink(true) {
                     Ok(_) => {
@@ -461,6 +945,131 @@ impl DirectoryTreeNode {
         Ok(())
     }
 
+    /// Mount a filesystem of type `fstype` on the directory `target`,
+    /// or change the flags of the mount at `target` if `MS_REMOUNT` is given.
+    /// `data` is the comma separated list of options specific to the filesystem.
+    pub fn mount(
+        &self,
+        source: &str,
+        target: &str,
+        fstype: &str,
+        flags: MountFlags,
+        data: &str,
+    ) -> Result<(), isize> {
+        let inode = match self.cd_path(target) {
+            Ok(inode) => inode,
+            Err(errno) => return Err(errno),
+        };
+
+        if flags.contains(MountFlags::MS_REMOUNT) {
+            let table = MOUNT_TABLE.lock();
+            if !table.iter().any(|mount| Arc::ptr_eq(&mount.root, &inode)) {
+                return Err(EINVAL);
+            }
+            match inode.filesystem.fs_type {
+                FS::Tmpfs => {
+                    let root = inode.file.downcast_ref::<TmpfsOSInode>().unwrap();
+                    root.get_inner()
+                        .get_fs()
+                        .remount(&TmpfsOptions::parse(data)?)?;
+                }
+                FS::Fat32 => {
+                    let root = inode.file.downcast_ref::<OSInode>().unwrap();
+                    root.remount(Fat32Options::parse(data)?);
+                }
+                _ => {}
+            }
+            inode.filesystem.set_mount_flags(per_mount_flags(flags));
+            return Ok(());
+        }
+        if flags.intersects(MountFlags::MS_BIND | MountFlags::MS_MOVE) {
+            log::warn!("[mount] bind and move mounts are not supported");
+            return Err(EINVAL);
+        }
+        // Only the propagation type is changed, and we don't propagate mounts at all
+        if flags.intersects(
+            MountFlags::MS_SHARED
+                | MountFlags::MS_PRIVATE
+                | MountFlags::MS_SLAVE
+                | MountFlags::MS_UNBINDABLE,
+        ) {
+            return Ok(());
+        }
+
+        if !inode.file.is_dir() {
+            return Err(ENOTDIR);
+        }
+        let father = inode.father.lock().clone();
+        // We can't replace ROOT
+        if father.upgrade().is_none() {
+            return Err(EBUSY);
+        }
+        let (fs_type, root_file) = match new_filesystem(fstype, data) {
+            Ok(result) => result,
+            Err(errno) => return Err(errno),
+        };
+        let filesystem = Arc::new(FileSystem::new(fs_type));
+        filesystem.set_mount_flags(per_mount_flags(flags));
+        // Take the name and the parent of the covered directory,
+        // so that `get_cwd()` and ".." work across the mount point.
+        let root = Self::new(inode.name.clone(), filesystem, root_file, father);
+        root.add_special_use();
+
+        let mut table = MOUNT_TABLE.lock();
+        *inode.mounted.lock() = Some(root.clone());
+        table.push(Mount {
+            source: source.to_string(),
+            fstype: fstype.to_string(),
+            root,
+            covered: Some(inode),
+        });
+        *PATH_CACHE.lock() = ("".to_string(), Weak::new());
+        Ok(())
+    }
+
+    /// Unmount the filesystem mounted on `target`.
+    /// A busy mount is only detached with `MNT_DETACH` or `MNT_FORCE`,
+    /// the nodes already reached keep working until they are dropped.
+    pub fn umount(&self, target: &str, flags: UmountFlags) -> Result<(), isize> {
+        if flags.contains(UmountFlags::MNT_EXPIRE)
+            && flags.intersects(UmountFlags::MNT_FORCE | UmountFlags::MNT_DETACH)
+        {
+            return Err(EINVAL);
+        }
+        let inode = match self.cd_path(target) {
+            Ok(inode) => inode,
+            Err(errno) => return Err(errno),
+        };
+
+        let mut table = MOUNT_TABLE.lock();
+        let covered = match table
+            .iter()
+            .find(|mount| Arc::ptr_eq(&mount.root, &inode))
+        {
+            Some(mount) => match &mount.covered {
+                Some(covered) => covered.clone(),
+                // the root filesystem
+                None => return Err(EBUSY),
+            },
+            // not a mount point
+            None => return Err(EINVAL),
+        };
+        // `spe_usage` of a mounted root is at least 1, see `mount()`
+        if !flags.intersects(UmountFlags::MNT_FORCE | UmountFlags::MNT_DETACH)
+            && (*inode.spe_usage.lock() > 1
+                || inode.has_busy_descendant()
+                || inode.has_open_descendant())
+        {
+            return Err(EBUSY);
+        }
+        // mounts inside this one go away with it
+        table.retain(|mount| !mount.root.is_within(&inode));
+        *covered.mounted.lock() = None;
+        inode.sub_special_use();
+        *PATH_CACHE.lock() = ("".to_string(), Weak::new());
+        Ok(())
+    }
+
     pub fn rename(old_path: &str, new_path: &str) -> Result<(), isize> {
         assert!(old_path.starts_with('/'));
         assert!(new_path.starts_with('/'));
@@ -493,8 +1102,9 @@ impl DirectoryTreeNode {
         let old_lock: Arc<Mutex<ChildLockType<'_>>>;
         let new_lock: Arc<Mutex<ChildLockType<'_>>>;
 
-        // Be careful about the lock ordering
-        if old_comps == new_comps {
+        // Be careful about the lock ordering.
+        // Different paths may lead to the same directory through symbolic links.
+        if Arc::ptr_eq(&old_par_inode, &new_par_inode) {
             old_lock = Arc::new(Mutex::new(old_par_inode.children.write()));
             new_lock = old_lock.clone();
         } else if old_comps < new_comps {
@@ -518,6 +1128,24 @@ impl DirectoryTreeNode {
         if old_inode.filesystem.fs_id != new_par_inode.filesystem.fs_id {
             return Err(EXDEV);
         }
+        if let Err(errno) = new_par_inode.check_writable() {
+            return Err(errno);
+        }
+        let cred = current_cred();
+        for par_inode in [&old_par_inode, &new_par_inode] {
+            if let Err(errno) = par_inode.check_access(&cred, Access::WRITE | Access::EXEC) {
+                return Err(errno);
+            }
+        }
+        if let Err(errno) = old_par_inode.check_sticky(&cred, &old_inode) {
+            return Err(errno);
+        }
+        // a directory moving elsewhere has its ".." rewritten
+        if old_inode.file.is_dir() && !Arc::ptr_eq(&old_par_inode, &new_par_inode) {
+            if let Err(errno) = old_inode.check_access(&cred, Access::WRITE) {
+                return Err(errno);
+            }
+        }
         let old_key = old_last_comp.to_string();
         let new_key = new_last_comp.to_string();
         match new_par_inode.try_to_open_subfile(new_last_comp, &mut (*new_lock.lock())) {
@@ -531,8 +1159,11 @@ impl DirectoryTreeNode {
                 if *new_inode.spe_usage.lock() > 0 {
                     return Err(EBUSY);
                 }
+                if let Err(errno) = new_par_inode.check_sticky(&cred, &new_inode) {
+                    return Err(errno);
+                }
                 // delete
-                match new_par_inode.file.unlink(true) {
+                match new_inode.file.unlink(true) {
                     Ok(_) => {
                         new_lock.lock().as_mut().unwrap().remove(&new_key);
                     }
@@ -552,57 +1183,214 @@ impl DirectoryTreeNode {
             FS::Fat32 => {
                 let old_file = old_inode.file.downcast_ref::<OSInode>().unwrap();
                 let new_par_file = new_par_inode.file.downcast_ref::<OSInode>().unwrap();
-                new_par_file.link_child(old_last_comp, old_file)?;
+                new_par_file.link_child(new_last_comp, old_file)?;
+            }
+            #[cfg(feature = "lwext4")]
+            FS::Ext4 => {
+                let old_file = old_inode.file.downcast_ref::<Ext4OSInode>().unwrap();
+                let new_par_file = new_par_inode.file.downcast_ref::<Ext4OSInode>().unwrap();
+                new_par_file.link_child(new_last_comp, old_file)?;
+            }
+            #[cfg(feature = "ext4rs")]
+            FS::Ext4Rs => {
+                let old_file = old_inode.file.downcast_ref::<Ext4RsOSInode>().unwrap();
+                let new_par_file = new_par_inode.file.downcast_ref::<Ext4RsOSInode>().unwrap();
+                new_par_file.link_child(new_last_comp, old_file)?;
+            }
+            FS::Tmpfs => {
+                let old_file = old_inode.file.downcast_ref::<TmpfsOSInode>().unwrap();
+                let new_par_file = new_par_inode.file.downcast_ref::<TmpfsOSInode>().unwrap();
+                new_par_file.link_child(new_last_comp, old_file)?;
             }
-            FS::Null => return Err(EACCES),
+            FS::Null | FS::Proc => return Err(EACCES),
         }
         *value.father.lock() = Arc::downgrade(&new_par_inode.get_arc());
         new_lock.lock().as_mut().unwrap().insert(new_key, value);
 
         Ok(())
     }
+
+    /// Make `new_path` another name of the file at `old_path`.
+    /// A symbolic link at `old_path` is only followed if `follow` is set.
+    pub fn link(old_path: &str, new_path: &str, follow: bool) -> Result<(), isize> {
+        assert!(old_path.starts_with('/'));
+        assert!(new_path.starts_with('/'));
+
+        let mut new_comps = Self::parse_dir_path(new_path);
+        let new_last_comp = match new_comps.pop() {
+            Some(comp) => comp,
+            None => return Err(EEXIST),
+        };
+        let old_inode = match ROOT.walk(&Self::parse_dir_path(old_path), follow, &mut 0) {
+            Ok(inode) => inode,
+            Err(errno) => return Err(errno),
+        };
+        if old_inode.file.is_dir() {
+            return Err(EPERM);
+        }
+        let new_par_inode = match ROOT.cd_comp(&new_comps) {
+            Ok(inode) => inode,
+            Err(errno) => return Err(errno),
+        };
+        if old_inode.filesystem.fs_id != new_par_inode.filesystem.fs_id {
+            return Err(EXDEV);
+        }
+        if let Err(errno) = new_par_inode.check_writable() {
+            return Err(errno);
+        }
+        if let Err(errno) =
+            new_par_inode.check_access(&current_cred(), Access::WRITE | Access::EXEC)
+        {
+            return Err(errno);
+        }
+
+        let mut lock = new_par_inode.children.write();
+        match new_par_inode.try_to_open_subfile(new_last_comp, &mut lock) {
+            Ok(_) => return Err(EEXIST),
+            Err(ENOENT) => {}
+            Err(errno) => return Err(errno),
+        }
+        let new_file = match old_inode.filesystem.fs_type {
+            FS::Tmpfs => {
+                let old_file = old_inode.file.downcast_ref::<TmpfsOSInode>().unwrap();
+                let new_par_file = new_par_inode.file.downcast_ref::<TmpfsOSInode>().unwrap();
+                new_par_file.link(new_last_comp, old_file)?
+            }
+            // FAT32 has no hard links, the others don't support them yet
+            _ => return Err(EPERM),
+        };
+        let key = new_last_comp.to_string();
+        let value = Self::new(
+            key.clone(),
+            new_par_inode.filesystem.clone(),
+            new_file,
+            Arc::downgrade(&new_par_inode.get_arc()),
+        );
+        lock.as_mut().unwrap().insert(key, value);
+        Ok(())
+    }
 }
+/// Write back the files in the tree, then the metadata of the root filesystem.
+/// `expire` only lets through data that has been dirty for that many milliseconds,
+/// the directory entries are always brought up to date.
+/// The root filesystem is the only disk filesystem, see `new_filesystem()`.
+pub fn sync_all(expire: Option<usize>) {
+    let mut lock = DIRECTORY_VEC.lock();
+    update_directory_vec(&mut lock);
+    let inodes: Vec<Arc<DirectoryTreeNode>> =
+        lock.0.iter().filter_map(|inode| inode.upgrade()).collect();
+    // a node dropped below takes the lock again
+    drop(lock);
+    let mut written = 0;
+    for inode in inodes.iter() {
+        if let Some(file) = inode.file.downcast_ref::<OSInode>() {
+            written += file.writeback(expire);
+        }
+        #[cfg(feature = "lwext4")]
+        if let Some(file) = inode.file.downcast_ref::<Ext4OSInode>() {
+            written += file.get_inner().writeback(expire);
+        }
+        #[cfg(feature = "ext4rs")]
+        if let Some(file) = inode.file.downcast_ref::<Ext4RsOSInode>() {
+            written += file.get_inner().writeback(expire);
+        }
+    }
+    match ROOT.filesystem.fs_type {
+        FS::Fat32 => FILE_SYSTEM.sync(),
+        #[cfg(feature = "lwext4")]
+        FS::Ext4 => {
+            let root = ROOT.file.downcast_ref::<Ext4OSInode>().unwrap();
+            if let Err(errno) = root.get_inner().get_fs().sync() {
+                log::error!("[sync_all] failed to flush ext4: {}", errno);
+            }
+        }
+        _ => {}
+    }
+    log::debug!(
+        "[sync_all] expire: {:?}, written pages: {}",
+        expire,
+        written
+    );
+}
+
+/// The nodes still alive.
 #[cfg(feature = "oom_handler")]
-pub fn oom() -> usize {
-    tlb_invalidate();
-    const MAX_FAIL_TIME: usize = 3;
-    let mut fail_time = 0;
-    log::warn!("[oom] start oom");
+fn alive_nodes() -> Vec<Arc<DirectoryTreeNode>> {
     let mut lock = DIRECTORY_VEC.lock();
     update_directory_vec(&mut lock);
-    loop {
-        let mut dropped = 0;
-        for inode in &lock.0 {
-            let inode = inode.upgrade().unwrap();
-            dropped += inode.file.oom();
+    let inodes = lock.0.iter().filter_map(|inode| inode.upgrade()).collect();
+    // a node dropped by the caller takes the lock again
+    drop(lock);
+    inodes
+}
+
+/// Drop the cached file pages nobody else holds, see `mm::reclaim` for when.
+/// Only clean pages are dropped, unless `write_back` is set: the dirty ones are then written back and dropped.
+#[cfg(feature = "oom_handler")]
+pub fn shrink_page_cache(write_back: bool) -> usize {
+    let mut dropped = 0;
+    for inode in alive_nodes().iter() {
+        if let Some(file) = inode.file.downcast_ref::<OSInode>() {
+            dropped += if write_back {
+                file.oom()
+            } else {
+                file.shrink_clean()
+            };
         }
-        if dropped > 0 {
-            log::warn!("[oom] recycle pages: {}", dropped);
-            return dropped;
+        #[cfg(feature = "lwext4")]
+        if let Some(file) = inode.file.downcast_ref::<Ext4OSInode>() {
+            dropped += if write_back {
+                file.oom()
+            } else {
+                file.get_inner().shrink_clean()
+            };
         }
-        fail_time += 1;
-        if fail_time >= MAX_FAIL_TIME {
-            return dropped;
+        #[cfg(feature = "ext4rs")]
+        if let Some(file) = inode.file.downcast_ref::<Ext4RsOSInode>() {
+            dropped += if write_back {
+                file.oom()
+            } else {
+                file.get_inner().shrink_clean()
+            };
         }
     }
+    dropped
+}
+
+/// Push the pages of tmpfs files nobody uses to zram or swap.
+/// Unlinked files and memfd files count too, so the inodes are found through their filesystem.
+#[cfg(feature = "oom_handler")]
+pub fn shrink_tmpfs() -> usize {
+    tmpfs::all_inodes().iter().map(|inode| inode.oom()).sum()
+}
+
+/// Read back the pages of tmpfs files kept in swap area `swap_area`, for `swapoff()`.
+#[cfg(feature = "oom_handler")]
+pub fn swapoff_tmpfs(swap_area: usize) -> Result<(), isize> {
+    for inode in tmpfs::all_inodes().iter() {
+        inode.swapoff(swap_area)?;
+    }
+    Ok(())
 }
 
 pub fn init_fs() {
     init_device_directory();
     init_tmp_directory();
     init_proc_directory();
+    #[cfg(feature = "swap")]
+    super::swap::init();
 }
 #[allow(unused)]
 fn init_device_directory() {
-    ROOT.mkdir("/dev");
+    ROOT.mkdir("/dev", 0o755);
 
     let dev_inode = match ROOT.cd_path("/dev") {
         Ok(inode) => inode,
         Err(_) => panic!("dev directory doesn't exist"),
     };
 
-    dev_inode.mkdir("shm");
-    dev_inode.mkdir("misc");
+    dev_inode.mkdir("shm", 0o1777);
+    dev_inode.mkdir("misc", 0o755);
 
     let null_dev = DirectoryTreeNode::new(
         "null".to_string(),
@@ -628,11 +1416,36 @@ fn init_device_directory() {
         Arc::new(Teletype::new()),
         Arc::downgrade(&dev_inode.get_arc()),
     );
+    let root_blocks = root_volume_blocks();
+    let sda1_dev = DirectoryTreeNode::new(
+        "sda1".to_string(),
+        Arc::new(FileSystem::new(FS::Null)),
+        BlockFile::new(
+            BLOCK_DEVICE.clone(),
+            0,
+            Some(root_blocks),
+            crate::makedev!(8, 1),
+        ),
+        Arc::downgrade(&dev_inode.get_arc()),
+    );
+    let sda2_dev = DirectoryTreeNode::new(
+        "sda2".to_string(),
+        Arc::new(FileSystem::new(FS::Null)),
+        BlockFile::new(
+            BLOCK_DEVICE.clone(),
+            root_blocks,
+            None,
+            crate::makedev!(8, 2),
+        ),
+        Arc::downgrade(&dev_inode.get_arc()),
+    );
     let mut lock = dev_inode.children.write();
     lock.as_mut().unwrap().insert("null".to_string(), null_dev);
     lock.as_mut().unwrap().insert("zero".to_string(), zero_dev);
     lock.as_mut().unwrap().insert("urandom".to_string(), urandom_dev);
     lock.as_mut().unwrap().insert("tty".to_string(), tty_dev);
+    lock.as_mut().unwrap().insert("sda1".to_string(), sda1_dev);
+    lock.as_mut().unwrap().insert("sda2".to_string(), sda2_dev);
     drop(lock);
 
     let misc_inode = match dev_inode.cd_path("./misc") {
@@ -653,18 +1466,32 @@ fn init_device_directory() {
     drop(lock);
 }
 fn init_tmp_directory() {
-    match ROOT.mkdir("/tmp") {
+    match ROOT.mkdir("/tmp", 0o1777) {
         _ => {}
     }
+    for target in ["/tmp", "/dev/shm"] {
+        if let Err(errno) = ROOT.mount(
+            "tmpfs",
+            target,
+            "tmpfs",
+            MountFlags::MS_NOSUID | MountFlags::MS_NODEV,
+            "mode=1777",
+        ) {
+            log::error!("[init_tmp_directory] failed to mount tmpfs on {}: {}", target, errno);
+        }
+    }
 }
 fn init_proc_directory() {
-    match ROOT.mkdir("/proc") {
-        _ => {}
-    }
-    match ROOT.open("/proc/meminfo", OpenFlags::O_CREAT, false) {
+    match ROOT.mkdir("/proc", 0o555) {
         _ => {}
     }
-    match ROOT.open("/proc/mounts", OpenFlags::O_CREAT, false) {
-        _ => {}
+    if let Err(errno) = ROOT.mount(
+        "proc",
+        "/proc",
+        "proc",
+        MountFlags::MS_NOSUID | MountFlags::MS_NODEV | MountFlags::MS_NOEXEC,
+        "",
+    ) {
+        log::error!("[init_proc_directory] failed to mount procfs: {}", errno);
     }
 }
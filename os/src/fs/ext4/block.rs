@@ -0,0 +1,53 @@
+use alloc::sync::Arc;
+use lwext4_rs::{BlockDeviceConfig, BlockDeviceInterface, Result};
+
+use crate::arch::BLOCK_SZ;
+use crate::fs::{filesystem::Ext4Geometry, BlockDevice};
+
+/// Glue between our `BlockDevice` and the block device interface of lwext4.
+/// lwext4 does its own block caching, so requests go straight to the device.
+pub struct Ext4Disk {
+    block_device: Arc<dyn BlockDevice>,
+    geometry: Ext4Geometry,
+}
+
+impl Ext4Disk {
+    pub fn new(block_device: Arc<dyn BlockDevice>, geometry: Ext4Geometry) -> Self {
+        Self {
+            block_device,
+            geometry,
+        }
+    }
+}
+
+impl BlockDeviceInterface for Ext4Disk {
+    fn open(&mut self) -> Result<BlockDeviceConfig> {
+        let part_size = self.geometry.volume_size();
+        Ok(BlockDeviceConfig {
+            block_size: BLOCK_SZ as u32,
+            block_count: part_size / BLOCK_SZ as u64,
+            part_size,
+            part_offset: 0,
+        })
+    }
+    fn read_block(&mut self, buf: &mut [u8], block_id: u64, block_count: u32) -> Result<usize> {
+        let len = block_count as usize * BLOCK_SZ;
+        self.block_device.read_block(block_id as usize, &mut buf[..len]);
+        Ok(len)
+    }
+    fn write_block(&mut self, buf: &[u8], block_id: u64, block_count: u32) -> Result<usize> {
+        let len = block_count as usize * BLOCK_SZ;
+        self.block_device.write_block(block_id as usize, &buf[..len]);
+        Ok(len)
+    }
+    fn close(&mut self) -> Result<()> {
+        Ok(())
+    }
+    /// Every call into lwext4 is already serialized by `Ext4FileSystem`.
+    fn lock(&mut self) -> Result<()> {
+        Ok(())
+    }
+    fn unlock(&mut self) -> Result<()> {
+        Ok(())
+    }
+}
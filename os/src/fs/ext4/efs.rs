@@ -0,0 +1,63 @@
+use alloc::{format, string::String, sync::Arc};
+use core::sync::atomic::{AtomicUsize, Ordering};
+use lwext4_rs::{FileSystem, MountHandle, RegisterHandle};
+use spin::{Mutex, MutexGuard};
+
+use super::{block::Ext4Disk, to_errno};
+use crate::fs::{filesystem::Ext4Geometry, BlockDevice};
+
+/// lwext4 keeps a global table of registered devices and mount points,
+/// every instance needs its own names in it.
+static MOUNT_ID: AtomicUsize = AtomicUsize::new(0);
+
+/// An ext4 volume mounted through lwext4.
+/// lwext4 addresses everything by absolute path under `mount_point`,
+/// see `Ext4Inode::path()` for how our inodes are translated.
+pub struct Ext4FileSystem {
+    inner: Mutex<FileSystem<Ext4Disk>>,
+    mount_point: String,
+}
+
+// The handles of lwext4 hold raw pointers into the C library.
+// They are only touched while `inner` is locked, which serializes every call into lwext4.
+unsafe impl Send for Ext4FileSystem {}
+unsafe impl Sync for Ext4FileSystem {}
+
+impl Ext4FileSystem {
+    pub fn open(
+        block_device: Arc<dyn BlockDevice>,
+        geometry: Ext4Geometry,
+        read_only: bool,
+    ) -> Result<Arc<Self>, isize> {
+        let id = MOUNT_ID.fetch_add(1, Ordering::Relaxed);
+        let mount_point = format!("/ext4_{}/", id);
+        log::info!(
+            "[Ext4FileSystem::open] block size: {}, blocks: {}, mount point: {}",
+            geometry.block_size,
+            geometry.blocks_count,
+            mount_point
+        );
+        let bdev = lwext4_rs::BlockDevice::new(Ext4Disk::new(block_device, geometry));
+        let register_handle =
+            RegisterHandle::register(bdev, format!("ext4dev{}", id)).map_err(to_errno)?;
+        let mount_handle = MountHandle::mount(register_handle, mount_point.clone(), true, read_only)
+            .map_err(to_errno)?;
+        let fs = FileSystem::new(mount_handle).map_err(to_errno)?;
+        Ok(Arc::new(Self {
+            inner: Mutex::new(fs),
+            mount_point,
+        }))
+    }
+    /// Lock the filesystem before calling anything in lwext4.
+    pub fn lock(&self) -> MutexGuard<'_, FileSystem<Ext4Disk>> {
+        self.inner.lock()
+    }
+    /// Write the blocks held in lwext4's cache back to the device.
+    pub fn sync(&self) -> Result<(), isize> {
+        self.lock().flush().map_err(to_errno)
+    }
+    /// The path of the root directory in lwext4's namespace, ends with '/'
+    pub fn mount_point(&self) -> &str {
+        self.mount_point.as_str()
+    }
+}
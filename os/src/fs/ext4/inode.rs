@@ -0,0 +1,335 @@
+use crate::fs::directory_tree::DirectoryTreeNode;
+use crate::fs::file_trait::File;
+use crate::fs::*;
+use crate::mm::UserBuffer;
+use crate::syscall::errno::*;
+
+use super::vfs::Ext4Inode;
+use alloc::string::String;
+use alloc::sync::{Arc, Weak};
+use alloc::vec::Vec;
+use lwext4_rs::MetaDataExt;
+use spin::Mutex;
+
+/// The ext4 counterpart of `fat32::inode::OSInode`.
+pub struct Ext4OSInode {
+    readable: bool,
+    writable: bool,
+    /// See `DirectoryTreeNode` for more details
+    special_use: bool,
+    append: bool,
+    inner: Arc<Ext4Inode>,
+    offset: Mutex<usize>,
+    dirnode_ptr: Arc<Mutex<Weak<DirectoryTreeNode>>>,
+}
+
+impl Ext4OSInode {
+    pub fn new(root_inode: Arc<Ext4Inode>) -> Arc<dyn File> {
+        Arc::new(Self {
+            readable: true,
+            writable: true,
+            special_use: true,
+            append: false,
+            inner: root_inode,
+            offset: Mutex::new(0),
+            dirnode_ptr: Arc::new(Mutex::new(Weak::new())),
+        })
+    }
+    fn from_inner(inner: Arc<Ext4Inode>) -> Arc<dyn File> {
+        Arc::new(Self {
+            readable: true,
+            writable: true,
+            special_use: false,
+            append: false,
+            inner,
+            offset: Mutex::new(0),
+            dirnode_ptr: Arc::new(Mutex::new(Weak::new())),
+        })
+    }
+    pub fn get_inner(&self) -> &Arc<Ext4Inode> {
+        &self.inner
+    }
+}
+
+impl Drop for Ext4OSInode {
+    fn drop(&mut self) {
+        if self.special_use {
+            let inode = self.get_dirtree_node();
+            match inode {
+                Some(inode) => inode.sub_special_use(),
+                None => {}
+            }
+        }
+    }
+}
+
+#[allow(unused)]
+impl File for Ext4OSInode {
+    fn deep_clone(&self) -> Arc<dyn File> {
+        if self.special_use {
+            let inode = self.get_dirtree_node();
+            match inode {
+                Some(inode) => inode.add_special_use(),
+                None => {}
+            }
+        }
+        Arc::new(Self {
+            readable: self.readable,
+            writable: self.writable,
+            special_use: self.special_use,
+            append: self.append,
+            inner: self.inner.clone(),
+            offset: Mutex::new(*self.offset.lock()),
+            dirnode_ptr: self.dirnode_ptr.clone(),
+        })
+    }
+    fn readable(&self) -> bool {
+        self.readable
+    }
+    fn writable(&self) -> bool {
+        self.writable
+    }
+    fn read(&self, offset: Option<&mut usize>, buffer: &mut [u8]) -> usize {
+        match offset {
+            Some(offset) => {
+                let len = self.inner.read_at(*offset, buffer);
+                *offset += len;
+                len
+            }
+            None => {
+                let mut offset = self.offset.lock();
+                let len = self.inner.read_at(*offset, buffer);
+                *offset += len;
+                len
+            }
+        }
+    }
+    fn write(&self, offset: Option<&mut usize>, buffer: &[u8]) -> usize {
+        match offset {
+            Some(offset) => {
+                let len = self.inner.write_at(*offset, buffer);
+                *offset += len;
+                len
+            }
+            None => {
+                let mut offset = self.offset.lock();
+                if self.append {
+                    *offset = self.inner.get_file_size();
+                }
+                let len = self.inner.write_at(*offset, buffer);
+                *offset += len;
+                len
+            }
+        }
+    }
+    fn r_ready(&self) -> bool {
+        true
+    }
+    fn w_ready(&self) -> bool {
+        true
+    }
+    fn read_user(&self, offset: Option<usize>, mut buf: UserBuffer) -> usize {
+        let mut total_read_size = 0usize;
+        let mut offset_lock = self.offset.lock();
+        let use_file_offset = offset.is_none();
+        let mut offset = match offset {
+            Some(offset) => offset,
+            None => *offset_lock,
+        };
+        for slice in buf.buffers.iter_mut() {
+            let read_size = self.inner.read_at(offset, *slice);
+            offset += read_size;
+            total_read_size += read_size;
+            if read_size < slice.len() {
+                break;
+            }
+        }
+        if use_file_offset {
+            *offset_lock = offset;
+        }
+        total_read_size
+    }
+    fn write_user(&self, offset: Option<usize>, buf: UserBuffer) -> usize {
+        let mut total_write_size = 0usize;
+        let mut offset_lock = self.offset.lock();
+        let use_file_offset = offset.is_none();
+        let mut offset = match offset {
+            Some(offset) => offset,
+            None if self.append => self.inner.get_file_size(),
+            None => *offset_lock,
+        };
+        for slice in buf.buffers.iter() {
+            let write_size = self.inner.write_at(offset, *slice);
+            offset += write_size;
+            total_write_size += write_size;
+            if write_size < slice.len() {
+                break;
+            }
+        }
+        if use_file_offset {
+            *offset_lock = offset;
+        }
+        total_write_size
+    }
+    fn get_size(&self) -> usize {
+        self.inner.get_file_size()
+    }
+    fn get_stat(&self) -> Stat {
+        match self.inner.metadata() {
+            Ok(metadata) => {
+                let mut stat = Stat::new(
+                    crate::makedev!(8, 0),
+                    self.inner.get_ino() as u64,
+                    metadata.mode(),
+                    metadata.nlink() as u32,
+                    metadata.rdev() as u64,
+                    metadata.size() as i64,
+                    metadata.atime(),
+                    metadata.mtime(),
+                    metadata.ctime(),
+                );
+                stat.set_owner(metadata.uid(), metadata.gid());
+                stat
+            }
+            Err(_) => Stat::new(
+                crate::makedev!(8, 0),
+                self.inner.get_ino() as u64,
+                0,
+                0,
+                0,
+                0,
+                0,
+                0,
+                0,
+            ),
+        }
+    }
+    fn get_file_type(&self) -> DiskInodeType {
+        self.inner.get_file_type()
+    }
+    fn info_dirtree_node(&self, dirnode_ptr: Weak<DirectoryTreeNode>) {
+        *self.dirnode_ptr.lock() = dirnode_ptr;
+    }
+    fn get_dirtree_node(&self) -> Option<Arc<DirectoryTreeNode>> {
+        self.dirnode_ptr.lock().upgrade()
+    }
+    fn open(&self, flags: OpenFlags, special_use: bool) -> Arc<dyn File> {
+        Arc::new(Self {
+            readable: flags.contains(OpenFlags::O_RDONLY) || flags.contains(OpenFlags::O_RDWR),
+            writable: flags.contains(OpenFlags::O_WRONLY) || flags.contains(OpenFlags::O_RDWR),
+            special_use,
+            append: flags.contains(OpenFlags::O_APPEND),
+            inner: self.inner.clone(),
+            offset: Mutex::new(0),
+            dirnode_ptr: self.dirnode_ptr.clone(),
+        })
+    }
+    fn open_subfile(&self) -> Result<Vec<(String, Arc<dyn File>)>, isize> {
+        Ok(self
+            .inner
+            .get_all_files()?
+            .into_iter()
+            .map(|(name, inner)| (name, Self::from_inner(inner)))
+            .collect())
+    }
+    fn create(&self, name: &str, file_type: DiskInodeType) -> Result<Arc<dyn File>, isize> {
+        Ok(Self::from_inner(self.inner.create(name, file_type)?))
+    }
+    fn link_child(&self, name: &str, child: &Self) -> Result<(), isize>
+    where
+        Self: Sized,
+    {
+        child.inner.rename_to(&self.inner, name)
+    }
+    fn symlink(&self, name: &str, target: &str) -> Result<Arc<dyn File>, isize> {
+        Ok(Self::from_inner(self.inner.symlink(name, target)?))
+    }
+    fn read_link(&self) -> Result<String, isize> {
+        self.inner.read_link()
+    }
+    /// lwext4 can't detach a name from an inode without freeing the inode,
+    /// so `unlink(false)` (used by rename) does nothing and `link_child()` moves the entry instead.
+    fn unlink(&self, delete: bool) -> Result<(), isize> {
+        if !delete {
+            return Ok(());
+        }
+        if self.inner.is_dir() && !self.inner.is_empty_dir()? {
+            return Err(ENOTEMPTY);
+        }
+        self.inner.unlink()
+    }
+    /// `offset` of a directory counts entries rather than bytes
+    fn get_dirent(&self, count: usize) -> Vec<Dirent> {
+        let dirents = match self.inner.dirents() {
+            Ok(dirents) => dirents,
+            Err(_) => return Vec::new(),
+        };
+        Dirent::pack(&dirents, &mut self.offset.lock(), count)
+    }
+    fn lseek(&self, offset: isize, whence: SeekWhence) -> Result<usize, isize> {
+        let new_offset = match whence {
+            SeekWhence::SEEK_SET => offset,
+            SeekWhence::SEEK_CUR => *self.offset.lock() as isize + offset,
+            SeekWhence::SEEK_END => self.inner.get_file_size() as isize + offset,
+            // whence is duplicated
+            _ => return Err(EINVAL),
+        };
+        let new_offset = match new_offset < 0 {
+            true => return Err(EINVAL),
+            false => new_offset as usize,
+        };
+        *self.offset.lock() = new_offset;
+        Ok(new_offset)
+    }
+    fn modify_size(&self, diff: isize) -> Result<(), isize> {
+        let old_size = self.inner.get_file_size();
+        self.inner.truncate((old_size as isize + diff) as usize)
+    }
+    fn truncate_size(&self, new_size: usize) -> Result<(), isize> {
+        self.inner.truncate(new_size)
+    }
+    fn set_timestamp(&self, ctime: Option<usize>, atime: Option<usize>, mtime: Option<usize>) {
+        self.inner.set_times(ctime, atime, mtime)
+    }
+    fn chmod(&self, mode: u32) -> Result<(), isize> {
+        self.inner.chmod(mode)
+    }
+    fn chown(&self, uid: Option<u32>, gid: Option<u32>) -> Result<(), isize> {
+        self.inner.chown(uid, gid)
+    }
+    /// lwext4 only has a cache for the whole volume
+    fn fsync(&self) -> Result<(), isize> {
+        self.inner.writeback(None);
+        self.inner.get_fs().sync()
+    }
+    fn get_xattr(&self, name: &str) -> Result<Vec<u8>, isize> {
+        self.inner.get_xattr(name)
+    }
+    fn set_xattr(&self, name: &str, value: &[u8], flags: XattrFlags) -> Result<(), isize> {
+        self.inner.set_xattr(name, value, flags)
+    }
+    fn list_xattr(&self) -> Result<Vec<String>, isize> {
+        self.inner.list_xattr()
+    }
+    fn remove_xattr(&self, name: &str) -> Result<(), isize> {
+        self.inner.remove_xattr(name)
+    }
+    fn get_single_cache(&self, offset: usize) -> Result<Arc<Mutex<PageCache>>, ()> {
+        if offset & 0xfff != 0 {
+            return Err(());
+        }
+        Ok(self.inner.get_single_cache(offset >> 12))
+    }
+    fn get_all_caches(&self) -> Result<Vec<Arc<Mutex<PageCache>>>, ()> {
+        Ok(self.inner.get_all_cache())
+    }
+    fn oom(&self) -> usize {
+        self.inner.oom()
+    }
+    fn hang_up(&self) -> bool {
+        false
+    }
+    fn fcntl(&self, cmd: u32, arg: u32) -> isize {
+        EINVAL
+    }
+}
@@ -0,0 +1,140 @@
+//! The little bit of C runtime lwext4 expects to be linked against.
+//! `memcpy`/`memset` and friends come from `rlibc`.
+use alloc::alloc::{alloc, dealloc, realloc as krealloc, Layout};
+use core::ffi::{c_char, c_int, c_void, CStr};
+use core::ptr::null_mut;
+
+/// Every allocation is prefixed by a header holding its total size,
+/// so that `free` and `realloc` can rebuild the `Layout`.
+const HEADER: usize = 16;
+const ALIGN: usize = 16;
+
+#[no_mangle]
+pub unsafe extern "C" fn malloc(size: usize) -> *mut c_void {
+    let layout = match Layout::from_size_align(size + HEADER, ALIGN) {
+        Ok(layout) => layout,
+        Err(_) => return null_mut(),
+    };
+    let ptr = alloc(layout);
+    if ptr.is_null() {
+        return null_mut();
+    }
+    *(ptr as *mut usize) = size + HEADER;
+    ptr.add(HEADER) as *mut c_void
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn calloc(nmemb: usize, size: usize) -> *mut c_void {
+    let total = match nmemb.checked_mul(size) {
+        Some(total) => total,
+        None => return null_mut(),
+    };
+    let ptr = malloc(total);
+    if !ptr.is_null() {
+        core::ptr::write_bytes(ptr as *mut u8, 0, total);
+    }
+    ptr
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn realloc(ptr: *mut c_void, size: usize) -> *mut c_void {
+    if ptr.is_null() {
+        return malloc(size);
+    }
+    let raw = (ptr as *mut u8).sub(HEADER);
+    let old_size = *(raw as *mut usize);
+    let new_raw = krealloc(
+        raw,
+        Layout::from_size_align_unchecked(old_size, ALIGN),
+        size + HEADER,
+    );
+    if new_raw.is_null() {
+        return null_mut();
+    }
+    *(new_raw as *mut usize) = size + HEADER;
+    new_raw.add(HEADER) as *mut c_void
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn free(ptr: *mut c_void) {
+    if ptr.is_null() {
+        return;
+    }
+    let raw = (ptr as *mut u8).sub(HEADER);
+    let size = *(raw as *mut usize);
+    dealloc(raw, Layout::from_size_align_unchecked(size, ALIGN));
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn strcmp(s1: *const c_char, s2: *const c_char) -> c_int {
+    strncmp(s1, s2, usize::MAX)
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn strncmp(s1: *const c_char, s2: *const c_char, n: usize) -> c_int {
+    for i in 0..n {
+        let c1 = *s1.add(i) as u8;
+        let c2 = *s2.add(i) as u8;
+        if c1 != c2 {
+            return c1 as c_int - c2 as c_int;
+        }
+        if c1 == 0 {
+            break;
+        }
+    }
+    0
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn strcpy(dst: *mut c_char, src: *const c_char) -> *mut c_char {
+    let mut i = 0;
+    loop {
+        *dst.add(i) = *src.add(i);
+        if *src.add(i) == 0 {
+            break;
+        }
+        i += 1;
+    }
+    dst
+}
+
+/// Insertion sort is good enough here: lwext4 only sorts the entries of a single
+/// directory block when building an htree index.
+#[no_mangle]
+pub unsafe extern "C" fn qsort(
+    base: *mut c_void,
+    nmemb: usize,
+    size: usize,
+    compar: extern "C" fn(*const c_void, *const c_void) -> c_int,
+) {
+    let base = base as *mut u8;
+    for i in 1..nmemb {
+        let mut j = i;
+        while j > 0 && compar(base.add((j - 1) * size) as _, base.add(j * size) as _) > 0 {
+            core::ptr::swap_nonoverlapping(base.add((j - 1) * size), base.add(j * size), size);
+            j -= 1;
+        }
+    }
+}
+
+/// lwext4 is built with debug output on. We don't have a `vprintf`,
+/// so only the format string itself is logged and the arguments are dropped.
+#[no_mangle]
+pub unsafe extern "C" fn printf(format: *const c_char) -> c_int {
+    let format = CStr::from_ptr(format);
+    log::trace!("[lwext4] {}", format.to_str().unwrap_or("").trim_end());
+    0
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn puts(s: *const c_char) -> c_int {
+    printf(s)
+}
+
+#[no_mangle]
+pub static stdout: usize = 0;
+
+#[no_mangle]
+pub extern "C" fn fflush(_file: *mut c_void) -> c_int {
+    0
+}
@@ -0,0 +1,22 @@
+//! ext4 backend built on `lwext4-rs`, the binding of the C lwext4 library.
+//! Enabled by the `lwext4` feature and picked for the root filesystem
+//! when an ext4 superblock is found on `BLOCK_DEVICE`.
+mod block;
+mod efs;
+pub mod inode;
+mod libc;
+mod vfs;
+
+pub use efs::Ext4FileSystem;
+pub use inode::Ext4OSInode;
+pub use vfs::Ext4Inode;
+
+use crate::syscall::errno::EIO;
+
+/// The discriminants of `lwext4_rs::Error` are positive errnos.
+pub fn to_errno(err: lwext4_rs::Error) -> isize {
+    match err {
+        lwext4_rs::Error::InvalidError => EIO,
+        err => -(err as isize),
+    }
+}
@@ -0,0 +1,363 @@
+use alloc::{
+    string::{String, ToString},
+    sync::Arc,
+    vec::Vec,
+};
+use embedded_io::{Read, Seek, SeekFrom, Write};
+use lwext4_rs::{FileTimes, MetaDataExt, Metadata, Permissions, Time};
+use spin::Mutex;
+
+use super::{efs::Ext4FileSystem, to_errno};
+use crate::config::PAGE_SIZE;
+use crate::fs::{
+    cache::{MappedPages, PageCache},
+    file_type_of, DiskInodeType, XattrFlags, DT_BLK, DT_CHR, DT_DIR, DT_FIFO, DT_LNK, DT_REG,
+    DT_SOCK, DT_UNKNOWN,
+};
+use crate::syscall::errno::*;
+
+/// An inode of an ext4 volume.
+/// lwext4 has no inode-based API, so we remember where the inode lives
+/// (its parent and its name) and rebuild its path on every access.
+/// Renaming only needs to update `parent` and `name` of the moved inode.
+pub struct Ext4Inode {
+    fs: Arc<Ext4FileSystem>,
+    ino: u32,
+    file_type: DiskInodeType,
+    /// `None` for the root directory
+    parent: Mutex<Option<Arc<Ext4Inode>>>,
+    name: Mutex<String>,
+    /// Pages handed out to `mmap()`, dropped when the file is truncated or unlinked
+    page_cache: MappedPages,
+}
+
+impl Ext4Inode {
+    pub fn root_inode(fs: &Arc<Ext4FileSystem>) -> Arc<Self> {
+        let ino = match fs.lock().metadata(fs.mount_point()) {
+            Ok(metadata) => metadata.ino() as u32,
+            Err(_) => 2,
+        };
+        Arc::new(Self {
+            fs: fs.clone(),
+            ino,
+            file_type: DiskInodeType::Directory,
+            parent: Mutex::new(None),
+            name: Mutex::new(String::new()),
+            page_cache: MappedPages::new(),
+        })
+    }
+    fn from_parent(parent: &Arc<Self>, name: &str, ino: u32, file_type: DiskInodeType) -> Arc<Self> {
+        Arc::new(Self {
+            fs: parent.fs.clone(),
+            ino,
+            file_type,
+            parent: Mutex::new(Some(parent.clone())),
+            name: Mutex::new(name.to_string()),
+            page_cache: MappedPages::new(),
+        })
+    }
+    /// Absolute path of this inode in lwext4's namespace, e.g. `/ext4_0/bin/busybox`
+    pub fn path(&self) -> String {
+        let mut names = Vec::with_capacity(8);
+        names.push(self.name.lock().clone());
+        let mut current = self.parent.lock().clone();
+        while let Some(inode) = current {
+            names.push(inode.name.lock().clone());
+            current = inode.parent.lock().clone();
+        }
+        // the last one is the root, whose name is empty
+        names.pop();
+        names.reverse();
+        let mut path = self.fs.mount_point().to_string();
+        path.push_str(names.join("/").as_str());
+        path
+    }
+    fn child_path(&self, name: &str) -> String {
+        let mut path = self.path();
+        if !path.ends_with('/') {
+            path.push('/');
+        }
+        path.push_str(name);
+        path
+    }
+    pub fn get_fs(&self) -> &Arc<Ext4FileSystem> {
+        &self.fs
+    }
+    pub fn get_ino(&self) -> usize {
+        self.ino as usize
+    }
+    pub fn get_file_type(&self) -> DiskInodeType {
+        self.file_type
+    }
+    pub fn is_dir(&self) -> bool {
+        self.file_type == DiskInodeType::Directory
+    }
+    pub fn metadata(&self) -> Result<Metadata, isize> {
+        self.fs.lock().metadata(self.path()).map_err(to_errno)
+    }
+    pub fn get_file_size(&self) -> usize {
+        match self.metadata() {
+            Ok(metadata) => metadata.size() as usize,
+            Err(_) => 0,
+        }
+    }
+    /// Read from `offset` until `buf` is full or EOF is reached
+    pub fn read_at(&self, offset: usize, buf: &mut [u8]) -> usize {
+        let fs = self.fs.lock();
+        let mut file = match fs.file_builder().read(true).open(self.path()) {
+            Ok(file) => file,
+            Err(err) => {
+                log::warn!("[Ext4Inode::read_at] open {} failed: {:?}", self.path(), err);
+                return 0;
+            }
+        };
+        if file.seek(SeekFrom::Start(offset as u64)).is_err() {
+            return 0;
+        }
+        let mut total = 0;
+        while total < buf.len() {
+            match file.read(&mut buf[total..]) {
+                Ok(0) | Err(_) => break,
+                Ok(len) => total += len,
+            }
+        }
+        total
+    }
+    /// Write the whole `buf` at `offset`, the file grows if needed
+    pub fn write_at(&self, offset: usize, buf: &[u8]) -> usize {
+        let written = self.write_through(offset, buf);
+        self.page_cache.update(offset, &buf[..written]);
+        written
+    }
+    /// `write_at()` without touching the cached pages
+    fn write_through(&self, offset: usize, buf: &[u8]) -> usize {
+        let fs = self.fs.lock();
+        let mut file = match fs.file_builder().read(true).write(true).open(self.path()) {
+            Ok(file) => file,
+            Err(err) => {
+                log::warn!("[Ext4Inode::write_at] open {} failed: {:?}", self.path(), err);
+                return 0;
+            }
+        };
+        if file.seek(SeekFrom::Start(offset as u64)).is_err() {
+            return 0;
+        }
+        let mut total = 0;
+        while total < buf.len() {
+            match file.write(&buf[total..]) {
+                Ok(0) | Err(_) => break,
+                Ok(len) => total += len,
+            }
+        }
+        total
+    }
+    pub fn truncate(&self, new_size: usize) -> Result<(), isize> {
+        self.writeback(None);
+        self.page_cache.clear();
+        let fs = self.fs.lock();
+        let mut file = fs
+            .file_builder()
+            .read(true)
+            .write(true)
+            .open(self.path())
+            .map_err(to_errno)?;
+        file.set_len(new_size as u64).map_err(to_errno)
+    }
+    pub fn set_times(&self, ctime: Option<usize>, atime: Option<usize>, mtime: Option<usize>) {
+        let to_time = |sec: usize| Time {
+            epoch_secs: sec as u64,
+            nanos: None,
+        };
+        let mut times = FileTimes::new();
+        if let Some(ctime) = ctime {
+            times = times.set_created(to_time(ctime));
+        }
+        if let Some(atime) = atime {
+            times = times.set_accessed(to_time(atime));
+        }
+        if let Some(mtime) = mtime {
+            times = times.set_modified(to_time(mtime));
+        }
+        if let Err(err) = self.fs.lock().set_times(self.path(), times) {
+            log::warn!("[Ext4Inode::set_times] {:?}", err);
+        }
+    }
+    /// lwext4-rs passes on the rwx bits only, the set-user-ID, set-group-ID and sticky bits are lost
+    pub fn chmod(&self, mode: u32) -> Result<(), isize> {
+        self.fs
+            .lock()
+            .set_permissions(self.path(), Permissions::from_mode(mode))
+            .map_err(to_errno)
+    }
+    pub fn chown(&self, uid: Option<u32>, gid: Option<u32>) -> Result<(), isize> {
+        // lwext4-rs wants both ids unless neither is given
+        let (uid, gid) = match (uid, gid) {
+            (Some(uid), Some(gid)) => (uid, gid),
+            _ => {
+                let metadata = self.metadata()?;
+                (uid.unwrap_or(metadata.uid()), gid.unwrap_or(metadata.gid()))
+            }
+        };
+        self.fs
+            .lock()
+            .chown(self.path(), Some(uid), Some(gid))
+            .map_err(to_errno)
+    }
+    pub fn get_xattr(&self, name: &str) -> Result<Vec<u8>, isize> {
+        self.fs
+            .lock()
+            .get_xattr(self.path(), name)
+            .map_err(to_errno)
+    }
+    pub fn set_xattr(&self, name: &str, value: &[u8], flags: XattrFlags) -> Result<(), isize> {
+        let fs = self.fs.lock();
+        if !flags.is_empty() {
+            let exists = match fs.get_xattr(self.path(), name).map_err(to_errno) {
+                Ok(_) => true,
+                Err(ENODATA) => false,
+                Err(errno) => return Err(errno),
+            };
+            flags.check(exists)?;
+        }
+        fs.set_xattr(self.path(), name, value).map_err(to_errno)
+    }
+    pub fn list_xattr(&self) -> Result<Vec<String>, isize> {
+        let names = self.fs.lock().list_xattr(self.path()).map_err(to_errno)?;
+        Ok(names
+            .into_iter()
+            .filter_map(|name| String::from_utf8(name).ok())
+            .collect())
+    }
+    pub fn remove_xattr(&self, name: &str) -> Result<(), isize> {
+        self.fs
+            .lock()
+            .remove_xattr(self.path(), name)
+            .map_err(to_errno)
+    }
+    /// Return the raw directory entries `(name, ino, d_type)`, "." and ".." included
+    pub fn dirents(&self) -> Result<Vec<(String, u32, u8)>, isize> {
+        if !self.is_dir() {
+            return Err(ENOTDIR);
+        }
+        let fs = self.fs.lock();
+        let read_dir = fs.readdir(self.path()).map_err(to_errno)?;
+        Ok(read_dir
+            .map(|entry| {
+                let d_type = match entry.file_type() {
+                    Ok(ty) if ty.is_dir() => DT_DIR,
+                    Ok(ty) if ty.is_file() => DT_REG,
+                    Ok(ty) if ty.is_symlink() => DT_LNK,
+                    Ok(ty) if ty.is_char_device() => DT_CHR,
+                    Ok(ty) if ty.is_block_device() => DT_BLK,
+                    Ok(ty) if ty.is_fifo() => DT_FIFO,
+                    Ok(ty) if ty.is_socket() => DT_SOCK,
+                    _ => DT_UNKNOWN,
+                };
+                (entry.name().to_string(), entry.inode(), d_type)
+            })
+            .collect())
+    }
+    pub fn is_empty_dir(&self) -> Result<bool, isize> {
+        Ok(self
+            .dirents()?
+            .iter()
+            .all(|(name, _, _)| name == "." || name == ".."))
+    }
+    /// Load every child, "." and ".." excluded
+    pub fn get_all_files(self: &Arc<Self>) -> Result<Vec<(String, Arc<Self>)>, isize> {
+        Ok(self
+            .dirents()?
+            .into_iter()
+            .filter(|(name, _, _)| name != "." && name != "..")
+            .map(|(name, ino, d_type)| {
+                let inode = Self::from_parent(self, name.as_str(), ino, file_type_of(d_type));
+                (name, inode)
+            })
+            .collect())
+    }
+    pub fn create(self: &Arc<Self>, name: &str, file_type: DiskInodeType) -> Result<Arc<Self>, isize> {
+        let path = self.child_path(name);
+        let fs = self.fs.lock();
+        match file_type {
+            DiskInodeType::Directory => fs.create_dir(path.as_str()).map_err(to_errno)?,
+            DiskInodeType::File => {
+                fs.file_builder()
+                    .write(true)
+                    .create_new(true)
+                    .open(path.as_str())
+                    .map_err(to_errno)?;
+            }
+            // links are made by `symlink()`, they need a target
+            DiskInodeType::SymLink => return Err(EINVAL),
+        }
+        let ino = fs.metadata(path.as_str()).map_err(to_errno)?.ino() as u32;
+        drop(fs);
+        Ok(Self::from_parent(self, name, ino, file_type))
+    }
+    /// Create a symbolic link `name` pointing to `target`
+    pub fn symlink(self: &Arc<Self>, name: &str, target: &str) -> Result<Arc<Self>, isize> {
+        let path = self.child_path(name);
+        let fs = self.fs.lock();
+        fs.soft_link(target, path.as_str()).map_err(to_errno)?;
+        let ino = fs.metadata(path.as_str()).map_err(to_errno)?.ino() as u32;
+        drop(fs);
+        Ok(Self::from_parent(self, name, ino, DiskInodeType::SymLink))
+    }
+    pub fn read_link(&self) -> Result<String, isize> {
+        if self.file_type != DiskInodeType::SymLink {
+            return Err(EINVAL);
+        }
+        self.fs.lock().read_link(self.path()).map_err(to_errno)
+    }
+    /// Remove this inode from its parent directory.
+    /// # Warning
+    /// lwext4 removes directories recursively, so the caller must make sure they are empty.
+    pub fn unlink(&self) -> Result<(), isize> {
+        self.page_cache.clear();
+        let path = self.path();
+        let fs = self.fs.lock();
+        if self.is_dir() {
+            fs.remove_dir(path.as_str()).map_err(to_errno)
+        } else {
+            fs.remove_file(path.as_str()).map_err(to_errno)
+        }
+    }
+    /// Move this inode to `new_parent` as `name`.
+    /// An existing target should have been unlinked by the caller.
+    pub fn rename_to(&self, new_parent: &Arc<Self>, name: &str) -> Result<(), isize> {
+        let old_path = self.path();
+        let new_path = new_parent.child_path(name);
+        self.fs
+            .lock()
+            .rename(old_path.as_str(), new_path.as_str())
+            .map_err(to_errno)?;
+        *self.parent.lock() = Some(new_parent.clone());
+        *self.name.lock() = name.to_string();
+        Ok(())
+    }
+    pub fn get_single_cache(&self, inner_cache_id: usize) -> Arc<Mutex<PageCache>> {
+        self.page_cache.get(inner_cache_id, |page| {
+            self.read_at(inner_cache_id * PAGE_SIZE, page)
+        })
+    }
+    pub fn get_all_cache(&self) -> Vec<Arc<Mutex<PageCache>>> {
+        self.page_cache.get_all()
+    }
+    /// See `MappedPages::writeback()`
+    pub fn writeback(&self, expire: Option<usize>) -> usize {
+        self.page_cache
+            .writeback(self.get_file_size(), expire, |offset, page| {
+                self.write_through(offset, page);
+            })
+    }
+    /// See `MappedPages::oom()`
+    pub fn oom(&self) -> usize {
+        self.page_cache.oom(self.get_file_size(), |offset, page| {
+            self.write_through(offset, page);
+        })
+    }
+    /// See `MappedPages::shrink_clean()`
+    pub fn shrink_clean(&self) -> usize {
+        self.page_cache.shrink_clean()
+    }
+}
@@ -0,0 +1,42 @@
+use alloc::{sync::Arc, vec, vec::Vec};
+
+use crate::arch::BLOCK_SZ;
+use crate::fs::BlockDevice;
+
+/// Glue between our `BlockDevice` and the one of ext4-rs.
+/// ext4-rs addresses the disk by byte offset and always transfers `ext4_rs::BLOCK_SIZE` bytes on reads,
+/// while writes may be shorter (e.g. a single inode), so unaligned requests are read-modify-write.
+pub struct Ext4RsDisk {
+    block_device: Arc<dyn BlockDevice>,
+}
+
+impl Ext4RsDisk {
+    pub fn new(block_device: Arc<dyn BlockDevice>) -> Self {
+        Self { block_device }
+    }
+    /// Read the device blocks covering `[offset, offset + len)`.
+    /// Returns the buffer and where `offset` lies in it.
+    fn read_covering(&self, offset: usize, len: usize) -> (Vec<u8>, usize) {
+        let start_block = offset / BLOCK_SZ;
+        let end_block = (offset + len + BLOCK_SZ - 1) / BLOCK_SZ;
+        let mut buf = vec![0u8; (end_block - start_block) * BLOCK_SZ];
+        self.block_device.read_block(start_block, &mut buf);
+        (buf, offset - start_block * BLOCK_SZ)
+    }
+}
+
+impl ext4_rs::BlockDevice for Ext4RsDisk {
+    fn read_offset(&self, offset: usize) -> Vec<u8> {
+        let (buf, start) = self.read_covering(offset, ext4_rs::BLOCK_SIZE);
+        buf[start..start + ext4_rs::BLOCK_SIZE].to_vec()
+    }
+    fn write_offset(&self, offset: usize, data: &[u8]) {
+        if offset % BLOCK_SZ == 0 && data.len() % BLOCK_SZ == 0 {
+            self.block_device.write_block(offset / BLOCK_SZ, data);
+            return;
+        }
+        let (mut buf, start) = self.read_covering(offset, data.len());
+        buf[start..start + data.len()].copy_from_slice(data);
+        self.block_device.write_block(offset / BLOCK_SZ, &buf);
+    }
+}
@@ -0,0 +1,41 @@
+use alloc::sync::Arc;
+use ext4_rs::Ext4;
+use spin::{Mutex, MutexGuard};
+
+use super::block::Ext4RsDisk;
+use crate::fs::{filesystem::Ext4Geometry, BlockDevice};
+use crate::syscall::errno::*;
+
+/// An ext4 volume driven by ext4-rs.
+pub struct Ext4RsFileSystem {
+    inner: Mutex<Ext4>,
+}
+
+impl Ext4RsFileSystem {
+    pub fn open(
+        block_device: Arc<dyn BlockDevice>,
+        geometry: Ext4Geometry,
+    ) -> Result<Arc<Self>, isize> {
+        log::info!(
+            "[Ext4RsFileSystem::open] block size: {}, blocks: {}",
+            geometry.block_size,
+            geometry.blocks_count
+        );
+        // ext4-rs is hard-wired to 4KiB blocks
+        if geometry.block_size != ext4_rs::BLOCK_SIZE {
+            log::error!(
+                "[Ext4RsFileSystem::open] unsupported block size {}",
+                geometry.block_size
+            );
+            return Err(EINVAL);
+        }
+        let ext4 = Ext4::open(Arc::new(Ext4RsDisk::new(block_device)));
+        Ok(Arc::new(Self {
+            inner: Mutex::new(ext4),
+        }))
+    }
+    /// ext4-rs does no locking on its own, every call into it has to hold this lock.
+    pub fn lock(&self) -> MutexGuard<'_, Ext4> {
+        self.inner.lock()
+    }
+}
@@ -0,0 +1,16 @@
+//! ext4 backend built on `ext4_rs`, a pure-Rust ext4 implementation.
+//! Enabled by the `ext4rs` feature, it is the alternative to the lwext4 backend
+//! in `fs::ext4` so that both can be compared on the same images.
+mod block;
+mod efs;
+pub mod inode;
+mod vfs;
+
+pub use efs::Ext4RsFileSystem;
+pub use inode::Ext4RsOSInode;
+pub use vfs::Ext4RsInode;
+
+/// The discriminants of `ext4_rs::Errno` are positive errnos.
+pub fn to_errno(err: ext4_rs::Ext4Error) -> isize {
+    -(err.error() as isize)
+}
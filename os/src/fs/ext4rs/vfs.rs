@@ -0,0 +1,388 @@
+use alloc::{
+    string::{String, ToString},
+    sync::Arc,
+    vec,
+    vec::Vec,
+};
+use ext4_rs::{Ext4, InodeFileType};
+use spin::Mutex;
+
+use super::{efs::Ext4RsFileSystem, to_errno};
+use crate::config::PAGE_SIZE;
+use crate::fs::{
+    cache::{MappedPages, PageCache},
+    file_type_of, DiskInodeType, Stat, DT_BLK, DT_CHR, DT_DIR, DT_FIFO, DT_LNK, DT_REG, DT_SOCK,
+    DT_UNKNOWN,
+};
+use crate::syscall::errno::*;
+use crate::timer::get_time_sec;
+
+const ROOT_INODE: u32 = 2;
+
+/// An inode of an ext4 volume, addressed by its inode number.
+/// ext4-rs unlinks and renames by (parent, name),
+/// so we also remember where the inode is linked.
+pub struct Ext4RsInode {
+    fs: Arc<Ext4RsFileSystem>,
+    ino: u32,
+    file_type: DiskInodeType,
+    /// `(inode number of the parent, name)`, the root is `(ROOT_INODE, "")`
+    location: Mutex<(u32, String)>,
+    /// Pages handed out to `mmap()`, dropped when the file is truncated or unlinked
+    page_cache: MappedPages,
+}
+
+impl Ext4RsInode {
+    pub fn root_inode(fs: &Arc<Ext4RsFileSystem>) -> Arc<Self> {
+        Arc::new(Self {
+            fs: fs.clone(),
+            ino: ROOT_INODE,
+            file_type: DiskInodeType::Directory,
+            location: Mutex::new((ROOT_INODE, String::new())),
+            page_cache: MappedPages::new(),
+        })
+    }
+    fn from_parent(
+        parent: &Arc<Self>,
+        name: &str,
+        ino: u32,
+        file_type: DiskInodeType,
+    ) -> Arc<Self> {
+        Arc::new(Self {
+            fs: parent.fs.clone(),
+            ino,
+            file_type,
+            location: Mutex::new((parent.ino, name.to_string())),
+            page_cache: MappedPages::new(),
+        })
+    }
+    pub fn get_fs(&self) -> &Arc<Ext4RsFileSystem> {
+        &self.fs
+    }
+    pub fn get_ino(&self) -> usize {
+        self.ino as usize
+    }
+    pub fn get_file_type(&self) -> DiskInodeType {
+        self.file_type
+    }
+    pub fn is_dir(&self) -> bool {
+        self.file_type == DiskInodeType::Directory
+    }
+    pub fn stat(&self) -> Stat {
+        let inode_ref = self.fs.lock().get_inode_ref(self.ino);
+        let inode = &inode_ref.inode;
+        let mut stat = Stat::new(
+            crate::makedev!(8, 0),
+            self.ino as u64,
+            inode.mode() as u32,
+            inode.links_count() as u32,
+            0,
+            inode.size() as i64,
+            inode.atime() as i64,
+            inode.mtime() as i64,
+            inode.ctime() as i64,
+        );
+        stat.set_owner(inode.uid() as u32, inode.gid() as u32);
+        stat
+    }
+    pub fn get_file_size(&self) -> usize {
+        self.fs.lock().get_inode_ref(self.ino).inode.size() as usize
+    }
+    /// Fill `[from, to)` with zeros.
+    /// ext4-rs appends blocks in order when a file grows, so holes have to be written out.
+    fn fill_zero(ext4: &Ext4, ino: u32, from: usize, to: usize) -> Result<(), isize> {
+        let zero = [0u8; PAGE_SIZE];
+        let mut offset = from;
+        while offset < to {
+            let len = (to - offset).min(PAGE_SIZE);
+            offset += ext4.write_at(ino, offset, &zero[..len]).map_err(to_errno)?;
+        }
+        Ok(())
+    }
+    /// Read from `offset` until `buf` is full or EOF is reached
+    pub fn read_at(&self, offset: usize, buf: &mut [u8]) -> usize {
+        match self.fs.lock().read_at(self.ino, offset, buf) {
+            Ok(len) => len,
+            Err(err) => {
+                log::warn!("[Ext4RsInode::read_at] ino {}: {:?}", self.ino, err);
+                0
+            }
+        }
+    }
+    /// Write the whole `buf` at `offset`, the file grows if needed
+    pub fn write_at(&self, offset: usize, buf: &[u8]) -> usize {
+        let written = self.write_through(offset, buf);
+        self.page_cache.update(offset, &buf[..written]);
+        written
+    }
+    /// `write_at()` without touching the cached pages
+    fn write_through(&self, offset: usize, buf: &[u8]) -> usize {
+        let ext4 = self.fs.lock();
+        let size = ext4.get_inode_ref(self.ino).inode.size() as usize;
+        if offset > size {
+            if let Err(errno) = Self::fill_zero(&ext4, self.ino, size, offset) {
+                log::warn!("[Ext4RsInode::write_at] ino {}: {}", self.ino, errno);
+                return 0;
+            }
+        }
+        match ext4.write_at(self.ino, offset, buf) {
+            Ok(len) => len,
+            Err(err) => {
+                log::warn!("[Ext4RsInode::write_at] ino {}: {:?}", self.ino, err);
+                0
+            }
+        }
+    }
+    pub fn truncate(&self, new_size: usize) -> Result<(), isize> {
+        self.writeback(None);
+        self.page_cache.clear();
+        let ext4 = self.fs.lock();
+        let mut inode_ref = ext4.get_inode_ref(self.ino);
+        let old_size = inode_ref.inode.size() as usize;
+        if new_size < old_size {
+            ext4.truncate_inode(&mut inode_ref, new_size as u64)
+                .map_err(to_errno)?;
+        } else if new_size > old_size {
+            Self::fill_zero(&ext4, self.ino, old_size, new_size)?;
+        }
+        Ok(())
+    }
+    pub fn set_times(&self, ctime: Option<usize>, atime: Option<usize>, mtime: Option<usize>) {
+        let ext4 = self.fs.lock();
+        let mut inode_ref = ext4.get_inode_ref(self.ino);
+        if let Some(ctime) = ctime {
+            inode_ref.inode.set_ctime(ctime as u32);
+        }
+        if let Some(atime) = atime {
+            inode_ref.inode.set_atime(atime as u32);
+        }
+        if let Some(mtime) = mtime {
+            inode_ref.inode.set_mtime(mtime as u32);
+        }
+        ext4.write_back_inode(&mut inode_ref);
+    }
+    /// Replace the permission bits, the file type stays
+    pub fn chmod(&self, mode: u32) {
+        let ext4 = self.fs.lock();
+        let mut inode_ref = ext4.get_inode_ref(self.ino);
+        let file_type = inode_ref.inode.mode() & 0o170000;
+        inode_ref.inode.set_mode(file_type | (mode & 0o7777) as u16);
+        inode_ref.inode.set_ctime(get_time_sec() as u32);
+        ext4.write_back_inode(&mut inode_ref);
+    }
+    /// ext4-rs only reaches the low 16 bits of the ids, `EINVAL` for larger ones
+    pub fn chown(&self, uid: Option<u32>, gid: Option<u32>) -> Result<(), isize> {
+        if uid.map_or(false, |uid| uid > u16::MAX as u32)
+            || gid.map_or(false, |gid| gid > u16::MAX as u32)
+        {
+            return Err(EINVAL);
+        }
+        let ext4 = self.fs.lock();
+        let mut inode_ref = ext4.get_inode_ref(self.ino);
+        if let Some(uid) = uid {
+            inode_ref.inode.set_uid(uid as u16);
+        }
+        if let Some(gid) = gid {
+            inode_ref.inode.set_gid(gid as u16);
+        }
+        inode_ref.inode.set_ctime(get_time_sec() as u32);
+        ext4.write_back_inode(&mut inode_ref);
+        Ok(())
+    }
+    /// Return the raw directory entries `(name, ino, d_type)`, "." and ".." included
+    pub fn dirents(&self) -> Result<Vec<(String, u32, u8)>, isize> {
+        if !self.is_dir() {
+            return Err(ENOTDIR);
+        }
+        Ok(self
+            .fs
+            .lock()
+            .dir_get_entries(self.ino)
+            .iter()
+            .map(|entry| {
+                // the file type recorded in the entry (EXT4_FT_*), not the one in i_mode
+                let d_type = match entry.get_de_type() {
+                    1 => DT_REG,
+                    2 => DT_DIR,
+                    3 => DT_CHR,
+                    4 => DT_BLK,
+                    5 => DT_FIFO,
+                    6 => DT_SOCK,
+                    7 => DT_LNK,
+                    _ => DT_UNKNOWN,
+                };
+                (entry.get_name(), entry.inode, d_type)
+            })
+            .collect())
+    }
+    pub fn is_empty_dir(&self) -> Result<bool, isize> {
+        Ok(self
+            .dirents()?
+            .iter()
+            .all(|(name, _, _)| name == "." || name == ".."))
+    }
+    /// Load every child, "." and ".." excluded
+    pub fn get_all_files(self: &Arc<Self>) -> Result<Vec<(String, Arc<Self>)>, isize> {
+        Ok(self
+            .dirents()?
+            .into_iter()
+            .filter(|(name, _, _)| name != "." && name != "..")
+            .map(|(name, ino, d_type)| {
+                let inode = Self::from_parent(self, name.as_str(), ino, file_type_of(d_type));
+                (name, inode)
+            })
+            .collect())
+    }
+    pub fn create(
+        self: &Arc<Self>,
+        name: &str,
+        file_type: DiskInodeType,
+    ) -> Result<Arc<Self>, isize> {
+        // ext4-rs only recognizes a bare file type here and grants 0777 by itself
+        let mode = match file_type {
+            DiskInodeType::Directory => InodeFileType::S_IFDIR.bits(),
+            DiskInodeType::File => InodeFileType::S_IFREG.bits(),
+            // links are made by `symlink()`, they need a target
+            DiskInodeType::SymLink => return Err(EINVAL),
+        };
+        let ino = self
+            .fs
+            .lock()
+            .create(self.ino, name, mode)
+            .map_err(to_errno)?
+            .inode_num;
+        Ok(Self::from_parent(self, name, ino, file_type))
+    }
+    /// Create a symbolic link `name` pointing to `target`.
+    /// ext4-rs only writes extents, so the target always goes to a data block.
+    pub fn symlink(self: &Arc<Self>, name: &str, target: &str) -> Result<Arc<Self>, isize> {
+        let ext4 = self.fs.lock();
+        let ino = ext4
+            .create(self.ino, name, InodeFileType::S_IFLNK.bits())
+            .map_err(to_errno)?
+            .inode_num;
+        let written = match ext4.write_at(ino, 0, target.as_bytes()) {
+            Ok(written) if written == target.len() => Ok(()),
+            Ok(_) => Err(ENOSPC),
+            Err(error) => Err(to_errno(error)),
+        };
+        drop(ext4);
+        let inode = Self::from_parent(self, name, ino, DiskInodeType::SymLink);
+        // a link without its target would be left behind
+        if let Err(errno) = written {
+            inode.unlink().ok();
+            return Err(errno);
+        }
+        Ok(inode)
+    }
+    /// Targets shorter than 60 bytes are usually kept in place of the extent tree ("fast" symlinks),
+    /// such a link has no data block.
+    pub fn read_link(&self) -> Result<String, isize> {
+        if self.file_type != DiskInodeType::SymLink {
+            return Err(EINVAL);
+        }
+        let ext4 = self.fs.lock();
+        let inode_ref = ext4.get_inode_ref(self.ino);
+        let size = inode_ref.inode.size() as usize;
+        let mut target = vec![0u8; size];
+        if inode_ref.inode.blocks_count() == 0 {
+            let inline: Vec<u8> = inode_ref
+                .inode
+                .block()
+                .iter()
+                .flat_map(|word| word.to_le_bytes())
+                .collect();
+            if size > inline.len() {
+                return Err(EIO);
+            }
+            target.copy_from_slice(&inline[..size]);
+        } else if ext4.read_at(self.ino, 0, &mut target).map_err(to_errno)? != size {
+            return Err(EIO);
+        }
+        String::from_utf8(target).map_err(|_| EIO)
+    }
+    /// Remove this inode from its parent directory and free it.
+    /// # Warning
+    /// The caller must make sure a directory is empty.
+    pub fn unlink(&self) -> Result<(), isize> {
+        self.page_cache.clear();
+        let (parent, name) = self.location.lock().clone();
+        let ext4 = self.fs.lock();
+        if self.is_dir() {
+            ext4.dir_remove(parent, name.as_str()).map_err(to_errno)?;
+            let mut parent_ref = ext4.get_inode_ref(parent);
+            let links_count = parent_ref.inode.links_count();
+            parent_ref.inode.set_links_count(links_count - 1);
+            ext4.write_back_inode(&mut parent_ref);
+        } else {
+            // `fuse_unlink()` would trip over the assertion in `truncate_inode()` on empty files,
+            // and a fast symlink has no extent tree to truncate
+            let mut child_ref = ext4.get_inode_ref(self.ino);
+            if child_ref.inode.links_count() == 1 && child_ref.inode.blocks_count() > 0 {
+                ext4.truncate_inode(&mut child_ref, 0).map_err(to_errno)?;
+            }
+            let mut parent_ref = ext4.get_inode_ref(parent);
+            ext4.unlink(&mut parent_ref, &mut child_ref, name.as_str())
+                .map_err(to_errno)?;
+        }
+        Ok(())
+    }
+    /// Move this inode to `new_parent` as `name`.
+    /// An existing target should have been unlinked by the caller.
+    /// # Warning
+    /// ext4-rs can't rewrite "..", a moved directory keeps pointing at its old parent on disk.
+    /// We never follow ".." on disk, but fsck will complain about it.
+    pub fn rename_to(&self, new_parent: &Arc<Self>, name: &str) -> Result<(), isize> {
+        let (old_parent, old_name) = self.location.lock().clone();
+        let moves_dir = self.is_dir() && old_parent != new_parent.ino;
+        let ext4 = self.fs.lock();
+
+        let child_ref = ext4.get_inode_ref(self.ino);
+        let mut new_parent_ref = ext4.get_inode_ref(new_parent.ino);
+        ext4.dir_add_entry(&mut new_parent_ref, &child_ref, name)
+            .map_err(to_errno)?;
+        if moves_dir {
+            let links_count = new_parent_ref.inode.links_count();
+            new_parent_ref.inode.set_links_count(links_count + 1);
+        }
+        ext4.write_back_inode(&mut new_parent_ref);
+
+        // reload, `old_parent` may be the directory we just added to
+        let mut old_parent_ref = ext4.get_inode_ref(old_parent);
+        ext4.dir_remove_entry(&mut old_parent_ref, old_name.as_str())
+            .map_err(to_errno)?;
+        if moves_dir {
+            let links_count = old_parent_ref.inode.links_count();
+            old_parent_ref.inode.set_links_count(links_count - 1);
+        }
+        ext4.write_back_inode(&mut old_parent_ref);
+
+        *self.location.lock() = (new_parent.ino, name.to_string());
+        Ok(())
+    }
+    pub fn get_single_cache(&self, inner_cache_id: usize) -> Arc<Mutex<PageCache>> {
+        self.page_cache.get(inner_cache_id, |page| {
+            self.read_at(inner_cache_id * PAGE_SIZE, page)
+        })
+    }
+    pub fn get_all_cache(&self) -> Vec<Arc<Mutex<PageCache>>> {
+        self.page_cache.get_all()
+    }
+    /// See `MappedPages::writeback()`
+    pub fn writeback(&self, expire: Option<usize>) -> usize {
+        self.page_cache
+            .writeback(self.get_file_size(), expire, |offset, page| {
+                self.write_through(offset, page);
+            })
+    }
+    /// See `MappedPages::oom()`
+    pub fn oom(&self) -> usize {
+        self.page_cache.oom(self.get_file_size(), |offset, page| {
+            self.write_through(offset, page);
+        })
+    }
+    /// See `MappedPages::shrink_clean()`
+    pub fn shrink_clean(&self) -> usize {
+        self.page_cache.shrink_clean()
+    }
+}
@@ -99,6 +99,13 @@ impl Fat {
         }
     }
 
+    /// Write the dirty sectors of the FAT back to the disk.
+    /// # Argument
+    /// + `block_device`: pointer of block device
+    pub fn sync(&self, block_device: &Arc<dyn BlockDevice>) {
+        self.fat_cache_mgr.lock().sync(block_device);
+    }
+
     /// For a given cluster number, calculate its sector ID in the fat region
     /// # Argument
     /// + `clus_num`: cluster number
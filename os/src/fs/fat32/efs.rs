@@ -22,6 +22,8 @@ pub struct EasyFileSystem {
     pub sec_per_clus: u8,
     /// Bytes per sector, 512 for SD card
     pub byts_per_sec: u16,
+    /// Number of sectors of the volume
+    pub tot_sec: u32,
 }
 
 // export implementation of methods from FAT.
@@ -96,6 +98,7 @@ impl EasyFileSystem {
                     root_clus: super_block.root_clus,
                     sec_per_clus: super_block.sec_per_clus,
                     byts_per_sec,
+                    tot_sec: super_block.tot_sec32,
                     data_area_start_block: super_block.first_data_sector(),
                 };
                 Arc::new(efs)
@@ -115,4 +118,8 @@ impl EasyFileSystem {
         }
         block_ids
     }
+    /// Write the cached FAT sectors back to the disk.
+    pub fn sync(&self) {
+        self.fat.sync(&self.block_device);
+    }
 }
@@ -7,6 +7,7 @@ use core::panic;
 
 use super::layout::FATDiskInodeType;
 pub use super::DiskInodeType;
+use super::vfs::SYMLINK_MAGIC;
 use super::Inode;
 use alloc::string::ToString;
 use alloc::sync::{Arc, Weak};
@@ -15,6 +16,60 @@ use spin::Mutex;
 
 pub type InodeImpl = Inode;
 
+/// Mount options of FAT32, e.g. `uid=1000,gid=1000,umask=022`.
+/// FAT keeps no owners or permissions, every file gets these.
+#[derive(Clone, Copy)]
+pub struct Fat32Options {
+    pub uid: u32,
+    pub gid: u32,
+    /// Permission bits cleared from regular files
+    pub fmask: u32,
+    /// Permission bits cleared from directories
+    pub dmask: u32,
+}
+
+impl Default for Fat32Options {
+    fn default() -> Self {
+        Self {
+            uid: 0,
+            gid: 0,
+            fmask: 0o022,
+            dmask: 0o022,
+        }
+    }
+}
+
+impl Fat32Options {
+    /// Parse the `data` argument of `mount()`, `umask` sets both `fmask` and `dmask`.
+    pub fn parse(data: &str) -> Result<Self, isize> {
+        fn parse_mask(value: &str) -> Result<u32, isize> {
+            match u32::from_str_radix(value, 8) {
+                Ok(mask) if mask <= 0o777 => Ok(mask),
+                _ => Err(EINVAL),
+            }
+        }
+        let mut options = Self::default();
+        for option in data.split(',').filter(|option| !option.is_empty()) {
+            let (key, value) = match option.find('=') {
+                Some(idx) => (&option[..idx], &option[idx + 1..]),
+                None => (option, ""),
+            };
+            match key {
+                "uid" => options.uid = value.parse().map_err(|_| EINVAL)?,
+                "gid" => options.gid = value.parse().map_err(|_| EINVAL)?,
+                "umask" => {
+                    options.fmask = parse_mask(value)?;
+                    options.dmask = options.fmask;
+                }
+                "fmask" => options.fmask = parse_mask(value)?,
+                "dmask" => options.dmask = parse_mask(value)?,
+                _ => log::warn!("[Fat32Options::parse] ignore unknown option: {}", option),
+            }
+        }
+        Ok(options)
+    }
+}
+
 pub struct OSInode {
     readable: bool,
     writable: bool,
@@ -24,10 +79,12 @@ pub struct OSInode {
     inner: Arc<InodeImpl>,
     offset: Mutex<usize>,
     dirnode_ptr: Arc<Mutex<Weak<DirectoryTreeNode>>>,
+    /// Shared by every file of the volume, `MS_REMOUNT` changes them all at once
+    options: Arc<Mutex<Fat32Options>>,
 }
 
 impl OSInode {
-    pub fn new(root_inode: Arc<InodeImpl>) -> Arc<dyn File> {
+    pub fn new(root_inode: Arc<InodeImpl>, options: Fat32Options) -> Arc<dyn File> {
         Arc::new(Self {
             readable: true,
             writable: true,
@@ -36,8 +93,21 @@ impl OSInode {
             inner: root_inode,
             offset: Mutex::new(0),
             dirnode_ptr: Arc::new(Mutex::new(Weak::new())),
+            options: Arc::new(Mutex::new(options)),
         })
     }
+    /// Apply the options given to `MS_REMOUNT` to every file of the volume
+    pub fn remount(&self, options: Fat32Options) {
+        *self.options.lock() = options;
+    }
+    /// See `Inode::writeback()`
+    pub fn writeback(&self, expire: Option<usize>) -> usize {
+        self.inner.writeback(expire)
+    }
+    /// See `Inode::shrink_clean()`
+    pub fn shrink_clean(&self) -> usize {
+        self.inner.shrink_clean()
+    }
 }
 
 impl Drop for OSInode {
@@ -69,6 +139,7 @@ impl File for OSInode {
             inner: self.inner.clone(),
             offset: Mutex::new(*self.offset.lock()),
             dirnode_ptr: self.dirnode_ptr.clone(),
+            options: self.options.clone(),
         })
     }
     fn readable(&self) -> bool {
@@ -205,17 +276,19 @@ impl File for OSInode {
         self.inner.get_file_size() as usize
     }
     fn get_stat(&self) -> Stat {
-        let (size, atime, mtime, ctime, ino) = self.inner.stat_lock(&self.inner.read());
-        let st_mod: u32 = {
-            if self.inner.is_dir() {
-                (StatMode::S_IFDIR | StatMode::S_IRWXU | StatMode::S_IRWXG | StatMode::S_IRWXO)
-                    .bits()
-            } else {
-                (StatMode::S_IFREG | StatMode::S_IRWXU | StatMode::S_IRWXG | StatMode::S_IRWXO)
+        let (mut size, atime, mtime, ctime, ino) = self.inner.stat_lock(&self.inner.read());
+        let options = *self.options.lock();
+        let st_mod: u32 = match self.inner.get_file_type() {
+            DiskInodeType::Directory => StatMode::S_IFDIR.bits() | (0o777 & !options.dmask),
+            DiskInodeType::SymLink => {
+                // the size of a link is the length of its target
+                size -= SYMLINK_MAGIC.len() as i64;
+                (StatMode::S_IFLNK | StatMode::S_IRWXU | StatMode::S_IRWXG | StatMode::S_IRWXO)
                     .bits()
             }
+            DiskInodeType::File => StatMode::S_IFREG.bits() | (0o777 & !options.fmask),
         };
-        Stat::new(
+        let mut stat = Stat::new(
             crate::makedev!(8, 0),
             ino,
             st_mod,
@@ -225,7 +298,9 @@ impl File for OSInode {
             atime,
             mtime,
             ctime,
-        )
+        );
+        stat.set_owner(options.uid, options.gid);
+        stat
     }
     fn get_file_type(&self) -> DiskInodeType {
         self.inner.get_file_type()
@@ -248,6 +323,7 @@ impl File for OSInode {
             inner: self.inner.clone(),
             offset: Mutex::new(0),
             dirnode_ptr: self.dirnode_ptr.clone(),
+            options: self.options.clone(),
         })
     }
     fn open_subfile(&self) -> Result<Vec<(String, Arc<dyn File>)>, isize> {
@@ -261,6 +337,7 @@ impl File for OSInode {
                 inner: Inode::from_ent(&self.inner, short_ent, offset),
                 offset: Mutex::new(0),
                 dirnode_ptr: Arc::new(Mutex::new(Weak::new())),
+                options: self.options.clone(),
             })
         };
         Ok(self
@@ -282,6 +359,7 @@ impl File for OSInode {
                 inner,
                 offset: Mutex::new(0),
                 dirnode_ptr: Arc::new(Mutex::new(Weak::new())),
+                options: self.options.clone(),
             }))
         } else {
             panic!()
@@ -307,6 +385,36 @@ impl File for OSInode {
         }
         Ok(())
     }
+    fn symlink(&self, name: &str, target: &str) -> Result<Arc<dyn File>, isize> {
+        let inode_lock = self.inner.write();
+        let inner = match Inode::create_lock(
+            &self.inner,
+            &inode_lock,
+            name.to_string(),
+            DiskInodeType::SymLink,
+        ) {
+            Ok(inner) => inner,
+            Err(_) => return Err(ENOSPC),
+        };
+        drop(inode_lock);
+        if let Err(errno) = inner.write_link(target) {
+            inner.unlink_lock(&inner.write(), true)?;
+            return Err(errno);
+        }
+        Ok(Arc::new(Self {
+            readable: true,
+            writable: true,
+            special_use: false,
+            append: false,
+            inner,
+            offset: Mutex::new(0),
+            dirnode_ptr: Arc::new(Mutex::new(Weak::new())),
+            options: self.options.clone(),
+        }))
+    }
+    fn read_link(&self) -> Result<String, isize> {
+        self.inner.read_link()
+    }
     fn unlink(&self, delete: bool) -> Result<(), isize> {
         let inode_lock = self.inner.write();
         if self.inner.is_dir() && !self.inner.is_empty_dir_lock(&inode_lock) {
@@ -399,6 +507,13 @@ impl File for OSInode {
             inode_time.set_modify_time(mtime as u64);
         }
     }
+    fn fsync(&self) -> Result<(), isize> {
+        self.inner.sync();
+        Ok(())
+    }
+    fn bmap(&self) -> Result<(Arc<dyn BlockDevice>, Vec<usize>), isize> {
+        self.inner.bmap().ok_or(EINVAL)
+    }
     fn get_single_cache(&self, offset: usize) -> Result<Arc<Mutex<PageCache>>, ()> {
         if offset & 0xfff != 0 {
             return Err(());
@@ -192,6 +192,7 @@ pub struct FSInfo {
 pub enum DiskInodeType {
     File,
     Directory,
+    SymLink,
 }
 
 #[derive(PartialEq, Debug, Clone, Copy)]
@@ -482,11 +483,12 @@ impl FATShortDirEnt {
         short_ent.attr = FATDiskInodeType::AttrArchive;
         short_ent.set_fst_clus(fst_clus);
         short_ent.name.copy_from_slice(&name);
-        if file_type == DiskInodeType::Directory {
-            short_ent.attr = FATDiskInodeType::AttrDirectory;
-        } else {
-            short_ent.attr = FATDiskInodeType::AttrArchive;
-        }
+        short_ent.attr = match file_type {
+            DiskInodeType::Directory => FATDiskInodeType::AttrDirectory,
+            // See `Inode::SYMLINK_MAGIC`
+            DiskInodeType::SymLink => FATDiskInodeType::AttrSystem,
+            DiskInodeType::File => FATDiskInodeType::AttrArchive,
+        };
         return short_ent;
     }
     pub fn set_fst_clus(&mut self, fst_clus: u32) {
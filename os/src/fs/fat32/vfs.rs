@@ -1,16 +1,30 @@
 #![allow(unused)]
 use super::dir_iter::*;
 use super::layout::{FATDirEnt, FATDiskInodeType, FATLongDirEnt, FATShortDirEnt};
-use super::{BlockCacheManager, Cache, PageCache, PageCacheManager};
+use super::{BlockCacheManager, BlockDevice, Cache, PageCache, PageCacheManager};
 use super::{DiskInodeType, EasyFileSystem};
 use alloc::string::String;
 use alloc::sync::Arc;
+use alloc::vec;
 use alloc::vec::Vec;
 use core::convert::TryInto;
 use core::ops::Mul;
 use core::panic;
 use spin::*;
 
+use crate::syscall::errno::*;
+
+/// FAT32 has no symbolic links, so they are stored the way Cygwin does:
+/// a file with the `ATTR_SYSTEM` attribute whose content is this magic followed by the target in UTF-8.
+/// For example, to put `libc.so -> ld-musl-loongarch64.so.1` on a mounted image:
+/// ```text
+/// printf '!<symlink>ld-musl-loongarch64.so.1' > /mnt/lib/libc.so
+/// fatattr +s /mnt/lib/libc.so
+/// ```
+/// `mattrib -i fat32.img +s ::/lib/libc.so` does the same without mounting it,
+/// and `buildfs.sh` converts the links it finds among the test cases this way.
+pub const SYMLINK_MAGIC: &[u8; 10] = b"!<symlink>";
+
 pub struct FileContent {
     /// For FAT32, size is a value computed from FAT.
     /// You should iterate around the FAT32 to get the size.
@@ -108,29 +122,7 @@ impl Drop for Inode {
             let length = lock.clus_list.len();
             self.dealloc_clus(&mut lock, length);
         } else {
-            if self.parent_dir.lock().is_none() {
-                return;
-            }
-            let par_dir_lock = self.parent_dir.lock();
-            let (parent_dir, offset) = par_dir_lock.as_ref().unwrap();
-
-            let par_inode_lock = parent_dir.write();
-            let dir_ent = parent_dir.get_dir_ent(&par_inode_lock, *offset).unwrap();
-            let mut short_dir_ent = *dir_ent.get_short_ent().unwrap();
-            // Modify size
-            short_dir_ent.file_size = self.get_file_size();
-            // Modify fst cluster
-            short_dir_ent.set_fst_clus(
-                self.get_first_clus_lock(&self.file_content.read())
-                    .unwrap_or(0),
-            );
-            // Modify time
-            // todo!
-            log::debug!("[Inode drop]: new_ent: {:?}", short_dir_ent);
-            // Write back
-            parent_dir
-                .set_dir_ent(&par_inode_lock, *offset, dir_ent)
-                .unwrap();
+            self.sync_dir_ent();
         }
     }
 }
@@ -318,6 +310,22 @@ impl Inode {
         }
         block_ids
     }
+    /// The device and the first block of each whole page of the file, for a swap file.
+    /// `None` if the blocks of a page are not contiguous.
+    pub fn bmap(&self) -> Option<(Arc<dyn BlockDevice>, Vec<usize>)> {
+        let lock = self.file_content.read();
+        let sec_per_cache = PageCacheManager::CACHE_SZ / self.fs.byts_per_sec as usize;
+        let pages = lock.get_file_size() as usize / PageCacheManager::CACHE_SZ;
+        let mut block_ids = Vec::with_capacity(pages);
+        for inner_cache_id in 0..pages {
+            let ids = self.get_neighboring_sec(&lock.clus_list, inner_cache_id);
+            if ids.len() < sec_per_cache || ids.windows(2).any(|pair| pair[1] != pair[0] + 1) {
+                return None;
+            }
+            block_ids.push(ids[0]);
+        }
+        Some((self.fs.block_device.clone(), block_ids))
+    }
     /// Open the root directory
     /// # Arguments
     /// + `efs`: The pointer to inner file system
@@ -467,7 +475,96 @@ impl Inode {
         let neighbor = |inner_cache_id| {
             self.get_neighboring_sec(&self.file_content.read().clus_list, inner_cache_id)
         };
-        self.file_cache_mgr.oom(neighbor, &self.fs.block_device)
+        self.file_cache_mgr
+            .oom(neighbor, &self.fs.block_device, true)
+    }
+    /// Like `oom()`, but only clean pages are dropped, nothing is written.
+    pub fn shrink_clean(&self) -> usize {
+        let neighbor = |inner_cache_id| {
+            self.get_neighboring_sec(&self.file_content.read().clus_list, inner_cache_id)
+        };
+        self.file_cache_mgr
+            .oom(neighbor, &self.fs.block_device, false)
+    }
+}
+
+/// Writeback
+impl Inode {
+    /// Write the dirty page caches of this file back to the disk.
+    /// # Arguments
+    /// + `expire`: if given, only pages dirty for at least this many milliseconds are written
+    /// # Return Value
+    /// The number of written pages
+    pub fn sync_data(&self, expire: Option<usize>) -> usize {
+        let neighbor = |inner_cache_id| {
+            self.get_neighboring_sec(&self.file_content.read().clus_list, inner_cache_id)
+        };
+        self.file_cache_mgr
+            .sync(neighbor, &self.fs.block_device, expire)
+    }
+    /// Record the size and the first cluster of this file in its directory entry.
+    /// The entry lives in the page caches of the parent directory.
+    /// # Return Value
+    /// The parent directory if the entry was changed
+    fn sync_dir_ent(&self) -> Option<Arc<Self>> {
+        let par_dir_lock = self.parent_dir.lock();
+        let (parent_dir, offset) = match par_dir_lock.as_ref() {
+            Some(parent_dir) => parent_dir,
+            // the root directory has no entry
+            None => return None,
+        };
+
+        let par_inode_lock = parent_dir.write();
+        let mut dir_ent = parent_dir.get_dir_ent(&par_inode_lock, *offset).unwrap();
+        let old_size = dir_ent.get_short_ent().unwrap().file_size;
+        let old_fst_clus = dir_ent.get_fst_clus();
+        // Modify size, it stays 0 for directories
+        if !self.is_dir() {
+            dir_ent.set_size(self.get_file_size());
+        }
+        // Modify fst cluster
+        dir_ent.set_fst_clus(
+            self.get_first_clus_lock(&self.file_content.read())
+                .unwrap_or(0),
+        );
+        // Modify time
+        // todo!
+        if dir_ent.get_short_ent().unwrap().file_size == old_size
+            && dir_ent.get_fst_clus() == old_fst_clus
+        {
+            return None;
+        }
+        log::debug!(
+            "[Inode sync_dir_ent]: new_ent: {:?}",
+            dir_ent.get_short_ent()
+        );
+        // Write back
+        parent_dir
+            .set_dir_ent(&par_inode_lock, *offset, dir_ent)
+            .unwrap();
+        Some(parent_dir.clone())
+    }
+    /// Write back the data of this file and update its directory entry.
+    /// The FAT is left to the caller, see `EasyFileSystem::sync()`.
+    /// # Arguments
+    /// + `expire`: passed to `sync_data()`, the directory entry is always brought up to date
+    /// # Return Value
+    /// The number of written pages
+    pub fn writeback(&self, expire: Option<usize>) -> usize {
+        let written = self.sync_data(expire);
+        if *self.deleted.lock() {
+            return written;
+        }
+        if let Some(parent_dir) = self.sync_dir_ent() {
+            parent_dir.sync_data(None);
+        }
+        written
+    }
+    /// Make everything needed to read this file back reach the disk:
+    /// its data, its directory entry and the FAT.
+    pub fn sync(&self) {
+        self.writeback(None);
+        self.fs.sync();
     }
 }
 
@@ -1219,7 +1316,7 @@ impl Inode {
         name: String,
         file_type: DiskInodeType,
     ) -> Result<Arc<Self>, ()> {
-        if parent_dir.is_file() || name.len() >= 256 {
+        if !parent_dir.is_dir() || name.len() >= 256 {
             Err(())
         } else {
             log::debug!(
@@ -1252,6 +1349,10 @@ impl Inode {
                 };
             // Generate current file
             let current_file = Self::from_ent(&parent_dir, &short_ent, short_ent_offset);
+            // It has no content yet, so `from_ent()` can't tell it is a link
+            if file_type == DiskInodeType::SymLink {
+                *current_file.file_type.lock() = DiskInodeType::SymLink;
+            }
             // If file_type is Directory, set first 3 directory entry
             if file_type == DiskInodeType::Directory {
                 // Set hint
@@ -1375,7 +1476,7 @@ impl Inode {
     /// # Return Value
     /// Pointer to Inode
     pub fn from_ent(parent_dir: &Arc<Self>, ent: &FATShortDirEnt, offset: u32) -> Arc<Self> {
-        Self::new(
+        let inode = Self::new(
             ent.get_first_clus(),
             if ent.is_dir() {
                 DiskInodeType::Directory
@@ -1389,7 +1490,11 @@ impl Inode {
             },
             Some((parent_dir.clone(), offset)),
             parent_dir.fs.clone(),
-        )
+        );
+        if ent.attr == FATDiskInodeType::AttrSystem && inode.has_symlink_magic() {
+            *inode.file_type.lock() = DiskInodeType::SymLink;
+        }
+        inode
     }
 
     /// Fill out an empty directory with only the '.' & '..' entries.
@@ -1429,6 +1534,44 @@ impl Inode {
     }
 }
 
+/// Symbolic link, see `SYMLINK_MAGIC`
+impl Inode {
+    fn has_symlink_magic(&self) -> bool {
+        let mut magic = [0u8; SYMLINK_MAGIC.len()];
+        self.get_file_size() as usize > magic.len()
+            && self.read_at_block_cache(0, &mut magic) == magic.len()
+            && &magic == SYMLINK_MAGIC
+    }
+    /// Get the target of a symbolic link
+    /// # Return Value
+    /// If successful, it will return the target.
+    /// Otherwise, it will return Error with error number.
+    pub fn read_link(&self) -> Result<String, isize> {
+        if self.get_file_type() != DiskInodeType::SymLink {
+            return Err(EINVAL);
+        }
+        let mut content = vec![0u8; self.get_file_size() as usize];
+        let len = self.read_at_block_cache(0, &mut content);
+        match content[..len].strip_prefix(&SYMLINK_MAGIC[..]) {
+            Some(target) => String::from_utf8(target.to_vec()).map_err(|_| EIO),
+            None => Err(EIO),
+        }
+    }
+    /// Fill a file created as `DiskInodeType::SymLink` with the link to `target`.
+    /// # Return Value
+    /// If successful, it will return Ok.
+    /// Otherwise, it will return Error with error number.
+    pub fn write_link(&self, target: &str) -> Result<(), isize> {
+        let mut content = Vec::with_capacity(SYMLINK_MAGIC.len() + target.len());
+        content.extend_from_slice(SYMLINK_MAGIC);
+        content.extend_from_slice(target.as_bytes());
+        if self.write_at_block_cache(0, &content) != content.len() {
+            return Err(ENOSPC);
+        }
+        Ok(())
+    }
+}
+
 // ls and find local
 impl Inode {
     /// ls - General Purose file filterer
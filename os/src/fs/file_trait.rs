@@ -1,5 +1,8 @@
 use super::fat32::DiskInodeType;
-use crate::{mm::UserBuffer, syscall::errno::ENOTTY};
+use crate::{
+    mm::UserBuffer,
+    syscall::errno::{EINVAL, ENOTSUP, ENOTTY, EPERM},
+};
 use __alloc::string::String;
 use alloc::{
     sync::{Arc, Weak},
@@ -8,7 +11,7 @@ use alloc::{
 use downcast_rs::*;
 use spin::Mutex;
 
-use super::{cache::PageCache, directory_tree::DirectoryTreeNode, layout::*};
+use super::{cache::PageCache, directory_tree::DirectoryTreeNode, layout::*, BlockDevice};
 
 pub trait File: DowncastSync {
     fn deep_clone(&self) -> Arc<dyn File>;
@@ -29,6 +32,9 @@ pub trait File: DowncastSync {
     fn is_file(&self) -> bool {
         self.get_file_type() == DiskInodeType::File
     }
+    fn is_symlink(&self) -> bool {
+        self.get_file_type() == DiskInodeType::SymLink
+    }
     fn info_dirtree_node(&self, dirnode_ptr: Weak<DirectoryTreeNode>);
     fn get_dirtree_node(&self) -> Option<Arc<DirectoryTreeNode>>;
     /// open
@@ -39,6 +45,14 @@ pub trait File: DowncastSync {
     fn link_child(&self, name: &str, child: &Self) -> Result<(), isize>
     where
         Self: Sized;
+    /// symbolic link, `EPERM` if the filesystem doesn't support them
+    fn symlink(&self, _name: &str, _target: &str) -> Result<Arc<dyn File>, isize> {
+        Err(EPERM)
+    }
+    /// `EINVAL` if this is not a symbolic link
+    fn read_link(&self) -> Result<String, isize> {
+        Err(EINVAL)
+    }
     /// delete(unlink)
     fn unlink(&self, delete: bool) -> Result<(), isize>;
     /// dirent
@@ -53,6 +67,45 @@ pub trait File: DowncastSync {
     fn truncate_size(&self, new_size: usize) -> Result<(), isize>;
     // time
     fn set_timestamp(&self, ctime: Option<usize>, atime: Option<usize>, mtime: Option<usize>);
+    /// permission bits, `EPERM` if the filesystem doesn't keep them
+    fn chmod(&self, _mode: u32) -> Result<(), isize> {
+        Err(EPERM)
+    }
+    /// owner, `None` leaves an id unchanged. `EPERM` if the filesystem doesn't keep them
+    fn chown(&self, _uid: Option<u32>, _gid: Option<u32>) -> Result<(), isize> {
+        Err(EPERM)
+    }
+    /// write back whatever is cached, `EINVAL` for files that can't be synchronized, e.g. a pipe
+    fn fsync(&self) -> Result<(), isize> {
+        Err(EINVAL)
+    }
+    /// the device holding the file and the first block of each of its pages, for a swap file.
+    /// `EINVAL` if its blocks can't be accessed directly
+    fn bmap(&self) -> Result<(Arc<dyn BlockDevice>, Vec<usize>), isize> {
+        Err(EINVAL)
+    }
+    /// extended attributes, `ENOTSUP` if the filesystem doesn't keep them.
+    /// `name` carries its namespace, e.g. "user.mime_type", which is checked by the caller.
+    fn get_xattr(&self, _name: &str) -> Result<Vec<u8>, isize> {
+        Err(ENOTSUP)
+    }
+    /// `flags` is checked with `XattrFlags::check()` under the same lock as the attribute is set
+    fn set_xattr(&self, _name: &str, _value: &[u8], _flags: XattrFlags) -> Result<(), isize> {
+        Err(ENOTSUP)
+    }
+    fn list_xattr(&self) -> Result<Vec<String>, isize> {
+        Err(ENOTSUP)
+    }
+    fn remove_xattr(&self, _name: &str) -> Result<(), isize> {
+        Err(ENOTSUP)
+    }
+    /// seals, `EINVAL` if the file can't be sealed
+    fn get_seals(&self) -> Result<SealFlags, isize> {
+        Err(EINVAL)
+    }
+    fn add_seals(&self, _seals: SealFlags) -> Result<(), isize> {
+        Err(EINVAL)
+    }
     /// cache
     fn get_single_cache(&self, offset: usize) -> Result<Arc<Mutex<PageCache>>, ()>;
     fn get_all_caches(&self) -> Result<Vec<Arc<Mutex<PageCache>>>, ()>;
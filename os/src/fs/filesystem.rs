@@ -1,17 +1,29 @@
-use core::ops::AddAssign;
+use core::{convert::TryInto, ops::AddAssign};
 
 use alloc::sync::Arc;
+use alloc::vec;
 use lazy_static::*;
 use spin::Mutex;
 
+use super::{BlockDevice, MountFlags};
+use crate::arch::BLOCK_SZ;
+
 pub enum FS {
     Null,
     Fat32,
+    Proc,
+    Tmpfs,
+    #[cfg(feature = "lwext4")]
+    Ext4,
+    #[cfg(feature = "ext4rs")]
+    Ext4Rs,
 }
 
 pub struct FileSystem {
     pub fs_id: usize,
     pub fs_type: FS,
+    /// Per-mount flags such as `MS_RDONLY`, changed by `MS_REMOUNT`
+    mount_flags: Mutex<MountFlags>,
 }
 
 lazy_static! {
@@ -22,6 +34,73 @@ impl FileSystem {
     pub fn new(fs_type: FS) -> Self {
         FS_ID_COUNTER.lock().add_assign(1);
         let fs_id = *FS_ID_COUNTER.lock();
-        Self { fs_id, fs_type }
+        Self {
+            fs_id,
+            fs_type,
+            mount_flags: Mutex::new(MountFlags::empty()),
+        }
+    }
+    pub fn mount_flags(&self) -> MountFlags {
+        *self.mount_flags.lock()
+    }
+    pub fn set_mount_flags(&self, flags: MountFlags) {
+        *self.mount_flags.lock() = flags;
+    }
+    pub fn is_read_only(&self) -> bool {
+        self.mount_flags().contains(MountFlags::MS_RDONLY)
+    }
+    /// Entries of a volatile filesystem come and go on their own, e.g. processes in procfs,
+    /// so the directory tree must look them up again instead of caching them.
+    pub fn is_volatile(&self) -> bool {
+        matches!(self.fs_type, FS::Proc)
+    }
+}
+
+/// The ext2/3/4 superblock always starts 1024 bytes into the volume.
+const EXT4_SUPERBLOCK_OFFSET: usize = 1024;
+const EXT4_SUPER_MAGIC: u16 = 0xEF53;
+const EXT4_FEATURE_INCOMPAT_64BIT: u32 = 0x80;
+
+/// The geometry of an ext4 volume, as recorded in its superblock.
+#[derive(Clone, Copy, Debug)]
+pub struct Ext4Geometry {
+    /// Size of a filesystem block in bytes (NOT the size of a device sector)
+    pub block_size: usize,
+    pub blocks_count: u64,
+}
+
+impl Ext4Geometry {
+    /// Size of the whole volume in bytes.
+    pub fn volume_size(&self) -> u64 {
+        self.blocks_count * self.block_size as u64
+    }
+}
+
+/// Look for an ext2/3/4 superblock on `block_device`.
+/// Returns `None` if the magic number doesn't match, e.g. on a FAT32 image.
+#[allow(unused)]
+pub fn probe_ext4(block_device: &Arc<dyn BlockDevice>) -> Option<Ext4Geometry> {
+    let len = (EXT4_SUPERBLOCK_OFFSET * 2 + BLOCK_SZ - 1) / BLOCK_SZ * BLOCK_SZ;
+    let mut buf = vec![0u8; len];
+    block_device.read_block(0, &mut buf);
+    let super_block = &buf[EXT4_SUPERBLOCK_OFFSET..EXT4_SUPERBLOCK_OFFSET * 2];
+    let read_u32 = |offset: usize| {
+        u32::from_le_bytes(super_block[offset..offset + 4].try_into().unwrap())
+    };
+    // s_magic
+    if u16::from_le_bytes([super_block[0x38], super_block[0x39]]) != EXT4_SUPER_MAGIC {
+        return None;
     }
+    // s_blocks_count_lo, s_log_block_size, s_feature_incompat, s_blocks_count_hi
+    let blocks_count_lo = read_u32(0x04) as u64;
+    let log_block_size = read_u32(0x18);
+    let blocks_count_hi = if read_u32(0x60) & EXT4_FEATURE_INCOMPAT_64BIT != 0 {
+        read_u32(0x150) as u64
+    } else {
+        0
+    };
+    Some(Ext4Geometry {
+        block_size: 1024 << log_block_size,
+        blocks_count: (blocks_count_hi << 32) | blocks_count_lo,
+    })
 }
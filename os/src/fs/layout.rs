@@ -1,6 +1,12 @@
+use alloc::{string::String, vec::Vec};
 use core::mem::size_of;
 
-use crate::{arch::BLOCK_SZ, timer::TimeSpec};
+use super::DiskInodeType;
+use crate::{
+    arch::BLOCK_SZ,
+    syscall::errno::{EEXIST, ENODATA},
+    timer::TimeSpec,
+};
 
 bitflags! {
     pub struct OpenFlags: u32 {
@@ -38,6 +44,88 @@ bitflags! {
     }
 }
 
+bitflags! {
+    pub struct UmountFlags: u32 {
+        const MNT_FORCE           =   1;
+        const MNT_DETACH          =   2;
+        const MNT_EXPIRE          =   4;
+        const UMOUNT_NOFOLLOW     =   8;
+    }
+}
+
+bitflags! {
+    pub struct MountFlags: usize {
+        const MS_RDONLY         =   1;
+        const MS_NOSUID         =   2;
+        const MS_NODEV          =   4;
+        const MS_NOEXEC         =   8;
+        const MS_SYNCHRONOUS    =   16;
+        const MS_REMOUNT        =   32;
+        const MS_MANDLOCK       =   64;
+        const MS_DIRSYNC        =   128;
+        const MS_NOATIME        =   1024;
+        const MS_NODIRATIME     =   2048;
+        const MS_BIND           =   4096;
+        const MS_MOVE           =   8192;
+        const MS_REC            =   16384;
+        const MS_SILENT         =   32768;
+        const MS_POSIXACL       =   (1<<16);
+        const MS_UNBINDABLE     =   (1<<17);
+        const MS_PRIVATE        =   (1<<18);
+        const MS_SLAVE          =   (1<<19);
+        const MS_SHARED         =   (1<<20);
+        const MS_RELATIME       =   (1<<21);
+        const MS_KERNMOUNT      =   (1<<22);
+        const MS_I_VERSION      =   (1<<23);
+        const MS_STRICTATIME    =   (1<<24);
+        const MS_LAZYTIME       =   (1<<25);
+        const MS_NOREMOTELOCK   =   (1<<27);
+        const MS_NOSEC          =   (1<<28);
+        const MS_BORN           =   (1<<29);
+        const MS_ACTIVE         =   (1<<30);
+        const MS_NOUSER         =   (1<<31);
+    }
+}
+
+bitflags! {
+    pub struct XattrFlags: u32 {
+        /// Fail if the attribute already exists
+        const XATTR_CREATE      =   1;
+        /// Fail if the attribute doesn't exist
+        const XATTR_REPLACE     =   2;
+    }
+}
+
+impl XattrFlags {
+    /// Whether an attribute may be set, given whether it `exists`.
+    /// Backends check this under the lock they set it with, so that concurrent setters can't both pass.
+    pub fn check(&self, exists: bool) -> Result<(), isize> {
+        if exists && self.contains(XattrFlags::XATTR_CREATE) {
+            return Err(EEXIST);
+        }
+        if !exists && self.contains(XattrFlags::XATTR_REPLACE) {
+            return Err(ENODATA);
+        }
+        Ok(())
+    }
+}
+
+bitflags! {
+    /// Seals of a file, see `fcntl(F_ADD_SEALS)`
+    pub struct SealFlags: u32 {
+        /// No more seals can be added
+        const F_SEAL_SEAL           =   0x1;
+        const F_SEAL_SHRINK         =   0x2;
+        const F_SEAL_GROW           =   0x4;
+        /// The content can't be modified
+        const F_SEAL_WRITE          =   0x8;
+        /// Like `F_SEAL_WRITE`, but shared writable mappings made before keep working
+        const F_SEAL_FUTURE_WRITE   =   0x10;
+        /// The executable bits can't be changed
+        const F_SEAL_EXEC           =   0x20;
+    }
+}
+
 bitflags! {
     pub struct StatMode: u32 {
         ///bit mask for the file type bit field
@@ -247,6 +335,17 @@ impl Stat {
     pub fn get_nlink(&self) -> u32 {
         self.st_nlink
     }
+    pub fn get_uid(&self) -> u32 {
+        self.st_uid
+    }
+    pub fn get_gid(&self) -> u32 {
+        self.st_gid
+    }
+    /// `new()` leaves the file to root, filesystems keeping owners set them here
+    pub fn set_owner(&mut self, uid: u32, gid: u32) {
+        self.st_uid = uid;
+        self.st_gid = gid;
+    }
     pub fn get_dev(&self) -> u32 {
         self.st_dev as u32
     }
@@ -339,4 +438,59 @@ impl Dirent {
         dirent.d_name[0..d_name.len()].copy_from_slice(d_name.as_bytes());
         dirent
     }
+    /// Pack the entries `(name, ino, d_type)` from `*offset` on, as many as `count` bytes hold,
+    /// and move `offset` past them.
+    pub fn pack(entries: &[(String, u32, u8)], offset: &mut usize, count: usize) -> Vec<Self> {
+        let start = *offset;
+        let vec: Vec<Self> = entries
+            .iter()
+            .enumerate()
+            .skip(start)
+            .take(count / size_of::<Self>())
+            .map(|(idx, (name, ino, d_type))| {
+                Self::new(*ino as usize, (idx + 1) as isize, *d_type, name.as_str())
+            })
+            .collect();
+        *offset = start + vec.len();
+        vec
+    }
+}
+
+/// `d_type` of a `Dirent`
+pub const DT_UNKNOWN: u8 = 0;
+pub const DT_FIFO: u8 = 1;
+pub const DT_CHR: u8 = 2;
+pub const DT_DIR: u8 = 4;
+pub const DT_BLK: u8 = 6;
+pub const DT_REG: u8 = 8;
+pub const DT_LNK: u8 = 10;
+pub const DT_SOCK: u8 = 12;
+
+/// What the directory tree makes of an entry of type `d_type`, special files are opened as regular ones
+pub fn file_type_of(d_type: u8) -> DiskInodeType {
+    match d_type {
+        DT_DIR => DiskInodeType::Directory,
+        DT_LNK => DiskInodeType::SymLink,
+        _ => DiskInodeType::File,
+    }
+}
+
+pub const F_RDLCK: i16 = 0;
+pub const F_WRLCK: i16 = 1;
+pub const F_UNLCK: i16 = 2;
+
+#[derive(Clone, Copy, Debug, Default)]
+#[repr(C)]
+/// Native Linux `struct flock`, describes a byte range lock for `fcntl()`.
+pub struct Flock {
+    /// `F_RDLCK`, `F_WRLCK` or `F_UNLCK`
+    pub l_type: i16,
+    /// How `l_start` is interpreted, see `SeekWhence`
+    pub l_whence: i16,
+    /// Starting offset of the range
+    pub l_start: i64,
+    /// Length of the range, 0 means up to the end of the file, however long it grows
+    pub l_len: i64,
+    /// Process holding the lock, set by `F_GETLK`. -1 for OFD locks.
+    pub l_pid: i32,
 }
@@ -0,0 +1,445 @@
+//! Advisory file locks.
+//! Byte range locks of `fcntl()` belong to a process (POSIX locks) or to an open file description (OFD locks),
+//! both kinds live in the same space and conflict with each other.
+//! Whole file locks of `flock()` belong to an open file description and are independent of byte range locks.
+//! Locks are kept per inode, keyed by its `DirectoryTreeNode`.
+use alloc::{
+    collections::BTreeMap,
+    sync::{Arc, Weak},
+    vec::Vec,
+};
+use lazy_static::*;
+use spin::{Mutex, MutexGuard};
+
+use super::{
+    directory_tree::DirectoryTreeNode, file_trait::File, Flock, SeekWhence, F_RDLCK, F_UNLCK,
+    F_WRLCK,
+};
+use crate::{
+    syscall::errno::*,
+    task::{block_current_and_run_next, current_task, WaitQueue},
+};
+
+pub const LOCK_SH: u32 = 1;
+pub const LOCK_EX: u32 = 2;
+pub const LOCK_NB: u32 = 4;
+pub const LOCK_UN: u32 = 8;
+
+#[derive(Clone)]
+enum LockOwner {
+    /// POSIX locks, by tgid
+    Process(usize),
+    /// OFD and `flock()` locks.
+    /// Dead descriptions are normally cleaned up by `release_on_close()`,
+    /// the rest, such as those kept alive by a mapping, are dropped when the inode is next looked at.
+    File(Weak<dyn File>),
+}
+
+impl LockOwner {
+    fn is(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Self::Process(a), Self::Process(b)) => a == b,
+            (Self::File(a), Self::File(b)) => {
+                Weak::as_ptr(a) as *const () == Weak::as_ptr(b) as *const ()
+            }
+            _ => false,
+        }
+    }
+    fn alive(&self) -> bool {
+        match self {
+            Self::Process(_) => true,
+            Self::File(file) => file.strong_count() != 0,
+        }
+    }
+}
+
+/// A byte range lock, `end` is inclusive
+#[derive(Clone)]
+struct RangeLock {
+    owner: LockOwner,
+    write: bool,
+    start: u64,
+    end: u64,
+}
+
+impl RangeLock {
+    fn overlaps(&self, start: u64, end: u64) -> bool {
+        self.start <= end && start <= self.end
+    }
+}
+
+struct InodeLocks {
+    /// Keeps the key of this entry from being reused by another node
+    _node: Arc<DirectoryTreeNode>,
+    ranges: Vec<RangeLock>,
+    /// `flock()` locks, `true` for exclusive ones
+    flocks: Vec<(LockOwner, bool)>,
+    /// Tasks waiting for any lock on this inode, woken whenever a lock is released
+    wait_queue: WaitQueue,
+}
+
+impl InodeLocks {
+    fn new(node: Arc<DirectoryTreeNode>) -> Self {
+        Self {
+            _node: node,
+            ranges: Vec::new(),
+            flocks: Vec::new(),
+            wait_queue: WaitQueue::new(),
+        }
+    }
+    fn prune(&mut self) {
+        self.ranges.retain(|lock| lock.owner.alive());
+        self.flocks.retain(|(owner, _)| owner.alive());
+    }
+    fn is_empty(&self) -> bool {
+        self.ranges.is_empty() && self.flocks.is_empty()
+    }
+    /// The first lock of another owner that keeps `owner` from locking `start..=end`
+    fn range_conflict(
+        &self,
+        owner: &LockOwner,
+        write: bool,
+        start: u64,
+        end: u64,
+    ) -> Option<&RangeLock> {
+        self.ranges.iter().find(|lock| {
+            !lock.owner.is(owner) && (write || lock.write) && lock.overlaps(start, end)
+        })
+    }
+    /// Replace whatever `owner` holds in `start..=end` with a lock of the given kind, or nothing if `write` is `None`.
+    /// Neighboring locks of the same kind are merged.
+    fn set_range(&mut self, owner: LockOwner, write: Option<bool>, mut start: u64, mut end: u64) {
+        let mut ranges = Vec::with_capacity(self.ranges.len() + 1);
+        for lock in self.ranges.drain(..) {
+            if !lock.owner.is(&owner) || !lock.overlaps(start, end) {
+                ranges.push(lock);
+                continue;
+            }
+            if lock.start < start {
+                ranges.push(RangeLock {
+                    end: start - 1,
+                    ..lock.clone()
+                });
+            }
+            if lock.end > end {
+                ranges.push(RangeLock {
+                    start: end + 1,
+                    ..lock
+                });
+            }
+        }
+        if let Some(write) = write {
+            ranges.retain(|lock| {
+                if !lock.owner.is(&owner) || lock.write != write {
+                    return true;
+                }
+                if start != 0 && lock.end == start - 1 {
+                    start = lock.start;
+                    false
+                } else if end != u64::MAX && lock.start == end + 1 {
+                    end = lock.end;
+                    false
+                } else {
+                    true
+                }
+            });
+            ranges.push(RangeLock {
+                owner,
+                write,
+                start,
+                end,
+            });
+        }
+        self.ranges = ranges;
+    }
+}
+
+struct LockTable {
+    inodes: BTreeMap<usize, InodeLocks>,
+    /// Which process each blocked process waits for, to detect deadlocks between POSIX locks
+    waiting_for: BTreeMap<usize, usize>,
+}
+
+impl LockTable {
+    /// Whether `tgid` waiting for `holder` would close a cycle of waiting processes
+    fn would_deadlock(&self, tgid: usize, mut holder: usize) -> bool {
+        // a cycle not involving `tgid` would loop forever
+        for _ in 0..=self.waiting_for.len() {
+            if holder == tgid {
+                return true;
+            }
+            holder = match self.waiting_for.get(&holder) {
+                Some(&next) => next,
+                None => return false,
+            };
+        }
+        false
+    }
+}
+
+lazy_static! {
+    static ref LOCK_TABLE: Mutex<LockTable> = Mutex::new(LockTable {
+        inodes: BTreeMap::new(),
+        waiting_for: BTreeMap::new(),
+    });
+}
+
+fn node_of(file: &Arc<dyn File>) -> Result<Arc<DirectoryTreeNode>, isize> {
+    // pipes, sockets and the like
+    file.get_dirtree_node().ok_or(EINVAL)
+}
+
+fn key_of(node: &Arc<DirectoryTreeNode>) -> usize {
+    Arc::as_ptr(node) as usize
+}
+
+/// Sleep until some lock of the inode is released, `table` is unlocked meanwhile.
+/// # Return Value
+/// `ERESTART` if a signal arrived in the meantime
+fn wait(mut table: MutexGuard<LockTable>, key: usize) -> Result<(), isize> {
+    let task = current_task().unwrap();
+    let inode = table.inodes.get_mut(&key).unwrap();
+    inode.wait_queue.add_task(Arc::downgrade(&task));
+    drop(table);
+    drop(task);
+    block_current_and_run_next();
+    let task = current_task().unwrap();
+    let inner = task.acquire_inner_lock();
+    if !inner.sigpending.difference(inner.sigmask).is_empty() {
+        return Err(ERESTART);
+    }
+    Ok(())
+}
+
+/// The range of `flock`, relative to the beginning of `file`
+fn flock_range(file: &Arc<dyn File>, flock: &Flock) -> Result<(u64, u64), isize> {
+    let base = match SeekWhence::from_bits(flock.l_whence as u32) {
+        Some(SeekWhence::SEEK_SET) => 0,
+        Some(SeekWhence::SEEK_CUR) => file.lseek(0, SeekWhence::SEEK_CUR)? as i64,
+        Some(SeekWhence::SEEK_END) => file.get_size() as i64,
+        _ => return Err(EINVAL),
+    };
+    let start = base.checked_add(flock.l_start).ok_or(EOVERFLOW)?;
+    let (start, end) = if flock.l_len > 0 {
+        (start, start.checked_add(flock.l_len - 1).ok_or(EOVERFLOW)?)
+    } else if flock.l_len == 0 {
+        (start, i64::MAX)
+    } else {
+        (start.checked_add(flock.l_len).ok_or(EINVAL)?, start - 1)
+    };
+    if start < 0 {
+        return Err(EINVAL);
+    }
+    let end = match end {
+        i64::MAX => u64::MAX,
+        end => end as u64,
+    };
+    Ok((start as u64, end))
+}
+
+fn range_owner(file: &Arc<dyn File>, ofd: bool) -> LockOwner {
+    if ofd {
+        LockOwner::File(Arc::downgrade(file))
+    } else {
+        LockOwner::Process(current_task().unwrap().tgid)
+    }
+}
+
+/// `F_GETLK` and `F_OFD_GETLK`: describe a lock that would keep `flock` from being placed,
+/// or set its type to `F_UNLCK` if there is none.
+pub fn get_range_lock(file: &Arc<dyn File>, flock: &mut Flock, ofd: bool) -> Result<(), isize> {
+    let write = match flock.l_type {
+        F_RDLCK => false,
+        F_WRLCK => true,
+        _ => return Err(EINVAL),
+    };
+    if ofd && flock.l_pid != 0 {
+        return Err(EINVAL);
+    }
+    let (start, end) = flock_range(file, flock)?;
+    let owner = range_owner(file, ofd);
+    let node = node_of(file)?;
+    let mut table = LOCK_TABLE.lock();
+    let conflict = table.inodes.get_mut(&key_of(&node)).and_then(|inode| {
+        inode.prune();
+        inode.range_conflict(&owner, write, start, end).cloned()
+    });
+    match conflict {
+        Some(lock) => {
+            flock.l_type = if lock.write { F_WRLCK } else { F_RDLCK };
+            flock.l_whence = SeekWhence::SEEK_SET.bits() as i16;
+            flock.l_start = lock.start as i64;
+            flock.l_len = match lock.end {
+                u64::MAX => 0,
+                end => (end - lock.start + 1) as i64,
+            };
+            flock.l_pid = match lock.owner {
+                LockOwner::Process(tgid) => tgid as i32,
+                LockOwner::File(_) => -1,
+            };
+        }
+        None => flock.l_type = F_UNLCK,
+    }
+    Ok(())
+}
+
+/// `F_SETLK`, `F_SETLKW` and their OFD versions: place or remove the lock described by `flock`.
+/// A read lock needs `file` to be open for reading, a write lock for writing.
+/// # Arguments
+/// + `block`: sleep until the lock can be placed instead of failing with `EAGAIN`
+pub fn set_range_lock(
+    file: &Arc<dyn File>,
+    flock: &Flock,
+    ofd: bool,
+    block: bool,
+) -> Result<(), isize> {
+    let write = match flock.l_type {
+        F_RDLCK if !file.readable() => return Err(EBADF),
+        F_WRLCK if !file.writable() => return Err(EBADF),
+        F_RDLCK => Some(false),
+        F_WRLCK => Some(true),
+        F_UNLCK => None,
+        _ => return Err(EINVAL),
+    };
+    if ofd && flock.l_pid != 0 {
+        return Err(EINVAL);
+    }
+    let (start, end) = flock_range(file, flock)?;
+    let owner = range_owner(file, ofd);
+    let node = node_of(file)?;
+    let key = key_of(&node);
+    loop {
+        let mut table = LOCK_TABLE.lock();
+        let inode = table
+            .inodes
+            .entry(key)
+            .or_insert_with(|| InodeLocks::new(node.clone()));
+        inode.prune();
+        let write = match write {
+            Some(write) => write,
+            None => {
+                inode.set_range(owner, None, start, end);
+                inode.wait_queue.wake_all();
+                if inode.is_empty() {
+                    table.inodes.remove(&key);
+                }
+                return Ok(());
+            }
+        };
+        let holder = match inode.range_conflict(&owner, write, start, end) {
+            Some(lock) => lock.owner.clone(),
+            None => {
+                inode.set_range(owner, Some(write), start, end);
+                return Ok(());
+            }
+        };
+        if !block {
+            return Err(EAGAIN);
+        }
+        // OFD locks are not checked for deadlocks, like on Linux
+        let waiting = match (&owner, &holder) {
+            (LockOwner::Process(tgid), LockOwner::Process(holder)) => {
+                if table.would_deadlock(*tgid, *holder) {
+                    return Err(EDEADLK);
+                }
+                table.waiting_for.insert(*tgid, *holder);
+                Some(*tgid)
+            }
+            _ => None,
+        };
+        let result = wait(table, key);
+        if let Some(tgid) = waiting {
+            LOCK_TABLE.lock().waiting_for.remove(&tgid);
+        }
+        result?;
+    }
+}
+
+/// `flock()`, see `LOCK_SH` and the other operations.
+/// Converting a lock is not atomic, the old one is released before the new one is waited for.
+pub fn flock(file: &Arc<dyn File>, operation: u32) -> Result<(), isize> {
+    let exclusive = match operation & !LOCK_NB {
+        LOCK_SH => Some(false),
+        LOCK_EX => Some(true),
+        LOCK_UN => None,
+        _ => return Err(EINVAL),
+    };
+    let owner = LockOwner::File(Arc::downgrade(file));
+    let node = node_of(file)?;
+    let key = key_of(&node);
+    loop {
+        let mut table = LOCK_TABLE.lock();
+        let inode = table
+            .inodes
+            .entry(key)
+            .or_insert_with(|| InodeLocks::new(node.clone()));
+        inode.prune();
+        let held = inode
+            .flocks
+            .iter()
+            .position(|(holder, _)| holder.is(&owner));
+        if let Some(idx) = held {
+            if Some(inode.flocks[idx].1) == exclusive {
+                return Ok(());
+            }
+            inode.flocks.remove(idx);
+            inode.wait_queue.wake_all();
+        }
+        let exclusive = match exclusive {
+            Some(exclusive) => exclusive,
+            None => {
+                if inode.is_empty() {
+                    table.inodes.remove(&key);
+                }
+                return Ok(());
+            }
+        };
+        if inode
+            .flocks
+            .iter()
+            .all(|(_, held_exclusive)| !exclusive && !held_exclusive)
+        {
+            inode.flocks.push((owner, exclusive));
+            return Ok(());
+        }
+        if operation & LOCK_NB != 0 {
+            return Err(EWOULDBLOCK);
+        }
+        wait(table, key)?;
+    }
+}
+
+/// Release the locks that go away when a file descriptor of process `tgid` referring to `file` is closed:
+/// all POSIX locks of the process on the inode, and the OFD and `flock()` locks of `file`
+/// if this descriptor was the last reference to it.
+/// Must be called before the file descriptor is dropped.
+pub fn release_on_close(tgid: usize, file: &Arc<dyn File>) {
+    let mut table = LOCK_TABLE.lock();
+    if table.inodes.is_empty() {
+        return;
+    }
+    let node = match file.get_dirtree_node() {
+        Some(node) => node,
+        None => return,
+    };
+    let key = key_of(&node);
+    let inode = match table.inodes.get_mut(&key) {
+        Some(inode) => inode,
+        None => return,
+    };
+    let process = LockOwner::Process(tgid);
+    let description = match Arc::strong_count(file) {
+        1 => Some(LockOwner::File(Arc::downgrade(file))),
+        _ => None,
+    };
+    let released = |owner: &LockOwner| {
+        owner.is(&process) || description.as_ref().map_or(false, |file| owner.is(file))
+    };
+    inode.ranges.retain(|lock| !released(&lock.owner));
+    inode.flocks.retain(|(owner, _)| !released(owner));
+    inode.prune();
+    inode.wait_queue.wake_all();
+    if inode.is_empty() {
+        table.inodes.remove(&key);
+    }
+}
@@ -1,13 +1,21 @@
 mod cache;
 pub mod dev;
 pub mod directory_tree;
+#[cfg(feature = "lwext4")]
+pub mod ext4;
+#[cfg(feature = "ext4rs")]
+pub mod ext4rs;
 pub mod fat32;
 pub mod file_trait;
 mod filesystem;
 mod layout;
+pub mod locks;
 pub mod poll;
+pub mod procfs;
 #[cfg(feature = "swap")]
 pub mod swap;
+pub mod tmpfs;
+pub mod writeback;
 
 pub use self::dev::{
     hwclock::*, 
@@ -16,7 +24,6 @@ pub use self::dev::{
     // socket::*, tty::*, zero::*
 };
 use core::slice::{Iter, IterMut};
-// use crate::fs::ext4::Ext4FileSystem;
 
 pub use self::layout::*;
 
@@ -27,6 +34,7 @@ use crate::{
     config::SYSTEM_FD_LIMIT,
     mm::{Frame, UserBuffer},
     syscall::errno::*,
+    task::{current_cred, Access, Credentials},
 };
 use alloc::{
     string::{String, ToString},
@@ -41,7 +49,7 @@ lazy_static! {
         false,
         false,
         self::directory_tree::ROOT
-            .open(".", OpenFlags::O_RDONLY | OpenFlags::O_DIRECTORY, true)
+            .open(".", OpenFlags::O_RDONLY | OpenFlags::O_DIRECTORY, 0, true)
             .unwrap()
     ));
 }
@@ -60,7 +68,9 @@ pub fn flush_preload() {
         sbash as usize,
         ebash as usize,
     );
-    let initproc = ROOT_FD.open("initproc", OpenFlags::O_CREAT, false).unwrap();
+    let initproc = ROOT_FD
+        .open_with_mode("initproc", OpenFlags::O_CREAT, 0o755, false)
+        .unwrap();
     initproc.write(None, unsafe {
         core::slice::from_raw_parts(
             sinitproc as *const u8,
@@ -73,7 +83,9 @@ pub fn flush_preload() {
     ) {
         crate::mm::frame_dealloc(ppn);
     }
-    let bash = ROOT_FD.open("bash", OpenFlags::O_CREAT, false).unwrap();
+    let bash = ROOT_FD
+        .open_with_mode("bash", OpenFlags::O_CREAT, 0o755, false)
+        .unwrap();
     bash.write(None, unsafe {
         core::slice::from_raw_parts(sbash as *const u8, ebash as usize - sbash as usize)
     });
@@ -84,6 +96,11 @@ pub fn flush_preload() {
         crate::mm::frame_dealloc(ppn);
     }
 }
+/// Longest name of an extended attribute, namespace included
+pub const XATTR_NAME_MAX: usize = 255;
+/// Largest value of an extended attribute
+pub const XATTR_SIZE_MAX: usize = 65536;
+
 #[derive(Clone)]
 pub struct FileDescriptor {
     cloexec: bool,
@@ -118,13 +135,141 @@ impl FileDescriptor {
         };
         Some(inode.get_cwd())
     }
-    /// Just used for cwd
+    /// Just used for cwd, which needs search permission rather than read permission
     pub fn cd(&self, path: &str) -> Result<Arc<Self>, isize> {
-        match self.open(path, OpenFlags::O_DIRECTORY | OpenFlags::O_RDONLY, true) {
-            Ok(fd) => Ok(Arc::new(fd)),
+        match self.open(path, OpenFlags::O_DIRECTORY | OpenFlags::O_PATH, true) {
+            Ok(fd) => match fd.check_access(&current_cred(), Access::EXEC) {
+                Ok(_) => Ok(Arc::new(fd)),
+                Err(errno) => Err(errno),
+            },
             Err(errno) => Err(errno),
         }
     }
+    /// Check `access` on this file for `cred`, `EACCES` if denied
+    pub fn check_access(&self, cred: &Credentials, access: Access) -> Result<(), isize> {
+        if cred.may_access(&self.file.get_stat(), access) {
+            Ok(())
+        } else {
+            Err(EACCES)
+        }
+    }
+    /// Only the owner or root may change the mode.
+    /// Like Linux, the set-group-ID bit is dropped if the caller isn't in the group of the file.
+    pub fn chmod(&self, mode: u32) -> Result<(), isize> {
+        let cred = current_cred();
+        let stat = self.file.get_stat();
+        if !cred.is_root() && cred.euid != stat.get_uid() {
+            return Err(EPERM);
+        }
+        let mut mode = mode & 0o7777;
+        if !cred.is_root() && !cred.in_group(stat.get_gid()) {
+            mode &= !StatMode::S_ISGID.bits();
+        }
+        if self.mount_flags().contains(MountFlags::MS_RDONLY) {
+            return Err(EROFS);
+        }
+        self.file.chmod(mode)
+    }
+    /// Only root may give a file away, the owner may pick one of its groups.
+    /// `None` leaves an id unchanged. A change clears the set-user-ID and set-group-ID bits.
+    pub fn chown(&self, uid: Option<u32>, gid: Option<u32>) -> Result<(), isize> {
+        let cred = current_cred();
+        let stat = self.file.get_stat();
+        if !cred.is_root() {
+            if uid.map_or(false, |uid| uid != stat.get_uid()) {
+                return Err(EPERM);
+            }
+            if gid.map_or(false, |gid| {
+                cred.euid != stat.get_uid() || !cred.in_group(gid)
+            }) {
+                return Err(EPERM);
+            }
+        }
+        if self.mount_flags().contains(MountFlags::MS_RDONLY) {
+            return Err(EROFS);
+        }
+        if uid.is_none() && gid.is_none() {
+            return Ok(());
+        }
+        self.file.chown(uid, gid)?;
+        let mode = stat.get_mode();
+        if !self.file.is_dir() && mode & (StatMode::S_ISUID | StatMode::S_ISGID).bits() != 0 {
+            self.file
+                .chmod(mode & 0o7777 & !(StatMode::S_ISUID | StatMode::S_ISGID).bits())?;
+        }
+        Ok(())
+    }
+    /// Check whether the caller may read, or with `write` set or remove, the extended attribute `name`.
+    /// `trusted.` attributes are for root only, `security.` ones may only be changed by root,
+    /// `user.` ones follow the permission bits and only exist on regular files and directories.
+    fn xattr_permission(&self, name: &str, write: bool) -> Result<(), isize> {
+        let namespace = match name.find('.') {
+            Some(idx) => &name[..=idx],
+            None => return Err(ENOTSUP),
+        };
+        if name.len() > XATTR_NAME_MAX {
+            return Err(ERANGE);
+        }
+        if name.len() == namespace.len() {
+            return Err(EINVAL);
+        }
+        let cred = current_cred();
+        match namespace {
+            "trusted." | "security." if !cred.is_root() && (write || namespace == "trusted.") => {
+                return Err(EPERM)
+            }
+            "trusted." | "security." => {}
+            "user." => {
+                let stat = self.file.get_stat();
+                let mode = stat.get_mode();
+                let file_type = mode & StatMode::S_IFMT.bits();
+                if file_type != StatMode::S_IFREG.bits() && file_type != StatMode::S_IFDIR.bits() {
+                    return Err(if write { EPERM } else { ENODATA });
+                }
+                // like unlinking, only the owner may change the attributes of a sticky directory
+                if write
+                    && file_type == StatMode::S_IFDIR.bits()
+                    && mode & StatMode::S_ISVTX.bits() != 0
+                    && !cred.is_root()
+                    && cred.euid != stat.get_uid()
+                {
+                    return Err(EPERM);
+                }
+                let access = if write { Access::WRITE } else { Access::READ };
+                self.check_access(&cred, access)?;
+            }
+            _ => return Err(ENOTSUP),
+        }
+        if write && self.mount_flags().contains(MountFlags::MS_RDONLY) {
+            return Err(EROFS);
+        }
+        Ok(())
+    }
+    pub fn get_xattr(&self, name: &str) -> Result<Vec<u8>, isize> {
+        self.xattr_permission(name, false)?;
+        self.file.get_xattr(name)
+    }
+    pub fn set_xattr(&self, name: &str, value: &[u8], flags: XattrFlags) -> Result<(), isize> {
+        if value.len() > XATTR_SIZE_MAX {
+            return Err(E2BIG);
+        }
+        self.xattr_permission(name, true)?;
+        self.file.set_xattr(name, value, flags)
+    }
+    /// Names of the extended attributes, those the caller can't read are left out
+    pub fn list_xattr(&self) -> Result<Vec<String>, isize> {
+        let is_root = current_cred().is_root();
+        Ok(self
+            .file
+            .list_xattr()?
+            .into_iter()
+            .filter(|name| is_root || !name.starts_with("trusted."))
+            .collect())
+    }
+    pub fn remove_xattr(&self, name: &str) -> Result<(), isize> {
+        self.xattr_permission(name, true)?;
+        self.file.remove_xattr(name)
+    }
     pub fn readable(&self) -> bool {
         self.file.readable()
     }
@@ -154,7 +299,7 @@ impl FileDescriptor {
     }
     pub fn get_statx(&self, mask: u32) -> Statx {
         let stat = self.file.get_stat();
-        Statx::new(
+        let mut statx = Statx::new(
             mask,
             stat.get_nlink(),
             stat.get_mode() as u16,
@@ -167,9 +312,23 @@ impl FileDescriptor {
             (stat.get_rdev() & 0xff) as u32,
             (stat.get_dev() & 0xffff_00) >> 8 as u32,
             (stat.get_dev() & 0xff) as u32,
-        )
+        );
+        statx.stx_uid = stat.get_uid();
+        statx.stx_gid = stat.get_gid();
+        statx
     }
+    /// Files created by `O_CREAT` are given mode 0o666
     pub fn open(&self, path: &str, flags: OpenFlags, special_use: bool) -> Result<Self, isize> {
+        self.open_with_mode(path, flags, 0o666, special_use)
+    }
+    /// `mode` holds the permission bits of a file created by `O_CREAT`, with the umask applied
+    pub fn open_with_mode(
+        &self,
+        path: &str,
+        flags: OpenFlags,
+        mode: u32,
+        special_use: bool,
+    ) -> Result<Self, isize> {
         if path == "" {
             return Ok(self.clone());
         }
@@ -181,14 +340,14 @@ impl FileDescriptor {
             Some(inode) => inode,
             None => return Err(ENOENT),
         };
-        let file = match inode.open(path, flags, special_use) {
+        let file = match inode.open(path, flags, mode, special_use) {
             Ok(file) => file,
             Err(errno) => return Err(errno),
         };
         let cloexec = flags.contains(OpenFlags::O_CLOEXEC);
         Ok(Self::new(cloexec, false, file))
     }
-    pub fn mkdir(&self, path: &str) -> Result<(), isize> {
+    pub fn mkdir(&self, path: &str, mode: u32) -> Result<(), isize> {
         if self.file.is_file() && !path.starts_with('/') {
             return Err(ENOTDIR);
         }
@@ -197,7 +356,18 @@ impl FileDescriptor {
             Some(inode) => inode,
             None => return Err(ENOENT),
         };
-        inode.mkdir(path)
+        inode.mkdir(path, mode)
+    }
+    pub fn symlink(&self, target: &str, path: &str) -> Result<(), isize> {
+        if self.file.is_file() && !path.starts_with('/') {
+            return Err(ENOTDIR);
+        }
+        let inode = self.file.get_dirtree_node();
+        let inode = match inode {
+            Some(inode) => inode,
+            None => return Err(ENOENT),
+        };
+        inode.symlink(target, path)
     }
     pub fn delete(&self, path: &str, delete_directory: bool) -> Result<(), isize> {
         if self.file.is_file() && !path.starts_with('/') {
@@ -210,6 +380,42 @@ impl FileDescriptor {
         };
         inode.delete(path, delete_directory)
     }
+    pub fn mount(
+        &self,
+        source: &str,
+        target: &str,
+        fstype: &str,
+        flags: MountFlags,
+        data: &str,
+    ) -> Result<(), isize> {
+        if self.file.is_file() && !target.starts_with('/') {
+            return Err(ENOTDIR);
+        }
+        let inode = self.file.get_dirtree_node();
+        let inode = match inode {
+            Some(inode) => inode,
+            None => return Err(ENOENT),
+        };
+        inode.mount(source, target, fstype, flags, data)
+    }
+    pub fn umount(&self, target: &str, flags: UmountFlags) -> Result<(), isize> {
+        if self.file.is_file() && !target.starts_with('/') {
+            return Err(ENOTDIR);
+        }
+        let inode = self.file.get_dirtree_node();
+        let inode = match inode {
+            Some(inode) => inode,
+            None => return Err(ENOENT),
+        };
+        inode.umount(target, flags)
+    }
+    /// Flags of the mount this file lives on
+    pub fn mount_flags(&self) -> MountFlags {
+        match self.file.get_dirtree_node() {
+            Some(inode) => inode.mount_flags(),
+            None => MountFlags::empty(),
+        }
+    }
     pub fn rename(
         old_fd: &Self,
         old_path: &str,
@@ -237,6 +443,34 @@ impl FileDescriptor {
         let new_abs_path = [new_inode.get_cwd(), new_path.to_string()].join("/");
         DirectoryTreeNode::rename(&old_abs_path, &new_abs_path)
     }
+    pub fn link(
+        old_fd: &Self,
+        old_path: &str,
+        new_fd: &Self,
+        new_path: &str,
+        follow: bool,
+    ) -> Result<(), isize> {
+        if old_fd.file.is_file() && !old_path.starts_with('/') {
+            return Err(ENOTDIR);
+        }
+        if new_fd.file.is_file() && !new_path.starts_with('/') {
+            return Err(ENOTDIR);
+        }
+        let old_inode = old_fd.file.get_dirtree_node();
+        let old_inode = match old_inode {
+            Some(inode) => inode,
+            None => return Err(ENOENT),
+        };
+        let new_inode = new_fd.file.get_dirtree_node();
+        let new_inode = match new_inode {
+            Some(inode) => inode,
+            None => return Err(ENOENT),
+        };
+
+        let old_abs_path = [old_inode.get_cwd(), old_path.to_string()].join("/");
+        let new_abs_path = [new_inode.get_cwd(), new_path.to_string()].join("/");
+        DirectoryTreeNode::link(&old_abs_path, &new_abs_path, follow)
+    }
     pub fn get_dirent(&self, count: usize) -> Result<Vec<Dirent>, isize> {
         if !self.file.is_dir() {
             return Err(ENOTDIR);
@@ -259,9 +493,21 @@ impl FileDescriptor {
         if new_size < 0 || !self.writable() {
             return Err(EINVAL);
         }
-        // todo: support ETXTBSY
+        if let Err(errno) = self.deny_write() {
+            return Err(errno);
+        }
         self.file.truncate_size(new_size as usize)
     }
+    /// See `DirectoryTreeNode::deny_write()`, for files opened before they were executed or swapped on
+    pub fn deny_write(&self) -> Result<(), isize> {
+        if !self.file.is_file() {
+            return Ok(());
+        }
+        match self.file.get_dirtree_node() {
+            Some(inode) => inode.deny_write(),
+            None => Ok(()),
+        }
+    }
     pub fn set_timestamp(
         &self,
         ctime: Option<usize>,
@@ -271,6 +517,9 @@ impl FileDescriptor {
         self.file.set_timestamp(ctime, atime, mtime);
         Ok(())
     }
+    pub fn fsync(&self) -> Result<(), isize> {
+        self.file.fsync()
+    }
     pub fn get_single_cache(&self, offset: usize) -> Result<Arc<Mutex<PageCache>>, ()> {
         self.file.get_single_cache(offset)
     }
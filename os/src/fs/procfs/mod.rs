@@ -0,0 +1,632 @@
+//! A synthetic filesystem mounted on `/proc`.
+//! Nothing is stored, files are generated from kernel state when they are opened
+//! and directories list their entries again on every lookup.
+mod pid;
+mod sys;
+mod sysctl;
+#[cfg(feature = "zram")]
+mod zram;
+
+use alloc::{
+    string::{String, ToString},
+    sync::{Arc, Weak},
+    vec,
+    vec::Vec,
+};
+use core::sync::atomic::{AtomicUsize, Ordering};
+use spin::Mutex;
+
+use crate::{
+    arch::TICKS_PER_SEC,
+    fs::{
+        cache::PageCache, directory_tree::DirectoryTreeNode, file_trait::File, layout::*,
+        DiskInodeType,
+    },
+    mm::UserBuffer,
+    syscall::errno::*,
+    task::{all_tasks, current_task},
+    timer::USEC_PER_SEC,
+};
+
+const PROC_ROOT_INO: usize = 1;
+
+/// The inode number of the entry `name` of the directory numbered `parent`.
+/// Files are generated again on every lookup, so the number is derived from where they are,
+/// which keeps it the same for as long as the entry exists, e.g. `/proc/<pid>/maps`.
+fn entry_ino(parent: usize, name: &str) -> usize {
+    // FNV-1a
+    let hash = parent
+        .to_ne_bytes()
+        .iter()
+        .chain(name.as_bytes())
+        .fold(0xcbf29ce484222325usize, |hash, byte| {
+            (hash ^ *byte as usize).wrapping_mul(0x100000001b3)
+        });
+    hash % (usize::MAX - PROC_ROOT_INO) + PROC_ROOT_INO + 1
+}
+
+/// Give `file`, listed as `name` in the directory numbered `parent`, its inode number
+fn assign_ino(parent: usize, name: &str, file: &Arc<dyn File>) {
+    let ino = if let Some(file) = file.downcast_ref::<ProcFile>() {
+        &file.ino
+    } else if let Some(file) = file.downcast_ref::<ProcDir>() {
+        &file.ino
+    } else if let Some(file) = file.downcast_ref::<ProcLink>() {
+        &file.ino
+    } else {
+        return;
+    };
+    ino.store(entry_ino(parent, name), Ordering::Relaxed);
+}
+
+/// Convert microseconds into clock ticks, the unit of times in procfs
+fn to_clock_ticks(us: usize) -> usize {
+    us / (USEC_PER_SEC / TICKS_PER_SEC)
+}
+
+fn proc_stat(ino: usize, mode: u32, nlink: u32) -> Stat {
+    Stat::new(
+        crate::makedev!(0, 4),
+        ino as u64,
+        mode,
+        nlink,
+        0,
+        0,
+        0,
+        0,
+        0,
+    )
+}
+
+/// The root directory of procfs
+pub fn root() -> Arc<dyn File> {
+    ProcDir::with_ino(PROC_ROOT_INO, || {
+        let mut entries: Vec<(String, Arc<dyn File>)> = Vec::new();
+        entries.push(("meminfo".to_string(), ProcFile::new(sys::meminfo)));
+        entries.push((
+            "mounts".to_string(),
+            ProcFile::new(super::directory_tree::mounts_info),
+        ));
+        entries.push(("cpuinfo".to_string(), ProcFile::new(sys::cpuinfo)));
+        entries.push(("uptime".to_string(), ProcFile::new(sys::uptime)));
+        entries.push(("loadavg".to_string(), ProcFile::new(sys::loadavg)));
+        entries.push(("stat".to_string(), ProcFile::new(sys::stat)));
+        entries.push(("vmstat".to_string(), ProcFile::new(sys::vmstat)));
+        #[cfg(feature = "swap")]
+        entries.push(("swaps".to_string(), ProcFile::new(super::swap::swaps)));
+        #[cfg(feature = "zram")]
+        entries.push(("zram0".to_string(), ProcDir::new(zram::zram_dir)));
+        entries.push(("sys".to_string(), sysctl::root()));
+        if let Some(task) = current_task() {
+            entries.push(("self".to_string(), pid::pid_dir(task.tgid)));
+        }
+        for task in all_tasks() {
+            // threads are listed under their process
+            if task.pid.0 == task.tgid {
+                entries.push((task.tgid.to_string(), pid::pid_dir(task.tgid)));
+            }
+        }
+        entries
+    })
+}
+
+/// A file under `/proc`.
+/// Its content is produced by `generator` when the file is opened,
+/// so every open file description reads a consistent snapshot.
+/// Files with a `setter` can be written, it gets what was written in a single `write()`.
+/// Files with an `owner` can only be read by it, see `ProcFile::owner_only`.
+pub struct ProcFile {
+    generator: Arc<dyn Fn() -> String + Send + Sync>,
+    setter: Option<Arc<dyn Fn(&str) -> Result<(), isize> + Send + Sync>>,
+    owner: Option<Arc<dyn Fn() -> (u32, u32) + Send + Sync>>,
+    /// Set by the directory listing the file, see `assign_ino`
+    ino: AtomicUsize,
+    content: Vec<u8>,
+    offset: Mutex<usize>,
+    dirnode_ptr: Arc<Mutex<Weak<DirectoryTreeNode>>>,
+}
+
+impl ProcFile {
+    pub fn new(generator: impl Fn() -> String + Send + Sync + 'static) -> Arc<Self> {
+        Arc::new(Self {
+            generator: Arc::new(generator),
+            setter: None,
+            owner: None,
+            ino: AtomicUsize::new(0),
+            content: Vec::new(),
+            offset: Mutex::new(0),
+            dirnode_ptr: Arc::new(Mutex::new(Weak::new())),
+        })
+    }
+    pub fn with_setter(
+        generator: impl Fn() -> String + Send + Sync + 'static,
+        setter: impl Fn(&str) -> Result<(), isize> + Send + Sync + 'static,
+    ) -> Arc<Self> {
+        Arc::new(Self {
+            generator: Arc::new(generator),
+            setter: Some(Arc::new(setter)),
+            owner: None,
+            ino: AtomicUsize::new(0),
+            content: Vec::new(),
+            offset: Mutex::new(0),
+            dirnode_ptr: Arc::new(Mutex::new(Weak::new())),
+        })
+    }
+    /// A read-only file with mode 0400, `owner` gives its user and group
+    pub fn owner_only(
+        generator: impl Fn() -> String + Send + Sync + 'static,
+        owner: impl Fn() -> (u32, u32) + Send + Sync + 'static,
+    ) -> Arc<Self> {
+        Arc::new(Self {
+            generator: Arc::new(generator),
+            setter: None,
+            owner: Some(Arc::new(owner)),
+            ino: AtomicUsize::new(0),
+            content: Vec::new(),
+            offset: Mutex::new(0),
+            dirnode_ptr: Arc::new(Mutex::new(Weak::new())),
+        })
+    }
+}
+
+#[allow(unused)]
+impl File for ProcFile {
+    fn deep_clone(&self) -> Arc<dyn File> {
+        Arc::new(Self {
+            generator: self.generator.clone(),
+            setter: self.setter.clone(),
+            owner: self.owner.clone(),
+            ino: AtomicUsize::new(self.ino.load(Ordering::Relaxed)),
+            content: self.content.clone(),
+            offset: Mutex::new(*self.offset.lock()),
+            dirnode_ptr: self.dirnode_ptr.clone(),
+        })
+    }
+    fn readable(&self) -> bool {
+        true
+    }
+    fn writable(&self) -> bool {
+        self.setter.is_some()
+    }
+    fn read(&self, offset: Option<&mut usize>, buf: &mut [u8]) -> usize {
+        let mut offset_lock = self.offset.lock();
+        let offset = match offset {
+            Some(offset) => offset,
+            None => &mut *offset_lock,
+        };
+        let start = (*offset).min(self.content.len());
+        let len = buf.len().min(self.content.len() - start);
+        buf[..len].copy_from_slice(&self.content[start..start + len]);
+        *offset = start + len;
+        len
+    }
+    /// The offset is ignored, nothing is written if `setter` refuses the value
+    fn write(&self, offset: Option<&mut usize>, buf: &[u8]) -> usize {
+        let setter = match &self.setter {
+            Some(setter) => setter,
+            None => return 0,
+        };
+        match core::str::from_utf8(buf) {
+            Ok(value) if setter(value).is_ok() => buf.len(),
+            _ => 0,
+        }
+    }
+    fn r_ready(&self) -> bool {
+        true
+    }
+    fn w_ready(&self) -> bool {
+        self.setter.is_some()
+    }
+    fn read_user(&self, offset: Option<usize>, mut buf: UserBuffer) -> usize {
+        let mut offset_lock = self.offset.lock();
+        let use_file_offset = offset.is_none();
+        let start = offset.unwrap_or(*offset_lock).min(self.content.len());
+        let len = buf.write(&self.content[start..]);
+        if use_file_offset {
+            *offset_lock = start + len;
+        }
+        len
+    }
+    fn write_user(&self, offset: Option<usize>, buf: UserBuffer) -> usize {
+        let mut value = vec![0u8; buf.len()];
+        buf.read(&mut value);
+        self.write(None, &value)
+    }
+    /// Like Linux, procfs files report a size of 0
+    fn get_size(&self) -> usize {
+        0
+    }
+    fn get_stat(&self) -> Stat {
+        let ino = self.ino.load(Ordering::Relaxed);
+        if let Some(owner) = &self.owner {
+            let (uid, gid) = owner();
+            let mut stat = proc_stat(ino, StatMode::S_IFREG.bits() | 0o400, 1);
+            stat.set_owner(uid, gid);
+            return stat;
+        }
+        let perm = match self.setter {
+            Some(_) => 0o644,
+            None => 0o444,
+        };
+        proc_stat(ino, StatMode::S_IFREG.bits() | perm, 1)
+    }
+    fn get_file_type(&self) -> DiskInodeType {
+        DiskInodeType::File
+    }
+    fn info_dirtree_node(&self, dirnode_ptr: Weak<DirectoryTreeNode>) {
+        *self.dirnode_ptr.lock() = dirnode_ptr;
+    }
+    fn get_dirtree_node(&self) -> Option<Arc<DirectoryTreeNode>> {
+        self.dirnode_ptr.lock().upgrade()
+    }
+    fn open(&self, flags: OpenFlags, special_use: bool) -> Arc<dyn File> {
+        Arc::new(Self {
+            generator: self.generator.clone(),
+            setter: self.setter.clone(),
+            owner: self.owner.clone(),
+            ino: AtomicUsize::new(self.ino.load(Ordering::Relaxed)),
+            content: (self.generator)().into_bytes(),
+            offset: Mutex::new(0),
+            dirnode_ptr: self.dirnode_ptr.clone(),
+        })
+    }
+    fn open_subfile(&self) -> Result<Vec<(String, Arc<dyn File>)>, isize> {
+        Err(ENOTDIR)
+    }
+    fn create(&self, name: &str, file_type: DiskInodeType) -> Result<Arc<dyn File>, isize> {
+        Err(ENOTDIR)
+    }
+    fn link_child(&self, name: &str, child: &Self) -> Result<(), isize>
+    where
+        Self: Sized,
+    {
+        Err(ENOTDIR)
+    }
+    fn unlink(&self, delete: bool) -> Result<(), isize> {
+        Err(EPERM)
+    }
+    fn get_dirent(&self, count: usize) -> Vec<Dirent> {
+        Vec::new()
+    }
+    fn lseek(&self, offset: isize, whence: SeekWhence) -> Result<usize, isize> {
+        let mut offset_lock = self.offset.lock();
+        let new_offset = match whence {
+            SeekWhence::SEEK_SET => offset,
+            SeekWhence::SEEK_CUR => *offset_lock as isize + offset,
+            SeekWhence::SEEK_END => self.content.len() as isize + offset,
+            // whence is duplicated
+            _ => return Err(EINVAL),
+        };
+        if new_offset < 0 {
+            return Err(EINVAL);
+        }
+        *offset_lock = new_offset as usize;
+        Ok(new_offset as usize)
+    }
+    fn modify_size(&self, diff: isize) -> Result<(), isize> {
+        Err(EPERM)
+    }
+    fn truncate_size(&self, new_size: usize) -> Result<(), isize> {
+        Err(EPERM)
+    }
+    fn set_timestamp(&self, ctime: Option<usize>, atime: Option<usize>, mtime: Option<usize>) {}
+    fn get_single_cache(&self, offset: usize) -> Result<Arc<Mutex<PageCache>>, ()> {
+        Err(())
+    }
+    fn get_all_caches(&self) -> Result<Vec<Arc<Mutex<PageCache>>>, ()> {
+        Err(())
+    }
+    fn oom(&self) -> usize {
+        0
+    }
+    fn hang_up(&self) -> bool {
+        false
+    }
+    fn fcntl(&self, cmd: u32, arg: u32) -> isize {
+        EINVAL
+    }
+}
+
+/// A directory under `/proc` whose entries are listed by `entries`.
+/// Processes come and go, so the directory tree never caches them, see `FileSystem::is_volatile`.
+pub struct ProcDir {
+    entries: Arc<dyn Fn() -> Vec<(String, Arc<dyn File>)> + Send + Sync>,
+    /// Set by the directory listing this one, see `assign_ino`
+    ino: AtomicUsize,
+    offset: Mutex<usize>,
+    dirnode_ptr: Arc<Mutex<Weak<DirectoryTreeNode>>>,
+}
+
+impl ProcDir {
+    pub fn new(
+        entries: impl Fn() -> Vec<(String, Arc<dyn File>)> + Send + Sync + 'static,
+    ) -> Arc<Self> {
+        Self::with_ino(0, entries)
+    }
+    fn with_ino(
+        ino: usize,
+        entries: impl Fn() -> Vec<(String, Arc<dyn File>)> + Send + Sync + 'static,
+    ) -> Arc<Self> {
+        Arc::new(Self {
+            entries: Arc::new(entries),
+            ino: AtomicUsize::new(ino),
+            offset: Mutex::new(0),
+            dirnode_ptr: Arc::new(Mutex::new(Weak::new())),
+        })
+    }
+    /// The entries, numbered after this directory
+    fn list(&self) -> Vec<(String, Arc<dyn File>)> {
+        let ino = self.ino.load(Ordering::Relaxed);
+        let entries = (self.entries)();
+        for (name, file) in entries.iter() {
+            assign_ino(ino, name, file);
+        }
+        entries
+    }
+}
+
+#[allow(unused)]
+impl File for ProcDir {
+    fn deep_clone(&self) -> Arc<dyn File> {
+        Arc::new(Self {
+            entries: self.entries.clone(),
+            ino: AtomicUsize::new(self.ino.load(Ordering::Relaxed)),
+            offset: Mutex::new(*self.offset.lock()),
+            dirnode_ptr: self.dirnode_ptr.clone(),
+        })
+    }
+    fn readable(&self) -> bool {
+        true
+    }
+    fn writable(&self) -> bool {
+        false
+    }
+    fn read(&self, offset: Option<&mut usize>, buf: &mut [u8]) -> usize {
+        0
+    }
+    fn write(&self, offset: Option<&mut usize>, buf: &[u8]) -> usize {
+        0
+    }
+    fn r_ready(&self) -> bool {
+        true
+    }
+    fn w_ready(&self) -> bool {
+        false
+    }
+    fn read_user(&self, offset: Option<usize>, buf: UserBuffer) -> usize {
+        0
+    }
+    fn write_user(&self, offset: Option<usize>, buf: UserBuffer) -> usize {
+        0
+    }
+    fn get_size(&self) -> usize {
+        0
+    }
+    fn get_stat(&self) -> Stat {
+        proc_stat(
+            self.ino.load(Ordering::Relaxed),
+            StatMode::S_IFDIR.bits() | 0o555,
+            2,
+        )
+    }
+    fn get_file_type(&self) -> DiskInodeType {
+        DiskInodeType::Directory
+    }
+    fn info_dirtree_node(&self, dirnode_ptr: Weak<DirectoryTreeNode>) {
+        *self.dirnode_ptr.lock() = dirnode_ptr;
+    }
+    fn get_dirtree_node(&self) -> Option<Arc<DirectoryTreeNode>> {
+        self.dirnode_ptr.lock().upgrade()
+    }
+    fn open(&self, flags: OpenFlags, special_use: bool) -> Arc<dyn File> {
+        Arc::new(Self {
+            entries: self.entries.clone(),
+            ino: AtomicUsize::new(self.ino.load(Ordering::Relaxed)),
+            offset: Mutex::new(0),
+            dirnode_ptr: self.dirnode_ptr.clone(),
+        })
+    }
+    fn open_subfile(&self) -> Result<Vec<(String, Arc<dyn File>)>, isize> {
+        Ok(self.list())
+    }
+    fn create(&self, name: &str, file_type: DiskInodeType) -> Result<Arc<dyn File>, isize> {
+        Err(EPERM)
+    }
+    fn link_child(&self, name: &str, child: &Self) -> Result<(), isize>
+    where
+        Self: Sized,
+    {
+        Err(EPERM)
+    }
+    fn unlink(&self, delete: bool) -> Result<(), isize> {
+        Err(EPERM)
+    }
+    fn get_dirent(&self, count: usize) -> Vec<Dirent> {
+        let mut offset = self.offset.lock();
+        let mut dirents = Vec::with_capacity(2);
+        dirents.push((".".to_string(), self.ino.load(Ordering::Relaxed), DT_DIR));
+        dirents.push(("..".to_string(), PROC_ROOT_INO, DT_DIR));
+        for (name, file) in self.list() {
+            let stat = file.get_stat();
+            let d_type = if file.is_dir() {
+                DT_DIR
+            } else if file.is_symlink() {
+                DT_LNK
+            } else {
+                DT_REG
+            };
+            dirents.push((name, stat.get_ino(), d_type));
+        }
+        let start = *offset;
+        let vec: Vec<Dirent> = dirents
+            .iter()
+            .enumerate()
+            .skip(start)
+            .take(count / core::mem::size_of::<Dirent>())
+            .map(|(idx, (name, ino, d_type))| {
+                Dirent::new(*ino, (idx + 1) as isize, *d_type, name.as_str())
+            })
+            .collect();
+        *offset = start + vec.len();
+        vec
+    }
+    fn lseek(&self, offset: isize, whence: SeekWhence) -> Result<usize, isize> {
+        let new_offset = match whence {
+            SeekWhence::SEEK_SET => offset,
+            SeekWhence::SEEK_CUR => *self.offset.lock() as isize + offset,
+            _ => return Err(EINVAL),
+        };
+        if new_offset < 0 {
+            return Err(EINVAL);
+        }
+        *self.offset.lock() = new_offset as usize;
+        Ok(new_offset as usize)
+    }
+    fn modify_size(&self, diff: isize) -> Result<(), isize> {
+        Err(EISDIR)
+    }
+    fn truncate_size(&self, new_size: usize) -> Result<(), isize> {
+        Err(EISDIR)
+    }
+    fn set_timestamp(&self, ctime: Option<usize>, atime: Option<usize>, mtime: Option<usize>) {}
+    fn get_single_cache(&self, offset: usize) -> Result<Arc<Mutex<PageCache>>, ()> {
+        Err(())
+    }
+    fn get_all_caches(&self) -> Result<Vec<Arc<Mutex<PageCache>>>, ()> {
+        Err(())
+    }
+    fn oom(&self) -> usize {
+        0
+    }
+    fn hang_up(&self) -> bool {
+        false
+    }
+    fn fcntl(&self, cmd: u32, arg: u32) -> isize {
+        EINVAL
+    }
+}
+
+/// A link under `/proc`, such as `/proc/<pid>/exe`.
+/// Its target is resolved by `readlink()`, `None` means the process is gone.
+pub struct ProcLink {
+    target: Arc<dyn Fn() -> Option<String> + Send + Sync>,
+    /// Set by the directory listing the link, see `assign_ino`
+    ino: AtomicUsize,
+    dirnode_ptr: Arc<Mutex<Weak<DirectoryTreeNode>>>,
+}
+
+impl ProcLink {
+    pub fn new(target: impl Fn() -> Option<String> + Send + Sync + 'static) -> Arc<Self> {
+        Arc::new(Self {
+            target: Arc::new(target),
+            ino: AtomicUsize::new(0),
+            dirnode_ptr: Arc::new(Mutex::new(Weak::new())),
+        })
+    }
+}
+
+#[allow(unused)]
+impl File for ProcLink {
+    fn deep_clone(&self) -> Arc<dyn File> {
+        Arc::new(Self {
+            target: self.target.clone(),
+            ino: AtomicUsize::new(self.ino.load(Ordering::Relaxed)),
+            dirnode_ptr: self.dirnode_ptr.clone(),
+        })
+    }
+    fn readable(&self) -> bool {
+        false
+    }
+    fn writable(&self) -> bool {
+        false
+    }
+    fn read(&self, offset: Option<&mut usize>, buf: &mut [u8]) -> usize {
+        0
+    }
+    fn write(&self, offset: Option<&mut usize>, buf: &[u8]) -> usize {
+        0
+    }
+    fn r_ready(&self) -> bool {
+        false
+    }
+    fn w_ready(&self) -> bool {
+        false
+    }
+    fn read_user(&self, offset: Option<usize>, buf: UserBuffer) -> usize {
+        0
+    }
+    fn write_user(&self, offset: Option<usize>, buf: UserBuffer) -> usize {
+        0
+    }
+    fn get_size(&self) -> usize {
+        0
+    }
+    fn get_stat(&self) -> Stat {
+        proc_stat(
+            self.ino.load(Ordering::Relaxed),
+            StatMode::S_IFLNK.bits() | 0o777,
+            1,
+        )
+    }
+    fn get_file_type(&self) -> DiskInodeType {
+        DiskInodeType::SymLink
+    }
+    fn info_dirtree_node(&self, dirnode_ptr: Weak<DirectoryTreeNode>) {
+        *self.dirnode_ptr.lock() = dirnode_ptr;
+    }
+    fn get_dirtree_node(&self) -> Option<Arc<DirectoryTreeNode>> {
+        self.dirnode_ptr.lock().upgrade()
+    }
+    fn open(&self, flags: OpenFlags, special_use: bool) -> Arc<dyn File> {
+        self.deep_clone()
+    }
+    fn open_subfile(&self) -> Result<Vec<(String, Arc<dyn File>)>, isize> {
+        Err(ENOTDIR)
+    }
+    fn create(&self, name: &str, file_type: DiskInodeType) -> Result<Arc<dyn File>, isize> {
+        Err(ENOTDIR)
+    }
+    fn link_child(&self, name: &str, child: &Self) -> Result<(), isize>
+    where
+        Self: Sized,
+    {
+        Err(ENOTDIR)
+    }
+    fn read_link(&self) -> Result<String, isize> {
+        match (self.target)() {
+            Some(target) => Ok(target),
+            None => Err(ENOENT),
+        }
+    }
+    fn unlink(&self, delete: bool) -> Result<(), isize> {
+        Err(EPERM)
+    }
+    fn get_dirent(&self, count: usize) -> Vec<Dirent> {
+        Vec::new()
+    }
+    fn lseek(&self, offset: isize, whence: SeekWhence) -> Result<usize, isize> {
+        Err(EINVAL)
+    }
+    fn modify_size(&self, diff: isize) -> Result<(), isize> {
+        Err(EPERM)
+    }
+    fn truncate_size(&self, new_size: usize) -> Result<(), isize> {
+        Err(EPERM)
+    }
+    fn set_timestamp(&self, ctime: Option<usize>, atime: Option<usize>, mtime: Option<usize>) {}
+    fn get_single_cache(&self, offset: usize) -> Result<Arc<Mutex<PageCache>>, ()> {
+        Err(())
+    }
+    fn get_all_caches(&self) -> Result<Vec<Arc<Mutex<PageCache>>>, ()> {
+        Err(())
+    }
+    fn oom(&self) -> usize {
+        0
+    }
+    fn hang_up(&self) -> bool {
+        false
+    }
+    fn fcntl(&self, cmd: u32, arg: u32) -> isize {
+        EINVAL
+    }
+}
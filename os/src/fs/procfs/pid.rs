@@ -0,0 +1,431 @@
+//! Per-process directories, `/proc/<pid>`
+use alloc::{
+    format,
+    string::{String, ToString},
+    sync::Arc,
+    vec::Vec,
+};
+use core::sync::atomic::Ordering;
+
+use super::{to_clock_ticks, ProcDir, ProcFile, ProcLink};
+use crate::{
+    config::PAGE_SIZE,
+    fs::{file_trait::File, tmpfs::TmpfsOSInode, Pipe},
+    mm::MapPermission,
+    syscall::errno::*,
+    task::{
+        all_tasks, current_cred, ptrace::tracer, Credentials, TaskControlBlock, TaskStatus,
+        OOM_SCORE_ADJ_MAX, OOM_SCORE_ADJ_MIN,
+    },
+};
+
+/// The task whose pid is `tgid` and all the threads of its group, zombies included.
+fn find_process(tgid: usize) -> Option<(Arc<TaskControlBlock>, Vec<Arc<TaskControlBlock>>)> {
+    let threads: Vec<_> = all_tasks()
+        .into_iter()
+        .filter(|task| task.tgid == tgid)
+        .collect();
+    threads
+        .iter()
+        .find(|task| task.pid.0 == tgid)
+        .cloned()
+        .map(|leader| (leader, threads))
+}
+
+/// Like Linux, the name of a process is the file name of its executable, cut to 15 bytes.
+fn comm(task: &TaskControlBlock) -> String {
+    let path = task.exe.lock().get_cwd().unwrap_or_default();
+    let name = path.rsplit('/').next().unwrap_or("");
+    name.chars().take(15).collect()
+}
+
+fn state(status: TaskStatus) -> (char, &'static str) {
+    match status {
+        TaskStatus::Ready | TaskStatus::Running => ('R', "running"),
+        TaskStatus::Interruptible => ('S', "sleeping"),
+        TaskStatus::Zombie => ('Z', "zombie"),
+        TaskStatus::Stopped => ('T', "stopped"),
+        TaskStatus::Traced => ('t', "tracing stop"),
+    }
+}
+
+/// Everything `stat` and `status` need, gathered with one lock at a time.
+struct Snapshot {
+    comm: String,
+    state: TaskStatus,
+    ppid: usize,
+    /// The process tracing the leader, 0 if none
+    tracer_pid: usize,
+    pgid: usize,
+    sid: usize,
+    utime: usize,
+    stime: usize,
+    threads: usize,
+    start_time: usize,
+    pages: usize,
+    resident: usize,
+    sigpending: usize,
+    sigmask: usize,
+    exit_signal: usize,
+    exit_code: u32,
+    cred: Credentials,
+    priority: isize,
+    nice: i32,
+    rt_priority: u32,
+    policy: u32,
+    /// The core the process runs on, or ran on last
+    processor: usize,
+}
+
+fn snapshot(tgid: usize) -> Option<Snapshot> {
+    let (leader, threads) = find_process(tgid)?;
+    let (utime, stime) = threads.iter().fold((0, 0), |(utime, stime), task| {
+        let rusage = task.acquire_inner_lock().rusage;
+        (
+            utime + to_clock_ticks(rusage.ru_utime.to_us()),
+            stime + to_clock_ticks(rusage.ru_stime.to_us()),
+        )
+    });
+    let alive = threads
+        .iter()
+        .filter(|task| !task.acquire_inner_lock().is_zombie())
+        .count();
+    let (pages, resident) = leader.vm.lock().user_page_usage();
+    let cred = leader.cred.lock().clone();
+    let sched = leader.sched.lock().clone();
+    let tracer_pid = tracer(&leader).map_or(0, |tracer| tracer.tgid);
+    let inner = leader.acquire_inner_lock();
+    Some(Snapshot {
+        comm: comm(&leader),
+        state: inner.task_status,
+        ppid: inner
+            .parent
+            .as_ref()
+            .and_then(|parent| parent.upgrade())
+            .map_or(0, |parent| parent.tgid),
+        tracer_pid,
+        pgid: inner.pgid,
+        sid: inner.sid,
+        utime,
+        stime,
+        threads: alive.max(1),
+        start_time: to_clock_ticks(leader.start_time.to_us()),
+        pages,
+        resident,
+        sigpending: inner.sigpending.bits() as usize,
+        sigmask: inner.sigmask.bits() as usize,
+        exit_signal: leader.exit_signal.to_signum().unwrap_or(0),
+        exit_code: inner.exit_code,
+        cred,
+        priority: sched.prio(),
+        nice: sched.nice,
+        rt_priority: sched.rt_priority,
+        policy: sched.policy.raw(),
+        processor: sched.cpu,
+    })
+}
+
+fn stat(tgid: usize) -> String {
+    let s = match snapshot(tgid) {
+        Some(snapshot) => snapshot,
+        None => return String::new(),
+    };
+    // see proc(5) for the meaning of each field, the ones we don't track are 0
+    format!(
+        "{} ({}) {} {} {} {} 0 -1 0 0 0 0 0 {} {} 0 0 {} {} {} 0 {} {} {} \
+         18446744073709551615 0 0 0 0 0 {} {} 0 0 0 0 0 {} {} {} {} 0 0 0 0 0 0 0 0 0 0 {}\n",
+        tgid,
+        s.comm,
+        state(s.state).0,
+        s.ppid,
+        s.pgid,
+        s.sid,
+        s.utime,
+        s.stime,
+        s.priority,
+        s.nice,
+        s.threads,
+        s.start_time,
+        s.pages * PAGE_SIZE,
+        s.resident,
+        s.sigpending,
+        s.sigmask,
+        s.exit_signal,
+        s.processor,
+        s.rt_priority,
+        s.policy,
+        s.exit_code
+    )
+}
+
+fn statm(tgid: usize) -> String {
+    match snapshot(tgid) {
+        Some(s) => format!("{} {} 0 0 0 0 0\n", s.pages, s.resident),
+        None => String::new(),
+    }
+}
+
+fn status(tgid: usize) -> String {
+    let s = match snapshot(tgid) {
+        Some(snapshot) => snapshot,
+        None => return String::new(),
+    };
+    let (state, description) = state(s.state);
+    let groups: String = s
+        .cred
+        .groups
+        .iter()
+        .map(|gid| format!("{} ", gid))
+        .collect();
+    // there are no filesystem ids, the last column repeats the effective one
+    format!(
+        "Name:\t{}\n\
+         State:\t{} ({})\n\
+         Tgid:\t{}\n\
+         Pid:\t{}\n\
+         PPid:\t{}\n\
+         TracerPid:\t{}\n\
+         Uid:\t{}\t{}\t{}\t{}\n\
+         Gid:\t{}\t{}\t{}\t{}\n\
+         Groups:\t{}\n\
+         VmSize:\t{:>8} kB\n\
+         VmRSS:\t{:>8} kB\n\
+         Threads:\t{}\n\
+         SigPnd:\t{:016x}\n\
+         ShdPnd:\t{:016x}\n\
+         SigBlk:\t{:016x}\n",
+        s.comm,
+        state,
+        description,
+        tgid,
+        tgid,
+        s.ppid,
+        s.tracer_pid,
+        s.cred.uid,
+        s.cred.euid,
+        s.cred.suid,
+        s.cred.euid,
+        s.cred.gid,
+        s.cred.egid,
+        s.cred.sgid,
+        s.cred.egid,
+        groups,
+        s.pages * PAGE_SIZE / 1024,
+        s.resident * PAGE_SIZE / 1024,
+        s.threads,
+        s.sigpending,
+        s.sigpending,
+        s.sigmask
+    )
+}
+
+fn comm_file(tgid: usize) -> String {
+    match find_process(tgid) {
+        Some((leader, _)) => comm(&leader) + "\n",
+        None => String::new(),
+    }
+}
+
+/// Each string followed by a NUL, as they are laid out on the user stack
+fn nul_separated(strings: &[String]) -> String {
+    strings.iter().map(|s| s.clone() + "\0").collect()
+}
+
+fn cmdline(tgid: usize) -> String {
+    match find_process(tgid) {
+        Some((leader, _)) => nul_separated(&leader.exec_args.lock().argv),
+        None => String::new(),
+    }
+}
+
+/// The leader of `tgid` if the caller may read its memory, which `environ` and `maps` expose.
+/// Like Linux, it takes the same rights as `ptrace()`.
+fn readable_process(tgid: usize) -> Option<Arc<TaskControlBlock>> {
+    let (leader, _) = find_process(tgid)?;
+    let cred = current_cred();
+    if !cred.may_trace(&leader.cred.lock()) {
+        return None;
+    }
+    Some(leader)
+}
+
+/// The files only the owner of the process may read belong to its effective user and group
+fn owner(tgid: usize) -> (u32, u32) {
+    match find_process(tgid) {
+        Some((leader, _)) => {
+            let cred = leader.cred.lock();
+            (cred.euid, cred.egid)
+        }
+        None => (0, 0),
+    }
+}
+
+fn environ(tgid: usize) -> String {
+    match readable_process(tgid) {
+        Some(leader) => nul_separated(&leader.exec_args.lock().envp),
+        None => String::new(),
+    }
+}
+
+fn maps(tgid: usize) -> String {
+    let leader = match readable_process(tgid) {
+        Some(leader) => leader,
+        None => return String::new(),
+    };
+    let heap_bottom = leader.acquire_inner_lock().heap_bottom;
+    let stack_bottom = leader.ustack_bottom_va();
+    let areas = leader.vm.lock().user_areas();
+    areas
+        .into_iter()
+        .map(|(start, end, perm, file)| {
+            let (ino, name) = match file {
+                Some(file) => (
+                    file.get_stat().get_ino(),
+                    file.get_dirtree_node()
+                        .map_or(String::new(), |inode| inode.get_cwd()),
+                ),
+                None if start.0 == heap_bottom => (0, "[heap]".to_string()),
+                None if end.0 == stack_bottom => (0, "[stack]".to_string()),
+                None => (0, String::new()),
+            };
+            let line = format!(
+                "{:08x}-{:08x} {}{}{}p 00000000 00:00 {}",
+                start.0,
+                end.0,
+                if perm.contains(MapPermission::R) {
+                    'r'
+                } else {
+                    '-'
+                },
+                if perm.contains(MapPermission::W) {
+                    'w'
+                } else {
+                    '-'
+                },
+                if perm.contains(MapPermission::X) {
+                    'x'
+                } else {
+                    '-'
+                },
+                ino
+            );
+            if name.is_empty() {
+                line + "\n"
+            } else {
+                format!("{:<72} {}\n", line, name)
+            }
+        })
+        .collect()
+}
+
+/// What `/proc/<pid>/fd/<fd>` points to.
+/// Files outside the directory tree get a made-up name like on Linux.
+fn fd_target(file: &Arc<dyn File>) -> String {
+    match file.get_dirtree_node() {
+        Some(inode) => inode.get_cwd(),
+        None if file.is::<Pipe>() => "pipe:[0]".to_string(),
+        None if file.is::<TmpfsOSInode>() => format!(
+            "/{} (deleted)",
+            file.downcast_ref::<TmpfsOSInode>().unwrap().get_name()
+        ),
+        None => "anon_inode:[unknown]".to_string(),
+    }
+}
+
+fn fd_dir(tgid: usize) -> Arc<dyn File> {
+    ProcDir::new(move || {
+        let leader = match find_process(tgid) {
+            Some((leader, _)) => leader,
+            None => return Vec::new(),
+        };
+        let fd_table = leader.files.lock();
+        fd_table
+            .iter()
+            .enumerate()
+            .filter_map(|(fd, file_descriptor)| {
+                let file = file_descriptor.as_ref()?.file.clone();
+                let link: Arc<dyn File> = ProcLink::new(move || Some(fd_target(&file)));
+                Some((fd.to_string(), link))
+            })
+            .collect()
+    })
+}
+
+/// `/proc/<pid>/oom_score_adj`, like Linux only root may lower it
+fn oom_score_adj(tgid: usize) -> Arc<dyn File> {
+    ProcFile::with_setter(
+        move || match find_process(tgid) {
+            Some((leader, _)) => format!("{}\n", leader.oom_score_adj.load(Ordering::Relaxed)),
+            None => String::new(),
+        },
+        move |value| {
+            let adj: isize = match value.trim().parse() {
+                Ok(adj) => adj,
+                Err(_) => return Err(EINVAL),
+            };
+            if !(OOM_SCORE_ADJ_MIN..=OOM_SCORE_ADJ_MAX).contains(&adj) {
+                return Err(EINVAL);
+            }
+            let leader = match find_process(tgid) {
+                Some((leader, _)) => leader,
+                None => return Err(ESRCH),
+            };
+            if adj < leader.oom_score_adj.load(Ordering::Relaxed) && !current_cred().is_root() {
+                return Err(EACCES);
+            }
+            leader.oom_score_adj.store(adj, Ordering::Relaxed);
+            Ok(())
+        },
+    )
+}
+
+#[cfg(feature = "oom_handler")]
+fn oom_score(tgid: usize) -> String {
+    match find_process(tgid) {
+        Some((leader, _)) => format!("{}\n", crate::mm::oom_score(&leader)),
+        None => String::new(),
+    }
+}
+
+/// The directory of the process `tgid`, it turns empty once the process is reaped.
+pub fn pid_dir(tgid: usize) -> Arc<dyn File> {
+    ProcDir::new(move || {
+        if find_process(tgid).is_none() {
+            return Vec::new();
+        }
+        let cwd = ProcLink::new(move || {
+            let (leader, _) = find_process(tgid)?;
+            let working_inode = leader.fs.lock().working_inode.clone();
+            working_inode.get_cwd()
+        });
+        let exe = ProcLink::new(move || {
+            let (leader, _) = find_process(tgid)?;
+            let exe = leader.exe.lock().clone();
+            exe.get_cwd()
+        });
+        let mut entries: Vec<(String, Arc<dyn File>)> = Vec::new();
+        entries.push(("stat".to_string(), ProcFile::new(move || stat(tgid))));
+        entries.push(("statm".to_string(), ProcFile::new(move || statm(tgid))));
+        entries.push(("status".to_string(), ProcFile::new(move || status(tgid))));
+        entries.push(("comm".to_string(), ProcFile::new(move || comm_file(tgid))));
+        entries.push(("cmdline".to_string(), ProcFile::new(move || cmdline(tgid))));
+        entries.push((
+            "environ".to_string(),
+            ProcFile::owner_only(move || environ(tgid), move || owner(tgid)),
+        ));
+        entries.push((
+            "maps".to_string(),
+            ProcFile::owner_only(move || maps(tgid), move || owner(tgid)),
+        ));
+        entries.push(("oom_score_adj".to_string(), oom_score_adj(tgid)));
+        #[cfg(feature = "oom_handler")]
+        entries.push((
+            "oom_score".to_string(),
+            ProcFile::new(move || oom_score(tgid)),
+        ));
+        entries.push(("fd".to_string(), fd_dir(tgid)));
+        entries.push(("cwd".to_string(), cwd));
+        entries.push(("exe".to_string(), exe));
+        entries
+    })
+}
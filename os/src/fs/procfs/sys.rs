@@ -0,0 +1,143 @@
+//! System-wide files in the root of `/proc`
+use alloc::{format, string::String, sync::Arc, vec};
+
+use super::to_clock_ticks;
+use crate::{
+    arch::TICKS_PER_SEC,
+    config::PAGE_SIZE,
+    mm::{total_frames, unallocated_frames},
+    task::{all_tasks, TaskControlBlock, TaskStatus},
+    timer::get_time_us,
+};
+
+/// Ticks spent in user and kernel mode by all living tasks.
+/// Time of the tasks already reaped is lost.
+fn busy_ticks() -> (usize, usize) {
+    all_tasks().iter().fold((0, 0), |(user, system), task| {
+        let rusage = task.acquire_inner_lock().rusage;
+        (
+            user + to_clock_ticks(rusage.ru_utime.to_us()),
+            system + to_clock_ticks(rusage.ru_stime.to_us()),
+        )
+    })
+}
+
+fn running_count(tasks: &[Arc<TaskControlBlock>]) -> usize {
+    tasks
+        .iter()
+        .filter(|task| {
+            let status = task.acquire_inner_lock().task_status;
+            status == TaskStatus::Ready || status == TaskStatus::Running
+        })
+        .count()
+}
+
+pub fn meminfo() -> String {
+    let kb = |pages: usize| pages * PAGE_SIZE / 1024;
+    // zram is counted as a swap device, as it is on Linux
+    #[allow(unused_mut)]
+    let (mut swap_used, mut swap_total) = (0, 0);
+    #[allow(unused_mut)]
+    let (mut zram_pages, mut zram_bytes) = (0, 0);
+    #[cfg(feature = "swap")]
+    {
+        let (used, total) = crate::fs::swap::SWAP_DEVICE.lock().usage();
+        swap_used += used;
+        swap_total += total;
+    }
+    #[cfg(feature = "zram")]
+    {
+        let (stored, bytes, capacity) = crate::mm::ZRAM_DEVICE.lock().usage();
+        swap_used += stored;
+        swap_total += capacity;
+        zram_pages = stored;
+        zram_bytes = bytes;
+    }
+    let free = unallocated_frames();
+    [
+        ("MemTotal:", kb(total_frames())),
+        ("MemFree:", kb(free)),
+        ("MemAvailable:", kb(free)),
+        ("Buffers:", 0),
+        ("Cached:", 0),
+        ("SwapCached:", 0),
+        ("Shmem:", 0),
+        ("SwapTotal:", kb(swap_total)),
+        ("SwapFree:", kb(swap_total - swap_used)),
+        ("Zswap:", zram_bytes / 1024),
+        ("Zswapped:", kb(zram_pages)),
+    ]
+    .iter()
+    .map(|(name, value)| format!("{:<16}{:>8} kB\n", name, value))
+    .collect()
+}
+
+pub fn cpuinfo() -> String {
+    String::from(
+        "system type\t\t: generic-loongson-machine\n\
+         processor\t\t: 0\n\
+         package\t\t\t: 0\n\
+         core\t\t\t: 0\n\
+         CPU Family\t\t: Loongson-64bit\n\
+         ISA\t\t\t: loongarch64\n\n",
+    )
+}
+
+pub fn uptime() -> String {
+    let uptime = get_time_us() / 10_000;
+    let (user, system) = busy_ticks();
+    let idle = to_clock_ticks(get_time_us()).saturating_sub(user + system) * 100 / TICKS_PER_SEC;
+    format!(
+        "{}.{:02} {}.{:02}\n",
+        uptime / 100,
+        uptime % 100,
+        idle / 100,
+        idle % 100
+    )
+}
+
+/// We don't keep a history of the run queue,
+/// so the current number of runnable tasks stands for all three averages.
+pub fn loadavg() -> String {
+    let tasks = all_tasks();
+    let running = running_count(&tasks);
+    let last_pid = tasks.iter().map(|task| task.pid.0).max().unwrap_or(0);
+    format!(
+        "{0}.00 {0}.00 {0}.00 {0}/{1} {2}\n",
+        running,
+        tasks.len(),
+        last_pid
+    )
+}
+
+pub fn stat() -> String {
+    let tasks = all_tasks();
+    let (user, system) = busy_ticks();
+    let idle = to_clock_ticks(get_time_us()).saturating_sub(user + system);
+    let running = running_count(&tasks);
+    let cpu = format!("{} 0 {} {} 0 0 0 0 0 0", user, system, idle);
+    format!(
+        "cpu  {0}\n\
+         cpu0 {0}\n\
+         intr 0\n\
+         ctxt 0\n\
+         btime 0\n\
+         processes {1}\n\
+         procs_running {2}\n\
+         procs_blocked 0\n",
+        cpu,
+        tasks.len(),
+        running
+    )
+}
+
+/// `/proc/vmstat`, the free pages and the page reclaim counters
+pub fn vmstat() -> String {
+    #[allow(unused_mut)]
+    let mut stat = vec![("nr_free_pages", unallocated_frames())];
+    #[cfg(feature = "oom_handler")]
+    stat.extend(crate::mm::vm_events());
+    stat.iter()
+        .map(|(name, value)| format!("{} {}\n", name, value))
+        .collect()
+}
@@ -0,0 +1,75 @@
+//! Kernel tunables, `/proc/sys`
+use alloc::{
+    format,
+    string::{String, ToString},
+    sync::Arc,
+    vec::Vec,
+};
+
+use super::{ProcDir, ProcFile};
+use crate::{
+    fs::{file_trait::File, writeback},
+    syscall::errno::*,
+};
+
+/// A tunable holding a number, read and written as decimal text.
+/// Like Linux, surrounding whitespace such as the newline of `echo` is ignored.
+fn number(get: fn() -> usize, set: fn(usize)) -> Arc<dyn File> {
+    ProcFile::with_setter(
+        move || format!("{}\n", get()),
+        move |value| match value.trim().parse() {
+            Ok(value) => {
+                set(value);
+                Ok(())
+            }
+            Err(_) => Err(EINVAL),
+        },
+    )
+}
+
+pub fn root() -> Arc<dyn File> {
+    ProcDir::new(|| {
+        let mut entries: Vec<(String, Arc<dyn File>)> = Vec::new();
+        entries.push(("kernel".to_string(), ProcDir::new(kernel)));
+        entries.push(("vm".to_string(), ProcDir::new(vm)));
+        entries
+    })
+}
+
+/// `/proc/sys/kernel`
+fn kernel() -> Vec<(String, Arc<dyn File>)> {
+    let mut entries: Vec<(String, Arc<dyn File>)> = Vec::new();
+    entries.push((
+        "randomize_va_space".to_string(),
+        number(
+            crate::mm::randomize_va_space,
+            crate::mm::set_randomize_va_space,
+        ),
+    ));
+    entries
+}
+
+/// `/proc/sys/vm`
+fn vm() -> Vec<(String, Arc<dyn File>)> {
+    let mut entries: Vec<(String, Arc<dyn File>)> = Vec::new();
+    entries.push((
+        "dirty_expire_centisecs".to_string(),
+        number(
+            writeback::dirty_expire_centisecs,
+            writeback::set_dirty_expire_centisecs,
+        ),
+    ));
+    entries.push((
+        "dirty_writeback_centisecs".to_string(),
+        number(
+            writeback::dirty_writeback_centisecs,
+            writeback::set_dirty_writeback_centisecs,
+        ),
+    ));
+    #[cfg(feature = "oom_handler")]
+    entries.push((
+        "min_free_kbytes".to_string(),
+        number(crate::mm::min_free_kbytes, crate::mm::set_min_free_kbytes),
+    ));
+    entries
+}
@@ -0,0 +1,58 @@
+//! `/proc/zram0`, the attributes Linux has in `/sys/block/zram0`
+use alloc::{
+    format,
+    string::{String, ToString},
+    sync::Arc,
+    vec::Vec,
+};
+
+use super::ProcFile;
+use crate::{fs::file_trait::File, mm::ZRAM_DEVICE, syscall::errno::*};
+
+/// A size in bytes, which may end with K, M or G like `memparse()` of Linux
+fn parse_size(value: &str) -> Option<usize> {
+    let value = value.trim();
+    let (number, shift) = match value.as_bytes().last()? {
+        b'k' | b'K' => (&value[..value.len() - 1], 10),
+        b'm' | b'M' => (&value[..value.len() - 1], 20),
+        b'g' | b'G' => (&value[..value.len() - 1], 30),
+        _ => (value, 0),
+    };
+    number.parse::<usize>().ok()?.checked_mul(1 << shift)
+}
+
+pub fn zram_dir() -> Vec<(String, Arc<dyn File>)> {
+    let mut entries: Vec<(String, Arc<dyn File>)> = Vec::new();
+    // shrinking below what is stored is refused, Linux wants a reset first
+    entries.push((
+        "disksize".to_string(),
+        ProcFile::with_setter(
+            || format!("{}\n", ZRAM_DEVICE.lock().disksize()),
+            |value| match parse_size(value) {
+                Some(bytes) => ZRAM_DEVICE.lock().set_disksize(bytes).or(Err(EBUSY)),
+                None => Err(EINVAL),
+            },
+        ),
+    ));
+    entries.push((
+        "comp_algorithm".to_string(),
+        ProcFile::with_setter(
+            || ZRAM_DEVICE.lock().comp_algorithm(),
+            |value| {
+                ZRAM_DEVICE
+                    .lock()
+                    .set_comp_algorithm(value.trim())
+                    .or(Err(EINVAL))
+            },
+        ),
+    ));
+    entries.push((
+        "mm_stat".to_string(),
+        ProcFile::new(|| ZRAM_DEVICE.lock().mm_stat()),
+    ));
+    entries.push((
+        "io_stat".to_string(),
+        ProcFile::new(|| ZRAM_DEVICE.lock().io_stat()),
+    ));
+    entries
+}
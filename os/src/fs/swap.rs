@@ -1,80 +1,452 @@
-use alloc::{sync::Arc, vec::Vec};
+//! Swap areas, activated by `swapon()` and released by `swapoff()`.
+//!
+//! An area is a file or a block device which starts with the header written by `mkswap`,
+//! so its page 0 is never handed out, nor are the bad pages the header lists.
+//! Like on Linux, the pages are read and written through the block map of the file, around the page cache,
+//! those of a device follow each other.
+//! At boot, an area is carved out of a FAT32 root, it is listed as `/dev/sda1`.
+//! Slots come from the area of the highest priority which has room, areas of the same priority take turns.
+use alloc::{
+    format,
+    string::{String, ToString},
+    sync::Arc,
+    vec,
+    vec::Vec,
+};
+use core::convert::TryInto;
+use lazy_static::*;
 use spin::Mutex;
 
-use crate::{arch::BLOCK_SZ, config::PAGE_SIZE, drivers::BLOCK_DEVICE};
+use super::{
+    dev::block::BlockFile,
+    directory_tree::{root_is_fat32, DirectoryTreeNode, FILE_SYSTEM},
+    file_trait::File,
+};
+use crate::{arch::BLOCK_SZ, config::PAGE_SIZE, drivers::block::BlockDevice, syscall::errno::*};
 
-use super::directory_tree::FILE_SYSTEM;
-use lazy_static::*;
+bitflags! {
+    /// The flags of `swapon()`, the low bits hold the priority
+    pub struct SwapFlags: u32 {
+        const SWAP_FLAG_PREFER          =   0x8000;
+        /// Discarding is left to the disk, these are accepted and ignored
+        const SWAP_FLAG_DISCARD         =   0x10000;
+        const SWAP_FLAG_DISCARD_ONCE    =   0x20000;
+        const SWAP_FLAG_DISCARD_PAGES   =   0x40000;
+    }
+}
+
+pub const SWAP_FLAG_PRIO_MASK: u32 = 0x7fff;
+
+const MAX_SWAPFILES: usize = 32;
+
+/// The signature which ends the header page, `SWAPSPACE2` is the version 1 header
+const SWAP_MAGIC: &[u8] = b"SWAPSPACE2";
+/// Offsets in the header page, see `union swap_header` of Linux
+const VERSION: usize = 1024;
+const LAST_PAGE: usize = 1028;
+const NR_BADPAGES: usize = 1032;
+const BADPAGES: usize = 1536;
+const MAX_SWAP_BADPAGES: usize = (PAGE_SIZE - SWAP_MAGIC.len() - BADPAGES) / 4;
+const BLK_PER_PG: usize = PAGE_SIZE / BLOCK_SZ;
+/// Megabytes carved out of a FAT32 root at boot, there is no header there
+const BOOT_SWAP_SIZE: usize = 16;
 
 lazy_static! {
-    pub static ref SWAP_DEVICE: Mutex<Swap> = Mutex::new(Swap::new(16));
+    pub static ref SWAP_DEVICE: Mutex<Swap> = Mutex::new(Swap::new());
 }
 
+/// A slot in use, given back when dropped
 #[derive(Debug)]
-pub struct SwapTracker(pub usize);
+pub struct SwapTracker {
+    pub area: usize,
+    pub slot: usize,
+}
 
 impl Drop for SwapTracker {
     fn drop(&mut self) {
-        SWAP_DEVICE.lock().discard(self.0);
+        SWAP_DEVICE.lock().discard(self.area, self.slot);
     }
 }
 
-pub struct Swap {
+/// Where the pages of an area are on its device
+enum Extent {
+    /// The first block of each page of a file
+    Map(Vec<usize>),
+    /// The first block of a device, its pages follow each other
+    Linear(usize),
+}
+
+impl Extent {
+    fn block_id(&self, page: usize) -> usize {
+        match self {
+            Extent::Map(block_ids) => block_ids[page],
+            Extent::Linear(start) => start + page * BLK_PER_PG,
+        }
+    }
+}
+
+struct SwapArea {
+    /// Absolute path, as shown in `/proc/swaps`
+    path: String,
+    /// Held open, the blocks must stay with the file. `None` for the area carved out at boot
+    _file: Option<Arc<dyn File>>,
+    /// Pinned by `add_special_use()`, the file is not written, truncated or removed while swapped on
+    inode: Option<Arc<DirectoryTreeNode>>,
+    device: Arc<dyn BlockDevice>,
+    extent: Extent,
+    /// A set bit is a slot in use, the header or a bad page
     bitmap: Vec<u64>,
-    block_ids: Vec<usize>,
+    /// Number of slots which can be handed out
+    pages: usize,
+    inuse: usize,
+    prio: isize,
+    /// Set while `swapoff()` reads the pages back, no slot is handed out then
+    draining: bool,
 }
-const BLK_PER_PG: usize = PAGE_SIZE / BLOCK_SZ;
-const SWAP_SIZE: usize = 1024 * 1024;
+
+impl SwapArea {
+    fn has_room(&self) -> bool {
+        !self.draining && self.inuse < self.pages
+    }
+    fn alloc(&mut self) -> Option<usize> {
+        let (i, word) = self
+            .bitmap
+            .iter_mut()
+            .enumerate()
+            .find(|(_, word)| **word != u64::MAX)?;
+        let bit = (!*word).trailing_zeros() as usize;
+        *word |= 1 << bit;
+        self.inuse += 1;
+        Some(i * 64 + bit)
+    }
+}
+
+pub struct Swap {
+    /// Indexed by `SwapTracker::area`, an index is reused once its area is gone
+    areas: Vec<Option<SwapArea>>,
+    /// Priority of the last area activated without `SWAP_FLAG_PREFER`
+    least_priority: isize,
+    /// The area which handed out the last slot
+    last: usize,
+}
+
 impl Swap {
-    /// size: the number of megabytes in swap
-    pub fn new(size: usize) -> Self {
-        let bit = size * (SWAP_SIZE / PAGE_SIZE); // 1MiB = 4KiB*256
-        let vec_len = bit / usize::MAX.count_ones() as usize;
-        let mut bitmap = Vec::<u64>::with_capacity(vec_len);
-        bitmap.resize(bitmap.capacity(), 0);
-        let blocks = size * (SWAP_SIZE / BLOCK_SZ); // 1MiB = 512B * 2048
+    fn new() -> Self {
         Self {
-            bitmap,
-            block_ids: FILE_SYSTEM.alloc_blocks(blocks),
+            areas: Vec::new(),
+            least_priority: -1,
+            last: 0,
+        }
+    }
+    fn find(&self, path: &str) -> Option<usize> {
+        self.areas
+            .iter()
+            .position(|area| area.as_ref().map_or(false, |area| area.path == path))
+    }
+    /// The next area after the last one used among those of the highest priority with room
+    fn pick(&self) -> Option<usize> {
+        let prio = self
+            .areas
+            .iter()
+            .flatten()
+            .filter(|area| area.has_room())
+            .map(|area| area.prio)
+            .max()?;
+        let len = self.areas.len();
+        (1..=len).map(|i| (self.last + i) % len).find(|&i| {
+            self.areas[i]
+                .as_ref()
+                .map_or(false, |area| area.prio == prio && area.has_room())
+        })
+    }
+    pub fn read(&self, swap_tracker: &SwapTracker, buf: &mut [u8]) {
+        let area = self.areas[swap_tracker.area].as_ref().unwrap();
+        area.device
+            .read_block(area.extent.block_id(swap_tracker.slot), buf);
+    }
+    /// Store a page, `None` if every area is full.
+    pub fn write(&mut self, buf: &[u8]) -> Option<Arc<SwapTracker>> {
+        let index = self.pick()?;
+        let area = self.areas[index].as_mut().unwrap();
+        let slot = area.alloc()?;
+        area.device.write_block(area.extent.block_id(slot), buf);
+        self.last = index;
+        Some(Arc::new(SwapTracker { area: index, slot }))
+    }
+    /// Returns the number of pages in use and the capacity in pages.
+    pub fn usage(&self) -> (usize, usize) {
+        self.areas
+            .iter()
+            .flatten()
+            .fold((0, 0), |(used, total), area| {
+                (used + area.inuse, total + area.pages)
+            })
+    }
+    /// Path, type, size and use in pages, and priority of each area
+    pub fn areas(&self) -> impl Iterator<Item = (&str, &str, usize, usize, isize)> {
+        self.areas.iter().flatten().map(|area| {
+            // padded like Linux, the columns line up
+            let kind = match area.extent {
+                Extent::Map(_) => "file\t",
+                Extent::Linear(_) => "partition",
+            };
+            (area.path.as_str(), kind, area.pages, area.inuse, area.prio)
+        })
+    }
+    /// Add `area`, with the next default priority if `prio` is `None`
+    fn insert(&mut self, mut area: SwapArea, prio: Option<isize>) -> Result<(), isize> {
+        let index = match self.areas.iter().position(|area| area.is_none()) {
+            Some(index) => index,
+            None if self.areas.len() < MAX_SWAPFILES => {
+                self.areas.push(None);
+                self.areas.len() - 1
+            }
+            None => return Err(EPERM),
+        };
+        area.prio = prio.unwrap_or_else(|| {
+            self.least_priority -= 1;
+            self.least_priority
+        });
+        log::info!(
+            "[swapon] Adding {}k swap on {}. Priority:{}",
+            area.pages * PAGE_SIZE / 1024,
+            area.path,
+            area.prio
+        );
+        self.areas[index] = Some(area);
+        Ok(())
+    }
+    fn discard(&mut self, area: usize, slot: usize) {
+        let area = self.areas[area].as_mut().unwrap();
+        area.bitmap[slot / 64] &= !(1 << (slot % 64));
+        area.inuse -= 1;
+    }
+}
+
+/// A bitmap of `max_pages` free slots
+fn new_bitmap(max_pages: usize) -> Vec<u64> {
+    let mut bitmap = vec![0u64; (max_pages + 63) / 64];
+    // the slots past the end are never free
+    for slot in max_pages..bitmap.len() * 64 {
+        bitmap[slot / 64] |= 1 << (slot % 64);
+    }
+    bitmap
+}
+
+/// Check the header of an area spanning `file_pages` pages.
+/// Returns the bitmap with the header and the bad pages marked, and the number of usable pages.
+fn parse_header(header: &[u8], file_pages: usize) -> Result<(Vec<u64>, usize), isize> {
+    if &header[PAGE_SIZE - SWAP_MAGIC.len()..PAGE_SIZE] != SWAP_MAGIC {
+        log::warn!("[swapon] unable to find swap-space signature");
+        return Err(EINVAL);
+    }
+    let word =
+        |offset: usize| u32::from_le_bytes(header[offset..offset + 4].try_into().unwrap()) as usize;
+    if word(VERSION) != 1 {
+        log::warn!(
+            "[swapon] unable to handle swap header version {}",
+            word(VERSION)
+        );
+        return Err(EINVAL);
+    }
+    let nr_badpages = word(NR_BADPAGES);
+    if nr_badpages > MAX_SWAP_BADPAGES {
+        return Err(EINVAL);
+    }
+    let last_page = word(LAST_PAGE);
+    let max_pages = (last_page + 1).min(file_pages);
+    let mut bitmap = new_bitmap(max_pages);
+    bitmap[0] |= 1;
+    let mut pages = max_pages - 1;
+    for i in 0..nr_badpages {
+        let page = word(BADPAGES + 4 * i);
+        if page == 0 || page > last_page {
+            return Err(EINVAL);
+        }
+        if page < max_pages && bitmap[page / 64] & (1 << (page % 64)) == 0 {
+            bitmap[page / 64] |= 1 << (page % 64);
+            pages -= 1;
         }
     }
-    fn read_page(block_ids: &[usize], buf: &mut [u8]) {
-        assert!(block_ids[0] + BLK_PER_PG - 1 == block_ids[BLK_PER_PG - 1]);
-        BLOCK_DEVICE.read_block(block_ids[0], buf);
+    if pages == 0 {
+        log::warn!("[swapon] empty swap-file");
+        return Err(EINVAL);
     }
-    fn write_page(block_ids: &[usize], buf: &[u8]) {
-        assert!(block_ids[0] + (BLK_PER_PG - 1) == block_ids[BLK_PER_PG - 1]);
-        BLOCK_DEVICE.write_block(block_ids[0], buf);
+    Ok((bitmap, pages))
+}
+
+/// Activate swap on `file`, a regular file or a block device, with priority `prio` if `SWAP_FLAG_PREFER` was given.
+pub fn swapon(file: Arc<dyn File>, prio: Option<isize>) -> Result<(), isize> {
+    let inode = match file.get_dirtree_node() {
+        Some(inode) => inode,
+        None => return Err(EINVAL),
+    };
+    let path = inode.get_cwd();
+    if SWAP_DEVICE.lock().find(&path).is_some() {
+        return Err(EBUSY);
     }
-    fn set_bit(&mut self, pos: usize) {
-        self.bitmap[pos / 64] |= 1 << (pos % 64);
+    // the blocks are accessed directly from now on, `mkswap` may have left the header in the page cache
+    file.fsync()?;
+    let (device, extent, file_pages) = match file.downcast_ref::<BlockFile>() {
+        // the header tells where an area on a device of unknown size ends
+        Some(block_file) => {
+            let (device, start, blocks) = block_file.extent();
+            let file_pages = blocks.map_or(usize::MAX, |blocks| blocks / BLK_PER_PG);
+            (device, Extent::Linear(start), file_pages)
+        }
+        None => {
+            let (device, block_ids) = file.bmap()?;
+            let file_pages = block_ids.len();
+            (device, Extent::Map(block_ids), file_pages)
+        }
+    };
+    if file_pages == 0 {
+        return Err(EINVAL);
     }
-    fn clear_bit(&mut self, pos: usize) {
-        self.bitmap[pos / 64] &= !(1 << (pos % 64));
+    let mut header = vec![0u8; PAGE_SIZE];
+    device.read_block(extent.block_id(0), &mut header);
+    let (bitmap, pages) = parse_header(&header, file_pages)?;
+
+    let mut swap = SWAP_DEVICE.lock();
+    if swap.find(&path).is_some() {
+        return Err(EBUSY);
+    }
+    let area = SwapArea {
+        path,
+        _file: Some(file),
+        inode: Some(inode.clone()),
+        device,
+        extent,
+        bitmap,
+        pages,
+        inuse: 0,
+        prio: 0,
+        draining: false,
+    };
+    swap.insert(area, prio)?;
+    inode.add_special_use();
+    Ok(())
+}
+
+/// Set up the swap area of the boot.
+/// `/dev/sda2` is not probed, the disk may end with the root filesystem, it is left to `swapon()`.
+pub fn init() {
+    if !root_is_fat32() {
+        return;
     }
-    fn alloc_page(&self) -> Option<usize> {
-        for (i, bit) in self.bitmap.iter().enumerate() {
-            if !*bit == 0 {
-                continue;
+    let blocks = FILE_SYSTEM.alloc_blocks(BOOT_SWAP_SIZE * 1024 * 1024 / BLOCK_SZ);
+    let max_pages = blocks.len() / BLK_PER_PG;
+    let mut bitmap = new_bitmap(max_pages);
+    let mut pages = max_pages;
+    let mut block_ids = Vec::with_capacity(max_pages);
+    for (page, ids) in blocks.chunks_exact(BLK_PER_PG).enumerate() {
+        // a page on blocks which don't follow each other is not used, like a bad page
+        if ids.windows(2).any(|pair| pair[1] != pair[0] + 1) {
+            bitmap[page / 64] |= 1 << (page % 64);
+            pages -= 1;
+        }
+        block_ids.push(ids[0]);
+    }
+    if pages == 0 {
+        return;
+    }
+    let area = SwapArea {
+        path: "/dev/sda1".to_string(),
+        _file: None,
+        inode: None,
+        device: FILE_SYSTEM.block_device.clone(),
+        extent: Extent::Map(block_ids),
+        bitmap,
+        pages,
+        inuse: 0,
+        prio: 0,
+        draining: false,
+    };
+    if let Err(errno) = SWAP_DEVICE.lock().insert(area, None) {
+        log::warn!("[swap] failed to add the boot-time swap area: {}", errno);
+    }
+}
+
+/// Read back every page kept in area `index`.
+/// Those which can't be, for lack of memory, are left there and `ENOMEM` is returned.
+fn try_to_unuse(index: usize) -> Result<(), isize> {
+    #[cfg(feature = "oom_handler")]
+    {
+        use crate::mm::{MemorySet, PageTableImpl};
+        let mut memory_sets: Vec<Arc<Mutex<MemorySet<PageTableImpl>>>> = Vec::new();
+        for task in crate::task::all_tasks() {
+            if !memory_sets.iter().any(|vm| Arc::ptr_eq(vm, &task.vm)) {
+                memory_sets.push(task.vm.clone());
+            }
+        }
+        for vm in memory_sets.iter() {
+            if vm.lock().swapoff(index).is_err() {
+                return Err(ENOMEM);
             }
-            return Some(i * 64 + (!*bit).trailing_zeros() as usize);
         }
-        None
+        super::directory_tree::swapoff_tmpfs(index)?;
     }
-    fn get_block_ids(&self, swap_id: usize) -> &[usize] {
-        &self.block_ids[swap_id * BLK_PER_PG + 0..swap_id * BLK_PER_PG + BLK_PER_PG]
+    let swap = SWAP_DEVICE.lock();
+    if swap.areas[index].as_ref().unwrap().inuse > 0 {
+        return Err(ENOMEM);
     }
-    pub fn read(&mut self, swap_id: usize, buf: &mut [u8]) {
-        Self::read_page(self.get_block_ids(swap_id), buf);
+    Ok(())
+}
+
+/// Deactivate swap on the file at the absolute `path`, its pages are read back first.
+pub fn swapoff(path: &str) -> Result<(), isize> {
+    let index = {
+        let mut swap = SWAP_DEVICE.lock();
+        let index = swap.find(path).ok_or(EINVAL)?;
+        swap.areas[index].as_mut().unwrap().draining = true;
+        index
+    };
+    let result = try_to_unuse(index);
+    let mut swap = SWAP_DEVICE.lock();
+    if let Err(errno) = result {
+        swap.areas[index].as_mut().unwrap().draining = false;
+        return Err(errno);
+    }
+    let area = swap.areas[index].take().unwrap();
+    // like Linux, the areas with a lower default priority move up
+    if area.prio < 0 {
+        for other in swap.areas.iter_mut().flatten() {
+            if other.prio < area.prio {
+                other.prio += 1;
+            }
+        }
+        swap.least_priority += 1;
     }
-    pub fn write(&mut self, buf: &[u8]) -> Arc<SwapTracker> {
-        let swap_id = self.alloc_page().unwrap();
-        Self::write_page(self.get_block_ids(swap_id), buf);
-        self.set_bit(swap_id);
-        Arc::new(SwapTracker(swap_id))
+    drop(swap);
+    if let Some(inode) = &area.inode {
+        inode.sub_special_use();
     }
-    #[inline(always)]
-    pub fn discard(&mut self, swap_id: usize) {
-        self.clear_bit(swap_id);
+    log::info!("[swapoff] Removed swap on {}", area.path);
+    Ok(())
+}
+
+/// Whether `inode` is swapped on, see `DirectoryTreeNode::delete()`
+pub fn is_swap_file(inode: &DirectoryTreeNode) -> bool {
+    SWAP_DEVICE.lock().areas.iter().flatten().any(|area| {
+        area.inode
+            .as_ref()
+            .map_or(false, |node| core::ptr::eq(Arc::as_ptr(node), inode))
+    })
+}
+
+/// The content of `/proc/swaps`
+pub fn swaps() -> String {
+    let kb = |pages: usize| pages * PAGE_SIZE / 1024;
+    let mut swaps = "Filename\t\t\t\tType\t\tSize\t\tUsed\t\tPriority\n".to_string();
+    for (path, kind, pages, inuse, prio) in SWAP_DEVICE.lock().areas() {
+        swaps += &format!(
+            "{:<40}{}\t{}\t\t{}\t\t{}\n",
+            path,
+            kind,
+            kb(pages),
+            kb(inuse),
+            prio
+        );
     }
+    swaps
 }
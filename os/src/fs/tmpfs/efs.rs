@@ -0,0 +1,198 @@
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use alloc::{
+    collections::BTreeMap,
+    sync::{Arc, Weak},
+    vec::Vec,
+};
+use lazy_static::*;
+use spin::Mutex;
+
+use super::TmpfsInode;
+use crate::{config::PAGE_SIZE, mm::total_frames, syscall::errno::*};
+
+/// Mount options understood by tmpfs, e.g. `size=64m,nr_inodes=4k,mode=1777`.
+#[derive(Clone, Copy, Default)]
+pub struct TmpfsOptions {
+    /// Limit of file data, in pages
+    pub max_pages: Option<usize>,
+    pub max_inodes: Option<usize>,
+    /// Permission bits of the root directory
+    pub mode: Option<u32>,
+}
+
+impl TmpfsOptions {
+    /// Parse the `data` argument of `mount()`.
+    /// Like Linux, sizes take a k, m or g suffix, and `size` may also be a percentage of RAM.
+    pub fn parse(data: &str) -> Result<Self, isize> {
+        fn parse_number(value: &str) -> Result<usize, isize> {
+            let (digits, shift) = match value.chars().last() {
+                Some('k' | 'K') => (&value[..value.len() - 1], 10),
+                Some('m' | 'M') => (&value[..value.len() - 1], 20),
+                Some('g' | 'G') => (&value[..value.len() - 1], 30),
+                _ => (value, 0),
+            };
+            match digits.parse::<usize>() {
+                Ok(number) => number.checked_shl(shift).ok_or(EINVAL),
+                Err(_) => Err(EINVAL),
+            }
+        }
+        let mut options = Self::default();
+        for option in data.split(',').filter(|option| !option.is_empty()) {
+            let (key, value) = match option.find('=') {
+                Some(idx) => (&option[..idx], &option[idx + 1..]),
+                None => (option, ""),
+            };
+            match key {
+                "size" => {
+                    let bytes = match value.strip_suffix('%') {
+                        Some(percent) => match percent.parse::<usize>() {
+                            Ok(percent) => total_frames() * PAGE_SIZE / 100 * percent,
+                            Err(_) => return Err(EINVAL),
+                        },
+                        None => parse_number(value)?,
+                    };
+                    options.max_pages = Some((bytes + PAGE_SIZE - 1) / PAGE_SIZE);
+                }
+                "nr_blocks" => options.max_pages = Some(parse_number(value)?),
+                "nr_inodes" => options.max_inodes = Some(parse_number(value)?),
+                "mode" => match u32::from_str_radix(value, 8) {
+                    Ok(mode) if mode <= 0o7777 => options.mode = Some(mode),
+                    _ => return Err(EINVAL),
+                },
+                _ => log::warn!("[TmpfsOptions::parse] ignore unknown option: {}", option),
+            }
+        }
+        Ok(options)
+    }
+}
+
+/// Every instance gets its own anonymous device number, like Linux does for virtual filesystems.
+static NEXT_MINOR: AtomicUsize = AtomicUsize::new(16);
+
+lazy_static! {
+    /// Every instance, mounted or not, for the reclaimer and `swapoff()`
+    static ref INSTANCES: Mutex<Vec<Weak<TmpFileSystem>>> = Mutex::new(Vec::new());
+}
+
+/// Every inode of every instance, including unlinked ones still open and memfd files,
+/// which the directory tree doesn't know about.
+pub fn all_inodes() -> Vec<Arc<TmpfsInode>> {
+    let instances: Vec<_> = INSTANCES
+        .lock()
+        .iter()
+        .filter_map(|fs| fs.upgrade())
+        .collect();
+    instances
+        .iter()
+        .flat_map(|fs| {
+            let inodes: Vec<_> = fs
+                .inodes
+                .lock()
+                .values()
+                .filter_map(|inode| inode.upgrade())
+                .collect();
+            inodes
+        })
+        .collect()
+}
+
+/// A limit and the usage counted against it, locked together so `remount()`
+/// can't lower the limit under a concurrent charge.
+struct Usage {
+    max: usize,
+    used: usize,
+}
+
+impl Usage {
+    fn new(max: usize) -> Mutex<Self> {
+        Mutex::new(Self { max, used: 0 })
+    }
+    fn charge(&mut self, count: usize) -> Result<(), isize> {
+        if count > self.max - self.used {
+            return Err(ENOSPC);
+        }
+        self.used += count;
+        Ok(())
+    }
+}
+
+/// An instance of tmpfs, every mount gets a new one.
+/// It keeps the limits and the usage, the data lives in the inodes.
+pub struct TmpFileSystem {
+    dev: u64,
+    page_usage: Mutex<Usage>,
+    inode_usage: Mutex<Usage>,
+    next_ino: AtomicUsize,
+    root_mode: u32,
+    /// Every inode by number, whether it has a name or not
+    inodes: Mutex<BTreeMap<usize, Weak<TmpfsInode>>>,
+}
+
+impl TmpFileSystem {
+    /// The root directory is given inode number 1
+    pub const ROOT_INO: usize = 1;
+
+    pub fn new(options: &TmpfsOptions) -> Arc<Self> {
+        // Linux defaults to half of the RAM for both limits
+        let default_limit = total_frames() / 2;
+        let minor = NEXT_MINOR.fetch_add(1, Ordering::Relaxed) as u64;
+        let fs = Arc::new(Self {
+            // `makedev!(0, minor)`, the macro only takes literals
+            dev: ((minor & 0xffffff00) << 12) | (minor & 0xff),
+            page_usage: Usage::new(options.max_pages.unwrap_or(default_limit)),
+            inode_usage: Usage::new(options.max_inodes.unwrap_or(default_limit)),
+            next_ino: AtomicUsize::new(Self::ROOT_INO),
+            root_mode: options.mode.unwrap_or(0o1777),
+            inodes: Mutex::new(BTreeMap::new()),
+        });
+        let mut instances = INSTANCES.lock();
+        instances.retain(|fs| fs.strong_count() > 0);
+        instances.push(Arc::downgrade(&fs));
+        fs
+    }
+    pub fn dev(&self) -> u64 {
+        self.dev
+    }
+    pub fn root_mode(&self) -> u32 {
+        self.root_mode
+    }
+    /// Apply the limits given to `MS_REMOUNT`, they can't go below the current usage.
+    pub fn remount(&self, options: &TmpfsOptions) -> Result<(), isize> {
+        // both are held, so neither limit changes unless both can
+        let mut pages = self.page_usage.lock();
+        let mut inodes = self.inode_usage.lock();
+        if options.max_pages.map_or(false, |max| max < pages.used)
+            || options.max_inodes.map_or(false, |max| max < inodes.used)
+        {
+            return Err(EINVAL);
+        }
+        if let Some(max_pages) = options.max_pages {
+            pages.max = max_pages;
+        }
+        if let Some(max_inodes) = options.max_inodes {
+            inodes.max = max_inodes;
+        }
+        Ok(())
+    }
+    /// Account `count` more pages of file data, `ENOSPC` if the limit would be exceeded.
+    pub fn charge_pages(&self, count: usize) -> Result<(), isize> {
+        self.page_usage.lock().charge(count)
+    }
+    pub fn uncharge_pages(&self, count: usize) {
+        self.page_usage.lock().used -= count;
+    }
+    /// Returns the number of the new inode, `ENOSPC` if there are too many inodes.
+    pub fn alloc_inode(&self) -> Result<usize, isize> {
+        self.inode_usage.lock().charge(1)?;
+        Ok(self.next_ino.fetch_add(1, Ordering::Relaxed))
+    }
+    /// Record the inode numbered `ino` just made by `alloc_inode()`
+    pub fn register_inode(&self, ino: usize, inode: &Arc<TmpfsInode>) {
+        self.inodes.lock().insert(ino, Arc::downgrade(inode));
+    }
+    pub fn free_inode(&self, ino: usize) {
+        self.inodes.lock().remove(&ino);
+        self.inode_usage.lock().used -= 1;
+    }
+}
@@ -0,0 +1,372 @@
+use crate::fs::directory_tree::DirectoryTreeNode;
+use crate::fs::file_trait::File;
+use crate::fs::*;
+use crate::mm::UserBuffer;
+use crate::syscall::errno::*;
+
+use super::vfs::TmpfsInode;
+use alloc::string::String;
+use alloc::sync::{Arc, Weak};
+use alloc::vec::Vec;
+use spin::Mutex;
+
+/// The tmpfs counterpart of `fat32::inode::OSInode`.
+pub struct TmpfsOSInode {
+    readable: bool,
+    writable: bool,
+    /// See `DirectoryTreeNode` for more details
+    special_use: bool,
+    append: bool,
+    inner: Arc<TmpfsInode>,
+    offset: Mutex<usize>,
+    dirnode_ptr: Arc<Mutex<Weak<DirectoryTreeNode>>>,
+    /// The directory and the name this file was reached through, needed by `unlink()`.
+    /// An inode may have several names, so this belongs to the file rather than to the inode.
+    location: Arc<Mutex<(Weak<TmpfsInode>, String)>>,
+}
+
+impl TmpfsOSInode {
+    pub fn new(root_inode: Arc<TmpfsInode>) -> Arc<dyn File> {
+        Arc::new(Self {
+            readable: true,
+            writable: true,
+            special_use: true,
+            append: false,
+            inner: root_inode,
+            offset: Mutex::new(0),
+            dirnode_ptr: Arc::new(Mutex::new(Weak::new())),
+            location: Arc::new(Mutex::new((Weak::new(), String::new()))),
+        })
+    }
+    fn from_inner(parent: &Arc<TmpfsInode>, name: &str, inner: Arc<TmpfsInode>) -> Arc<dyn File> {
+        Arc::new(Self {
+            readable: true,
+            writable: true,
+            special_use: false,
+            append: false,
+            inner,
+            offset: Mutex::new(0),
+            dirnode_ptr: Arc::new(Mutex::new(Weak::new())),
+            location: Arc::new(Mutex::new((Arc::downgrade(parent), name.into()))),
+        })
+    }
+    /// A file reached through no directory, e.g. one made by `memfd_create()`.
+    /// `name` is only shown in `/proc/<pid>/fd`.
+    pub fn new_unlinked(inner: Arc<TmpfsInode>, name: &str) -> Arc<dyn File> {
+        Arc::new(Self {
+            readable: true,
+            writable: true,
+            special_use: false,
+            append: false,
+            inner,
+            offset: Mutex::new(0),
+            dirnode_ptr: Arc::new(Mutex::new(Weak::new())),
+            location: Arc::new(Mutex::new((Weak::new(), name.into()))),
+        })
+    }
+    /// The last name this file was reached through
+    pub fn get_name(&self) -> String {
+        self.location.lock().1.clone()
+    }
+    pub fn get_inner(&self) -> &Arc<TmpfsInode> {
+        &self.inner
+    }
+    /// Make `name` in this directory a hard link to `child`, returns the file of the new name.
+    pub fn link(&self, name: &str, child: &Self) -> Result<Arc<dyn File>, isize> {
+        if child.inner.is_dir() {
+            return Err(EPERM);
+        }
+        self.inner.link(name, &child.inner)?;
+        Ok(Self::from_inner(&self.inner, name, child.inner.clone()))
+    }
+}
+
+impl Drop for TmpfsOSInode {
+    fn drop(&mut self) {
+        if self.special_use {
+            let inode = self.get_dirtree_node();
+            match inode {
+                Some(inode) => inode.sub_special_use(),
+                None => {}
+            }
+        }
+    }
+}
+
+#[allow(unused)]
+impl File for TmpfsOSInode {
+    fn deep_clone(&self) -> Arc<dyn File> {
+        if self.special_use {
+            let inode = self.get_dirtree_node();
+            match inode {
+                Some(inode) => inode.add_special_use(),
+                None => {}
+            }
+        }
+        Arc::new(Self {
+            readable: self.readable,
+            writable: self.writable,
+            special_use: self.special_use,
+            append: self.append,
+            inner: self.inner.clone(),
+            offset: Mutex::new(*self.offset.lock()),
+            dirnode_ptr: self.dirnode_ptr.clone(),
+            location: self.location.clone(),
+        })
+    }
+    fn readable(&self) -> bool {
+        self.readable
+    }
+    fn writable(&self) -> bool {
+        self.writable
+    }
+    fn read(&self, offset: Option<&mut usize>, buffer: &mut [u8]) -> usize {
+        match offset {
+            Some(offset) => {
+                let len = self.inner.read_at(*offset, buffer);
+                *offset += len;
+                len
+            }
+            None => {
+                let mut offset = self.offset.lock();
+                let len = self.inner.read_at(*offset, buffer);
+                *offset += len;
+                len
+            }
+        }
+    }
+    fn write(&self, offset: Option<&mut usize>, buffer: &[u8]) -> usize {
+        match offset {
+            Some(offset) => {
+                let len = self.inner.write_at(*offset, buffer).unwrap_or(0);
+                *offset += len;
+                len
+            }
+            None => {
+                let mut offset = self.offset.lock();
+                if self.append {
+                    *offset = self.inner.get_file_size();
+                }
+                let len = self.inner.write_at(*offset, buffer).unwrap_or(0);
+                *offset += len;
+                len
+            }
+        }
+    }
+    fn r_ready(&self) -> bool {
+        true
+    }
+    fn w_ready(&self) -> bool {
+        true
+    }
+    fn read_user(&self, offset: Option<usize>, mut buf: UserBuffer) -> usize {
+        let mut total_read_size = 0usize;
+        let mut offset_lock = self.offset.lock();
+        let use_file_offset = offset.is_none();
+        let mut offset = match offset {
+            Some(offset) => offset,
+            None => *offset_lock,
+        };
+        for slice in buf.buffers.iter_mut() {
+            let read_size = self.inner.read_at(offset, *slice);
+            offset += read_size;
+            total_read_size += read_size;
+            if read_size < slice.len() {
+                break;
+            }
+        }
+        if use_file_offset {
+            *offset_lock = offset;
+        }
+        total_read_size
+    }
+    fn write_user(&self, offset: Option<usize>, buf: UserBuffer) -> usize {
+        let mut total_write_size = 0usize;
+        let mut offset_lock = self.offset.lock();
+        let use_file_offset = offset.is_none();
+        let mut offset = match offset {
+            Some(offset) => offset,
+            None if self.append => self.inner.get_file_size(),
+            None => *offset_lock,
+        };
+        for slice in buf.buffers.iter() {
+            let write_size = match self.inner.write_at(offset, *slice) {
+                Ok(write_size) => write_size,
+                Err(errno) if total_write_size == 0 => return errno as usize,
+                Err(_) => break,
+            };
+            offset += write_size;
+            total_write_size += write_size;
+            if write_size < slice.len() {
+                break;
+            }
+        }
+        if use_file_offset {
+            *offset_lock = offset;
+        }
+        total_write_size
+    }
+    fn get_size(&self) -> usize {
+        self.inner.get_file_size()
+    }
+    fn get_stat(&self) -> Stat {
+        self.inner.stat()
+    }
+    fn get_file_type(&self) -> DiskInodeType {
+        self.inner.get_file_type()
+    }
+    fn info_dirtree_node(&self, dirnode_ptr: Weak<DirectoryTreeNode>) {
+        *self.dirnode_ptr.lock() = dirnode_ptr;
+    }
+    fn get_dirtree_node(&self) -> Option<Arc<DirectoryTreeNode>> {
+        self.dirnode_ptr.lock().upgrade()
+    }
+    fn open(&self, flags: OpenFlags, special_use: bool) -> Arc<dyn File> {
+        Arc::new(Self {
+            readable: flags.contains(OpenFlags::O_RDONLY) || flags.contains(OpenFlags::O_RDWR),
+            writable: flags.contains(OpenFlags::O_WRONLY) || flags.contains(OpenFlags::O_RDWR),
+            special_use,
+            append: flags.contains(OpenFlags::O_APPEND),
+            inner: self.inner.clone(),
+            offset: Mutex::new(0),
+            dirnode_ptr: self.dirnode_ptr.clone(),
+            location: self.location.clone(),
+        })
+    }
+    fn open_subfile(&self) -> Result<Vec<(String, Arc<dyn File>)>, isize> {
+        Ok(self
+            .inner
+            .get_all_files()?
+            .into_iter()
+            .map(|(name, inner)| {
+                let file = Self::from_inner(&self.inner, name.as_str(), inner);
+                (name, file)
+            })
+            .collect())
+    }
+    fn create(&self, name: &str, file_type: DiskInodeType) -> Result<Arc<dyn File>, isize> {
+        let child = self.inner.create(name, file_type)?;
+        Ok(Self::from_inner(&self.inner, name, child))
+    }
+    /// Used by `rename()`, after `child` was unlinked from its old directory
+    fn link_child(&self, name: &str, child: &Self) -> Result<(), isize>
+    where
+        Self: Sized,
+    {
+        self.inner.link(name, &child.inner)?;
+        *child.location.lock() = (Arc::downgrade(&self.inner), name.into());
+        Ok(())
+    }
+    fn symlink(&self, name: &str, target: &str) -> Result<Arc<dyn File>, isize> {
+        let child = self.inner.symlink(name, target)?;
+        Ok(Self::from_inner(&self.inner, name, child))
+    }
+    fn read_link(&self) -> Result<String, isize> {
+        self.inner.read_link()
+    }
+    /// Remove the name this file was reached through.
+    /// The data stays as long as the inode is open somewhere, so `delete` makes no difference.
+    fn unlink(&self, delete: bool) -> Result<(), isize> {
+        if delete && self.inner.is_dir() && !self.inner.is_empty_dir()? {
+            return Err(ENOTEMPTY);
+        }
+        let (parent, name) = self.location.lock().clone();
+        match parent.upgrade() {
+            Some(parent) => parent.unlink(name.as_str()).map(|_| ()),
+            // the root directory
+            None => Err(EBUSY),
+        }
+    }
+    /// `offset` of a directory counts entries rather than bytes
+    fn get_dirent(&self, count: usize) -> Vec<Dirent> {
+        let mut offset = self.offset.lock();
+        let dirents = match self.inner.dirents() {
+            Ok(dirents) => dirents,
+            Err(_) => return Vec::new(),
+        };
+        let start = *offset;
+        let vec: Vec<Dirent> = dirents
+            .iter()
+            .enumerate()
+            .skip(start)
+            .take(count / core::mem::size_of::<Dirent>())
+            .map(|(idx, (name, ino, d_type))| {
+                Dirent::new(*ino, (idx + 1) as isize, *d_type, name.as_str())
+            })
+            .collect();
+        *offset = start + vec.len();
+        vec
+    }
+    fn lseek(&self, offset: isize, whence: SeekWhence) -> Result<usize, isize> {
+        let new_offset = match whence {
+            SeekWhence::SEEK_SET => offset,
+            SeekWhence::SEEK_CUR => *self.offset.lock() as isize + offset,
+            SeekWhence::SEEK_END => self.inner.get_file_size() as isize + offset,
+            // whence is duplicated
+            _ => return Err(EINVAL),
+        };
+        let new_offset = match new_offset < 0 {
+            true => return Err(EINVAL),
+            false => new_offset as usize,
+        };
+        *self.offset.lock() = new_offset;
+        Ok(new_offset)
+    }
+    fn modify_size(&self, diff: isize) -> Result<(), isize> {
+        let old_size = self.inner.get_file_size();
+        self.inner.truncate((old_size as isize + diff) as usize)
+    }
+    fn truncate_size(&self, new_size: usize) -> Result<(), isize> {
+        self.inner.truncate(new_size)
+    }
+    fn set_timestamp(&self, ctime: Option<usize>, atime: Option<usize>, mtime: Option<usize>) {
+        self.inner.set_times(ctime, atime, mtime)
+    }
+    fn chmod(&self, mode: u32) -> Result<(), isize> {
+        self.inner.chmod(mode)
+    }
+    fn chown(&self, uid: Option<u32>, gid: Option<u32>) -> Result<(), isize> {
+        self.inner.chown(uid, gid);
+        Ok(())
+    }
+    /// Nothing to write back, the data only lives in memory
+    fn fsync(&self) -> Result<(), isize> {
+        Ok(())
+    }
+    fn get_xattr(&self, name: &str) -> Result<Vec<u8>, isize> {
+        self.inner.get_xattr(name)
+    }
+    fn set_xattr(&self, name: &str, value: &[u8], flags: XattrFlags) -> Result<(), isize> {
+        self.inner.set_xattr(name, value, flags)
+    }
+    fn list_xattr(&self) -> Result<Vec<String>, isize> {
+        Ok(self.inner.list_xattr())
+    }
+    fn remove_xattr(&self, name: &str) -> Result<(), isize> {
+        self.inner.remove_xattr(name)
+    }
+    fn get_seals(&self) -> Result<SealFlags, isize> {
+        self.inner.get_seals()
+    }
+    fn add_seals(&self, seals: SealFlags) -> Result<(), isize> {
+        self.inner.add_seals(seals)
+    }
+    fn get_single_cache(&self, offset: usize) -> Result<Arc<Mutex<PageCache>>, ()> {
+        if offset & 0xfff != 0 {
+            return Err(());
+        }
+        self.inner.get_single_cache(offset >> 12).map_err(|_| ())
+    }
+    fn get_all_caches(&self) -> Result<Vec<Arc<Mutex<PageCache>>>, ()> {
+        Ok(self.inner.get_all_cache())
+    }
+    fn oom(&self) -> usize {
+        self.inner.oom()
+    }
+    fn hang_up(&self) -> bool {
+        false
+    }
+    fn fcntl(&self, cmd: u32, arg: u32) -> isize {
+        EINVAL
+    }
+}
@@ -0,0 +1,54 @@
+//! `memfd_create()`: the files live in a tmpfs instance of their own, which is mounted nowhere.
+use super::{TmpFileSystem, TmpfsInode, TmpfsOSInode, TmpfsOptions};
+use crate::fs::{file_trait::File, SealFlags};
+use crate::syscall::errno::*;
+use crate::task::current_cred;
+use alloc::format;
+use alloc::sync::Arc;
+use lazy_static::*;
+
+bitflags! {
+    pub struct MemfdFlags: u32 {
+        const MFD_CLOEXEC       =   0x1;
+        const MFD_ALLOW_SEALING =   0x2;
+        const MFD_HUGETLB       =   0x4;
+        /// Clear the executable bits and seal them, implies `MFD_ALLOW_SEALING`
+        const MFD_NOEXEC_SEAL   =   0x8;
+        const MFD_EXEC          =   0x10;
+    }
+}
+
+/// Longest name, the "memfd:" prefix makes it `NAME_MAX`
+pub const MFD_NAME_MAX_LEN: usize = 249;
+
+lazy_static! {
+    static ref MEMFD_FS: Arc<TmpFileSystem> = TmpFileSystem::new(&TmpfsOptions::default());
+}
+
+pub fn memfd_create(name: &str, flags: MemfdFlags) -> Result<Arc<dyn File>, isize> {
+    if name.len() > MFD_NAME_MAX_LEN {
+        return Err(EINVAL);
+    }
+    // there is no hugetlbfs
+    if flags.contains(MemfdFlags::MFD_HUGETLB) {
+        return Err(EINVAL);
+    }
+    if flags.contains(MemfdFlags::MFD_EXEC | MemfdFlags::MFD_NOEXEC_SEAL) {
+        return Err(EINVAL);
+    }
+    let (mode, mut seals) = if flags.contains(MemfdFlags::MFD_NOEXEC_SEAL) {
+        (0o666, SealFlags::F_SEAL_EXEC)
+    } else {
+        (0o777, SealFlags::empty())
+    };
+    if !flags.intersects(MemfdFlags::MFD_ALLOW_SEALING | MemfdFlags::MFD_NOEXEC_SEAL) {
+        seals |= SealFlags::F_SEAL_SEAL;
+    }
+    let inode = TmpfsInode::create_unlinked(&MEMFD_FS, mode, seals)?;
+    let cred = current_cred();
+    inode.chown(Some(cred.euid), Some(cred.egid));
+    Ok(TmpfsOSInode::new_unlinked(
+        inode,
+        format!("memfd:{}", name).as_str(),
+    ))
+}
@@ -0,0 +1,10 @@
+//! tmpfs, a filesystem living in memory, mounted on `/tmp` and `/dev/shm`.
+//! File data is kept in `PageCache` frames and pushed to zram or swap under memory pressure.
+mod efs;
+pub mod inode;
+pub mod memfd;
+mod vfs;
+
+pub use efs::{all_inodes, TmpFileSystem, TmpfsOptions};
+pub use inode::TmpfsOSInode;
+pub use vfs::TmpfsInode;
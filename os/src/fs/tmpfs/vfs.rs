@@ -0,0 +1,641 @@
+use alloc::{
+    collections::BTreeMap,
+    string::{String, ToString},
+    sync::{Arc, Weak},
+    vec::Vec,
+};
+use spin::Mutex;
+
+use super::efs::TmpFileSystem;
+use crate::config::PAGE_SIZE;
+#[cfg(feature = "oom_handler")]
+use crate::fs::swap::{SwapTracker, SWAP_DEVICE};
+use crate::fs::{
+    cache::{Cache, PageCache},
+    DiskInodeType, SealFlags, Stat, StatMode, XattrFlags, DT_DIR, DT_LNK, DT_REG,
+};
+#[cfg(feature = "oom_handler")]
+use crate::mm::{unallocated_frames, ZramTracker, ZRAM_DEVICE};
+use crate::syscall::errno::*;
+use crate::timer::get_time_sec;
+
+/// Linux pretends every directory entry takes this many bytes
+const BOGO_DIRENT_SIZE: usize = 20;
+
+/// A page of file data.
+/// Under memory pressure it is compressed into zram, or written to swap once zram is full.
+enum TmpfsPage {
+    InMemory(Arc<Mutex<PageCache>>),
+    #[cfg(feature = "oom_handler")]
+    Compressed(Arc<ZramTracker>),
+    #[cfg(feature = "oom_handler")]
+    SwappedOut(Arc<SwapTracker>),
+}
+
+impl TmpfsPage {
+    fn zeroed() -> Self {
+        let mut page_cache = PageCache::new();
+        page_cache.modify(0, |page: &mut [u8; PAGE_SIZE]| page.fill(0));
+        TmpfsPage::InMemory(Arc::new(Mutex::new(page_cache)))
+    }
+    /// Bring the page back into memory if it was pushed out
+    #[allow(unreachable_code)]
+    fn get(&mut self) -> Arc<Mutex<PageCache>> {
+        let page_cache = match self {
+            TmpfsPage::InMemory(page_cache) => return page_cache.clone(),
+            #[cfg(feature = "oom_handler")]
+            TmpfsPage::Compressed(zram_tracker) => {
+                let mut page_cache = PageCache::new();
+                page_cache.modify(0, |page: &mut [u8; PAGE_SIZE]| {
+                    ZRAM_DEVICE.lock().read(zram_tracker.0, page).unwrap()
+                });
+                page_cache
+            }
+            #[cfg(feature = "oom_handler")]
+            TmpfsPage::SwappedOut(swap_tracker) => {
+                let mut page_cache = PageCache::new();
+                page_cache.modify(0, |page: &mut [u8; PAGE_SIZE]| {
+                    SWAP_DEVICE.lock().read(swap_tracker, page)
+                });
+                page_cache
+            }
+        };
+        let page_cache = Arc::new(Mutex::new(page_cache));
+        // the old tracker is dropped here, which releases the zram or swap slot
+        *self = TmpfsPage::InMemory(page_cache.clone());
+        page_cache
+    }
+    /// Push the page out of memory, returns whether a frame was released.
+    /// Pages that are mapped or being accessed are left alone.
+    #[cfg(feature = "oom_handler")]
+    fn evict(&mut self) -> bool {
+        let page_cache = match self {
+            TmpfsPage::InMemory(page_cache) => page_cache,
+            _ => return false,
+        };
+        if Arc::strong_count(page_cache) > 1 {
+            return false;
+        }
+        let locked = page_cache.lock();
+        // `get_tracker()` hands out one more reference of its own
+        if Arc::strong_count(&locked.get_tracker()) > 2 {
+            return false;
+        }
+        let evicted = locked.read(0, |page: &[u8; PAGE_SIZE]| {
+            if let Ok(zram_tracker) = ZRAM_DEVICE.lock().write(page) {
+                return Some(TmpfsPage::Compressed(zram_tracker));
+            }
+            SWAP_DEVICE.lock().write(page).map(TmpfsPage::SwappedOut)
+        });
+        drop(locked);
+        match evicted {
+            Some(evicted) => {
+                *self = evicted;
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+enum Content {
+    Directory(BTreeMap<String, Arc<TmpfsInode>>),
+    /// `None` stands for a hole, which reads as zeros and takes no memory
+    Regular {
+        size: usize,
+        pages: Vec<Option<TmpfsPage>>,
+    },
+    Symlink(String),
+}
+
+struct TmpfsInodeInner {
+    content: Content,
+    /// Number of names of a regular file or a symlink, directories compute theirs
+    nlink: usize,
+    /// Permission bits
+    mode: u32,
+    uid: u32,
+    gid: u32,
+    atime: usize,
+    mtime: usize,
+    ctime: usize,
+    /// Extended attributes by full name, namespace included
+    xattrs: BTreeMap<String, Vec<u8>>,
+    /// Only files made by `memfd_create()` may start without `F_SEAL_SEAL`
+    seals: SealFlags,
+}
+
+/// An inode of tmpfs, everything it holds lives in memory.
+/// It is freed, data included, when the last name is removed and the last user is gone.
+pub struct TmpfsInode {
+    fs: Arc<TmpFileSystem>,
+    ino: usize,
+    /// The directory holding a directory, for "..".
+    /// Directories can't be hard linked, so there is only one.
+    parent: Mutex<Weak<TmpfsInode>>,
+    inner: Mutex<TmpfsInodeInner>,
+}
+
+impl Drop for TmpfsInode {
+    fn drop(&mut self) {
+        if let Content::Regular { pages, .. } = &self.inner.get_mut().content {
+            self.fs
+                .uncharge_pages(pages.iter().filter(|page| page.is_some()).count());
+        }
+        self.fs.free_inode(self.ino);
+    }
+}
+
+impl TmpfsInode {
+    pub fn root_inode(fs: &Arc<TmpFileSystem>) -> Arc<Self> {
+        let mode = fs.root_mode();
+        Self::new(fs, Content::Directory(BTreeMap::new()), mode).unwrap()
+    }
+    /// A regular file without any name, see `memfd_create()`
+    pub fn create_unlinked(
+        fs: &Arc<TmpFileSystem>,
+        mode: u32,
+        seals: SealFlags,
+    ) -> Result<Arc<Self>, isize> {
+        let content = Content::Regular {
+            size: 0,
+            pages: Vec::new(),
+        };
+        let inode = Self::new(fs, content, mode)?;
+        inode.inner.lock().seals = seals;
+        Ok(inode)
+    }
+    fn new(fs: &Arc<TmpFileSystem>, content: Content, mode: u32) -> Result<Arc<Self>, isize> {
+        let ino = fs.alloc_inode()?;
+        let now = get_time_sec();
+        let inode = Arc::new(Self {
+            fs: fs.clone(),
+            ino,
+            parent: Mutex::new(Weak::new()),
+            inner: Mutex::new(TmpfsInodeInner {
+                content,
+                nlink: 0,
+                mode,
+                uid: 0,
+                gid: 0,
+                atime: now,
+                mtime: now,
+                ctime: now,
+                xattrs: BTreeMap::new(),
+                seals: SealFlags::F_SEAL_SEAL,
+            }),
+        });
+        fs.register_inode(ino, &inode);
+        Ok(inode)
+    }
+    pub fn get_fs(&self) -> &Arc<TmpFileSystem> {
+        &self.fs
+    }
+    pub fn get_ino(&self) -> usize {
+        self.ino
+    }
+    pub fn get_file_type(&self) -> DiskInodeType {
+        match self.inner.lock().content {
+            Content::Directory(_) => DiskInodeType::Directory,
+            Content::Regular { .. } => DiskInodeType::File,
+            Content::Symlink(_) => DiskInodeType::SymLink,
+        }
+    }
+    pub fn is_dir(&self) -> bool {
+        matches!(self.inner.lock().content, Content::Directory(_))
+    }
+    pub fn is_symlink(&self) -> bool {
+        matches!(self.inner.lock().content, Content::Symlink(_))
+    }
+    pub fn get_file_size(&self) -> usize {
+        match &self.inner.lock().content {
+            Content::Directory(children) => (children.len() + 2) * BOGO_DIRENT_SIZE,
+            Content::Regular { size, .. } => *size,
+            Content::Symlink(target) => target.len(),
+        }
+    }
+    pub fn stat(&self) -> Stat {
+        let inner = self.inner.lock();
+        let (file_type, nlink, size) = match &inner.content {
+            Content::Directory(children) => (
+                StatMode::S_IFDIR,
+                2 + children.values().filter(|child| child.is_dir()).count(),
+                (children.len() + 2) * BOGO_DIRENT_SIZE,
+            ),
+            Content::Regular { size, .. } => (StatMode::S_IFREG, inner.nlink, *size),
+            Content::Symlink(target) => (StatMode::S_IFLNK, inner.nlink, target.len()),
+        };
+        let mut stat = Stat::new(
+            self.fs.dev(),
+            self.ino as u64,
+            file_type.bits() | inner.mode,
+            nlink as u32,
+            0,
+            size as i64,
+            inner.atime as i64,
+            inner.mtime as i64,
+            inner.ctime as i64,
+        );
+        stat.set_owner(inner.uid, inner.gid);
+        stat
+    }
+    pub fn chmod(&self, mode: u32) -> Result<(), isize> {
+        let mut inner = self.inner.lock();
+        if inner.seals.contains(SealFlags::F_SEAL_EXEC) && (inner.mode ^ mode) & 0o111 != 0 {
+            return Err(EPERM);
+        }
+        inner.mode = mode & 0o7777;
+        inner.ctime = get_time_sec();
+        Ok(())
+    }
+    pub fn chown(&self, uid: Option<u32>, gid: Option<u32>) {
+        let mut inner = self.inner.lock();
+        if let Some(uid) = uid {
+            inner.uid = uid;
+        }
+        if let Some(gid) = gid {
+            inner.gid = gid;
+        }
+        inner.ctime = get_time_sec();
+    }
+    pub fn set_times(&self, ctime: Option<usize>, atime: Option<usize>, mtime: Option<usize>) {
+        let mut inner = self.inner.lock();
+        if let Some(ctime) = ctime {
+            inner.ctime = ctime;
+        }
+        if let Some(atime) = atime {
+            inner.atime = atime;
+        }
+        if let Some(mtime) = mtime {
+            inner.mtime = mtime;
+        }
+    }
+    pub fn get_xattr(&self, name: &str) -> Result<Vec<u8>, isize> {
+        self.inner.lock().xattrs.get(name).cloned().ok_or(ENODATA)
+    }
+    pub fn set_xattr(&self, name: &str, value: &[u8], flags: XattrFlags) -> Result<(), isize> {
+        let mut inner = self.inner.lock();
+        flags.check(inner.xattrs.contains_key(name))?;
+        inner.xattrs.insert(name.to_string(), value.to_vec());
+        inner.ctime = get_time_sec();
+        Ok(())
+    }
+    pub fn list_xattr(&self) -> Vec<String> {
+        self.inner.lock().xattrs.keys().cloned().collect()
+    }
+    pub fn remove_xattr(&self, name: &str) -> Result<(), isize> {
+        let mut inner = self.inner.lock();
+        inner.xattrs.remove(name).ok_or(ENODATA)?;
+        inner.ctime = get_time_sec();
+        Ok(())
+    }
+    pub fn get_seals(&self) -> Result<SealFlags, isize> {
+        let inner = self.inner.lock();
+        match inner.content {
+            Content::Regular { .. } => Ok(inner.seals),
+            _ => Err(EINVAL),
+        }
+    }
+    /// `F_SEAL_WRITE` can't be added while a page is mapped, `EBUSY` then.
+    /// Unlike Linux, read-only and private mappings count as well.
+    pub fn add_seals(&self, seals: SealFlags) -> Result<(), isize> {
+        let mut inner = self.inner.lock();
+        let pages = match &inner.content {
+            Content::Regular { pages, .. } => pages,
+            _ => return Err(EINVAL),
+        };
+        if inner.seals.contains(SealFlags::F_SEAL_SEAL) {
+            return Err(EPERM);
+        }
+        if seals.contains(SealFlags::F_SEAL_WRITE) && !inner.seals.contains(SealFlags::F_SEAL_WRITE)
+        {
+            let mapped = pages.iter().any(|page| match page {
+                // `get_tracker()` hands out one more reference of its own
+                Some(TmpfsPage::InMemory(page_cache)) => {
+                    Arc::strong_count(&page_cache.lock().get_tracker()) > 2
+                }
+                _ => false,
+            });
+            if mapped {
+                return Err(EBUSY);
+            }
+        }
+        inner.seals |= seals;
+        Ok(())
+    }
+    /// Read from `offset` until `buf` is full or EOF is reached
+    pub fn read_at(&self, offset: usize, buf: &mut [u8]) -> usize {
+        let mut inner = self.inner.lock();
+        let (size, pages) = match &mut inner.content {
+            Content::Regular { size, pages } => (*size, pages),
+            _ => return 0,
+        };
+        if offset >= size {
+            return 0;
+        }
+        let end = size.min(offset + buf.len());
+        let mut pos = offset;
+        while pos < end {
+            let page_offset = pos % PAGE_SIZE;
+            let len = (PAGE_SIZE - page_offset).min(end - pos);
+            let dst = &mut buf[pos - offset..pos - offset + len];
+            match &mut pages[pos / PAGE_SIZE] {
+                Some(page) => page.get().lock().read(0, |page: &[u8; PAGE_SIZE]| {
+                    dst.copy_from_slice(&page[page_offset..page_offset + len])
+                }),
+                None => dst.fill(0),
+            }
+            pos += len;
+        }
+        inner.atime = get_time_sec();
+        end - offset
+    }
+    /// Write `buf` at `offset`, the file grows if needed.
+    /// The write stops early once the size limit of the filesystem is reached,
+    /// it fails with `EPERM` if the seals forbid it.
+    pub fn write_at(&self, offset: usize, buf: &[u8]) -> Result<usize, isize> {
+        let mut inner = self.inner.lock();
+        let seals = inner.seals;
+        let (size, pages) = match &mut inner.content {
+            Content::Regular { size, pages } => (size, pages),
+            _ => return Ok(0),
+        };
+        let end = offset + buf.len();
+        if seals.intersects(SealFlags::F_SEAL_WRITE | SealFlags::F_SEAL_FUTURE_WRITE)
+            || seals.contains(SealFlags::F_SEAL_GROW) && end > *size
+        {
+            return Err(EPERM);
+        }
+        if pages.len() < (end + PAGE_SIZE - 1) / PAGE_SIZE {
+            pages.resize_with((end + PAGE_SIZE - 1) / PAGE_SIZE, || None);
+        }
+        let mut pos = offset;
+        let mut error = None;
+        while pos < end {
+            let page_offset = pos % PAGE_SIZE;
+            let len = (PAGE_SIZE - page_offset).min(end - pos);
+            let page = &mut pages[pos / PAGE_SIZE];
+            if page.is_none() {
+                if let Err(errno) = self.fs.charge_pages(1) {
+                    log::warn!("[TmpfsInode::write_at] tmpfs is full");
+                    error = Some(errno);
+                    break;
+                }
+                *page = Some(TmpfsPage::zeroed());
+            }
+            let src = &buf[pos - offset..pos - offset + len];
+            page.as_mut()
+                .unwrap()
+                .get()
+                .lock()
+                .modify(0, |page: &mut [u8; PAGE_SIZE]| {
+                    page[page_offset..page_offset + len].copy_from_slice(src)
+                });
+            pos += len;
+        }
+        if pos > *size {
+            *size = pos;
+        }
+        // drop the holes allocated past what was actually written
+        let used_pages = (*size + PAGE_SIZE - 1) / PAGE_SIZE;
+        pages.truncate(used_pages);
+        // a short count only when something was written, callers retry on 0 forever
+        if pos == offset {
+            if let Some(errno) = error {
+                return Err(errno);
+            }
+        }
+        let now = get_time_sec();
+        inner.mtime = now;
+        inner.ctime = now;
+        Ok(pos - offset)
+    }
+    /// Resize the file, growing it leaves a hole
+    pub fn truncate(&self, new_size: usize) -> Result<(), isize> {
+        let mut inner = self.inner.lock();
+        let seals = inner.seals;
+        let (size, pages) = match &mut inner.content {
+            Content::Regular { size, pages } => (size, pages),
+            Content::Directory(_) => return Err(EISDIR),
+            Content::Symlink(_) => return Err(EINVAL),
+        };
+        if new_size < *size && seals.contains(SealFlags::F_SEAL_SHRINK)
+            || new_size > *size && seals.contains(SealFlags::F_SEAL_GROW)
+        {
+            return Err(EPERM);
+        }
+        let new_pages = (new_size + PAGE_SIZE - 1) / PAGE_SIZE;
+        if new_pages < pages.len() {
+            let dropped = pages
+                .drain(new_pages..)
+                .filter(|page| page.is_some())
+                .count();
+            self.fs.uncharge_pages(dropped);
+        }
+        // the tail of the last page must read as zeros if the file grows again
+        if new_size < *size && new_size % PAGE_SIZE != 0 {
+            if let Some(page) = &mut pages[new_size / PAGE_SIZE] {
+                let page_offset = new_size % PAGE_SIZE;
+                page.get()
+                    .lock()
+                    .modify(0, |page: &mut [u8; PAGE_SIZE]| page[page_offset..].fill(0));
+            }
+        }
+        pages.resize_with(new_pages, || None);
+        *size = new_size;
+        let now = get_time_sec();
+        inner.mtime = now;
+        inner.ctime = now;
+        Ok(())
+    }
+    /// Every child, "." and ".." excluded
+    pub fn get_all_files(&self) -> Result<Vec<(String, Arc<Self>)>, isize> {
+        match &self.inner.lock().content {
+            Content::Directory(children) => Ok(children
+                .iter()
+                .map(|(name, child)| (name.clone(), child.clone()))
+                .collect()),
+            _ => Err(ENOTDIR),
+        }
+    }
+    /// Return the raw directory entries `(name, ino, d_type)`, "." and ".." included
+    pub fn dirents(&self) -> Result<Vec<(String, usize, u8)>, isize> {
+        let children = self.get_all_files()?;
+        let parent_ino = self
+            .parent
+            .lock()
+            .upgrade()
+            .map_or(self.ino, |parent| parent.ino);
+        let mut dirents = Vec::with_capacity(children.len() + 2);
+        dirents.push((".".to_string(), self.ino, DT_DIR));
+        dirents.push(("..".to_string(), parent_ino, DT_DIR));
+        for (name, child) in children {
+            let d_type = match child.inner.lock().content {
+                Content::Directory(_) => DT_DIR,
+                Content::Regular { .. } => DT_REG,
+                Content::Symlink(_) => DT_LNK,
+            };
+            dirents.push((name, child.ino, d_type));
+        }
+        Ok(dirents)
+    }
+    pub fn is_empty_dir(&self) -> Result<bool, isize> {
+        Ok(self.get_all_files()?.is_empty())
+    }
+    /// Add the entry `name` pointing to `child`
+    fn insert(self: &Arc<Self>, name: &str, child: &Arc<Self>) -> Result<(), isize> {
+        let mut inner = self.inner.lock();
+        let children = match &mut inner.content {
+            Content::Directory(children) => children,
+            _ => return Err(ENOTDIR),
+        };
+        if children.contains_key(name) {
+            return Err(EEXIST);
+        }
+        children.insert(name.to_string(), child.clone());
+        let now = get_time_sec();
+        inner.mtime = now;
+        inner.ctime = now;
+        drop(inner);
+
+        if child.is_dir() {
+            *child.parent.lock() = Arc::downgrade(self);
+        }
+        let mut child_inner = child.inner.lock();
+        child_inner.nlink += 1;
+        child_inner.ctime = now;
+        Ok(())
+    }
+    pub fn create(
+        self: &Arc<Self>,
+        name: &str,
+        file_type: DiskInodeType,
+    ) -> Result<Arc<Self>, isize> {
+        let (content, mode) = match file_type {
+            DiskInodeType::Directory => (Content::Directory(BTreeMap::new()), 0o777),
+            DiskInodeType::File => (
+                Content::Regular {
+                    size: 0,
+                    pages: Vec::new(),
+                },
+                0o666,
+            ),
+            // links are made by `symlink()`, they need a target
+            DiskInodeType::SymLink => return Err(EINVAL),
+        };
+        let child = Self::new(&self.fs, content, mode)?;
+        self.insert(name, &child)?;
+        Ok(child)
+    }
+    pub fn symlink(self: &Arc<Self>, name: &str, target: &str) -> Result<Arc<Self>, isize> {
+        let child = Self::new(&self.fs, Content::Symlink(target.to_string()), 0o777)?;
+        self.insert(name, &child)?;
+        Ok(child)
+    }
+    pub fn read_link(&self) -> Result<String, isize> {
+        match &self.inner.lock().content {
+            Content::Symlink(target) => Ok(target.clone()),
+            _ => Err(EINVAL),
+        }
+    }
+    /// Give `child` another name, used by both `link()` and `rename()`.
+    /// Only `rename()` may move a directory, see `TmpfsOSInode`.
+    pub fn link(self: &Arc<Self>, name: &str, child: &Arc<Self>) -> Result<(), isize> {
+        if !Arc::ptr_eq(&self.fs, &child.fs) {
+            return Err(EXDEV);
+        }
+        self.insert(name, child)
+    }
+    /// Remove the entry `name`.
+    /// The inode goes away with its last name, unless it is still open or mapped.
+    pub fn unlink(&self, name: &str) -> Result<Arc<Self>, isize> {
+        let mut inner = self.inner.lock();
+        let child = match &mut inner.content {
+            Content::Directory(children) => match children.remove(name) {
+                Some(child) => child,
+                None => return Err(ENOENT),
+            },
+            _ => return Err(ENOTDIR),
+        };
+        let now = get_time_sec();
+        inner.mtime = now;
+        inner.ctime = now;
+        drop(inner);
+
+        let mut child_inner = child.inner.lock();
+        child_inner.nlink -= 1;
+        child_inner.ctime = now;
+        drop(child_inner);
+        Ok(child)
+    }
+    /// The page `inner_cache_id` of the file, a hole is filled first.
+    pub fn get_single_cache(&self, inner_cache_id: usize) -> Result<Arc<Mutex<PageCache>>, isize> {
+        crate::mm::frame_reserve(1);
+        let mut inner = self.inner.lock();
+        let pages = match &mut inner.content {
+            Content::Regular { pages, .. } => pages,
+            _ => return Err(EINVAL),
+        };
+        if inner_cache_id >= pages.len() {
+            pages.resize_with(inner_cache_id + 1, || None);
+        }
+        let page = &mut pages[inner_cache_id];
+        if page.is_none() {
+            self.fs.charge_pages(1)?;
+            *page = Some(TmpfsPage::zeroed());
+        }
+        Ok(page.as_mut().unwrap().get())
+    }
+    /// Pages that are in memory right now
+    pub fn get_all_cache(&self) -> Vec<Arc<Mutex<PageCache>>> {
+        match &self.inner.lock().content {
+            Content::Regular { pages, .. } => pages
+                .iter()
+                .filter_map(|page| match page {
+                    Some(TmpfsPage::InMemory(page_cache)) => Some(page_cache.clone()),
+                    _ => None,
+                })
+                .collect(),
+            _ => Vec::new(),
+        }
+    }
+    /// Push the pages nobody is using to zram or swap.
+    /// Called while memory is being allocated, so a busy inode is skipped rather than waited for.
+    #[cfg(feature = "oom_handler")]
+    pub fn oom(&self) -> usize {
+        let mut inner = match self.inner.try_lock() {
+            Some(inner) => inner,
+            None => return 0,
+        };
+        match &mut inner.content {
+            Content::Regular { pages, .. } => pages
+                .iter_mut()
+                .flatten()
+                .map(|page| page.evict() as usize)
+                .sum(),
+            _ => 0,
+        }
+    }
+    #[cfg(not(feature = "oom_handler"))]
+    pub fn oom(&self) -> usize {
+        0
+    }
+    /// Read back the pages kept in swap area `swap_area`, for `swapoff()`.
+    #[cfg(feature = "oom_handler")]
+    pub fn swapoff(&self, swap_area: usize) -> Result<(), isize> {
+        let mut inner = self.inner.lock();
+        if let Content::Regular { pages, .. } = &mut inner.content {
+            for page in pages.iter_mut().flatten() {
+                match page {
+                    TmpfsPage::SwappedOut(swap_tracker) if swap_tracker.area == swap_area => {}
+                    _ => continue,
+                }
+                if unallocated_frames() == 0 {
+                    return Err(ENOMEM);
+                }
+                page.get();
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,52 @@
+//! Periodic writeback of dirty file data.
+//! There are no kernel threads, so the writeback task runs from the idle loop of the boot core,
+//! which it goes back to between two tasks, with no lock held and outside of any trap.
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use super::directory_tree::sync_all;
+use crate::timer::get_time_ms;
+
+/// How long data may stay dirty before the writeback task writes it, `vm.dirty_expire_centisecs`
+static DIRTY_EXPIRE_CENTISECS: AtomicUsize = AtomicUsize::new(3000);
+/// How often the writeback task wakes up, `vm.dirty_writeback_centisecs`.
+/// 0 disables it, data then only reaches the disk through `sync()` or when it is evicted.
+static DIRTY_WRITEBACK_CENTISECS: AtomicUsize = AtomicUsize::new(500);
+/// When the writeback task last ran, in milliseconds
+static LAST_WRITEBACK: AtomicUsize = AtomicUsize::new(0);
+
+pub fn dirty_expire_centisecs() -> usize {
+    DIRTY_EXPIRE_CENTISECS.load(Ordering::Relaxed)
+}
+
+pub fn set_dirty_expire_centisecs(centisecs: usize) {
+    DIRTY_EXPIRE_CENTISECS.store(centisecs, Ordering::Relaxed);
+}
+
+pub fn dirty_writeback_centisecs() -> usize {
+    DIRTY_WRITEBACK_CENTISECS.load(Ordering::Relaxed)
+}
+
+pub fn set_dirty_writeback_centisecs(centisecs: usize) {
+    DIRTY_WRITEBACK_CENTISECS.store(centisecs, Ordering::Relaxed);
+}
+
+/// Run the writeback task if it is due. Cheap enough to be called on every tick.
+pub fn writeback_tick() {
+    let interval = dirty_writeback_centisecs() * 10;
+    if interval == 0 {
+        return;
+    }
+    let now = get_time_ms();
+    let last = LAST_WRITEBACK.load(Ordering::Relaxed);
+    if now.saturating_sub(last) < interval {
+        return;
+    }
+    // whoever claims the interval runs the task, the others leave it
+    if LAST_WRITEBACK
+        .compare_exchange(last, now, Ordering::Relaxed, Ordering::Relaxed)
+        .is_err()
+    {
+        return;
+    }
+    sync_all(Some(dirty_expire_centisecs() * 10));
+}
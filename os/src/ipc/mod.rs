@@ -0,0 +1,207 @@
+//! System V IPC: shared memory, semaphores and message queues,
+//! and the key and permission handling their objects share.
+//! There is a single IPC namespace.
+pub mod msg;
+pub mod sem;
+pub mod shm;
+
+use crate::syscall::errno::*;
+use crate::task::{
+    block_current_and_run_next, current_task, wait_with_timeout, Access, Credentials,
+};
+use crate::timer::TimeSpec;
+use alloc::collections::BTreeMap;
+use alloc::sync::Arc;
+
+/// `key` which always creates a new object
+pub const IPC_PRIVATE: i32 = 0;
+
+pub const IPC_CREAT: u32 = 0o1000;
+pub const IPC_EXCL: u32 = 0o2000;
+pub const IPC_NOWAIT: u32 = 0o4000;
+
+pub const IPC_RMID: u32 = 0;
+pub const IPC_SET: u32 = 1;
+pub const IPC_STAT: u32 = 2;
+pub const IPC_INFO: u32 = 3;
+/// Set by libc in `cmd` to ask for the 64-bit structures, which are the only ones we have.
+pub const IPC_64: u32 = 0x100;
+
+/// `struct ipc64_perm`
+#[derive(Clone, Copy, Debug, Default)]
+#[repr(C)]
+pub struct IpcPerm {
+    pub key: i32,
+    pub uid: u32,
+    pub gid: u32,
+    pub cuid: u32,
+    pub cgid: u32,
+    pub mode: u32,
+    pub seq: u16,
+    __pad: u16,
+    __unused: [u64; 2],
+}
+
+impl IpcPerm {
+    /// Owned by and created by `cred`, only the low 9 bits of `mode` are kept.
+    pub fn new(key: i32, mode: u32, cred: &Credentials) -> Self {
+        Self {
+            key,
+            uid: cred.euid,
+            gid: cred.egid,
+            cuid: cred.euid,
+            cgid: cred.egid,
+            mode: mode & 0o777,
+            ..Default::default()
+        }
+    }
+    /// Check `access` against the mode, like `Credentials::may_access()` does for inodes.
+    /// The creator counts as owner too.
+    pub fn check(&self, cred: &Credentials, access: Access) -> Result<(), isize> {
+        if cred.is_root() {
+            return Ok(());
+        }
+        let granted = if cred.euid == self.uid || cred.euid == self.cuid {
+            self.mode >> 6
+        } else if cred.in_group(self.gid) || cred.in_group(self.cgid) {
+            self.mode >> 3
+        } else {
+            self.mode
+        } & 0o7;
+        if granted & access.bits() == access.bits() {
+            Ok(())
+        } else {
+            Err(EACCES)
+        }
+    }
+    /// `IPC_SET` and `IPC_RMID` are for root, the owner and the creator.
+    pub fn check_owner(&self, cred: &Credentials) -> Result<(), isize> {
+        if cred.is_root() || cred.euid == self.uid || cred.euid == self.cuid {
+            Ok(())
+        } else {
+            Err(EPERM)
+        }
+    }
+    /// `IPC_SET` takes the owner and the permission bits from `new`.
+    pub fn set(&mut self, new: &IpcPerm) {
+        self.uid = new.uid;
+        self.gid = new.gid;
+        self.mode = (self.mode & !0o777) | (new.mode & 0o777);
+    }
+}
+
+pub trait IpcObject {
+    fn perm(&self) -> &IpcPerm;
+}
+
+/// The objects of one IPC family, indexed by their id.
+/// Like Linux, an id is `seq * SEQ_MULTIPLIER + index`, so a recycled index doesn't bring back an old id.
+pub struct IpcIds<T: IpcObject> {
+    objects: BTreeMap<usize, T>,
+    max: usize,
+    seq: u16,
+}
+
+const SEQ_MULTIPLIER: usize = 32768;
+
+impl<T: IpcObject> IpcIds<T> {
+    /// `max` objects may exist at once, it must not exceed `SEQ_MULTIPLIER`.
+    pub fn new(max: usize) -> Self {
+        Self {
+            objects: BTreeMap::new(),
+            max,
+            seq: 0,
+        }
+    }
+    pub fn get(&self, id: usize) -> Result<&T, isize> {
+        self.objects.get(&id).ok_or(EINVAL)
+    }
+    pub fn get_mut(&mut self, id: usize) -> Result<&mut T, isize> {
+        self.objects.get_mut(&id).ok_or(EINVAL)
+    }
+    pub fn remove(&mut self, id: usize) -> Option<T> {
+        self.objects.remove(&id)
+    }
+    /// Number of objects in use
+    pub fn count(&self) -> usize {
+        self.objects.len()
+    }
+    pub fn values(&self) -> impl Iterator<Item = &T> {
+        self.objects.values()
+    }
+    pub fn values_mut(&mut self) -> impl Iterator<Item = &mut T> {
+        self.objects.values_mut()
+    }
+    /// The `*get()` lookup. `check` validates an existing object besides the permission bits,
+    /// `create` builds a new one for the given id and `IpcPerm::seq`.
+    pub fn get_or_create(
+        &mut self,
+        key: i32,
+        flags: u32,
+        cred: &Credentials,
+        check: impl FnOnce(&T) -> Result<(), isize>,
+        create: impl FnOnce(u16) -> Result<T, isize>,
+    ) -> Result<usize, isize> {
+        if key != IPC_PRIVATE {
+            if let Some((&id, object)) = self
+                .objects
+                .iter()
+                .find(|(_, object)| object.perm().key == key)
+            {
+                if flags & (IPC_CREAT | IPC_EXCL) == IPC_CREAT | IPC_EXCL {
+                    return Err(EEXIST);
+                }
+                // the permission bits in `flags` are what the caller intends to do
+                let access = (flags >> 6 | flags >> 3 | flags) & 0o7;
+                object
+                    .perm()
+                    .check(cred, Access::from_bits_truncate(access))?;
+                check(object)?;
+                return Ok(id);
+            }
+            if flags & IPC_CREAT == 0 {
+                return Err(ENOENT);
+            }
+        }
+        let index = match (0..self.max)
+            .find(|index| !self.objects.keys().any(|id| id % SEQ_MULTIPLIER == *index))
+        {
+            Some(index) => index,
+            None => return Err(ENOSPC),
+        };
+        let id = self.seq as usize * SEQ_MULTIPLIER + index;
+        let object = create(self.seq)?;
+        self.objects.insert(id, object);
+        self.seq = (self.seq + 1) % (i32::MAX as usize / SEQ_MULTIPLIER) as u16;
+        Ok(id)
+    }
+}
+
+/// For the pid fields of the objects
+fn current_tgid() -> i32 {
+    match current_task() {
+        Some(task) => task.tgid as i32,
+        None => 0,
+    }
+}
+
+/// Block the current task, which the caller has put on a wait queue of an object behind `guard`.
+/// `guard` is released meanwhile, the caller looks the object up again afterwards.
+/// # Return Value
+/// `EINTR` if a signal arrived, `EAGAIN` if `deadline` has passed.
+fn block_current<G>(guard: G, deadline: Option<TimeSpec>) -> Result<(), isize> {
+    if let Some(deadline) = deadline {
+        wait_with_timeout(Arc::downgrade(&current_task().unwrap()), deadline);
+    }
+    drop(guard);
+    block_current_and_run_next();
+    let task = current_task().unwrap();
+    let inner = task.acquire_inner_lock();
+    if !inner.sigpending.difference(inner.sigmask).is_empty() {
+        return Err(EINTR);
+    }
+    match deadline {
+        Some(deadline) if TimeSpec::now() >= deadline => Err(EAGAIN),
+        _ => Ok(()),
+    }
+}
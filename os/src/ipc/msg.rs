@@ -0,0 +1,259 @@
+//! Message queues. Senders wait for room in a queue and receivers for a matching message,
+//! each on their own wait queue.
+use super::{block_current, current_tgid, IpcIds, IpcObject, IpcPerm, IPC_NOWAIT};
+use crate::syscall::errno::*;
+use crate::task::{current_cred, current_task, Access, WaitQueue};
+use crate::timer::get_time_sec;
+use alloc::collections::VecDeque;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use lazy_static::*;
+use spin::Mutex;
+
+pub const MSG_INFO: u32 = 12;
+
+pub const MSG_NOERROR: u32 = 0o10000;
+pub const MSG_EXCEPT: u32 = 0o20000;
+pub const MSG_COPY: u32 = 0o40000;
+
+/// Size limit of a message
+pub const MSGMAX: usize = 8192;
+/// Default size limit of a queue, only root may raise `msg_qbytes` above it
+pub const MSGMNB: usize = 16384;
+pub const MSGMNI: usize = 32000;
+
+/// `struct msqid64_ds`
+#[derive(Clone, Copy, Debug, Default)]
+#[repr(C)]
+pub struct MsqidDs {
+    pub msg_perm: IpcPerm,
+    pub msg_stime: i64,
+    pub msg_rtime: i64,
+    pub msg_ctime: i64,
+    pub msg_cbytes: u64,
+    pub msg_qnum: u64,
+    pub msg_qbytes: u64,
+    pub msg_lspid: i32,
+    pub msg_lrpid: i32,
+    __unused: [u64; 2],
+}
+
+/// `struct msginfo`, for `IPC_INFO` and `MSG_INFO`
+#[derive(Clone, Copy, Debug, Default)]
+#[repr(C)]
+pub struct MsgInfo {
+    pub msgpool: i32,
+    pub msgmap: i32,
+    pub msgmax: i32,
+    pub msgmnb: i32,
+    pub msgmni: i32,
+    pub msgssz: i32,
+    pub msgtql: i32,
+    pub msgseg: u16,
+}
+
+struct Message {
+    mtype: isize,
+    text: Vec<u8>,
+}
+
+struct MsgQueue {
+    ds: MsqidDs,
+    messages: VecDeque<Message>,
+    /// Tasks waiting for room in the queue
+    senders: WaitQueue,
+    /// Tasks waiting for a message
+    receivers: WaitQueue,
+}
+
+impl IpcObject for MsgQueue {
+    fn perm(&self) -> &IpcPerm {
+        &self.ds.msg_perm
+    }
+}
+
+impl MsgQueue {
+    /// The first message `msgrcv()` takes for `msgtyp`
+    fn find(&self, msgtyp: isize, except: bool) -> Option<usize> {
+        let mut messages = self.messages.iter().enumerate();
+        let found = if msgtyp == 0 {
+            messages.next()
+        } else if msgtyp > 0 {
+            messages.find(|(_, message)| (message.mtype == msgtyp) != except)
+        } else {
+            // the lowest type up to `-msgtyp`, the first of them if there are several
+            messages
+                .filter(|(_, message)| message.mtype <= -msgtyp)
+                .min_by_key(|(_, message)| message.mtype)
+        };
+        found.map(|(index, _)| index)
+    }
+}
+
+lazy_static! {
+    static ref MSG_IDS: Mutex<IpcIds<MsgQueue>> = Mutex::new(IpcIds::new(MSGMNI));
+}
+
+/// `msgget()`, returns the id of the queue.
+pub fn get(key: i32, flags: u32) -> Result<usize, isize> {
+    let cred = current_cred();
+    MSG_IDS.lock().get_or_create(
+        key,
+        flags,
+        &cred,
+        |_| Ok(()),
+        |seq| {
+            let mut perm = IpcPerm::new(key, flags, &cred);
+            perm.seq = seq;
+            Ok(MsgQueue {
+                ds: MsqidDs {
+                    msg_perm: perm,
+                    msg_ctime: get_time_sec() as i64,
+                    msg_qbytes: MSGMNB as u64,
+                    ..Default::default()
+                },
+                messages: VecDeque::new(),
+                senders: WaitQueue::new(),
+                receivers: WaitQueue::new(),
+            })
+        },
+    )
+}
+
+/// `msgsnd()`
+pub fn send(id: usize, mtype: isize, text: Vec<u8>, flags: u32) -> Result<(), isize> {
+    if mtype < 1 || text.len() > MSGMAX {
+        return Err(EINVAL);
+    }
+    let mut waited = false;
+    loop {
+        let mut msg_ids = MSG_IDS.lock();
+        let queue = match msg_ids.get_mut(id) {
+            Ok(queue) => queue,
+            Err(_) if waited => return Err(EIDRM),
+            Err(errno) => return Err(errno),
+        };
+        queue.ds.msg_perm.check(&current_cred(), Access::WRITE)?;
+        let ds = &mut queue.ds;
+        if ds.msg_cbytes + text.len() as u64 <= ds.msg_qbytes && ds.msg_qnum < ds.msg_qbytes {
+            ds.msg_cbytes += text.len() as u64;
+            ds.msg_qnum += 1;
+            ds.msg_lspid = current_tgid();
+            ds.msg_stime = get_time_sec() as i64;
+            queue.messages.push_back(Message { mtype, text });
+            queue.receivers.wake_all();
+            return Ok(());
+        }
+        if flags & IPC_NOWAIT != 0 {
+            return Err(EAGAIN);
+        }
+        queue
+            .senders
+            .add_task(Arc::downgrade(&current_task().unwrap()));
+        block_current(msg_ids, None)?;
+        waited = true;
+    }
+}
+
+/// `msgrcv()`, returns the type and the text of the message, cut to `max_size` with `MSG_NOERROR`.
+pub fn receive(
+    id: usize,
+    msgtyp: isize,
+    max_size: usize,
+    flags: u32,
+) -> Result<(isize, Vec<u8>), isize> {
+    if flags & MSG_COPY != 0 {
+        return Err(ENOSYS);
+    }
+    let mut waited = false;
+    loop {
+        let mut msg_ids = MSG_IDS.lock();
+        let queue = match msg_ids.get_mut(id) {
+            Ok(queue) => queue,
+            Err(_) if waited => return Err(EIDRM),
+            Err(errno) => return Err(errno),
+        };
+        queue.ds.msg_perm.check(&current_cred(), Access::READ)?;
+        if let Some(index) = queue.find(msgtyp, flags & MSG_EXCEPT != 0) {
+            if queue.messages[index].text.len() > max_size && flags & MSG_NOERROR == 0 {
+                return Err(E2BIG);
+            }
+            let mut message = queue.messages.remove(index).unwrap();
+            let ds = &mut queue.ds;
+            ds.msg_cbytes -= message.text.len() as u64;
+            ds.msg_qnum -= 1;
+            ds.msg_lrpid = current_tgid();
+            ds.msg_rtime = get_time_sec() as i64;
+            queue.senders.wake_all();
+            message.text.truncate(max_size);
+            return Ok((message.mtype, message.text));
+        }
+        if flags & IPC_NOWAIT != 0 {
+            return Err(ENOMSG);
+        }
+        queue
+            .receivers
+            .add_task(Arc::downgrade(&current_task().unwrap()));
+        block_current(msg_ids, None)?;
+        waited = true;
+    }
+}
+
+/// `IPC_STAT`
+pub fn stat(id: usize) -> Result<MsqidDs, isize> {
+    let msg_ids = MSG_IDS.lock();
+    let queue = msg_ids.get(id)?;
+    queue.ds.msg_perm.check(&current_cred(), Access::READ)?;
+    Ok(queue.ds)
+}
+
+/// `IPC_SET`, which may change `msg_qbytes` as well
+pub fn set(id: usize, ds: &MsqidDs) -> Result<(), isize> {
+    let cred = current_cred();
+    let mut msg_ids = MSG_IDS.lock();
+    let queue = msg_ids.get_mut(id)?;
+    queue.ds.msg_perm.check_owner(&cred)?;
+    if ds.msg_qbytes > MSGMNB as u64 && !cred.is_root() {
+        return Err(EPERM);
+    }
+    queue.ds.msg_perm.set(&ds.msg_perm);
+    queue.ds.msg_qbytes = ds.msg_qbytes;
+    queue.ds.msg_ctime = get_time_sec() as i64;
+    // there may be room now
+    queue.senders.wake_all();
+    Ok(())
+}
+
+/// `IPC_RMID`, sleeping tasks fail with `EIDRM`.
+pub fn remove(id: usize) -> Result<(), isize> {
+    let mut msg_ids = MSG_IDS.lock();
+    msg_ids.get(id)?.ds.msg_perm.check_owner(&current_cred())?;
+    let mut queue = msg_ids.remove(id).unwrap();
+    queue.senders.wake_all();
+    queue.receivers.wake_all();
+    Ok(())
+}
+
+/// `IPC_INFO`, or `MSG_INFO` which reports the usage in `msgpool`, `msgmap` and `msgtql`
+pub fn info(usage: bool) -> MsgInfo {
+    let mut info = MsgInfo {
+        msgpool: (MSGMNI * MSGMNB / 1024) as i32,
+        msgmap: MSGMNB as i32,
+        msgmax: MSGMAX as i32,
+        msgmnb: MSGMNB as i32,
+        msgmni: MSGMNI as i32,
+        msgssz: 16,
+        msgtql: MSGMNB as i32,
+        msgseg: 0xffff,
+    };
+    if usage {
+        let msg_ids = MSG_IDS.lock();
+        info.msgpool = msg_ids.count() as i32;
+        info.msgmap = msg_ids.values().map(|queue| queue.ds.msg_qnum).sum::<u64>() as i32;
+        info.msgtql = msg_ids
+            .values()
+            .map(|queue| queue.ds.msg_cbytes)
+            .sum::<u64>() as i32;
+    }
+    info
+}
@@ -0,0 +1,408 @@
+//! Semaphore sets. A task whose `semop()` can't proceed sleeps on the wait queue of the set
+//! and tries again whenever the set changes.
+use super::{block_current, current_tgid, IpcIds, IpcObject, IpcPerm, IPC_NOWAIT};
+use crate::syscall::errno::*;
+use crate::task::{current_cred, current_task, Access, WaitQueue};
+use crate::timer::{get_time_sec, TimeSpec};
+use alloc::collections::BTreeMap;
+use alloc::sync::Arc;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use lazy_static::*;
+use spin::Mutex;
+
+pub const GETPID: u32 = 11;
+pub const GETVAL: u32 = 12;
+pub const GETALL: u32 = 13;
+pub const GETNCNT: u32 = 14;
+pub const GETZCNT: u32 = 15;
+pub const SETVAL: u32 = 16;
+pub const SETALL: u32 = 17;
+pub const SEM_INFO: u32 = 19;
+
+/// `sem_flg` of an operation to be reverted when the process exits
+pub const SEM_UNDO: u16 = 0x1000;
+
+pub const SEMMNI: usize = 32000;
+pub const SEMMSL: usize = 32000;
+pub const SEMMNS: usize = SEMMNI * SEMMSL;
+pub const SEMOPM: usize = 500;
+pub const SEMVMX: i32 = 32767;
+/// Bound of the `SEM_UNDO` adjustment of a semaphore
+pub const SEMAEM: i32 = SEMVMX;
+
+/// `struct semid64_ds`
+#[derive(Clone, Copy, Debug, Default)]
+#[repr(C)]
+pub struct SemidDs {
+    pub sem_perm: IpcPerm,
+    pub sem_otime: i64,
+    pub sem_ctime: i64,
+    pub sem_nsems: u64,
+    __unused: [u64; 2],
+}
+
+/// `struct seminfo`, for `IPC_INFO` and `SEM_INFO`
+#[derive(Clone, Copy, Debug, Default)]
+#[repr(C)]
+pub struct SemInfo {
+    pub semmap: i32,
+    pub semmni: i32,
+    pub semmns: i32,
+    pub semmnu: i32,
+    pub semmsl: i32,
+    pub semopm: i32,
+    pub semume: i32,
+    pub semusz: i32,
+    pub semvmx: i32,
+    pub semaem: i32,
+}
+
+/// `struct sembuf`
+#[derive(Clone, Copy, Debug, Default)]
+#[repr(C)]
+pub struct Sembuf {
+    pub sem_num: u16,
+    pub sem_op: i16,
+    pub sem_flg: u16,
+}
+
+#[derive(Clone, Copy, Default)]
+struct Semaphore {
+    val: i32,
+    /// The process which changed it last
+    pid: i32,
+    /// Tasks waiting for it to increase
+    ncnt: usize,
+    /// Tasks waiting for it to become zero
+    zcnt: usize,
+}
+
+struct SemSet {
+    ds: SemidDs,
+    sems: Vec<Semaphore>,
+    /// `SEM_UNDO` adjustments of each semaphore, by the id of the `SemUndoList` they belong to
+    undo: BTreeMap<usize, Vec<i32>>,
+    wait_queue: WaitQueue,
+}
+
+impl IpcObject for SemSet {
+    fn perm(&self) -> &IpcPerm {
+        &self.ds.sem_perm
+    }
+}
+
+impl SemSet {
+    /// Perform all of `sops` or none of them.
+    /// # Return Value
+    /// The operation which has to wait, if any
+    fn try_op<'a>(
+        &mut self,
+        sops: &'a [Sembuf],
+        undo: usize,
+        pid: i32,
+    ) -> Result<Option<&'a Sembuf>, isize> {
+        let mut vals: Vec<i32> = self.sems.iter().map(|sem| sem.val).collect();
+        for sop in sops {
+            let val = &mut vals[sop.sem_num as usize];
+            let op = sop.sem_op as i32;
+            if (op == 0 && *val != 0) || *val + op < 0 {
+                return Ok(Some(sop));
+            }
+            if *val + op > SEMVMX {
+                return Err(ERANGE);
+            }
+            *val += op;
+        }
+        if sops.iter().any(|sop| sop.sem_flg & SEM_UNDO != 0) {
+            let nsems = self.sems.len();
+            let adj = self.undo.entry(undo).or_insert_with(|| vec![0; nsems]);
+            let mut new_adj = adj.clone();
+            for sop in sops.iter().filter(|sop| sop.sem_flg & SEM_UNDO != 0) {
+                new_adj[sop.sem_num as usize] -= sop.sem_op as i32;
+                if new_adj[sop.sem_num as usize].abs() > SEMAEM {
+                    return Err(ERANGE);
+                }
+            }
+            *adj = new_adj;
+        }
+        for sop in sops {
+            self.sems[sop.sem_num as usize].pid = pid;
+        }
+        for (sem, val) in self.sems.iter_mut().zip(vals) {
+            sem.val = val;
+        }
+        Ok(None)
+    }
+    /// `SETVAL` and `SETALL` void the adjustments of the semaphores they set.
+    fn set_vals(&mut self, first: usize, vals: &[i32], pid: i32) {
+        for (num, &val) in (first..).zip(vals) {
+            self.sems[num].val = val;
+            self.sems[num].pid = pid;
+            for adj in self.undo.values_mut() {
+                adj[num] = 0;
+            }
+        }
+        self.ds.sem_ctime = get_time_sec() as i64;
+        self.wait_queue.wake_all();
+    }
+}
+
+lazy_static! {
+    static ref SEM_IDS: Mutex<IpcIds<SemSet>> = Mutex::new(IpcIds::new(SEMMNI));
+}
+
+static NEXT_UNDO_LIST: AtomicUsize = AtomicUsize::new(0);
+
+/// The `SEM_UNDO` adjustments of a process, shared by `CLONE_SYSVSEM`.
+/// The adjustments themselves are kept by the sets, under the id of this list.
+pub struct SemUndoList {
+    id: usize,
+    /// Processes sharing the list, whose last thread hasn't exited yet
+    processes: AtomicUsize,
+}
+
+impl SemUndoList {
+    pub fn new() -> Self {
+        Self {
+            id: NEXT_UNDO_LIST.fetch_add(1, Ordering::Relaxed),
+            processes: AtomicUsize::new(1),
+        }
+    }
+    pub fn id(&self) -> usize {
+        self.id
+    }
+    /// Another process shares the list, through `CLONE_SYSVSEM` without `CLONE_THREAD`
+    pub fn add_process(&self) {
+        self.processes.fetch_add(1, Ordering::Relaxed);
+    }
+    /// Called as the last thread of process `tgid` exits.
+    /// Once no process shares the list anymore, the operations done with `SEM_UNDO` are reverted.
+    /// Values are clamped to the valid range rather than waiting.
+    pub fn apply(&self, tgid: usize) {
+        if self.processes.fetch_sub(1, Ordering::AcqRel) != 1 {
+            return;
+        }
+        for set in SEM_IDS.lock().values_mut() {
+            let adj = match set.undo.remove(&self.id) {
+                Some(adj) => adj,
+                None => continue,
+            };
+            for (sem, adj) in set.sems.iter_mut().zip(adj) {
+                if adj != 0 {
+                    sem.val = (sem.val + adj).clamp(0, SEMVMX);
+                    sem.pid = tgid as i32;
+                }
+            }
+            set.ds.sem_otime = get_time_sec() as i64;
+            set.wait_queue.wake_all();
+        }
+    }
+}
+
+/// `semget()`, returns the id of the set.
+pub fn get(key: i32, nsems: usize, flags: u32) -> Result<usize, isize> {
+    if nsems > SEMMSL {
+        return Err(EINVAL);
+    }
+    let cred = current_cred();
+    SEM_IDS.lock().get_or_create(
+        key,
+        flags,
+        &cred,
+        |set| {
+            if nsems > set.sems.len() {
+                Err(EINVAL)
+            } else {
+                Ok(())
+            }
+        },
+        |seq| {
+            if nsems == 0 {
+                return Err(EINVAL);
+            }
+            let mut perm = IpcPerm::new(key, flags, &cred);
+            perm.seq = seq;
+            Ok(SemSet {
+                ds: SemidDs {
+                    sem_perm: perm,
+                    sem_ctime: get_time_sec() as i64,
+                    sem_nsems: nsems as u64,
+                    ..Default::default()
+                },
+                sems: vec![Semaphore::default(); nsems],
+                undo: BTreeMap::new(),
+                wait_queue: WaitQueue::new(),
+            })
+        },
+    )
+}
+
+/// `semtimedop()`, `deadline` is absolute. `undo` is the id of the `SemUndoList` of the caller.
+pub fn op(
+    id: usize,
+    sops: &[Sembuf],
+    deadline: Option<TimeSpec>,
+    undo: usize,
+) -> Result<(), isize> {
+    let alter = sops.iter().any(|sop| sop.sem_op != 0);
+    let pid = current_tgid();
+    // the semaphore we sleep on, whether we wait for zero, and how the sleep ended
+    let mut blocked: Option<(usize, bool, Result<(), isize>)> = None;
+    loop {
+        let mut sem_ids = SEM_IDS.lock();
+        let set = match sem_ids.get_mut(id) {
+            Ok(set) => set,
+            Err(_) if blocked.is_some() => return Err(EIDRM),
+            Err(errno) => return Err(errno),
+        };
+        if let Some((num, zero, slept)) = blocked.take() {
+            if zero {
+                set.sems[num].zcnt -= 1;
+            } else {
+                set.sems[num].ncnt -= 1;
+            }
+            slept?;
+        }
+        if sops
+            .iter()
+            .any(|sop| sop.sem_num as usize >= set.sems.len())
+        {
+            return Err(EFBIG);
+        }
+        let access = if alter { Access::WRITE } else { Access::READ };
+        set.ds.sem_perm.check(&current_cred(), access)?;
+        let sop = match set.try_op(sops, undo, pid)? {
+            Some(sop) => sop,
+            None => {
+                set.ds.sem_otime = get_time_sec() as i64;
+                if alter {
+                    set.wait_queue.wake_all();
+                }
+                return Ok(());
+            }
+        };
+        if u32::from(sop.sem_flg) & IPC_NOWAIT != 0 {
+            return Err(EAGAIN);
+        }
+        let num = sop.sem_num as usize;
+        let zero = sop.sem_op == 0;
+        if zero {
+            set.sems[num].zcnt += 1;
+        } else {
+            set.sems[num].ncnt += 1;
+        }
+        set.wait_queue
+            .add_task(Arc::downgrade(&current_task().unwrap()));
+        blocked = Some((num, zero, block_current(sem_ids, deadline)));
+    }
+}
+
+/// The number of semaphores in the set, to size the buffers of `GETALL` and `SETALL`
+pub fn nsems(id: usize) -> Result<usize, isize> {
+    Ok(SEM_IDS.lock().get(id)?.sems.len())
+}
+
+/// `IPC_STAT`
+pub fn stat(id: usize) -> Result<SemidDs, isize> {
+    let sem_ids = SEM_IDS.lock();
+    let set = sem_ids.get(id)?;
+    set.ds.sem_perm.check(&current_cred(), Access::READ)?;
+    Ok(set.ds)
+}
+
+/// `IPC_SET`
+pub fn set(id: usize, perm: &IpcPerm) -> Result<(), isize> {
+    let mut sem_ids = SEM_IDS.lock();
+    let set = sem_ids.get_mut(id)?;
+    set.ds.sem_perm.check_owner(&current_cred())?;
+    set.ds.sem_perm.set(perm);
+    set.ds.sem_ctime = get_time_sec() as i64;
+    Ok(())
+}
+
+/// `IPC_RMID`, sleeping tasks fail with `EIDRM`.
+pub fn remove(id: usize) -> Result<(), isize> {
+    let mut sem_ids = SEM_IDS.lock();
+    sem_ids.get(id)?.ds.sem_perm.check_owner(&current_cred())?;
+    let mut set = sem_ids.remove(id).unwrap();
+    set.wait_queue.wake_all();
+    Ok(())
+}
+
+/// `GETVAL`, `GETPID`, `GETNCNT` and `GETZCNT`
+pub fn get_one(id: usize, num: usize, cmd: u32) -> Result<isize, isize> {
+    let sem_ids = SEM_IDS.lock();
+    let set = sem_ids.get(id)?;
+    set.ds.sem_perm.check(&current_cred(), Access::READ)?;
+    let sem = set.sems.get(num).ok_or(EINVAL)?;
+    Ok(match cmd {
+        GETVAL => sem.val as isize,
+        GETPID => sem.pid as isize,
+        GETNCNT => sem.ncnt as isize,
+        GETZCNT => sem.zcnt as isize,
+        _ => return Err(EINVAL),
+    })
+}
+
+/// `GETALL`
+pub fn get_all(id: usize) -> Result<Vec<u16>, isize> {
+    let sem_ids = SEM_IDS.lock();
+    let set = sem_ids.get(id)?;
+    set.ds.sem_perm.check(&current_cred(), Access::READ)?;
+    Ok(set.sems.iter().map(|sem| sem.val as u16).collect())
+}
+
+/// `SETVAL`
+pub fn set_one(id: usize, num: usize, val: i32) -> Result<(), isize> {
+    if !(0..=SEMVMX).contains(&val) {
+        return Err(ERANGE);
+    }
+    let mut sem_ids = SEM_IDS.lock();
+    let set = sem_ids.get_mut(id)?;
+    set.ds.sem_perm.check(&current_cred(), Access::WRITE)?;
+    if num >= set.sems.len() {
+        return Err(EINVAL);
+    }
+    set.set_vals(num, &[val], current_tgid());
+    Ok(())
+}
+
+/// `SETALL`, `vals` holds a value for each semaphore.
+pub fn set_all(id: usize, vals: &[u16]) -> Result<(), isize> {
+    let vals: Vec<i32> = vals.iter().map(|&val| val as i32).collect();
+    if vals.iter().any(|&val| val > SEMVMX) {
+        return Err(ERANGE);
+    }
+    let mut sem_ids = SEM_IDS.lock();
+    let set = sem_ids.get_mut(id)?;
+    set.ds.sem_perm.check(&current_cred(), Access::WRITE)?;
+    if vals.len() != set.sems.len() {
+        return Err(EINVAL);
+    }
+    set.set_vals(0, &vals, current_tgid());
+    Ok(())
+}
+
+/// `IPC_INFO`, or `SEM_INFO` which reports the usage in `semusz` and `semaem`
+pub fn info(usage: bool) -> SemInfo {
+    let mut info = SemInfo {
+        semmap: SEMMNS as i32,
+        semmni: SEMMNI as i32,
+        semmns: SEMMNS as i32,
+        semmnu: SEMMNS as i32,
+        semmsl: SEMMSL as i32,
+        semopm: SEMOPM as i32,
+        semume: SEMOPM as i32,
+        semusz: 20,
+        semvmx: SEMVMX,
+        semaem: SEMAEM,
+    };
+    if usage {
+        let sem_ids = SEM_IDS.lock();
+        info.semusz = sem_ids.count() as i32;
+        info.semaem = sem_ids.values().map(|set| set.sems.len()).sum::<usize>() as i32;
+    }
+    info
+}
@@ -0,0 +1,247 @@
+//! Shared memory segments. The frames of a segment are allocated when it is created
+//! and mapped shared by every attachment, see `MemorySet::attach_shm()`.
+use super::{current_tgid, IpcIds, IpcObject, IpcPerm, IPC_PRIVATE};
+use crate::config::PAGE_SIZE;
+use crate::mm::{frame_alloc, FrameTracker, MapPermission};
+use crate::syscall::errno::*;
+use crate::task::{current_cred, Access};
+use crate::timer::get_time_sec;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use lazy_static::*;
+use spin::Mutex;
+
+pub const SHM_RDONLY: u32 = 0o10000;
+pub const SHM_RND: u32 = 0o20000;
+pub const SHM_REMAP: u32 = 0o40000;
+pub const SHM_EXEC: u32 = 0o100000;
+
+pub const SHM_LOCK: u32 = 11;
+pub const SHM_UNLOCK: u32 = 12;
+
+/// Attach addresses are rounded down to this by `SHM_RND`
+pub const SHMLBA: usize = PAGE_SIZE;
+pub const SHMMIN: usize = 1;
+pub const SHMMAX: usize = 0x1000_0000;
+pub const SHMMNI: usize = 4096;
+/// The segments are not charged to anything, this is only reported by `IPC_INFO`.
+pub const SHMALL: usize = SHMMAX / PAGE_SIZE * 16;
+
+/// Mode bits of a segment: destroy it with the last detach, and `SHM_LOCK`ed
+const SHM_DEST: u32 = 0o1000;
+const SHM_LOCKED: u32 = 0o2000;
+
+/// `struct shmid64_ds`
+#[derive(Clone, Copy, Debug, Default)]
+#[repr(C)]
+pub struct ShmidDs {
+    pub shm_perm: IpcPerm,
+    pub shm_segsz: usize,
+    pub shm_atime: i64,
+    pub shm_dtime: i64,
+    pub shm_ctime: i64,
+    pub shm_cpid: i32,
+    pub shm_lpid: i32,
+    pub shm_nattch: u64,
+    __unused: [u64; 2],
+}
+
+/// `struct shminfo64`, for `IPC_INFO`
+#[derive(Clone, Copy, Debug, Default)]
+#[repr(C)]
+pub struct ShmInfo {
+    pub shmmax: u64,
+    pub shmmin: u64,
+    pub shmmni: u64,
+    pub shmseg: u64,
+    pub shmall: u64,
+    __unused: [u64; 4],
+}
+
+struct ShmSegment {
+    ds: ShmidDs,
+    frames: Vec<Arc<FrameTracker>>,
+}
+
+impl IpcObject for ShmSegment {
+    fn perm(&self) -> &IpcPerm {
+        &self.ds.shm_perm
+    }
+}
+
+lazy_static! {
+    static ref SHM_IDS: Mutex<IpcIds<ShmSegment>> = Mutex::new(IpcIds::new(SHMMNI));
+}
+
+/// An attachment of a segment, kept by the `MapArea` it is mapped by.
+/// Cloning and dropping it keep `shm_nattch` up to date,
+/// so `fork()`, `exec()`, `exit()` and `munmap()` count attachments like Linux does.
+/// # Warning
+/// Must not be cloned or dropped while `SHM_IDS` is locked.
+pub struct ShmAttachment {
+    id: usize,
+    pages: usize,
+}
+
+impl ShmAttachment {
+    pub fn id(&self) -> usize {
+        self.id
+    }
+    /// Size of the whole segment in pages
+    pub fn pages(&self) -> usize {
+        self.pages
+    }
+}
+
+impl Clone for ShmAttachment {
+    fn clone(&self) -> Self {
+        // the segment stays while it is attached
+        SHM_IDS.lock().get_mut(self.id).unwrap().ds.shm_nattch += 1;
+        Self {
+            id: self.id,
+            pages: self.pages,
+        }
+    }
+}
+
+impl Drop for ShmAttachment {
+    fn drop(&mut self) {
+        let mut shm_ids = SHM_IDS.lock();
+        let segment = shm_ids.get_mut(self.id).unwrap();
+        segment.ds.shm_nattch -= 1;
+        segment.ds.shm_dtime = get_time_sec() as i64;
+        segment.ds.shm_lpid = current_tgid();
+        if segment.ds.shm_nattch == 0 && segment.ds.shm_perm.mode & SHM_DEST != 0 {
+            shm_ids.remove(self.id);
+        }
+    }
+}
+
+/// `shmget()`, returns the id of the segment.
+pub fn get(key: i32, size: usize, flags: u32) -> Result<usize, isize> {
+    let cred = current_cred();
+    SHM_IDS.lock().get_or_create(
+        key,
+        flags,
+        &cred,
+        |segment| {
+            if size > segment.ds.shm_segsz {
+                Err(EINVAL)
+            } else {
+                Ok(())
+            }
+        },
+        |seq| {
+            if size < SHMMIN || size > SHMMAX {
+                return Err(EINVAL);
+            }
+            let pages = (size + PAGE_SIZE - 1) / PAGE_SIZE;
+            let mut frames = Vec::with_capacity(pages);
+            for _ in 0..pages {
+                match frame_alloc() {
+                    Some(frame) => frames.push(frame),
+                    None => return Err(ENOMEM),
+                }
+            }
+            let mut perm = IpcPerm::new(key, flags, &cred);
+            perm.seq = seq;
+            Ok(ShmSegment {
+                ds: ShmidDs {
+                    shm_perm: perm,
+                    shm_segsz: size,
+                    shm_ctime: get_time_sec() as i64,
+                    shm_cpid: current_tgid(),
+                    ..Default::default()
+                },
+                frames,
+            })
+        },
+    )
+}
+
+/// The first half of `shmat()`, the caller maps the frames with the returned permission.
+pub fn attach(
+    id: usize,
+    flags: u32,
+) -> Result<(Vec<Arc<FrameTracker>>, MapPermission, ShmAttachment), isize> {
+    let mut access = Access::READ;
+    let mut map_perm = MapPermission::U | MapPermission::R;
+    if flags & SHM_RDONLY == 0 {
+        access |= Access::WRITE;
+        map_perm |= MapPermission::W;
+    }
+    if flags & SHM_EXEC != 0 {
+        access |= Access::EXEC;
+        map_perm |= MapPermission::X;
+    }
+    let mut shm_ids = SHM_IDS.lock();
+    let segment = shm_ids.get_mut(id)?;
+    segment.ds.shm_perm.check(&current_cred(), access)?;
+    segment.ds.shm_nattch += 1;
+    segment.ds.shm_atime = get_time_sec() as i64;
+    segment.ds.shm_lpid = current_tgid();
+    let attachment = ShmAttachment {
+        id,
+        pages: segment.frames.len(),
+    };
+    Ok((segment.frames.clone(), map_perm, attachment))
+}
+
+/// `IPC_STAT`
+pub fn stat(id: usize) -> Result<ShmidDs, isize> {
+    let shm_ids = SHM_IDS.lock();
+    let segment = shm_ids.get(id)?;
+    segment.ds.shm_perm.check(&current_cred(), Access::READ)?;
+    Ok(segment.ds)
+}
+
+/// `IPC_SET`
+pub fn set(id: usize, perm: &IpcPerm) -> Result<(), isize> {
+    let mut shm_ids = SHM_IDS.lock();
+    let segment = shm_ids.get_mut(id)?;
+    segment.ds.shm_perm.check_owner(&current_cred())?;
+    segment.ds.shm_perm.set(perm);
+    segment.ds.shm_ctime = get_time_sec() as i64;
+    Ok(())
+}
+
+/// `IPC_RMID`, the segment goes away once it is no longer attached.
+/// Its key is free for a new segment right away.
+pub fn remove(id: usize) -> Result<(), isize> {
+    let mut shm_ids = SHM_IDS.lock();
+    let segment = shm_ids.get_mut(id)?;
+    segment.ds.shm_perm.check_owner(&current_cred())?;
+    if segment.ds.shm_nattch == 0 {
+        shm_ids.remove(id);
+    } else {
+        segment.ds.shm_perm.key = IPC_PRIVATE;
+        segment.ds.shm_perm.mode |= SHM_DEST;
+        segment.ds.shm_ctime = get_time_sec() as i64;
+    }
+    Ok(())
+}
+
+/// `SHM_LOCK` and `SHM_UNLOCK` only keep the flag, shared pages are never swapped out anyway.
+pub fn lock(id: usize, lock: bool) -> Result<(), isize> {
+    let mut shm_ids = SHM_IDS.lock();
+    let segment = shm_ids.get_mut(id)?;
+    segment.ds.shm_perm.check_owner(&current_cred())?;
+    if lock {
+        segment.ds.shm_perm.mode |= SHM_LOCKED;
+    } else {
+        segment.ds.shm_perm.mode &= !SHM_LOCKED;
+    }
+    Ok(())
+}
+
+/// `IPC_INFO`
+pub fn info() -> ShmInfo {
+    ShmInfo {
+        shmmax: SHMMAX as u64,
+        shmmin: SHMMIN as u64,
+        shmmni: SHMMNI as u64,
+        shmseg: SHMMNI as u64,
+        shmall: SHMALL as u64,
+        ..Default::default()
+    }
+}
@@ -26,6 +26,7 @@ mod console;
 mod arch;
 mod drivers;
 mod fs;
+mod ipc;
 mod lang_items;
 mod mm;
 mod syscall;
@@ -34,7 +35,7 @@ mod timer;
 mod net;
 mod utils;
 
-use crate::arch::{bootstrap_init, machine_init};
+use crate::arch::{boot_secondary_cpus, bootstrap_init, machine_init, secondary_init};
 #[cfg(feature = "block_mem")]
 use crate::config::DISK_IMAGE_BASE;
 #[cfg(feature = "la64")]
@@ -109,6 +110,7 @@ pub fn rust_main() -> ! {
     #[cfg(not(feature = "block_mem"))]
     fs::flush_preload();
     task::add_initproc();
+    boot_secondary_cpus();
 
     // note that in run_tasks(), there is yet *another* pre_start_init(),
     // which is used to turn on interrupts in some archs like LoongArch.
@@ -116,5 +118,14 @@ pub fn rust_main() -> ! {
     panic!("Unreachable in rust_main!");
 }
 
+/// Where the other cores enter the kernel, see `_start_secondary`
+#[no_mangle]
+pub fn rust_main_secondary() -> ! {
+    secondary_init();
+    mm::KERNEL_SPACE.lock().activate();
+    task::run_tasks();
+    panic!("Unreachable in rust_main_secondary!");
+}
+
 #[cfg(test)]
 fn test_runner(_tests: &[&dyn Fn()]) {}
@@ -0,0 +1,71 @@
+//! Address space layout randomization, drawn anew by `execve()`.
+//!
+//! Like Linux, `/proc/sys/kernel/randomize_va_space` turns it off with 0,
+//! moves the stacks, the `mmap()` base and the load bias of position independent programs with 1,
+//! and the start of the heap as well with 2, the default.
+//! `personality(ADDR_NO_RANDOMIZE)` turns it off for a process and the programs it runs.
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::config::PAGE_SIZE;
+use crate::utils::random::get_random_u64;
+
+/// The `personality()` flag which turns randomization off
+pub const ADDR_NO_RANDOMIZE: u32 = 0x0040000;
+/// The `personality()` flags a set-user-ID or set-group-ID program doesn't inherit
+pub const PER_CLEAR_ON_SETID: u32 = 0x0040000 | 0x0200000 | 0x0400000 | 0x4000000;
+
+/// How far each part may move, in pages
+const STACK_RND_PAGES: usize = 1 << 18;
+const MMAP_RND_PAGES: usize = 1 << 18;
+const ELF_RND_PAGES: usize = 1 << 18;
+const BRK_RND_PAGES: usize = 1 << 13;
+
+static RANDOMIZE_VA_SPACE: AtomicUsize = AtomicUsize::new(2);
+
+pub fn randomize_va_space() -> usize {
+    RANDOMIZE_VA_SPACE.load(Ordering::Relaxed)
+}
+
+pub fn set_randomize_va_space(value: usize) {
+    RANDOMIZE_VA_SPACE.store(value.min(2), Ordering::Relaxed);
+}
+
+/// The offsets of an address space from the fixed layout, in bytes, page aligned
+#[derive(Clone, Copy, Default, Debug)]
+pub struct Aslr {
+    /// Down from `USER_STACK_BASE`, for every stack
+    pub stack: usize,
+    /// Up from `USR_MMAP_BASE`
+    pub mmap: usize,
+    /// Added to the load bias of a position independent executable run by an interpreter
+    pub elf: usize,
+    /// Added to the load bias of a program run without an interpreter, e.g. the interpreter itself
+    pub interp: usize,
+    /// Up from the end of the program
+    pub brk: usize,
+}
+
+impl Aslr {
+    /// Draw the offsets for a program run by a process with `personality`
+    pub fn new(personality: u32) -> Self {
+        let level = if personality & ADDR_NO_RANDOMIZE != 0 {
+            0
+        } else {
+            randomize_va_space()
+        };
+        let offset = |pages: usize, min_level: usize| {
+            if level >= min_level {
+                get_random_u64() as usize % pages * PAGE_SIZE
+            } else {
+                0
+            }
+        };
+        Self {
+            stack: offset(STACK_RND_PAGES, 1),
+            mmap: offset(MMAP_RND_PAGES, 1),
+            elf: offset(ELF_RND_PAGES, 1),
+            interp: offset(ELF_RND_PAGES, 1),
+            brk: offset(BRK_RND_PAGES, 2),
+        }
+    }
+}
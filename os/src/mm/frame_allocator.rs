@@ -1,9 +1,5 @@
-#[cfg(feature = "oom_handler")]
-use super::super::fs;
 use super::{PhysAddr, PhysPageNum};
 use crate::config::MEMORY_END;
-#[cfg(feature = "oom_handler")]
-use crate::task::current_task;
 // KISS
 use alloc::{sync::Arc, vec::Vec};
 use core::fmt::{self, Debug, Formatter};
@@ -137,37 +133,19 @@ pub fn init_frame_allocator() {
     );
 }
 
-/// Try to release `req` pages through all possible methods,
+/// Try to release `req` pages through direct reclaim,
 /// on success returns `Ok(())` else return `Err(())`.
 #[cfg(feature = "oom_handler")]
 pub fn oom_handler(req: usize) -> Result<(), ()> {
-    // step 1: clean fs
-    let mut released = 0;
-    released += fs::directory_tree::oom();
-    if released >= req {
-        return Ok(());
-    }
-    // step 2: clean current task's memory space
-    let task = current_task().unwrap();
-    if let Some(mut memory_set) = task.vm.try_lock() {
-        released += memory_set.do_shallow_clean();
-        log::warn!("[oom_handler] current task released: {}", released);
-    } else {
-        log::warn!("[oom_handler] try lock current task vm failed!");
-    }
-    if released >= req {
-        return Ok(());
-    }
-    // step 3: clean all tasks' memory space
-    log::warn!("[oom_handler] notify all tasks!");
-    crate::task::do_oom(req - released)
+    super::reclaim::direct_reclaim(req)
 }
 
 #[cfg(feature = "oom_handler")]
 pub fn frame_reserve(num: usize) {
     let remain = FRAME_ALLOCATOR.read().unallocated_frames();
+    // best effort, the OOM killer runs on entry to the trap handler
     if remain < num {
-        oom_handler(num - remain).unwrap()
+        let _ = oom_handler(num - remain);
     }
 }
 
@@ -184,7 +162,7 @@ pub fn frame_alloc() -> Option<Arc<FrameTracker>> {
         None => {
             crate::show_frame_consumption! {
                 "GC";
-                oom_handler(1).unwrap();
+                let _ = oom_handler(1);
             };
             FRAME_ALLOCATOR
                 .write()
@@ -210,7 +188,7 @@ pub unsafe fn frame_alloc_uninit() -> Option<Arc<FrameTracker>> {
         None => {
             crate::show_frame_consumption! {
                 "GC";
-                oom_handler(1).unwrap();
+                let _ = oom_handler(1);
             };
             FRAME_ALLOCATOR
                 .write()
@@ -236,6 +214,14 @@ pub fn unallocated_frames() -> usize {
     FRAME_ALLOCATOR.write().unallocated_frames()
 }
 
+/// Number of frames managed by the allocator, i.e. the memory not taken by the kernel image.
+pub fn total_frames() -> usize {
+    extern "C" {
+        fn ekernel();
+    }
+    PhysAddr::from(MEMORY_END).floor().0 - PhysAddr::from(ekernel as usize).ceil().0
+}
+
 #[allow(unused)]
 pub fn frame_allocator_test() {
     let mut v: Vec<Arc<FrameTracker>> = Vec::new();
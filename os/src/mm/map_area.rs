@@ -1,6 +1,8 @@
 use core::fmt::Debug;
 
 use super::page_table::PageTable;
+#[cfg(feature = "oom_handler")]
+use super::reclaim::{count_vm_events, ScanControl, VmEvent};
 #[cfg(feature = "zram")]
 use super::zram::{ZramTracker, ZRAM_DEVICE};
 use super::MemoryError;
@@ -8,17 +10,21 @@ use super::VPNRange;
 use super::KERNEL_SPACE;
 use super::{frame_alloc, FrameTracker};
 use super::{PhysPageNum, VirtAddr, VirtPageNum};
+#[cfg(feature = "oom_handler")]
+use crate::arch::tlb_shootdown;
 use crate::fs::file_trait::File;
 #[cfg(feature = "swap")]
 use crate::fs::swap::{SwapTracker, SWAP_DEVICE};
 use crate::fs::SeekWhence;
+use crate::ipc::shm::ShmAttachment;
 use crate::mm::frame_allocator::frame_alloc_uninit;
 
 #[cfg(feature = "oom_handler")]
-use alloc::collections::VecDeque;
+use alloc::collections::{BTreeSet, VecDeque};
 use alloc::sync::Arc;
 use alloc::vec::Vec;
 use log::{error, trace, warn};
+use num_enum::FromPrimitive;
 #[cfg(feature = "oom_handler")]
 #[derive(Clone, Debug)]
 pub enum Frame {
@@ -65,8 +71,12 @@ impl Frame {
         match self {
             Frame::InMemory(frame_ref) => {
                 if Arc::strong_count(frame_ref) == 1 {
-                    let swap_tracker = SWAP_DEVICE.lock().write(frame_ref.ppn.get_bytes_array());
-                    let swap_id = swap_tracker.0;
+                    let swap_tracker =
+                        match SWAP_DEVICE.lock().write(frame_ref.ppn.get_bytes_array()) {
+                            Some(swap_tracker) => swap_tracker,
+                            None => return Err(MemoryError::SwapIsFull),
+                        };
+                    let swap_id = swap_tracker.slot;
                     // frame_tracker should be dropped
                     *self = Frame::SwappedOut(swap_tracker);
                     Ok(swap_id)
@@ -77,31 +87,38 @@ impl Frame {
             _ => Err(MemoryError::NotInMemory),
         }
     }
-    /// # Warning
-    /// This function do not check reference count of frame,
-    /// So it's possible that some pages was write to external storage, but no page is released.
+    /// Move a compressed page on to swap, giving its room in zram back.
+    /// `buf` is a page sized scratch buffer.
     #[cfg(feature = "oom_handler")]
-    pub fn force_swap_out(&mut self) -> Result<usize, MemoryError> {
+    pub fn writeback(&mut self, buf: &mut [u8]) -> Result<usize, MemoryError> {
         match self {
-            Frame::InMemory(frame_ref) => {
-                let swap_tracker = SWAP_DEVICE.lock().write(frame_ref.ppn.get_bytes_array());
-                let swap_id = swap_tracker.0;
-                // frame_tracker should be dropped
+            Frame::Compressed(zram_tracker) => {
+                if Arc::strong_count(zram_tracker) > 1 {
+                    return Err(MemoryError::SharedPage);
+                }
+                ZRAM_DEVICE.lock().read(zram_tracker.0, buf).unwrap();
+                let swap_tracker = match SWAP_DEVICE.lock().write(buf) {
+                    Some(swap_tracker) => swap_tracker,
+                    None => return Err(MemoryError::SwapIsFull),
+                };
+                let swap_id = swap_tracker.slot;
+                // zram_tracker should be dropped
                 *self = Frame::SwappedOut(swap_tracker);
                 Ok(swap_id)
             }
-            _ => Err(MemoryError::NotInMemory),
+            _ => Err(MemoryError::NotCompressed),
         }
     }
     #[cfg(feature = "oom_handler")]
     pub fn swap_in(&mut self) -> Result<PhysPageNum, MemoryError> {
         match self {
             Frame::SwappedOut(swap_tracker) => {
-                let frame = frame_alloc().unwrap();
+                let frame = match frame_alloc() {
+                    Some(frame) => frame,
+                    None => return Err(MemoryError::OutOfMemory),
+                };
                 let ppn = frame.ppn;
-                SWAP_DEVICE
-                    .lock()
-                    .read(swap_tracker.0, ppn.get_bytes_array());
+                SWAP_DEVICE.lock().read(swap_tracker, ppn.get_bytes_array());
                 *self = Frame::InMemory(frame);
                 Ok(ppn)
             }
@@ -152,8 +169,15 @@ impl Frame {
 pub struct LinearMap {
     pub vpn_range: VPNRange,
     pub frames: Vec<Frame>,
+    /// Resident pages used lately, by index into `frames`
+    #[cfg(feature = "oom_handler")]
+    pub active: VecDeque<usize>,
+    /// Resident pages not used for a while, reclaim takes them first
+    #[cfg(feature = "oom_handler")]
+    pub inactive: VecDeque<usize>,
+    /// Pages given up by `MADV_FREE`, reclaim drops them if they were not written since
     #[cfg(feature = "oom_handler")]
-    pub active: VecDeque<u16>,
+    pub lazy_free: BTreeSet<usize>,
     #[cfg(feature = "oom_handler")]
     pub compressed: usize,
     #[cfg(feature = "oom_handler")]
@@ -166,6 +190,7 @@ impl Debug for LinearMap {
             .debug_struct("LinearMap")
             .field("vpn_range", &self.vpn_range)
             .field("active", &self.active.len())
+            .field("inactive", &self.inactive.len())
             .field("compressed", &self.compressed)
             .field("swapped", &self.swapped)
             .finish();
@@ -186,6 +211,10 @@ impl LinearMap {
             #[cfg(feature = "oom_handler")]
             active: VecDeque::new(),
             #[cfg(feature = "oom_handler")]
+            inactive: VecDeque::new(),
+            #[cfg(feature = "oom_handler")]
+            lazy_free: BTreeSet::new(),
+            #[cfg(feature = "oom_handler")]
             compressed: 0,
             #[cfg(feature = "oom_handler")]
             swapped: 0,
@@ -209,7 +238,7 @@ impl LinearMap {
     pub fn alloc_in_memory(&mut self, key: VirtPageNum, value: Arc<FrameTracker>) {
         let idx = key.0 - self.vpn_range.get_start().0;
         #[cfg(feature = "oom_handler")]
-        self.active.push_back(idx as u16);
+        self.active.push_back(idx);
         self.frames[idx].insert_in_memory(value).unwrap()
     }
     /// # Warning
@@ -217,9 +246,31 @@ impl LinearMap {
     pub fn remove_in_memory(&mut self, key: &VirtPageNum) -> Option<Arc<FrameTracker>> {
         let idx = key.0 - self.vpn_range.get_start().0;
         #[cfg(feature = "oom_handler")]
-        self.active.retain(|&elem| elem as usize != idx);
+        {
+            self.active.retain(|&elem| elem != idx);
+            self.inactive.retain(|&elem| elem != idx);
+        }
         self.frames[idx].take_in_memory()
     }
+    /// Forget the frame of `key`, wherever it is kept.
+    /// # Warning
+    /// a key which exceeds the end of `vpn_range` would cause panic
+    pub fn remove(&mut self, key: &VirtPageNum) {
+        let idx = key.0 - self.vpn_range.get_start().0;
+        match core::mem::replace(&mut self.frames[idx], Frame::Unallocated) {
+            #[cfg(feature = "oom_handler")]
+            Frame::InMemory(_) => {
+                self.active.retain(|&elem| elem != idx);
+                self.inactive.retain(|&elem| elem != idx);
+                self.lazy_free.remove(&idx);
+            }
+            #[cfg(feature = "oom_handler")]
+            Frame::Compressed(_) => self.compressed -= 1,
+            #[cfg(feature = "oom_handler")]
+            Frame::SwappedOut(_) => self.swapped -= 1,
+            _ => {}
+        }
+    }
     // /// # Warning
     // /// a key which exceeds the end of `vpn_range` would cause panic
     pub fn set_start(&mut self, new_vpn_start: VirtPageNum) -> Result<(), ()> {
@@ -262,6 +313,16 @@ impl LinearMap {
             LinearMap::split_active_into_two(&self.active, cut.0 - vpn_start.0),
             self.count_compressed_and_swapped(0, cut.0 - vpn_start.0),
         );
+        #[cfg(feature = "oom_handler")]
+        let (first_inactive, second_inactive) =
+            LinearMap::split_active_into_two(&self.inactive, cut.0 - vpn_start.0);
+        #[cfg(feature = "oom_handler")]
+        let second_lazy_free = self
+            .lazy_free
+            .split_off(&(cut.0 - vpn_start.0))
+            .into_iter()
+            .map(|idx| idx - (cut.0 - vpn_start.0))
+            .collect();
 
         let second = LinearMap {
             vpn_range: VPNRange::new(cut, vpn_end),
@@ -269,6 +330,10 @@ impl LinearMap {
             #[cfg(feature = "oom_handler")]
             active: second_active,
             #[cfg(feature = "oom_handler")]
+            inactive: second_inactive,
+            #[cfg(feature = "oom_handler")]
+            lazy_free: second_lazy_free,
+            #[cfg(feature = "oom_handler")]
             compressed: self.compressed - first_compressed,
             #[cfg(feature = "oom_handler")]
             swapped: self.swapped - first_swapped,
@@ -279,6 +344,7 @@ impl LinearMap {
         #[cfg(feature = "oom_handler")]
         {
             self.active = first_active;
+            self.inactive = first_inactive;
             self.compressed = first_compressed;
             self.swapped = first_swapped;
         }
@@ -314,19 +380,19 @@ impl LinearMap {
         }
     }
     fn split_active_into_two(
-        active: &VecDeque<u16>,
+        active: &VecDeque<usize>,
         cut_idx: usize,
-    ) -> (VecDeque<u16>, VecDeque<u16>) {
+    ) -> (VecDeque<usize>, VecDeque<usize>) {
         if active.is_empty() {
             (VecDeque::new(), VecDeque::new())
         } else {
             active.iter().fold(
                 (VecDeque::new(), VecDeque::new()),
                 |(mut first_active, mut second_active), &idx| {
-                    if (idx as usize) < cut_idx {
+                    if idx < cut_idx {
                         first_active.push_back(idx);
                     } else {
-                        second_active.push_back(idx - cut_idx as u16);
+                        second_active.push_back(idx - cut_idx);
                     }
                     (first_active, second_active)
                 },
@@ -335,10 +401,10 @@ impl LinearMap {
     }
     #[allow(unused)]
     fn split_active_into_three(
-        active: &VecDeque<u16>,
+        active: &VecDeque<usize>,
         first_cut_idx: usize,
         second_cut_idx: usize,
-    ) -> (VecDeque<u16>, VecDeque<u16>, VecDeque<u16>) {
+    ) -> (VecDeque<usize>, VecDeque<usize>, VecDeque<usize>) {
         assert!(first_cut_idx < second_cut_idx);
         if active.is_empty() {
             (VecDeque::new(), VecDeque::new(), VecDeque::new())
@@ -346,12 +412,12 @@ impl LinearMap {
             active.iter().fold(
                 (VecDeque::new(), VecDeque::new(), VecDeque::new()),
                 |(mut first_active, mut second_active, mut third_active), &idx| {
-                    if (idx as usize) < first_cut_idx {
+                    if idx < first_cut_idx {
                         first_active.push_back(idx);
-                    } else if (idx as usize) < second_cut_idx {
-                        second_active.push_back(idx - first_cut_idx as u16);
+                    } else if idx < second_cut_idx {
+                        second_active.push_back(idx - first_cut_idx);
                     } else {
-                        third_active.push_back(idx - second_cut_idx as u16)
+                        third_active.push_back(idx - second_cut_idx)
                     }
                     (first_active, second_active, third_active)
                 },
@@ -369,6 +435,7 @@ impl Debug for MapArea {
                 "map_file",
                 &if self.map_file.is_some() { "yes" } else { "no" },
             )
+            .field("shared", &self.shared)
             .finish()
     }
 }
@@ -384,6 +451,10 @@ pub struct MapArea {
     /// Permissions which are the or of RWXU, where U stands for user.
     pub map_perm: MapPermission,
     pub map_file: Option<Arc<dyn File>>,
+    /// `MAP_SHARED`, the pages are never copied on write and stores reach the file.
+    pub shared: bool,
+    /// Set if this maps a SysV shared memory segment, which is then `shared`.
+    pub shm: Option<ShmAttachment>,
 }
 
 impl MapArea {
@@ -408,6 +479,8 @@ impl MapArea {
             map_type,
             map_perm,
             map_file,
+            shared: false,
+            shm: None,
         }
     }
     /// Copier, but the physical pages are not allocated,
@@ -421,6 +494,8 @@ impl MapArea {
             map_type: another.map_type,
             map_perm: another.map_perm,
             map_file: another.map_file.clone(),
+            shared: another.shared,
+            shm: another.shm.clone(),
         }
     }
     /// Create `MapArea` from `Vec<Arc<FrameTracker>>`. This function should only be used to
@@ -444,12 +519,16 @@ impl MapArea {
                 frames,
                 // Unsafe if this `MapArea` is inserted to somewhere except `KERNEL_SPACE`.
                 active: VecDeque::new(),
+                inactive: VecDeque::new(),
+                lazy_free: BTreeSet::new(),
                 compressed: 0,
                 swapped: 0,
             },
             map_type,
             map_perm,
             map_file: None,
+            shared: false,
+            shm: None,
         }
     }
     #[cfg(not(feature = "oom_handler"))]
@@ -469,6 +548,8 @@ impl MapArea {
             map_type,
             map_perm,
             map_file: None,
+            shared: false,
+            shm: None,
         }
     }
     /// Map an included page in current area.
@@ -547,6 +628,7 @@ impl MapArea {
     /// Convert map areas to physical pages.
     /// # Of Course...
     /// Since the area is shared, the pages have been allocated.
+    /// Unless the area is `shared`, W is revoked on both sides for copy on write.
     /// # Argument
     /// `dst_page_table`: The destination to be mapped into.
     /// `src_page_table`: The source to be mapped from. This is also the page table where `self` should be included.
@@ -555,9 +637,18 @@ impl MapArea {
         dst_page_table: &mut T,
         src_page_table: &mut T,
     ) -> Result<(), ()> {
-        let map_perm = self.map_perm.difference(MapPermission::W);
+        let map_perm = if self.shared {
+            self.map_perm
+        } else {
+            self.map_perm.difference(MapPermission::W)
+        };
         for vpn in self.inner.vpn_range {
-            if let Some(ppn) = src_page_table.block_and_ret_mut(vpn) {
+            let ppn = if self.shared {
+                src_page_table.translate(vpn)
+            } else {
+                src_page_table.block_and_ret_mut(vpn)
+            };
+            if let Some(ppn) = ppn {
                 if !dst_page_table.is_mapped(vpn) {
                     dst_page_table.map(vpn, ppn, map_perm);
                 } else {
@@ -655,6 +746,117 @@ impl MapArea {
             Ok(new_ppn)
         }
     }
+    /// Let a store to a mapped page of a `shared` area go ahead, the page is not copied.
+    /// It faults when the page was cleaned by `sync_shared()` or W was revoked by `fork()` or `mprotect()`.
+    pub fn write_shared<T: PageTable>(
+        &self,
+        page_table: &mut T,
+        vpn: VirtPageNum,
+    ) -> Result<PhysPageNum, MemoryError> {
+        let ppn = page_table.translate(vpn).ok_or(MemoryError::NotMapped)?;
+        self.mark_page_cache_dirty(vpn, ppn);
+        page_table.set_pte_flags(vpn, self.map_perm).unwrap();
+        Ok(ppn)
+    }
+    /// Hand the stores through a shared file mapping in `[start_vpn, end_vpn)` over to the page cache.
+    /// The pages are cleaned, so that the next store faults and is noticed again.
+    pub fn sync_shared<T: PageTable>(
+        &self,
+        page_table: &mut T,
+        start_vpn: VirtPageNum,
+        end_vpn: VirtPageNum,
+    ) {
+        if !self.shared || self.map_file.is_none() {
+            return;
+        }
+        for vpn in VPNRange::new(start_vpn, end_vpn) {
+            if page_table.is_dirty(vpn) != Some(true) {
+                continue;
+            }
+            self.mark_page_cache_dirty(vpn, page_table.translate(vpn).unwrap());
+            page_table.clear_dirty_bit(vpn).unwrap();
+        }
+    }
+    /// `ppn` is the page mapped at `vpn`, it is left alone if it is no longer the cached page,
+    /// e.g. after the file was truncated.
+    fn mark_page_cache_dirty(&self, vpn: VirtPageNum, ppn: PhysPageNum) {
+        let file = match &self.map_file {
+            Some(file) => file,
+            None => return,
+        };
+        let offset = file.get_offset() + VirtAddr::from(vpn).0
+            - VirtAddr::from(self.inner.vpn_range.get_start()).0;
+        if let Ok(page_cache) = file.get_single_cache(offset) {
+            let mut page_cache = page_cache.lock();
+            if page_cache.get_tracker().ppn == ppn {
+                page_cache.mark_dirty();
+            }
+        }
+    }
+    /// `MADV_DONTNEED`: give up the pages in `[start_vpn, end_vpn)`,
+    /// the next access finds them zeroed or reads them from the file again.
+    /// Shared anonymous pages have nowhere else to live, so they are kept.
+    pub fn dont_need<T: PageTable>(
+        &mut self,
+        page_table: &mut T,
+        start_vpn: VirtPageNum,
+        end_vpn: VirtPageNum,
+    ) {
+        if self.shared && self.map_file.is_none() {
+            return;
+        }
+        self.sync_shared(page_table, start_vpn, end_vpn);
+        for vpn in VPNRange::new(start_vpn, end_vpn) {
+            if page_table.is_mapped(vpn) {
+                page_table.unmap(vpn);
+            }
+            self.inner.remove(&vpn);
+        }
+    }
+    /// `MADV_FREE` on a private anonymous area: the pages in `[start_vpn, end_vpn)` are cleaned,
+    /// and reclaim drops them instead of swapping them out unless they are written again.
+    /// Pages which are already compressed or swapped out are dropped right away.
+    #[cfg(feature = "oom_handler")]
+    pub fn lazy_free<T: PageTable>(
+        &mut self,
+        page_table: &mut T,
+        start_vpn: VirtPageNum,
+        end_vpn: VirtPageNum,
+    ) {
+        let area_start_vpn = self.inner.vpn_range.get_start();
+        for vpn in VPNRange::new(start_vpn, end_vpn) {
+            let idx = vpn.0 - area_start_vpn.0;
+            match self.inner.frames[idx] {
+                Frame::InMemory(_) => {
+                    page_table.clear_dirty_bit(vpn).unwrap();
+                    self.inner.lazy_free.insert(idx);
+                }
+                Frame::Compressed(_) | Frame::SwappedOut(_) => self.inner.remove(&vpn),
+                Frame::Unallocated => {}
+            }
+        }
+    }
+    /// Move the whole area to `new_start_vpn`, taking the mapped pages along.
+    /// The new range must not overlap any area. Shared pages should be synced before.
+    pub fn move_to<T: PageTable>(&mut self, page_table: &mut T, new_start_vpn: VirtPageNum) {
+        let old_range = self.inner.vpn_range;
+        let new_end_vpn =
+            VirtPageNum::from(new_start_vpn.0 + old_range.get_end().0 - old_range.get_start().0);
+        let new_range = VPNRange::new(new_start_vpn, new_end_vpn);
+        for (old_vpn, new_vpn) in old_range.into_iter().zip(new_range) {
+            if let Some(ppn) = page_table.translate(old_vpn) {
+                // keep W revoked on pages still waiting for copy on write
+                let map_perm = if page_table.writable(old_vpn) == Some(true) {
+                    self.map_perm
+                } else {
+                    self.map_perm.difference(MapPermission::W)
+                };
+                page_table.unmap(old_vpn);
+                page_table.map(new_vpn, ppn, map_perm);
+            }
+        }
+        self.inner.vpn_range = new_range;
+    }
     /// If `new_end` is equal to the current end of area, do nothing and return `Ok(())`.
     pub fn expand_to<T: PageTable>(&mut self, new_end: VirtAddr) -> Result<(), ()> {
         let new_end_vpn: VirtPageNum = new_end.ceil();
@@ -778,6 +980,8 @@ impl MapArea {
             map_type: self.map_type,
             map_perm: self.map_perm,
             map_file: second_file,
+            shared: self.shared,
+            shm: self.shm.clone(),
         })
     }
     pub fn into_three(
@@ -796,71 +1000,186 @@ impl MapArea {
                 map_type: self.map_type,
                 map_perm: self.map_perm,
                 map_file: None,
+                shared: self.shared,
+                shm: self.shm.clone(),
             },
             MapArea {
                 inner: third_frames,
                 map_type: self.map_type,
                 map_perm: self.map_perm,
                 map_file: None,
+                shared: self.shared,
+                shm: self.shm.clone(),
             },
         ))
     }
+    /// Whether the page at `idx` was used since the last look, which is forgotten then.
+    /// On LA only stores count, see `LAFlexPageTableEntry::is_accessed()`.
+    /// A page given up by `MADV_FREE` which was written is wanted after all.
+    #[cfg(feature = "oom_handler")]
+    fn test_and_clear_referenced<T: PageTable>(&mut self, page_table: &mut T, idx: usize) -> bool {
+        let vpn = VirtPageNum::from(self.inner.vpn_range.get_start().0 + idx);
+        if page_table.is_accessed(vpn) != Some(true) {
+            return false;
+        }
+        if page_table.is_dirty(vpn) == Some(true) {
+            self.inner.lazy_free.remove(&idx);
+        }
+        page_table.clear_access_bit(vpn).unwrap();
+        true
+    }
+    /// Indices on the lists go stale when the area is resized, so they are checked before use.
+    #[cfg(feature = "oom_handler")]
+    fn is_resident(&self, idx: usize) -> bool {
+        matches!(self.inner.frames.get(idx), Some(Frame::InMemory(_)))
+    }
+    /// Move the pages not used since the last scan from the active to the inactive list.
+    /// Returns the number of pages moved.
     #[cfg(feature = "oom_handler")]
-    pub fn do_oom<T: PageTable>(&mut self, page_table: &mut T) -> usize {
+    pub fn age_active<T: PageTable>(&mut self, page_table: &mut T) -> usize {
+        let mut deactivated = 0;
+        for _ in 0..self.inner.active.len() {
+            let idx = self.inner.active.pop_front().unwrap();
+            if !self.is_resident(idx) {
+                continue;
+            }
+            if self.test_and_clear_referenced(page_table, idx) {
+                self.inner.active.push_back(idx);
+            } else {
+                self.inner.inactive.push_back(idx);
+                deactivated += 1;
+            }
+        }
+        deactivated
+    }
+    /// Reclaim the pages on the inactive list which were not used since they got there, until `sc` is done.
+    /// A page given up by `MADV_FREE` is dropped, others are compressed, or swapped out once zram is full.
+    /// Pages which were used go back to the active list, those still shared after `fork()` stay.
+    ///
+    /// Like Linux, the pages of a batch are unmapped and shot down from every TLB before they are saved or freed,
+    /// so that no other core stores into a page whose contents were already saved.
+    #[cfg(feature = "oom_handler")]
+    pub fn shrink_inactive<T: PageTable>(&mut self, page_table: &mut T, sc: &mut ScanControl) {
         let start_vpn = self.inner.vpn_range.get_start();
-        let compressed_before = self.inner.compressed;
-        let swapped_before = self.inner.swapped;
-        warn!("{:?}", self.inner.active);
-        while let Some(idx) = self.inner.active.pop_front() {
-            let frame = &mut self.inner.frames[idx as usize];
+        let mut kept = VecDeque::new();
+        // the unmapped pages, and whether they are lazily freed ones which are still clean
+        let mut batch = Vec::new();
+        while sc.nr_reclaimed + batch.len() < sc.nr_to_reclaim {
+            let idx = match self.inner.inactive.pop_front() {
+                Some(idx) => idx,
+                None => break,
+            };
+            if !self.is_resident(idx) {
+                continue;
+            }
+            sc.nr_scanned += 1;
+            if self.test_and_clear_referenced(page_table, idx) {
+                self.inner.active.push_back(idx);
+                count_vm_events(VmEvent::PgActivate, 1);
+                continue;
+            }
+            let shared = match &self.inner.frames[idx] {
+                Frame::InMemory(frame) => Arc::strong_count(frame) > 1,
+                _ => false,
+            };
+            if shared {
+                kept.push_back(idx);
+                continue;
+            }
+            let vpn = VirtPageNum::from(start_vpn.0 + idx);
+            let mut lazy_free = false;
+            if self.inner.lazy_free.remove(&idx) {
+                lazy_free = page_table.is_dirty(vpn) == Some(false);
+            }
+            page_table.unmap(vpn);
+            batch.push((idx, lazy_free));
+        }
+        if !batch.is_empty() {
+            tlb_shootdown(Some(page_table.token()));
+        }
+        for (idx, lazy_free) in batch {
+            let vpn = VirtPageNum::from(start_vpn.0 + idx);
+            let frame = &mut self.inner.frames[idx];
+            if lazy_free {
+                *frame = Frame::Unallocated;
+                sc.nr_reclaimed += 1;
+                count_vm_events(VmEvent::PgLazyFreed, 1);
+                trace!("[shrink_inactive] drop lazily freed page: {:?}", vpn);
+                continue;
+            }
             // first, try to compress
             match frame.zip() {
                 Ok(zram_id) => {
-                    page_table.unmap(VirtPageNum::from(start_vpn.0 + idx as usize));
                     self.inner.compressed += 1;
-                    trace!("[do_oom] compress frame: {:?}, zram_id: {}", frame, zram_id);
+                    sc.nr_reclaimed += 1;
+                    count_vm_events(VmEvent::PgStealAnonZram, 1);
+                    trace!("[shrink_inactive] compress {:?}, zram_id: {}", vpn, zram_id);
                     continue;
                 }
-                Err(MemoryError::SharedPage) => continue,
                 Err(MemoryError::ZramIsFull) => {}
                 _ => unreachable!(),
             }
             // zram is full, try to swap out
             match frame.swap_out() {
                 Ok(swap_id) => {
-                    page_table.unmap(VirtPageNum::from(start_vpn.0 + idx as usize));
                     self.inner.swapped += 1;
-                    trace!("[do_oom] swap out frame: {:?}, swap_id: {}", frame, swap_id);
-                    continue;
+                    sc.nr_reclaimed += 1;
+                    count_vm_events(VmEvent::PgStealAnonSwap, 1);
+                    trace!("[shrink_inactive] swap out {:?}, swap_id: {}", vpn, swap_id);
+                }
+                Err(MemoryError::SwapIsFull) => {
+                    // the page is not shared, so it may be mapped writable again
+                    if let Frame::InMemory(frame) = frame {
+                        page_table.map(vpn, frame.ppn, self.map_perm);
+                    }
+                    kept.push_back(idx);
                 }
-                Err(MemoryError::SharedPage) => continue,
                 _ => unreachable!(),
             }
         }
-        self.inner.compressed + self.inner.swapped - compressed_before - swapped_before
+        self.inner.inactive.append(&mut kept);
     }
+    /// Move up to `nr` compressed pages on to swap, returns the number of pages moved.
     #[cfg(feature = "oom_handler")]
-    pub fn force_swap<T: PageTable>(&mut self, page_table: &mut T) -> usize {
-        let start_vpn = self.inner.vpn_range.get_start();
-        let swapped_before = self.inner.swapped;
-        warn!("{:?}", self.inner.active);
-        while let Some(idx) = self.inner.active.pop_front() {
-            let frame = &mut self.inner.frames[idx as usize];
-            match frame.force_swap_out() {
+    pub fn writeback_zram(&mut self, nr: usize, buf: &mut [u8]) -> usize {
+        let mut moved = 0;
+        for frame in self.inner.frames.iter_mut() {
+            if moved >= nr || moved == self.inner.compressed {
+                break;
+            }
+            match frame.writeback(buf) {
                 Ok(swap_id) => {
-                    page_table.unmap(VirtPageNum::from(start_vpn.0 + idx as usize));
-                    self.inner.swapped += 1;
-                    trace!(
-                        "[force_swap] swap out frame: {:?}, swap_id: {}",
-                        frame,
-                        swap_id
-                    );
-                    continue;
+                    moved += 1;
+                    trace!("[writeback_zram] swap_id: {}", swap_id);
                 }
-                _ => unreachable!(),
+                Err(MemoryError::SwapIsFull) => break,
+                _ => {}
             }
         }
-        self.inner.swapped - swapped_before
+        self.inner.compressed -= moved;
+        self.inner.swapped += moved;
+        moved
+    }
+    /// Read back the pages kept in swap area `swap_area`, for `swapoff()`.
+    #[cfg(feature = "oom_handler")]
+    pub fn swapoff<T: PageTable>(
+        &mut self,
+        page_table: &mut T,
+        swap_area: usize,
+    ) -> Result<(), MemoryError> {
+        let start_vpn = self.inner.vpn_range.get_start();
+        for idx in 0..self.inner.frames.len() {
+            let frame = &mut self.inner.frames[idx];
+            match frame {
+                Frame::SwappedOut(swap_tracker) if swap_tracker.area == swap_area => {}
+                _ => continue,
+            }
+            let ppn = frame.swap_in()?;
+            page_table.map(VirtPageNum::from(start_vpn.0 + idx), ppn, self.map_perm);
+            self.inner.active.push_back(idx);
+            self.inner.swapped -= 1;
+        }
+        Ok(())
     }
 }
 
@@ -917,3 +1236,42 @@ bitflags! {
         const MAP_FILE              =   0;
     }
 }
+
+bitflags! {
+    pub struct MremapFlags: u32 {
+        const MREMAP_MAYMOVE    =   1;
+        const MREMAP_FIXED      =   2;
+        const MREMAP_DONTUNMAP  =   4;
+    }
+}
+
+/// `advice` of `madvise()`
+#[allow(unused)]
+#[derive(Debug, Eq, PartialEq, FromPrimitive)]
+#[repr(usize)]
+pub enum Advice {
+    Normal = 0,
+    Random = 1,
+    Sequential = 2,
+    WillNeed = 3,
+    DontNeed = 4,
+    Free = 8,
+    Remove = 9,
+    DontFork = 10,
+    DoFork = 11,
+    Mergeable = 12,
+    Unmergeable = 13,
+    HugePage = 14,
+    NoHugePage = 15,
+    DontDump = 16,
+    DoDump = 17,
+    WipeOnFork = 18,
+    KeepOnFork = 19,
+    Cold = 20,
+    PageOut = 21,
+    PopulateRead = 22,
+    PopulateWrite = 23,
+    DontNeedLocked = 24,
+    #[num_enum(default)]
+    Invalid,
+}
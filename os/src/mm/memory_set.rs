@@ -1,13 +1,18 @@
+use super::aslr::Aslr;
 use super::map_area::*;
 use super::page_table::PageTable;
-use super::{PhysAddr, PhysPageNum, VirtAddr, VirtPageNum};
+#[cfg(feature = "oom_handler")]
+use super::reclaim::ScanControl;
+use super::{FrameTracker, PhysAddr, PhysPageNum, VPNRange, VirtAddr, VirtPageNum};
 use crate::arch::TrapContext;
-use crate::arch::{MMIO, TICKS_PER_SEC};
-use crate::fs::SeekWhence;
+use crate::arch::{tlb_shootdown, MMIO, TICKS_PER_SEC};
+use crate::fs::{file_trait::File, SealFlags, SeekWhence};
+use crate::ipc::shm::ShmAttachment;
 use crate::syscall::errno::*;
 use crate::task::{
     current_task, trap_cx_bottom_from_tid, ustack_bottom_from_tid, AuxvEntry, AuxvType, ELFInfo,
 };
+use crate::utils::random::get_random_u64;
 use crate::{config::*, should_map_trampoline};
 use alloc::string::String;
 use alloc::sync::Arc;
@@ -55,9 +60,19 @@ pub enum MemoryError {
     SharedPage,
     ZramIsFull,
     SwapIsFull,
+    OutOfMemory,
     BeyondEOF,
 }
 
+/// Whether the pages of `area` are on the reclaim lists, only private anonymous user memory is.
+/// Pages of a file mapping are read from the file again, shared ones can't be taken from their other users.
+#[cfg(feature = "oom_handler")]
+fn is_reclaimable(area: &MapArea) -> bool {
+    area.inner.vpn_range.get_start().0 < (TASK_SIZE >> PAGE_SIZE_BITS)
+        && area.map_file.is_none()
+        && !area.shared
+}
+
 /// The memory "space" as in user space or kernel space
 pub struct MemorySet<T: PageTable> {
     page_table: T,
@@ -65,6 +80,8 @@ pub struct MemorySet<T: PageTable> {
     /// Segments are implemented using this mechanism. In other words, they may be considered a subset of MapArea.
     /// Yet, other purposes may exist in this struct, such as file mapping.
     areas: Vec<MapArea>,
+    /// Where the stacks, `mmap()` and the heap start, see `Aslr`
+    aslr: Aslr,
 }
 
 impl<T: PageTable> MemorySet<T> {
@@ -73,6 +90,7 @@ impl<T: PageTable> MemorySet<T> {
         Self {
             page_table: T::new_kern_space(),
             areas: Vec::with_capacity(16),
+            aslr: Aslr::default(),
         }
     }
     /// Create a new struct with no information at all.
@@ -80,6 +98,7 @@ impl<T: PageTable> MemorySet<T> {
         Self {
             page_table: T::new(),
             areas: Vec::with_capacity(16),
+            aslr: Aslr::default(),
         }
     }
     /// Getter to the token of current memory space, or "this" page table.
@@ -282,7 +301,7 @@ impl<T: PageTable> MemorySet<T> {
                     if old_offset + offset_in_area > (file.get_size() + PAGE_SIZE - 1) & !0xfff {
                         return Err(MemoryError::BeyondEOF);
                     }
-                    if area.map_perm.contains(MapPermission::W) {
+                    if area.map_perm.contains(MapPermission::W) && !area.shared {
                         let allocated_ppn = area.map_one_unchecked(&mut self.page_table, vpn);
                         file.lseek(offset_in_area as isize, SeekWhence::SEEK_CUR)
                             .unwrap();
@@ -295,7 +314,7 @@ impl<T: PageTable> MemorySet<T> {
                         file.lseek(old_offset as isize, SeekWhence::SEEK_SET)
                             .unwrap();
                         Ok(allocated_ppn.offset(addr.page_offset()))
-                    // map to phys page directly
+                    // map to phys page directly, stores to a shared one are noticed by `write_shared()`
                     } else {
                         let cache_phys_page = file
                             .get_single_cache(old_offset + offset_in_area)
@@ -327,9 +346,7 @@ impl<T: PageTable> MemorySet<T> {
                         Frame::Compressed(_) => {
                             let ppn = frame.unzip().unwrap();
                             self.page_table.map(vpn, ppn, area.map_perm);
-                            area.inner
-                                .active
-                                .push_back((vpn.0 - area.get_start::<T>().0) as u16);
+                            area.inner.active.push_back(vpn.0 - area.get_start::<T>().0);
                             area.inner.compressed -= 1;
                             info!("[do_page_fault] addr: {:?}, solution: decompress", addr);
                             ppn
@@ -338,9 +355,7 @@ impl<T: PageTable> MemorySet<T> {
                         Frame::SwappedOut(_) => {
                             let ppn = frame.swap_in().unwrap();
                             self.page_table.map(vpn, ppn, area.map_perm);
-                            area.inner
-                                .active
-                                .push_back((vpn.0 - area.get_start::<T>().0) as u16);
+                            area.inner.active.push_back(vpn.0 - area.get_start::<T>().0);
                             area.inner.swapped -= 1;
                             info!("[do_page_fault] addr: {:?}, solution: swap in", addr);
                             ppn
@@ -350,9 +365,15 @@ impl<T: PageTable> MemorySet<T> {
                 }
             } else {
                 // mapped before the assignment
-                if area.map_perm.contains(MapPermission::W) {
+                if area.map_perm.contains(MapPermission::W) && area.shared {
+                    let ppn = area.write_shared(&mut self.page_table, vpn)?;
+                    info!("[do_page_fault] addr: {:?}, solution: write shared", addr);
+                    Ok(ppn.offset(addr.page_offset()))
+                } else if area.map_perm.contains(MapPermission::W) {
                     // Whoever triggers this fault shall cause the area to be copied into a new area.
                     let allocated_ppn = area.copy_on_write(&mut self.page_table, vpn)?;
+                    // the other threads may still see the page shared
+                    tlb_shootdown(Some(self.token()));
                     info!("[do_page_fault] addr: {:?}, solution: copy on write", addr);
                     Ok(allocated_ppn.offset(addr.page_offset()))
                 } else {
@@ -370,37 +391,97 @@ impl<T: PageTable> MemorySet<T> {
             Err(MemoryError::BadAddress)
         }
     }
+    /// Read `buf` from `addr` of this space, or write it there with `write`, for a tracer,
+    /// faulting the pages in as the owner would. Like `FOLL_FORCE` of Linux, a private page is written
+    /// even without `W`, e.g. a breakpoint into code, but into a copy of its own,
+    /// so that neither the file nor another process sees the store.
+    pub fn access_remote(
+        &mut self,
+        addr: usize,
+        buf: &mut [u8],
+        write: bool,
+    ) -> Result<(), MemoryError> {
+        let mut done = 0;
+        while done < buf.len() {
+            let va = VirtAddr::from(addr + done);
+            let vpn = va.floor();
+            if !self.page_table.is_mapped(vpn) {
+                self.do_page_fault(va)?;
+            }
+            if write && !self.page_table.writable(vpn).unwrap_or(false) {
+                let area = self
+                    .areas
+                    .iter_mut()
+                    .find(|area| area.get_start::<T>() <= vpn && vpn < area.get_end::<T>())
+                    .ok_or(MemoryError::BadAddress)?;
+                if area.map_perm.contains(MapPermission::W) {
+                    // copy on write, as a store of the owner would
+                    self.do_page_fault(va)?;
+                } else if area.shared {
+                    return Err(MemoryError::NoPermission);
+                } else {
+                    area.copy_on_write(&mut self.page_table, vpn)?;
+                    // the other threads may still see the page shared
+                    tlb_shootdown(Some(self.token()));
+                }
+            }
+            let pa = self
+                .page_table
+                .translate_va(va)
+                .ok_or(MemoryError::NotMapped)?;
+            let len = (PAGE_SIZE - va.page_offset()).min(buf.len() - done);
+            let page = unsafe { core::slice::from_raw_parts_mut(pa.0 as *mut u8, len) };
+            if write {
+                page.copy_from_slice(&buf[done..done + len]);
+            } else {
+                buf[done..done + len].copy_from_slice(page);
+            }
+            done += len;
+        }
+        Ok(())
+    }
+    /// Reclaim from the inactive lists until `sc` is done, see `MapArea::shrink_inactive()`.
     #[cfg(feature = "oom_handler")]
-    pub fn do_shallow_clean(&mut self) -> usize {
+    pub fn shrink_inactive(&mut self, sc: &mut ScanControl) {
         let page_table = &mut self.page_table;
-        self.areas
-            .iter_mut()
-            .filter(|area| {
-                let start_vpn = area.get_start::<T>();
-                start_vpn.0 >= (USR_MMAP_BASE >> PAGE_SIZE_BITS)
-                    && start_vpn.0 < (TASK_SIZE >> PAGE_SIZE_BITS)
-                    && area.map_file.is_none()
-            })
-            .map(|area| area.do_oom(page_table))
-            .sum()
+        for area in self.areas.iter_mut().filter(|area| is_reclaimable(area)) {
+            if sc.is_done() {
+                break;
+            }
+            area.shrink_inactive(page_table, sc);
+        }
     }
+    /// Refill the inactive lists, returns the number of pages deactivated.
     #[cfg(feature = "oom_handler")]
-    pub fn do_deep_clean(&mut self) -> usize {
+    pub fn age_active(&mut self) -> usize {
         let page_table = &mut self.page_table;
         self.areas
             .iter_mut()
-            .filter(|area| {
-                area.get_start::<T>().0 < (TASK_SIZE >> PAGE_SIZE_BITS) && area.map_file.is_none()
-            })
-            .map(|area| {
-                if area.get_start::<T>().0 < USR_MMAP_BASE >> PAGE_SIZE_BITS {
-                    area.force_swap(page_table)
-                } else {
-                    area.do_oom(page_table)
-                }
-            })
+            .filter(|area| is_reclaimable(area))
+            .map(|area| area.age_active(page_table))
             .sum()
     }
+    /// Move up to `nr` compressed pages on to swap, returns the number of pages moved.
+    #[cfg(feature = "oom_handler")]
+    pub fn writeback_zram(&mut self, nr: usize, buf: &mut [u8]) -> usize {
+        let mut moved = 0;
+        for area in self.areas.iter_mut().filter(|area| is_reclaimable(area)) {
+            if moved >= nr {
+                break;
+            }
+            moved += area.writeback_zram(nr - moved, buf);
+        }
+        moved
+    }
+    /// Read back the pages kept in swap area `swap_area`, see `MapArea::swapoff()`.
+    #[cfg(feature = "oom_handler")]
+    pub fn swapoff(&mut self, swap_area: usize) -> Result<(), MemoryError> {
+        let page_table = &mut self.page_table;
+        for area in self.areas.iter_mut().filter(|area| is_reclaimable(area)) {
+            area.swapoff(page_table, swap_area)?;
+        }
+        Ok(())
+    }
     /// Mention that trampoline is not collected by areas.
     fn map_trampoline(&mut self) {
         self.page_table.map(
@@ -500,9 +581,9 @@ impl<T: PageTable> MemorySet<T> {
                     .count()
                 {
                     // It's a loader!
-                    0 => ELF_DYN_BASE,
+                    0 => ELF_DYN_BASE + self.aslr.interp,
                     // It's a dynamically linked ELF.
-                    1 => 0,
+                    1 => self.aslr.elf,
                     // Emmm, It has multiple interpreters.
                     _ => return Err(EINVAL),
                 }
@@ -610,9 +691,11 @@ impl<T: PageTable> MemorySet<T> {
         }
     }
     /// Include sections in elf and trampoline and TrapContext and user stack,
-    /// also returns user_sp and entry point.
-    pub fn from_elf(elf_data: &[u8]) -> Result<(Self, usize, ELFInfo), isize> {
+    /// also returns the program break and entry point.
+    /// The layout is randomized unless `personality` says otherwise.
+    pub fn from_elf(elf_data: &[u8], personality: u32) -> Result<(Self, usize, ELFInfo), isize> {
         let mut memory_set = Self::new_bare();
+        memory_set.aslr = Aslr::new(personality);
         // map trampoline
         if should_map_trampoline!() {
             memory_set.map_trampoline();
@@ -621,11 +704,13 @@ impl<T: PageTable> MemorySet<T> {
         memory_set.map_signaltrampoline();
         let elf = xmas_elf::ElfFile::new(elf_data).unwrap();
         let (program_break, elf_info) = memory_set.map_elf(&elf)?;
+        let program_break = program_break + memory_set.aslr.brk;
 
         Ok((memory_set, program_break, elf_info))
     }
     pub fn from_existing_user(user_space: &mut MemorySet<T>) -> MemorySet<T> {
         let mut memory_set = Self::new_bare();
+        memory_set.aslr = user_space.aslr;
         // map trampoline
         if should_map_trampoline!() {
             memory_set.map_trampoline();
@@ -635,6 +720,18 @@ impl<T: PageTable> MemorySet<T> {
         // map data sections/user heap/mmap area/user stack
         for i in 0..user_space.areas.len() - 1 {
             // user_space.areas[i]
+            let area = &mut user_space.areas[i];
+            // `fork()` cleans the pages of both sides, they would no longer show whether they were written
+            #[cfg(feature = "oom_handler")]
+            area.inner.lazy_free.clear();
+            // a shared anonymous page left to lazy alloc would be allocated by each side on its own
+            if area.shared && area.map_file.is_none() {
+                for vpn in area.inner.vpn_range {
+                    if !user_space.page_table.is_mapped(vpn) {
+                        area.map_one_zeroed_unchecked(&mut user_space.page_table, vpn);
+                    }
+                }
+            }
             let mut new_area = user_space.areas[i].clone();
             new_area
                 .map_from_existing_page_table(
@@ -669,6 +766,8 @@ impl<T: PageTable> MemorySet<T> {
             "[fork] copy trap_cx area: {:?}",
             trap_cx_area.inner.vpn_range
         );
+        // the pages of the parent are no longer writable
+        tlb_shootdown(Some(user_space.token()));
         memory_set
     }
     pub fn activate(&self) {
@@ -693,6 +792,10 @@ impl<T: PageTable> MemorySet<T> {
     }
     pub fn recycle_data_pages(&mut self) {
         //*self = Self::new_bare();
+        let page_table = &mut self.page_table;
+        for area in self.areas.iter() {
+            area.sync_shared(page_table, area.get_start::<T>(), area.get_end::<T>());
+        }
         self.areas.clear();
     }
     #[allow(unused)]
@@ -707,6 +810,63 @@ impl<T: PageTable> MemorySet<T> {
             );
         })
     }
+    /// Range, permission and backing file of every user area, as listed in `/proc/<pid>/maps`.
+    pub fn user_areas(&self) -> Vec<(VirtAddr, VirtAddr, MapPermission, Option<Arc<dyn File>>)> {
+        self.areas
+            .iter()
+            .filter(|area| area.map_perm.contains(MapPermission::U))
+            .map(|area| {
+                (
+                    area.inner.vpn_range.get_start().into(),
+                    area.inner.vpn_range.get_end().into(),
+                    area.map_perm,
+                    area.map_file.clone(),
+                )
+            })
+            .collect()
+    }
+    /// Returns the number of pages spanned by user areas and how many of them are resident.
+    pub fn user_page_usage(&self) -> (usize, usize) {
+        self.areas
+            .iter()
+            .filter(|area| area.map_perm.contains(MapPermission::U))
+            .fold((0, 0), |(total, resident), area| {
+                (
+                    total + area.inner.frames.len(),
+                    resident
+                        + area
+                            .inner
+                            .frames
+                            .iter()
+                            .filter(|frame| matches!(frame, Frame::InMemory(_)))
+                            .count(),
+                )
+            })
+    }
+    /// Returns the number of resident, compressed and swapped out pages of the user areas.
+    #[cfg(feature = "oom_handler")]
+    pub fn oom_page_usage(&self) -> (usize, usize, usize) {
+        let (mut resident, mut compressed, mut swapped) = (0, 0, 0);
+        for area in self.areas.iter() {
+            if !area.map_perm.contains(MapPermission::U) {
+                continue;
+            }
+            for frame in area.inner.frames.iter() {
+                match frame {
+                    Frame::InMemory(_) => resident += 1,
+                    Frame::Compressed(_) => compressed += 1,
+                    Frame::SwappedOut(_) => swapped += 1,
+                    Frame::Unallocated => {}
+                }
+            }
+        }
+        (resident, compressed, swapped)
+    }
+    /// Whether `recycle_data_pages()` has run, i.e. the memory of the process was given back
+    #[cfg(feature = "oom_handler")]
+    pub fn is_torn_down(&self) -> bool {
+        self.areas.is_empty()
+    }
     pub fn sbrk(&mut self, heap_pt: usize, heap_bottom: usize, increment: isize) -> usize {
         let old_pt: usize = heap_pt;
         let new_pt: usize = old_pt + increment as usize;
@@ -762,7 +922,13 @@ impl<T: PageTable> MemorySet<T> {
         if start & 0xfff != 0 {
             return EINVAL;
         }
+        // file pages are mapped from the page cache
+        if !flags.contains(MapFlags::MAP_ANONYMOUS) && offset & 0xfff != 0 {
+            return EINVAL;
+        }
         let len = if len == 0 { PAGE_SIZE } else { len };
+        // MAP_SHARED_VALIDATE has this bit as well
+        let shared = flags.contains(MapFlags::MAP_SHARED);
         let task = current_task().unwrap();
         let idx = self.last_mmap_area_idx();
         let start_va: VirtAddr = if flags.contains(MapFlags::MAP_FIXED) {
@@ -773,8 +939,10 @@ impl<T: PageTable> MemorySet<T> {
             if let Some(idx) = idx {
                 let area = &mut self.areas[idx];
                 if flags.contains(MapFlags::MAP_PRIVATE | MapFlags::MAP_ANONYMOUS)
+                    && !shared
                     && prot == area.map_perm
                     && area.map_file.is_none()
+                    && !area.shared
                 {
                     debug!("[mmap] merge with previous area, call expand_to");
                     let end_va: VirtAddr = area.get_end::<T>().into();
@@ -783,7 +951,7 @@ impl<T: PageTable> MemorySet<T> {
                 }
                 area.get_end::<T>().into()
             } else {
-                USR_MMAP_BASE.into()
+                self.mmap_base().into()
             }
         };
         let mut new_area = MapArea::new(
@@ -793,6 +961,7 @@ impl<T: PageTable> MemorySet<T> {
             prot,
             None,
         );
+        new_area.shared = shared;
         if !flags.contains(MapFlags::MAP_ANONYMOUS) {
             warn!("[mmap] file-backed map!");
             let fd_table = task.files.lock();
@@ -801,6 +970,15 @@ impl<T: PageTable> MemorySet<T> {
                     if !file_descriptor.readable() {
                         return EACCES;
                     }
+                    if shared && prot.contains(MapPermission::W) && !file_descriptor.writable() {
+                        return EACCES;
+                    }
+                    let write_sealed = file_descriptor.file.get_seals().map_or(false, |seals| {
+                        seals.intersects(SealFlags::F_SEAL_WRITE | SealFlags::F_SEAL_FUTURE_WRITE)
+                    });
+                    if shared && prot.contains(MapPermission::W) && write_sealed {
+                        return EPERM;
+                    }
                     let file = file_descriptor.file.deep_clone();
                     file.lseek(offset as isize, SeekWhence::SEEK_SET).unwrap();
                     new_area.map_file = Some(file);
@@ -808,20 +986,119 @@ impl<T: PageTable> MemorySet<T> {
                 Err(errno) => return errno,
             }
         }
-        // insert MapArea and keep the order
+        self.insert_mmap_area(new_area);
+        start_va.0 as isize
+    }
+    /// Where `mmap()` puts the first area it picks the address of
+    fn mmap_base(&self) -> usize {
+        USR_MMAP_BASE + self.aslr.mmap
+    }
+    /// insert MapArea and keep the order
+    fn insert_mmap_area(&mut self, new_area: MapArea) {
+        let start_vpn = new_area.get_start::<T>();
         if let Some((idx, _)) = self
             .areas
             .iter()
             .enumerate()
             .skip_while(|(_, area)| area.get_start::<T>() >= VirtAddr::from(USR_MMAP_END).into())
-            .find(|(_, area)| area.get_start::<T>() >= start_va.into())
+            .find(|(_, area)| area.get_start::<T>() >= start_vpn)
         {
             self.areas.insert(idx, new_area);
         } else {
             error!("[MemorySet::mmap] No area found higher than new_area {:?} in beginning address. TRAMPOLINES may have been mapped to wrong places!",new_area);
             self.areas.push(new_area);
         }
-        start_va.0 as isize
+    }
+    /// Whether no area overlaps `[start_vpn, end_vpn)`
+    fn is_free(&self, start_vpn: VirtPageNum, end_vpn: VirtPageNum) -> bool {
+        !self
+            .areas
+            .iter()
+            .any(|area| area.get_start::<T>() < end_vpn && start_vpn < area.get_end::<T>())
+    }
+    /// The user area which `[start_vpn, end_vpn)` lies within
+    fn user_area_within(&self, start_vpn: VirtPageNum, end_vpn: VirtPageNum) -> Option<usize> {
+        self.areas.iter().position(|area| {
+            area.map_perm.contains(MapPermission::U)
+                && area.get_start::<T>() <= start_vpn
+                && end_vpn <= area.get_end::<T>()
+        })
+    }
+    /// Map the frames of a shared memory segment at `start`, or where `mmap()` would put them if it is 0.
+    /// Without `remap`, `start` must not overlap an existing mapping.
+    pub fn attach_shm(
+        &mut self,
+        start: usize,
+        remap: bool,
+        map_perm: MapPermission,
+        frames: Vec<Arc<FrameTracker>>,
+        attachment: ShmAttachment,
+    ) -> Result<usize, isize> {
+        let len = frames.len() * PAGE_SIZE;
+        let start_va: VirtAddr = if start != 0 {
+            let start_vpn = VirtAddr::from(start).floor();
+            let end_vpn = VirtAddr::from(start + len).ceil();
+            if !self.is_free(start_vpn, end_vpn) {
+                if !remap {
+                    return Err(EINVAL);
+                }
+                self.munmap(start, len)?;
+            }
+            start.into()
+        } else {
+            match self.last_mmap_area_idx() {
+                Some(idx) => self.areas[idx].get_end::<T>().into(),
+                None => self.mmap_base().into(),
+            }
+        };
+        let mut new_area = MapArea::new(
+            start_va,
+            VirtAddr::from(start_va.0 + len),
+            MapType::Framed,
+            map_perm,
+            None,
+        );
+        new_area.shared = true;
+        for (vpn, frame) in new_area.inner.vpn_range.into_iter().zip(frames) {
+            self.page_table.map(vpn, frame.ppn, map_perm);
+            new_area.inner.alloc_in_memory(vpn, frame);
+        }
+        new_area.shm = Some(attachment);
+        self.insert_mmap_area(new_area);
+        Ok(start_va.0)
+    }
+    /// Unmap the shared memory segment attached at `start`,
+    /// including the parts `mprotect()` split off.
+    pub fn detach_shm(&mut self, start: usize) -> Result<(), isize> {
+        let start_vpn = VirtAddr::from(start).floor();
+        let (id, end_vpn) = match self
+            .areas
+            .iter()
+            .filter_map(|area| area.shm.as_ref().map(|shm| (area, shm)))
+            .find(|(area, _)| area.get_start::<T>() == start_vpn)
+        {
+            Some((_, shm)) => (shm.id(), VirtPageNum::from(start_vpn.0 + shm.pages())),
+            None => return Err(EINVAL),
+        };
+        let page_table = &mut self.page_table;
+        self.areas.retain_mut(|area| {
+            let attached = match &area.shm {
+                Some(shm) => {
+                    shm.id() == id
+                        && area.get_start::<T>() >= start_vpn
+                        && area.get_end::<T>() <= end_vpn
+                }
+                None => false,
+            };
+            if attached {
+                if let Err(_) = area.unmap(page_table) {
+                    warn!("[detach_shm] Some pages are already unmapped");
+                }
+            }
+            !attached
+        });
+        tlb_shootdown(Some(self.token()));
+        Ok(())
     }
     pub fn munmap(&mut self, start: usize, len: usize) -> Result<(), isize> {
         let start_va = VirtAddr::from(start);
@@ -839,6 +1116,7 @@ impl<T: PageTable> MemorySet<T> {
         self.areas.iter_mut().enumerate().for_each(|(idx, area)| {
             if let Some((overlap_start, overlap_end)) = area.check_overlapping(start_vpn, end_vpn) {
                 found_area = true;
+                area.sync_shared(page_table, overlap_start, overlap_end);
                 let area_start_vpn: VirtPageNum = area.get_start::<T>();
                 let area_end_vpn = area.get_end::<T>();
                 if overlap_start == area_start_vpn && overlap_end == area_end_vpn {
@@ -879,12 +1157,221 @@ impl<T: PageTable> MemorySet<T> {
             }
             self.areas.insert(idx + 1, third);
         }
+        tlb_shootdown(Some(self.token()));
         if found_area {
             Ok(())
         } else {
             Err(EINVAL)
         }
     }
+    /// Hand the stores through shared file mappings in `[start, start + len)` over to the page cache.
+    /// Returns the mapped files, to be written back by the caller once it dropped the lock.
+    pub fn msync(&mut self, start: usize, len: usize) -> Vec<Arc<dyn File>> {
+        let start_vpn = VirtAddr::from(start).floor();
+        let end_vpn = VirtAddr::from(start + len).ceil();
+        let page_table = &mut self.page_table;
+        let mut files = Vec::new();
+        for area in self.areas.iter() {
+            if let Some((overlap_start, overlap_end)) = area.check_overlapping(start_vpn, end_vpn) {
+                area.sync_shared(page_table, overlap_start, overlap_end);
+                match &area.map_file {
+                    Some(file) if area.shared => files.push(file.clone()),
+                    _ => {}
+                }
+            }
+        }
+        // other cores may hold dirty entries, their next stores must fault to be seen again
+        tlb_shootdown(Some(self.token()));
+        files
+    }
+    /// Resize the mapping `[old_start, old_start + old_len)`, which must lie within one area,
+    /// and move it if `MREMAP_MAYMOVE` allows. Returns the new address.
+    pub fn mremap(
+        &mut self,
+        old_start: usize,
+        old_len: usize,
+        new_len: usize,
+        flags: MremapFlags,
+        new_start: usize,
+    ) -> Result<usize, isize> {
+        let old_start_va = VirtAddr::from(old_start);
+        // duplicating a shared mapping with `old_len` 0 is not supported
+        if !old_start_va.aligned() || old_len == 0 || new_len == 0 {
+            return Err(EINVAL);
+        }
+        let fixed = flags.contains(MremapFlags::MREMAP_FIXED);
+        let dont_unmap = flags.contains(MremapFlags::MREMAP_DONTUNMAP);
+        if (fixed || dont_unmap) && !flags.contains(MremapFlags::MREMAP_MAYMOVE) {
+            return Err(EINVAL);
+        }
+        let old_start_vpn = old_start_va.floor();
+        let old_end_vpn = VirtAddr::from(old_start + old_len).ceil();
+        let old_pages = old_end_vpn.0 - old_start_vpn.0;
+        let new_pages = VirtAddr::from(new_len).ceil().0;
+        if dont_unmap && old_pages != new_pages {
+            return Err(EINVAL);
+        }
+        let idx = match self.user_area_within(old_start_vpn, old_end_vpn) {
+            Some(idx) => idx,
+            None => return Err(EFAULT),
+        };
+        if dont_unmap && (self.areas[idx].shared || self.areas[idx].map_file.is_some()) {
+            return Err(EINVAL);
+        }
+        if !fixed && !dont_unmap {
+            if new_pages <= old_pages {
+                if new_pages < old_pages {
+                    self.munmap(
+                        old_start + new_pages * PAGE_SIZE,
+                        (old_pages - new_pages) * PAGE_SIZE,
+                    )?;
+                }
+                return Ok(old_start);
+            }
+            let new_end_vpn = VirtPageNum::from(old_start_vpn.0 + new_pages);
+            if old_end_vpn == self.areas[idx].get_end::<T>()
+                && self.is_free(old_end_vpn, new_end_vpn)
+            {
+                trace!("[mremap] expand in place, call expand_to");
+                self.areas[idx]
+                    .expand_to::<T>(VirtAddr::from(new_end_vpn))
+                    .unwrap();
+                return Ok(old_start);
+            }
+            if !flags.contains(MremapFlags::MREMAP_MAYMOVE) {
+                return Err(ENOMEM);
+            }
+        }
+        let new_start_vpn: VirtPageNum = if fixed {
+            let new_start_va = VirtAddr::from(new_start);
+            // the new range must be page aligned, below the mmap area top and apart from the old one
+            match new_start.checked_add(new_pages * PAGE_SIZE) {
+                Some(new_end) if new_start_va.aligned() && new_end <= USR_MMAP_END => {}
+                _ => return Err(EINVAL),
+            }
+            let new_start_vpn = new_start_va.floor();
+            let new_end_vpn = VirtPageNum::from(new_start_vpn.0 + new_pages);
+            if new_start_vpn < old_end_vpn && old_start_vpn < new_end_vpn {
+                return Err(EINVAL);
+            }
+            // `munmap()` fails when nothing is mapped there
+            if !self.is_free(new_start_vpn, new_end_vpn) {
+                self.munmap(new_start, new_pages * PAGE_SIZE)?;
+            }
+            new_start_vpn
+        } else {
+            match self.last_mmap_area_idx() {
+                Some(idx) => self.areas[idx].get_end::<T>(),
+                None => VirtAddr::from(self.mmap_base()).floor(),
+            }
+        };
+        // `munmap()` may have moved the area
+        let mut idx = self.user_area_within(old_start_vpn, old_end_vpn).unwrap();
+        self.areas[idx].sync_shared(&mut self.page_table, old_start_vpn, old_end_vpn);
+        if self.areas[idx].get_start::<T>() < old_start_vpn {
+            let second = self.areas[idx].into_two(old_start_vpn).unwrap();
+            idx += 1;
+            self.areas.insert(idx, second);
+        }
+        if old_end_vpn < self.areas[idx].get_end::<T>() {
+            let third = self.areas[idx].into_two(old_end_vpn).unwrap();
+            self.areas.insert(idx + 1, third);
+        }
+        let mut area = self.areas.remove(idx);
+        if dont_unmap {
+            // the old range stays mapped, its pages are lazily allocated again
+            self.areas.insert(idx, MapArea::from_another(&area));
+        }
+        trace!("[mremap] move to {:?}, call move_to", new_start_vpn);
+        area.move_to(&mut self.page_table, new_start_vpn);
+        let new_end_va = VirtAddr::from(VirtPageNum::from(new_start_vpn.0 + new_pages));
+        if new_pages < old_pages {
+            if let Err(_) = area.shrink_to(&mut self.page_table, new_end_va) {
+                warn!("[mremap] Some pages are already unmapped, is it caused by lazy alloc?");
+            }
+        } else {
+            area.expand_to::<T>(new_end_va).unwrap();
+        }
+        tlb_shootdown(Some(self.token()));
+        self.insert_mmap_area(area);
+        Ok(VirtAddr::from(new_start_vpn).0)
+    }
+    /// Apply `advice` to `[start, start + len)`. Only `MADV_DONTNEED` and `MADV_FREE` have an effect,
+    /// see `MapArea::dont_need()` and `MapArea::lazy_free()`.
+    pub fn madvise(&mut self, start: usize, len: usize, advice: Advice) -> Result<(), isize> {
+        let start_va = VirtAddr::from(start);
+        if !start_va.aligned() || advice == Advice::Invalid {
+            return Err(EINVAL);
+        }
+        let start_vpn = start_va.floor();
+        let end_vpn = VirtAddr::from(start + len).ceil();
+        // like Linux, every area is checked before any is changed
+        if advice == Advice::Free
+            && self.areas.iter().any(|area| {
+                area.map_perm.contains(MapPermission::U)
+                    && area.check_overlapping(start_vpn, end_vpn).is_some()
+                    && (area.shared || area.map_file.is_some())
+            })
+        {
+            return Err(EINVAL);
+        }
+        let page_table = &mut self.page_table;
+        let mut advised_pages = 0;
+        for area in self.areas.iter_mut() {
+            if !area.map_perm.contains(MapPermission::U) {
+                continue;
+            }
+            let (overlap_start, overlap_end) = match area.check_overlapping(start_vpn, end_vpn) {
+                Some(overlap) => overlap,
+                None => continue,
+            };
+            advised_pages += overlap_end.0 - overlap_start.0;
+            match advice {
+                Advice::DontNeed | Advice::DontNeedLocked => {
+                    area.dont_need(page_table, overlap_start, overlap_end)
+                }
+                Advice::Free => {
+                    #[cfg(feature = "oom_handler")]
+                    area.lazy_free(page_table, overlap_start, overlap_end);
+                }
+                _ => {}
+            }
+        }
+        tlb_shootdown(Some(self.token()));
+        // part of the range is not mapped
+        if advised_pages < end_vpn.0 - start_vpn.0 {
+            Err(ENOMEM)
+        } else {
+            Ok(())
+        }
+    }
+    /// Whether each page of `[start, start + len)` is resident, one byte per page as `mincore()` reports it.
+    pub fn mincore(&self, start: usize, len: usize) -> Result<Vec<u8>, isize> {
+        let start_va = VirtAddr::from(start);
+        if !start_va.aligned() {
+            return Err(EINVAL);
+        }
+        let start_vpn = start_va.floor();
+        let end_vpn = VirtAddr::from(start + len).ceil();
+        let mut resident = Vec::with_capacity(end_vpn.0 - start_vpn.0);
+        let mut vpn = start_vpn;
+        while vpn < end_vpn {
+            let area = match self.areas.iter().find(|area| {
+                area.map_perm.contains(MapPermission::U)
+                    && area.get_start::<T>() <= vpn
+                    && vpn < area.get_end::<T>()
+            }) {
+                Some(area) => area,
+                None => return Err(ENOMEM),
+            };
+            let end = end_vpn.min(area.get_end::<T>());
+            for vpn in VPNRange::new(vpn, end) {
+                resident.push(area.inner.get_in_memory(&vpn).is_some() as u8);
+            }
+            vpn = end;
+        }
+        Ok(resident)
+    }
     pub fn mprotect(&mut self, addr: usize, len: usize, prot: usize) -> Result<(), isize> {
         let start_va = VirtAddr::from(addr);
         let end_va = VirtAddr::from(addr + len);
@@ -914,6 +1401,19 @@ impl<T: PageTable> MemorySet<T> {
                     warn!("[mprotect] addr: {:X} is not in any MapArea", addr);
                     return Err(ENOMEM);
                 }
+                // a write sealed file can't be made writable through a shared mapping
+                let area = &self.areas[idx];
+                if let (true, Some(file)) = (area.shared, &area.map_file) {
+                    let write_sealed = file.get_seals().map_or(false, |seals| {
+                        seals.intersects(SealFlags::F_SEAL_WRITE | SealFlags::F_SEAL_FUTURE_WRITE)
+                    });
+                    if prot.contains(MapPermission::W)
+                        && !area.map_perm.contains(MapPermission::W)
+                        && write_sealed
+                    {
+                        return Err(EACCES);
+                    }
+                }
                 let area: &mut MapArea = if start_vpn == area_start_vpn && end_vpn == area_end_vpn {
                     trace!("[mprotect] change prot of whole area, idx: {}", idx);
                     &mut self.areas[idx]
@@ -955,6 +1455,7 @@ impl<T: PageTable> MemorySet<T> {
                 return Err(EINVAL);
             }
         }
+        tlb_shootdown(Some(self.token()));
         Ok(())
     }
     pub fn create_elf_tables(
@@ -1010,8 +1511,8 @@ impl<T: PageTable> MemorySet<T> {
         // should be virt addr!
         let random_bits_ptr = phys_user_sp + virt_phys_offset;
         unsafe {
-            *(phys_user_sp as *mut usize) = 0xdeadbeefcafebabe;
-            *(phys_user_sp as *mut usize).add(1) = 0xdeadbeefcafebabe;
+            *(phys_user_sp as *mut u64) = get_random_u64();
+            *(phys_user_sp as *mut u64).add(1) = get_random_u64();
         }
         // padding
         phys_user_sp -= core::mem::size_of::<usize>();
@@ -1086,9 +1587,13 @@ impl<T: PageTable> MemorySet<T> {
         // }
         user_sp
     }
+    /// The highest address of the stack of thread `tid`
+    pub fn ustack_bottom(&self, tid: usize) -> usize {
+        ustack_bottom_from_tid(tid) - self.aslr.stack
+    }
     pub fn alloc_user_res(&mut self, tid: usize, alloc_stack: bool) {
         if alloc_stack {
-            let ustack_bottom = ustack_bottom_from_tid(tid);
+            let ustack_bottom = self.ustack_bottom(tid);
             let ustack_top = ustack_bottom - USER_STACK_SIZE;
             trace!(
                 "[alloc_user_res] user stack start_va: {:X}, end_va: {:X}",
@@ -1124,7 +1629,7 @@ impl<T: PageTable> MemorySet<T> {
 
     pub fn dealloc_user_res(&mut self, tid: usize) {
         // dealloc ustack manually
-        let ustack_top_va: VirtAddr = (ustack_bottom_from_tid(tid) - USER_STACK_SIZE).into();
+        let ustack_top_va: VirtAddr = (self.ustack_bottom(tid) - USER_STACK_SIZE).into();
         if let Err(err) = self.remove_area_with_start_vpn(ustack_top_va.into()) {
             match err {
                 MemoryError::AreaNotFound => {
@@ -1149,6 +1654,13 @@ impl<T: PageTable> MemorySet<T> {
     }
 }
 
+impl<T: PageTable> Drop for MemorySet<T> {
+    /// `exec()` drops the old space without recycling it first.
+    fn drop(&mut self) {
+        self.recycle_data_pages();
+    }
+}
+
 #[allow(unused)]
 pub fn remap_test() {
     let mut kernel_space = KERNEL_SPACE.lock();
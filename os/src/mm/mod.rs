@@ -1,9 +1,14 @@
 pub mod address;
+mod aslr;
 mod frame_allocator;
 mod heap_allocator;
 mod map_area;
 mod memory_set;
+#[cfg(feature = "oom_handler")]
+mod oom_kill;
 mod page_table;
+#[cfg(feature = "oom_handler")]
+mod reclaim;
 #[cfg(feature = "zram")]
 mod zram;
 pub use crate::arch::KernelPageTableImpl;
@@ -11,10 +16,18 @@ pub use crate::arch::PageTableImpl;
 use address::VPNRange;
 pub use address::PPNRange;
 pub use address::{PhysAddr, PhysPageNum, StepByOne, VirtAddr, VirtPageNum};
+pub use aslr::{randomize_va_space, set_randomize_va_space, PER_CLEAR_ON_SETID};
 pub use frame_allocator::{
-    frame_alloc, frame_alloc_uninit, frame_dealloc, frame_reserve, unallocated_frames, FrameTracker,
+    frame_alloc, frame_alloc_uninit, frame_dealloc, frame_reserve, total_frames, unallocated_frames,
+    FrameTracker,
 };
-pub use map_area::{Frame, MapFlags, MapPermission};
+pub use map_area::{Advice, Frame, MapFlags, MapPermission, MremapFlags};
+#[cfg(feature = "oom_handler")]
+pub use oom_kill::{oom_score, out_of_memory};
+#[cfg(feature = "oom_handler")]
+pub use reclaim::{kswapd_tick, min_free_kbytes, set_min_free_kbytes, vm_events};
+#[cfg(feature = "zram")]
+pub use zram::{ZramTracker, ZRAM_DEVICE};
 pub use memory_set::{
     MemoryError, MemorySet, KERNEL_SPACE
 };
@@ -28,6 +41,8 @@ pub use page_table::{
 pub fn init() {
     heap_allocator::init_heap();
     frame_allocator::init_frame_allocator();
+    #[cfg(feature = "oom_handler")]
+    reclaim::init();
     KERNEL_SPACE.lock().activate();
 }
 pub use crate::arch::tlb_invalidate;
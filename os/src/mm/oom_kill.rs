@@ -0,0 +1,178 @@
+//! The OOM killer.
+//!
+//! When reclaim can't keep the min watermark free, a process is killed to get frames back.
+//! The victim is the process which gives back the most, see `oom_badness()`,
+//! and `/proc/<pid>/oom_score_adj` biases the choice.
+//!
+//! Frames are allocated with locks held, where the tasks can neither be walked nor waited for.
+//! So the killer only runs on entry to the trap handler, see `out_of_memory()`,
+//! and the min watermark is left to the allocations made until the next trap.
+use alloc::sync::{Arc, Weak};
+use core::sync::atomic::Ordering;
+use lazy_static::*;
+use spin::Mutex;
+
+use super::frame_allocator::{total_frames, unallocated_frames};
+use super::reclaim::{direct_reclaim, min_wmark_pages};
+use super::{MemorySet, PageTableImpl};
+use crate::config::PAGE_SIZE;
+use crate::fs::swap::SWAP_DEVICE;
+use crate::task::{
+    all_tasks, current_task, send_signal, suspend_current_and_run_next, Signals, TaskControlBlock,
+    INITPROC, OOM_SCORE_ADJ_MIN,
+};
+use crate::timer::get_time_ms;
+
+/// How long a victim may take to exit before another one is chosen
+const OOM_TIMEOUT_MS: usize = 1000;
+
+/// The process killed last
+struct Victim {
+    tgid: usize,
+    vm: Weak<Mutex<MemorySet<PageTableImpl>>>,
+    deadline: usize,
+}
+
+impl Victim {
+    fn is_gone(&self) -> bool {
+        self.vm.upgrade().map_or(true, |vm| {
+            vm.try_lock().map_or(false, |vm| vm.is_torn_down())
+        })
+    }
+}
+
+lazy_static! {
+    static ref VICTIM: Mutex<Option<Victim>> = Mutex::new(None);
+}
+
+/// Pages of memory and swap, `oom_score_adj` is in thousandths of it
+fn total_pages() -> usize {
+    total_frames() + SWAP_DEVICE.lock().usage().1
+}
+
+/// The number of pages the process of `task` holds, resident, compressed or swapped out,
+/// biased by its `oom_score_adj`. `None` if it is not to be killed: it is initproc,
+/// it is already dying, or its `oom_score_adj` is the minimum.
+fn oom_badness(task: &TaskControlBlock) -> Option<usize> {
+    let adj = task.oom_score_adj.load(Ordering::Relaxed);
+    if adj == OOM_SCORE_ADJ_MIN || task.tgid == INITPROC.tgid {
+        return None;
+    }
+    let inner = task.acquire_inner_lock();
+    if inner.is_zombie() || inner.sigpending.contains(Signals::SIGKILL) {
+        return None;
+    }
+    drop(inner);
+    let (resident, compressed, swapped) = task.vm.lock().oom_page_usage();
+    let points = (resident + compressed + swapped) as isize + adj * total_pages() as isize / 1000;
+    // like Linux, a process which may be killed scores at least 1
+    Some(points.max(1) as usize)
+}
+
+/// `/proc/<pid>/oom_score`, the badness in thousandths of the memory, 0 if the process is not to be killed
+pub fn oom_score(task: &TaskControlBlock) -> usize {
+    oom_badness(task).map_or(0, |points| points * 1000 / total_pages())
+}
+
+/// A task of the process with the highest badness, and its badness.
+/// The threads of a process score the same.
+fn select_victim() -> Option<(Arc<TaskControlBlock>, usize)> {
+    let mut chosen: Option<(Arc<TaskControlBlock>, usize)> = None;
+    for task in all_tasks() {
+        if let Some(points) = oom_badness(&task) {
+            if chosen.as_ref().map_or(true, |(_, max)| points > *max) {
+                chosen = Some((task, points));
+            }
+        }
+    }
+    chosen
+}
+
+/// Send SIGKILL to every thread of the process of `victim`.
+fn oom_kill_process(victim: &Arc<TaskControlBlock>, points: usize) {
+    let (resident, compressed, swapped) = victim.vm.lock().oom_page_usage();
+    let exe = victim.exe.lock().get_cwd().unwrap_or_default();
+    println!(
+        "[kernel] Out of memory: Killed process {} ({}) score:{} rss:{}kB zram:{}kB swap:{}kB oom_score_adj:{}",
+        victim.tgid,
+        exe,
+        points,
+        resident * PAGE_SIZE / 1024,
+        compressed * PAGE_SIZE / 1024,
+        swapped * PAGE_SIZE / 1024,
+        victim.oom_score_adj.load(Ordering::Relaxed)
+    );
+    for task in all_tasks()
+        .into_iter()
+        .filter(|task| task.tgid == victim.tgid)
+    {
+        if task.acquire_inner_lock().is_zombie() {
+            continue;
+        }
+        // wakes up target task if it is sleeping, or interrupts it on another core
+        send_signal(task, Signals::SIGKILL);
+    }
+    *VICTIM.lock() = Some(Victim {
+        tgid: victim.tgid,
+        vm: Arc::downgrade(&victim.vm),
+        deadline: get_time_ms() + OOM_TIMEOUT_MS,
+    });
+}
+
+/// Whether the last victim is still exiting. It is forgotten once it is gone or timed out.
+fn victim_exiting() -> bool {
+    let mut victim = VICTIM.lock();
+    match victim.as_ref() {
+        None => return false,
+        Some(victim) if victim.is_gone() => {}
+        Some(victim) if get_time_ms() >= victim.deadline => {
+            log::warn!(
+                "[out_of_memory] process {} did not exit in time",
+                victim.tgid
+            );
+        }
+        Some(_) => return true,
+    }
+    *victim = None;
+    false
+}
+
+/// Called on entry to the trap handler, where the current task holds no lock.
+/// Bring the free frames back to the min watermark, by direct reclaim or else
+/// by killing a process and waiting until its memory is torn down.
+/// Returns `false` if the current process is to die, the trap should not be handled then.
+pub fn out_of_memory() -> bool {
+    loop {
+        let min = min_wmark_pages();
+        let free = unallocated_frames();
+        if free >= min {
+            return true;
+        }
+        let current = current_task().unwrap();
+        if current
+            .acquire_inner_lock()
+            .sigpending
+            .contains(Signals::SIGKILL)
+        {
+            return false;
+        }
+        if direct_reclaim(min - free).is_ok() {
+            return true;
+        }
+        if !victim_exiting() {
+            let (victim, points) = match select_victim() {
+                Some(victim) => victim,
+                None => {
+                    log::warn!("[out_of_memory] no process to kill");
+                    return true;
+                }
+            };
+            oom_kill_process(&victim, points);
+            if victim.tgid == current.tgid {
+                return false;
+            }
+        }
+        drop(current);
+        suspend_current_and_run_next();
+    }
+}
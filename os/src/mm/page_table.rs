@@ -59,6 +59,8 @@ pub trait PageTable {
     fn activate(&self);
     fn is_valid(&self, vpn: VirtPageNum) -> Option<bool>;
     fn is_dirty(&self, vpn: VirtPageNum) -> Option<bool>;
+    /// Whether the page was accessed since `clear_access_bit()`, reclaim keeps such pages.
+    fn is_accessed(&self, vpn: VirtPageNum) -> Option<bool>;
     fn readable(&self, vpn: VirtPageNum) -> Option<bool>;
     fn writable(&self, vpn: VirtPageNum) -> Option<bool>;
     fn executable(&self, vpn: VirtPageNum) -> Option<bool>;
@@ -0,0 +1,284 @@
+//! Page reclaim.
+//!
+//! Frames are taken back in a fixed order, cheapest first:
+//! 1. clean file pages, which are just dropped,
+//! 2. dirty file pages, which are written back before they are dropped,
+//! 3. anonymous pages, compressed into zram, or swapped out once zram is full,
+//!    the pages of tmpfs files go the same way,
+//! 4. zram pages, which move on to swap when zram is full, so that zram keeps the pages reclaimed last.
+//!    Step 3 is then tried again.
+//!
+//! Anonymous pages are kept on the active and inactive lists of their `LinearMap`.
+//! An inactive page which was not used since it got there is reclaimed, one which was goes back to the active list.
+//! The active lists are aged to refill the inactive ones when they run dry.
+//!
+//! There are no kernel threads, so the background reclaimer runs from the idle loop
+//! like the writeback task, see `kswapd_tick()`.
+//! An allocation which finds no free frame reclaims directly, see `direct_reclaim()`.
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+use alloc::{sync::Arc, vec};
+
+use super::frame_allocator::{total_frames, unallocated_frames};
+use crate::config::PAGE_SIZE;
+use crate::fs::directory_tree;
+use crate::task::{current_task, memory_sets};
+
+/// Reclaim events, exported through `/proc/vmstat`
+#[derive(Clone, Copy)]
+pub enum VmEvent {
+    AllocStall,
+    KswapdWakeups,
+    PageOutRun,
+    PgScanKswapd,
+    PgScanDirect,
+    PgStealKswapd,
+    PgStealDirect,
+    PgActivate,
+    PgDeactivate,
+    PgLazyFreed,
+    PgStealFileClean,
+    PgStealFileDirty,
+    PgStealAnonZram,
+    PgStealAnonSwap,
+    PgStealShmem,
+    ZramWriteback,
+}
+
+const VM_EVENT_NAMES: [&str; 16] = [
+    "allocstall",
+    "kswapd_wakeups",
+    "pageoutrun",
+    "pgscan_kswapd",
+    "pgscan_direct",
+    "pgsteal_kswapd",
+    "pgsteal_direct",
+    "pgactivate",
+    "pgdeactivate",
+    "pglazyfreed",
+    "pgsteal_file_clean",
+    "pgsteal_file_dirty",
+    "pgsteal_anon_zram",
+    "pgsteal_anon_swap",
+    "pgsteal_shmem",
+    "zram_writeback",
+];
+
+#[allow(clippy::declare_interior_mutable_const)]
+const ZERO: AtomicUsize = AtomicUsize::new(0);
+static VM_EVENTS: [AtomicUsize; VM_EVENT_NAMES.len()] = [ZERO; VM_EVENT_NAMES.len()];
+
+pub fn count_vm_events(event: VmEvent, count: usize) {
+    VM_EVENTS[event as usize].fetch_add(count, Ordering::Relaxed);
+}
+
+/// Name and count of every reclaim event
+pub fn vm_events() -> impl Iterator<Item = (&'static str, usize)> {
+    VM_EVENT_NAMES
+        .iter()
+        .zip(VM_EVENTS.iter())
+        .map(|(name, count)| (*name, count.load(Ordering::Relaxed)))
+}
+
+/// `vm.min_free_kbytes`, the low and high watermarks are derived from it like on Linux
+static MIN_FREE_KBYTES: AtomicUsize = AtomicUsize::new(0);
+
+pub fn min_free_kbytes() -> usize {
+    MIN_FREE_KBYTES.load(Ordering::Relaxed)
+}
+
+pub fn set_min_free_kbytes(kbytes: usize) {
+    MIN_FREE_KBYTES.store(kbytes, Ordering::Relaxed);
+}
+
+/// Reclaim, then the OOM killer, keep this many frames free on entry to the trap handler
+pub fn min_wmark_pages() -> usize {
+    min_free_kbytes() * 1024 / PAGE_SIZE
+}
+
+/// The background reclaimer wakes up below this many free frames
+fn low_wmark_pages() -> usize {
+    min_wmark_pages() + min_wmark_pages() / 4
+}
+
+/// The background reclaimer goes back to sleep once this many frames are free
+fn high_wmark_pages() -> usize {
+    min_wmark_pages() + min_wmark_pages() / 2
+}
+
+/// Pick `min_free_kbytes` as Linux does, sqrt(16 * memory in kB) within [128, 262144].
+pub fn init() {
+    let kbytes = total_frames() * PAGE_SIZE / 1024;
+    let mut root = 0;
+    while (root + 1) * (root + 1) <= kbytes * 16 {
+        root += 1;
+    }
+    set_min_free_kbytes(root.clamp(128, 262144));
+}
+
+/// One reclaim run
+pub struct ScanControl {
+    /// How many frames the run is after
+    pub nr_to_reclaim: usize,
+    /// Pages looked at on the inactive lists
+    pub nr_scanned: usize,
+    pub nr_reclaimed: usize,
+}
+
+impl ScanControl {
+    pub fn is_done(&self) -> bool {
+        self.nr_reclaimed >= self.nr_to_reclaim
+    }
+}
+
+/// Steps 1 and 2. The page caches keep no lists, they age their pages on their own.
+fn shrink_file(sc: &mut ScanControl) {
+    let clean = directory_tree::shrink_page_cache(false);
+    count_vm_events(VmEvent::PgStealFileClean, clean);
+    sc.nr_reclaimed += clean;
+    if sc.is_done() {
+        return;
+    }
+    let dirty = directory_tree::shrink_page_cache(true);
+    count_vm_events(VmEvent::PgStealFileDirty, dirty);
+    sc.nr_reclaimed += dirty;
+}
+
+/// Step 3. Sleeping tasks come first, the current one last.
+/// A memory set which is locked is skipped, its owner may be the one allocating.
+fn shrink_anon(sc: &mut ScanControl) {
+    let mut memory_sets = memory_sets();
+    if let Some(task) = current_task() {
+        if !memory_sets.iter().any(|vm| Arc::ptr_eq(vm, &task.vm)) {
+            memory_sets.push(task.vm.clone());
+        }
+    }
+    for vm in memory_sets.iter() {
+        if sc.is_done() {
+            return;
+        }
+        if let Some(mut vm) = vm.try_lock() {
+            vm.shrink_inactive(sc);
+        }
+    }
+    let mut deactivated = 0;
+    for vm in memory_sets.iter() {
+        if let Some(mut vm) = vm.try_lock() {
+            deactivated += vm.age_active();
+        }
+    }
+    count_vm_events(VmEvent::PgDeactivate, deactivated);
+    for vm in memory_sets.iter() {
+        if sc.is_done() {
+            return;
+        }
+        if let Some(mut vm) = vm.try_lock() {
+            vm.shrink_inactive(sc);
+        }
+    }
+    let shmem = directory_tree::shrink_tmpfs();
+    count_vm_events(VmEvent::PgStealShmem, shmem);
+    sc.nr_reclaimed += shmem;
+}
+
+/// Step 4, returns the number of pages moved.
+fn writeback_zram(nr: usize) -> usize {
+    let (stored, _, capacity) = super::ZRAM_DEVICE.lock().usage();
+    if stored < capacity {
+        return 0;
+    }
+    let mut buf = vec![0u8; PAGE_SIZE];
+    let mut moved = 0;
+    for vm in memory_sets().iter() {
+        if moved >= nr {
+            break;
+        }
+        if let Some(mut vm) = vm.try_lock() {
+            moved += vm.writeback_zram(nr - moved, &mut buf);
+        }
+    }
+    count_vm_events(VmEvent::ZramWriteback, moved);
+    moved
+}
+
+/// Try to release `nr_to_reclaim` frames, returns the number of frames released.
+fn shrink(nr_to_reclaim: usize, kswapd: bool) -> usize {
+    let mut sc = ScanControl {
+        nr_to_reclaim,
+        nr_scanned: 0,
+        nr_reclaimed: 0,
+    };
+    shrink_file(&mut sc);
+    if !sc.is_done() {
+        shrink_anon(&mut sc);
+    }
+    if !sc.is_done() && writeback_zram(sc.nr_to_reclaim - sc.nr_reclaimed) > 0 {
+        shrink_anon(&mut sc);
+    }
+    if kswapd {
+        count_vm_events(VmEvent::PgScanKswapd, sc.nr_scanned);
+        count_vm_events(VmEvent::PgStealKswapd, sc.nr_reclaimed);
+    } else {
+        count_vm_events(VmEvent::PgScanDirect, sc.nr_scanned);
+        count_vm_events(VmEvent::PgStealDirect, sc.nr_reclaimed);
+    }
+    log::debug!(
+        "[shrink] kswapd: {}, scanned: {}, reclaimed: {}/{}",
+        kswapd,
+        sc.nr_scanned,
+        sc.nr_reclaimed,
+        sc.nr_to_reclaim
+    );
+    sc.nr_reclaimed
+}
+
+/// Frames the background reclaimer takes back per tick at most
+const KSWAPD_BATCH: usize = 32;
+/// The background reclaimer gives up after this many runs in a row which released nothing,
+/// until a direct reclaim succeeds.
+const MAX_RECLAIM_RETRIES: usize = 16;
+
+static KSWAPD_AWAKE: AtomicBool = AtomicBool::new(false);
+static KSWAPD_FAILURES: AtomicUsize = AtomicUsize::new(0);
+
+/// Run the background reclaimer if it is due. Cheap enough to be called on every tick.
+pub fn kswapd_tick() {
+    if KSWAPD_FAILURES.load(Ordering::Relaxed) >= MAX_RECLAIM_RETRIES {
+        return;
+    }
+    let free = unallocated_frames();
+    if !KSWAPD_AWAKE.load(Ordering::Relaxed) {
+        if free >= low_wmark_pages() {
+            return;
+        }
+        KSWAPD_AWAKE.store(true, Ordering::Relaxed);
+        count_vm_events(VmEvent::KswapdWakeups, 1);
+    }
+    let high = high_wmark_pages();
+    if free >= high {
+        KSWAPD_AWAKE.store(false, Ordering::Relaxed);
+        return;
+    }
+    count_vm_events(VmEvent::PageOutRun, 1);
+    if shrink((high - free).min(KSWAPD_BATCH), true) == 0 {
+        KSWAPD_FAILURES.fetch_add(1, Ordering::Relaxed);
+    } else {
+        KSWAPD_FAILURES.store(0, Ordering::Relaxed);
+    }
+}
+
+/// Called by an allocation which found fewer than `req` free frames,
+/// `Err(())` if they could not be released.
+pub fn direct_reclaim(req: usize) -> Result<(), ()> {
+    count_vm_events(VmEvent::AllocStall, 1);
+    let reclaimed = shrink(req, false);
+    if reclaimed > 0 {
+        KSWAPD_FAILURES.store(0, Ordering::Relaxed);
+    }
+    if reclaimed >= req {
+        Ok(())
+    } else {
+        log::warn!("[direct_reclaim] released {} of {} frames", reclaimed, req);
+        Err(())
+    }
+}
@@ -0,0 +1,131 @@
+//! A high compression LZ4 encoder, like `lz4hc` of Linux.
+//!
+//! It writes plain LZ4 blocks, so `lz4_flex` decodes them,
+//! but looks through every earlier position with the same hash for the longest match
+//! instead of taking the first one, and defers a match when the next byte starts a longer one.
+use alloc::{vec, vec::Vec};
+use core::convert::TryInto;
+
+const MIN_MATCH: usize = 4;
+/// The last bytes of a block are always literals
+const LAST_LITERALS: usize = 5;
+/// No match starts in the last bytes of a block
+const MFLIMIT: usize = 12;
+const MAX_DISTANCE: usize = u16::MAX as usize;
+const HASH_LOG: u32 = 12;
+/// Candidates looked at per position, the deeper the slower and the better
+const MAX_ATTEMPTS: usize = 64;
+
+/// The hash chains, kept between calls to spare the allocations
+pub struct Lz4Hc {
+    /// The last position with a hash, plus one so that 0 means none
+    head: Vec<u16>,
+    /// The previous position with the same hash, by position, plus one as well
+    chain: Vec<u16>,
+}
+
+impl Lz4Hc {
+    pub fn new() -> Self {
+        Self {
+            head: vec![0; 1 << HASH_LOG],
+            chain: Vec::new(),
+        }
+    }
+    fn hash(input: &[u8], pos: usize) -> usize {
+        let sequence = u32::from_le_bytes(input[pos..pos + 4].try_into().unwrap());
+        (sequence.wrapping_mul(2654435761) >> (32 - HASH_LOG)) as usize
+    }
+    fn insert(&mut self, input: &[u8], pos: usize) {
+        let hash = Self::hash(input, pos);
+        self.chain[pos] = self.head[hash];
+        self.head[hash] = pos as u16 + 1;
+    }
+    /// The length and the offset of the longest match for `pos` ending before `end`
+    fn longest_match(&self, input: &[u8], pos: usize, end: usize) -> (usize, usize) {
+        let mut best = (0, 0);
+        let mut candidate = self.head[Self::hash(input, pos)];
+        for _ in 0..MAX_ATTEMPTS {
+            if candidate == 0 {
+                break;
+            }
+            let start = candidate as usize - 1;
+            if pos - start > MAX_DISTANCE {
+                break;
+            }
+            let len = input[start..]
+                .iter()
+                .zip(input[pos..end].iter())
+                .take_while(|(a, b)| a == b)
+                .count();
+            if len > best.0 {
+                best = (len, pos - start);
+            }
+            candidate = self.chain[start];
+        }
+        best
+    }
+    /// Compress `input`, which is shorter than 64 KiB, into an LZ4 block.
+    pub fn compress(&mut self, input: &[u8]) -> Vec<u8> {
+        assert!(input.len() < u16::MAX as usize);
+        self.head.fill(0);
+        self.chain.clear();
+        self.chain.resize(input.len(), 0);
+        let mut output = Vec::with_capacity(input.len() / 2);
+        let match_limit = input.len().saturating_sub(MFLIMIT);
+        let end = input.len().saturating_sub(LAST_LITERALS);
+        let mut anchor = 0;
+        let mut pos = 0;
+        while pos < match_limit {
+            let (mut len, mut offset) = self.longest_match(input, pos, end);
+            self.insert(input, pos);
+            if len < MIN_MATCH {
+                pos += 1;
+                continue;
+            }
+            // lazy matching, a literal is worth a longer match starting at the next byte
+            while pos + 1 < match_limit {
+                let (next_len, next_offset) = self.longest_match(input, pos + 1, end);
+                if next_len <= len {
+                    break;
+                }
+                pos += 1;
+                self.insert(input, pos);
+                len = next_len;
+                offset = next_offset;
+            }
+            write_sequence(&mut output, &input[anchor..pos], Some((offset, len)));
+            for next in pos + 1..(pos + len).min(match_limit) {
+                self.insert(input, next);
+            }
+            pos += len;
+            anchor = pos;
+        }
+        write_sequence(&mut output, &input[anchor..], None);
+        output.shrink_to_fit();
+        output
+    }
+}
+
+fn write_length(output: &mut Vec<u8>, mut len: usize) {
+    while len >= 255 {
+        output.push(255);
+        len -= 255;
+    }
+    output.push(len as u8);
+}
+
+/// A sequence is a token, the literals and the match, the last one has no match.
+fn write_sequence(output: &mut Vec<u8>, literals: &[u8], matched: Option<(usize, usize)>) {
+    let match_len = matched.map_or(0, |(_, len)| len - MIN_MATCH);
+    output.push(((literals.len().min(15) as u8) << 4) | match_len.min(15) as u8);
+    if literals.len() >= 15 {
+        write_length(output, literals.len() - 15);
+    }
+    output.extend_from_slice(literals);
+    if let Some((offset, _)) = matched {
+        output.extend_from_slice(&(offset as u16).to_le_bytes());
+        if match_len >= 15 {
+            write_length(output, match_len - 15);
+        }
+    }
+}
@@ -0,0 +1,305 @@
+//! zram, pages kept compressed in memory.
+//!
+//! Like the block device of Linux, a page filled with one repeated word is kept as that word,
+//! and a page which doesn't compress well is kept as it is.
+//! The capacity and the compressor can be changed at any time through `/proc/zram0`,
+//! both compressors write LZ4 blocks so the pages stored before still read back.
+mod lz4hc;
+
+use alloc::{format, string::String, sync::Arc, vec::Vec};
+use core::convert::TryInto;
+use core::mem::size_of;
+use lazy_static::lazy_static;
+use spin::Mutex;
+
+use self::lz4hc::Lz4Hc;
+use crate::config::PAGE_SIZE;
+
+#[derive(Debug)]
+pub enum ZramError {
+    InvalidIndex,
+    NoSpace,
+    NotAllocated,
+    Corrupted,
+}
+
+#[derive(Debug)]
+pub struct ZramTracker(pub usize);
+
+impl Drop for ZramTracker {
+    fn drop(&mut self) {
+        ZRAM_DEVICE.lock().discard(self.0).unwrap();
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Compressor {
+    Lz4,
+    /// Slower than `Lz4` but compresses better, decoded the same way
+    Lz4Hc,
+}
+
+const COMPRESSORS: [(Compressor, &str); 2] =
+    [(Compressor::Lz4, "lz4"), (Compressor::Lz4Hc, "lz4hc")];
+
+/// Pages compressed to more than this are stored as they are, like `huge_class_size` of Linux
+const HUGE_THRESHOLD: usize = PAGE_SIZE / 4 * 3;
+
+/// Pages by default, 8 MiB
+const DEFAULT_CAPACITY: usize = 2048;
+
+enum ZramEntry {
+    SameFilled(u64),
+    Huge(Vec<u8>),
+    Compressed(Vec<u8>),
+}
+
+impl ZramEntry {
+    /// Bytes taken on the heap
+    fn size(&self) -> usize {
+        match self {
+            ZramEntry::SameFilled(_) => 0,
+            ZramEntry::Huge(data) | ZramEntry::Compressed(data) => data.capacity(),
+        }
+    }
+}
+
+/// Counters shown in `/proc/zram0`
+#[derive(Default)]
+struct ZramStats {
+    /// Pages stored
+    pages_stored: usize,
+    /// Bytes the data of the pages takes, same filled pages take none
+    compr_data_size: usize,
+    mem_used_max: usize,
+    same_pages: usize,
+    huge_pages: usize,
+    huge_pages_since: usize,
+    failed_reads: usize,
+    /// Pages refused for lack of room
+    failed_writes: usize,
+    invalid_io: usize,
+    notify_free: usize,
+}
+
+pub struct Zram {
+    slots: Vec<Option<ZramEntry>>,
+    recycled: Vec<usize>,
+    /// Pages which may be stored
+    capacity: usize,
+    compressor: Compressor,
+    /// Kept from one page to the next, only allocated once `Lz4Hc` is chosen
+    lz4hc: Option<Lz4Hc>,
+    stats: ZramStats,
+}
+
+impl Zram {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            slots: Vec::new(),
+            recycled: Vec::new(),
+            capacity,
+            compressor: Compressor::Lz4,
+            lz4hc: None,
+            stats: ZramStats::default(),
+        }
+    }
+    fn insert(&mut self, entry: ZramEntry) -> Result<Arc<ZramTracker>, ZramError> {
+        if self.stats.pages_stored >= self.capacity {
+            self.stats.failed_writes += 1;
+            return Err(ZramError::NoSpace);
+        }
+        let zram_id = match self.recycled.pop() {
+            Some(zram_id) => zram_id,
+            None => {
+                self.slots.push(None);
+                self.slots.len() - 1
+            }
+        };
+        match &entry {
+            ZramEntry::SameFilled(_) => self.stats.same_pages += 1,
+            ZramEntry::Huge(_) => {
+                self.stats.huge_pages += 1;
+                self.stats.huge_pages_since += 1;
+            }
+            ZramEntry::Compressed(_) => {}
+        }
+        self.stats.pages_stored += 1;
+        self.stats.compr_data_size += entry.size();
+        self.slots[zram_id] = Some(entry);
+        self.stats.mem_used_max = self.stats.mem_used_max.max(self.mem_used_total());
+        Ok(Arc::new(ZramTracker(zram_id)))
+    }
+    fn get(&self, zram_id: usize) -> Result<&ZramEntry, ZramError> {
+        match self.slots.get(zram_id) {
+            Some(Some(entry)) => Ok(entry),
+            Some(None) => Err(ZramError::NotAllocated),
+            None => Err(ZramError::InvalidIndex),
+        }
+    }
+    fn remove(&mut self, zram_id: usize) -> Result<ZramEntry, ZramError> {
+        let entry = match self.slots.get_mut(zram_id) {
+            Some(slot) => slot.take().ok_or(ZramError::NotAllocated)?,
+            None => return Err(ZramError::InvalidIndex),
+        };
+        self.recycled.push(zram_id);
+        match &entry {
+            ZramEntry::SameFilled(_) => self.stats.same_pages -= 1,
+            ZramEntry::Huge(_) => self.stats.huge_pages -= 1,
+            ZramEntry::Compressed(_) => {}
+        }
+        self.stats.pages_stored -= 1;
+        self.stats.compr_data_size -= entry.size();
+        Ok(entry)
+    }
+    pub fn read(&mut self, zram_id: usize, buf: &mut [u8]) -> Result<(), ZramError> {
+        let result = match self.get(zram_id) {
+            Ok(ZramEntry::SameFilled(value)) => {
+                for word in buf.chunks_exact_mut(size_of::<u64>()) {
+                    word.copy_from_slice(&value.to_ne_bytes());
+                }
+                Ok(())
+            }
+            Ok(ZramEntry::Huge(data)) => {
+                buf.copy_from_slice(data);
+                Ok(())
+            }
+            Ok(ZramEntry::Compressed(data)) => match lz4_flex::decompress_into(data, buf) {
+                Ok(len) if len == buf.len() => Ok(()),
+                _ => Err(ZramError::Corrupted),
+            },
+            Err(error) => Err(error),
+        };
+        match result {
+            Err(ZramError::Corrupted) => self.stats.failed_reads += 1,
+            Err(_) => self.stats.invalid_io += 1,
+            Ok(_) => {}
+        }
+        result
+    }
+    pub fn write(&mut self, buf: &[u8]) -> Result<Arc<ZramTracker>, ZramError> {
+        if let Some(value) = same_filled(buf) {
+            return self.insert(ZramEntry::SameFilled(value));
+        }
+        // no point in compressing a page which can't be kept
+        if self.stats.pages_stored >= self.capacity {
+            self.stats.failed_writes += 1;
+            return Err(ZramError::NoSpace);
+        }
+        let mut compressed = match self.compressor {
+            Compressor::Lz4 => lz4_flex::compress(buf),
+            Compressor::Lz4Hc => self.lz4hc.get_or_insert_with(Lz4Hc::new).compress(buf),
+        };
+        log::trace!("[zram] compressed len: {}", compressed.len());
+        if compressed.len() > HUGE_THRESHOLD {
+            return self.insert(ZramEntry::Huge(buf.to_vec()));
+        }
+        compressed.shrink_to_fit();
+        self.insert(ZramEntry::Compressed(compressed))
+    }
+    /// Returns the number of pages stored, their compressed size in bytes and the capacity in pages.
+    pub fn usage(&self) -> (usize, usize, usize) {
+        (
+            self.stats.pages_stored,
+            self.stats.compr_data_size,
+            self.capacity,
+        )
+    }
+    #[inline(always)]
+    pub fn discard(&mut self, zram_id: usize) -> Result<(), ZramError> {
+        match self.remove(zram_id) {
+            Ok(_) => {
+                self.stats.notify_free += 1;
+                Ok(())
+            }
+            Err(error) => Err(error),
+        }
+    }
+    /// The data and the table of slots
+    fn mem_used_total(&self) -> usize {
+        self.stats.compr_data_size
+            + self.slots.capacity() * size_of::<Option<ZramEntry>>()
+            + self.recycled.capacity() * size_of::<usize>()
+    }
+    /// `disksize`, the capacity in bytes
+    pub fn disksize(&self) -> usize {
+        self.capacity * PAGE_SIZE
+    }
+    /// Change the capacity, `Err(())` if more pages than that are stored.
+    pub fn set_disksize(&mut self, bytes: usize) -> Result<(), ()> {
+        let capacity = bytes / PAGE_SIZE;
+        if capacity < self.stats.pages_stored {
+            return Err(());
+        }
+        self.capacity = capacity;
+        Ok(())
+    }
+    /// `comp_algorithm`, the compressors with the one in use in brackets
+    pub fn comp_algorithm(&self) -> String {
+        let names: Vec<String> = COMPRESSORS
+            .iter()
+            .map(|(compressor, name)| {
+                if *compressor == self.compressor {
+                    format!("[{}]", name)
+                } else {
+                    String::from(*name)
+                }
+            })
+            .collect();
+        format!("{}\n", names.join(" "))
+    }
+    /// Use the compressor called `name` for the pages to come, `Err(())` if there is none.
+    pub fn set_comp_algorithm(&mut self, name: &str) -> Result<(), ()> {
+        let (compressor, _) = COMPRESSORS
+            .iter()
+            .find(|(_, known)| *known == name)
+            .ok_or(())?;
+        self.compressor = *compressor;
+        if self.compressor != Compressor::Lz4Hc {
+            self.lz4hc = None;
+        }
+        Ok(())
+    }
+    /// `mm_stat`, in the order of Linux. There is no limit and nothing is compacted.
+    pub fn mm_stat(&self) -> String {
+        let stats = &self.stats;
+        format!(
+            "{:>8} {:>8} {:>8} {:>8} {:>8} {:>8} {:>8} {:>8} {:>8}\n",
+            stats.pages_stored * PAGE_SIZE,
+            stats.compr_data_size,
+            self.mem_used_total(),
+            0,
+            stats.mem_used_max,
+            stats.same_pages,
+            0,
+            stats.huge_pages,
+            stats.huge_pages_since
+        )
+    }
+    /// `io_stat`, in the order of Linux
+    pub fn io_stat(&self) -> String {
+        let stats = &self.stats;
+        format!(
+            "{:>8} {:>8} {:>8} {:>8}\n",
+            stats.failed_reads, stats.failed_writes, stats.invalid_io, stats.notify_free
+        )
+    }
+}
+
+/// The word `buf` is filled with, if it is
+fn same_filled(buf: &[u8]) -> Option<u64> {
+    let mut words = buf
+        .chunks_exact(size_of::<u64>())
+        .map(|word| u64::from_ne_bytes(word.try_into().unwrap()));
+    let first = words.next()?;
+    if words.all(|word| word == first) {
+        Some(first)
+    } else {
+        None
+    }
+}
+
+lazy_static! {
+    pub static ref ZRAM_DEVICE: Arc<Mutex<Zram>> =
+        Arc::new(Mutex::new(Zram::new(DEFAULT_CAPACITY)));
+}
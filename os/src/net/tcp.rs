@@ -362,7 +362,7 @@ impl File for TcpSocket {
     fn is_dir(&self) -> bool {todo!();}
     fn is_file(&self) -> bool {todo!();}
     fn info_dirtree_node(&self, _dirnode_ptr: Weak<DirectoryTreeNode>){todo!();}
-    fn get_dirtree_node(&self) -> Option<Arc<DirectoryTreeNode>>{todo!();}
+    fn get_dirtree_node(&self) -> Option<Arc<DirectoryTreeNode>>{None}
     /// open
     fn open(&self, _flags: OpenFlags, _special_use: bool) -> Arc<dyn File>{todo!();}
     fn open_subfile(&self) -> Result<Vec<(String, Arc<dyn File>)>, isize>{todo!();}
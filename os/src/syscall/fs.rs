@@ -1,12 +1,16 @@
 use crate::arch::BLOCK_SZ;
+use crate::fs::directory_tree::sync_all;
 use crate::fs::poll::{ppoll, pselect, FdSet, PollFd};
+#[cfg(feature = "swap")]
+use crate::fs::swap::{self, SwapFlags, SWAP_FLAG_PRIO_MASK};
+use crate::fs::tmpfs::memfd::{memfd_create, MemfdFlags};
 use crate::fs::*;
 use crate::mm::{
-    copy_from_user, copy_from_user_array, copy_to_user, copy_to_user_array, copy_to_user_string,
+    copy_from_user, copy_from_user_array, copy_to_user, copy_to_user_array,
     translated_byte_buffer, translated_byte_buffer_append_to_existing_vec, translated_refmut,
     translated_str, try_get_from_user, MapPermission, UserBuffer, VirtAddr,
 };
-use crate::task::{current_task, current_user_token};
+use crate::task::{current_cred, current_task, current_user_token, Access};
 use crate::timer::TimeSpec;
 use alloc::boxed::Box;
 use alloc::string::String;
@@ -21,7 +25,7 @@ pub const AT_FDCWD: usize = 100usize.wrapping_neg();
 
 /// # Warning
 /// `fs` & `files` is locked in this function
-fn __openat(dirfd: usize, path: &str) -> Result<FileDescriptor, isize> {
+fn __openat(dirfd: usize, path: &str, flags: OpenFlags) -> Result<FileDescriptor, isize> {
     let task = current_task().unwrap();
     let file_descriptor = match dirfd {
         AT_FDCWD => task.fs.lock().working_inode.as_ref().clone(),
@@ -33,9 +37,14 @@ fn __openat(dirfd: usize, path: &str) -> Result<FileDescriptor, isize> {
             }
         }
     };
-    file_descriptor.open(path, OpenFlags::O_RDONLY, false)
+    file_descriptor.open(path, flags, false)
 }
 
+/// Flags for `open()` to reach `path` itself, even if it is a symbolic link.
+const NOFOLLOW_FLAGS: OpenFlags = OpenFlags::from_bits_truncate(
+    OpenFlags::O_PATH.bits() | OpenFlags::O_NOFOLLOW.bits(),
+);
+
 pub fn sys_getcwd(buf: usize, size: usize) -> isize {
     let task = current_task().unwrap();
     if !task
@@ -125,6 +134,9 @@ pub fn sys_write(fd: usize, buf: usize, count: usize) -> isize {
     if !file_descriptor.writable() {
         return EBADF;
     }
+    if let Err(errno) = file_descriptor.deny_write() {
+        return errno;
+    }
     let token = task.get_user_token();
     file_descriptor.write_user(
         None,
@@ -171,6 +183,9 @@ pub fn sys_pwrite(fd: usize, buf: usize, count: usize, offset: usize) -> isize {
     if !file_descriptor.writable() {
         return EBADF;
     }
+    if let Err(errno) = file_descriptor.deny_write() {
+        return errno;
+    }
     let token = task.get_user_token();
     file_descriptor.write_user(
         Some(offset),
@@ -240,6 +255,9 @@ pub fn sys_writev(fd: usize, iov: usize, iovcnt: usize) -> isize {
     if !file_descriptor.writable() {
         return EBADF;
     }
+    if let Err(errno) = file_descriptor.deny_write() {
+        return errno;
+    }
     let token = task.get_user_token();
     let mut iovecs = Vec::<IOVec>::with_capacity(iovcnt);
     if copy_from_user_array(token, iov as *const IOVec, iovecs.as_mut_ptr(), iovcnt).is_err() {
@@ -296,6 +314,9 @@ pub fn sys_sendfile(out_fd: usize, in_fd: usize, offset: *mut usize, count: usiz
     if !in_file.readable() || !out_file.writable() {
         return EBADF;
     }
+    if let Err(errno) = out_file.deny_write() {
+        return errno;
+    }
 
     let token = task.get_user_token();
     // turn a pointer in user space into a pointer in kernel space if it is not null
@@ -377,7 +398,10 @@ pub fn sys_close(fd: usize) -> isize {
     let task = current_task().unwrap();
     let mut fd_table = task.files.lock();
     match fd_table.remove(fd) {
-        Ok(_) => SUCCESS,
+        Ok(file_descriptor) => {
+            locks::release_on_close(task.tgid, &file_descriptor.file);
+            SUCCESS
+        }
         Err(errno) => errno,
     }
 }
@@ -506,6 +530,11 @@ pub fn sys_dup2(oldfd: usize, newfd: usize) -> isize {
         Err(errno) => return errno,
     };
     file_descriptor.set_cloexec(false);
+    if oldfd != newfd {
+        if let Ok(replaced) = fd_table.get_ref(newfd) {
+            locks::release_on_close(task.tgid, &replaced.file);
+        }
+    }
     match fd_table.insert_at(file_descriptor, newfd) {
         Ok(fd) => fd as isize,
         Err(errno) => errno,
@@ -544,13 +573,15 @@ pub fn sys_dup3(oldfd: usize, newfd: usize, flags: u32) -> isize {
         Err(errno) => return errno,
     };
     file_descriptor.set_cloexec(is_cloexec);
+    if let Ok(replaced) = fd_table.get_ref(newfd) {
+        locks::release_on_close(task.tgid, &replaced.file);
+    }
     match fd_table.insert_at(file_descriptor, newfd) {
         Ok(fd) => fd as isize,
         Err(errno) => errno,
     }
 }
 
-// This syscall is not complete at all, only /read proc/self/exe
 pub fn sys_readlinkat(dirfd: usize, pathname: *const u8, buf: *mut u8, bufsiz: usize) -> isize {
     let task = current_task().unwrap();
     let token = task.get_user_token();
@@ -558,35 +589,30 @@ pub fn sys_readlinkat(dirfd: usize, pathname: *const u8, buf: *mut u8, bufsiz: u
         Ok(path) => path,
         Err(errno) => return errno,
     };
-    let real_path = if path.as_str() == "/proc/self/exe" {
-        task.exe.lock().get_cwd().unwrap()
-    } else {
-        match __openat(dirfd, &path) {
-            Ok(_) => {
-                // we don't implement symbolic link, so if we found it...
-                warn!(
-                    "[sys_readlinkat] not a symbolic link! dirfd: {}, path: {}",
-                    dirfd as isize, path
-                );
-                // The file of `pathname` is not a symbolic link
-                return EINVAL;
-            }
+    if bufsiz == 0 {
+        return EINVAL;
+    }
+    let target = match __openat(dirfd, &path, NOFOLLOW_FLAGS) {
+        // `EINVAL` if the file is not a symbolic link
+        Ok(file_descriptor) => match file_descriptor.file.read_link() {
+            Ok(target) => target,
             Err(errno) => return errno,
-        }
+        },
+        Err(errno) => return errno,
     };
-    let len = real_path.len().min(bufsiz - 1);
-    // `copy_to_user_string` will add '\0' in the end, so written length is `len + 1`
-    if copy_to_user_string(token, &real_path[0..len], buf).is_err() {
+    // The result is silently truncated and not terminated by '\0'
+    let len = target.len().min(bufsiz);
+    if copy_to_user_array(token, target.as_ptr(), buf, len).is_err() {
         log::error!("[sys_readlinkat] Failed to copy to {:?}", buf);
         return EFAULT;
     };
 
     debug!(
-        "[sys_readlinkat] dirfd: {}, pathname: {}, buf: {:?}, bufsiz: {}, written: {}",
-        dirfd as isize, path, buf, bufsiz, real_path
+        "[sys_readlinkat] dirfd: {}, pathname: {}, buf: {:?}, bufsiz: {}, target: {}",
+        dirfd as isize, path, buf, bufsiz, target
     );
 
-    (len + 1) as isize
+    len as isize
 }
 
 bitflags! {
@@ -628,7 +654,12 @@ pub fn sys_fstatat(dirfd: usize, path: *const u8, buf: *mut u8, flags: u32) -> i
         }
     };
 
-    match file_descriptor.open(&path, OpenFlags::O_RDONLY, false) {
+    let open_flags = if flags.contains(FstatatFlags::AT_SYMLINK_NOFOLLOW) {
+        NOFOLLOW_FLAGS
+    } else {
+        OpenFlags::O_PATH
+    };
+    match file_descriptor.open(&path, open_flags, false) {
         Ok(file_descriptor) => {
             if copy_to_user(token, &file_descriptor.get_stat(), buf as *mut Stat).is_err() {
                 log::error!("[sys_fstatat] Failed to copy to {:?}", buf);
@@ -671,7 +702,12 @@ pub fn sys_statx(dirfd: usize, path: *const u8, flags: u32, mask: u32, buf: *mut
         }
     };
 
-    match file_descriptor.open(&path, OpenFlags::O_RDONLY, false) {
+    let open_flags = if flags.contains(FstatatFlags::AT_SYMLINK_NOFOLLOW) {
+        NOFOLLOW_FLAGS
+    } else {
+        OpenFlags::O_PATH
+    };
+    match file_descriptor.open(&path, open_flags, false) {
         Ok(file_descriptor) => {
             if copy_to_user(token, &file_descriptor.get_statx(mask), buf as *mut Statx).is_err() {
                 log::error!("[sys_statx] Failed to copy to {:?}", buf);
@@ -759,19 +795,328 @@ pub fn sys_statfs(_path: *const u8, buf: *mut Statfs) -> isize {
     SUCCESS
 }
 
-pub fn sys_fsync(fd: usize) -> isize {
+pub fn sys_sync() -> isize {
+    info!("[sys_sync]");
+    sync_all(None);
+    SUCCESS
+}
+
+/// Every disk filesystem lives on the one block device, so this is `sync()` for a valid `fd`.
+pub fn sys_syncfs(fd: usize) -> isize {
     let task = current_task().unwrap();
 
-    info!("[sys_fsync] fd: {}", fd);
+    info!("[sys_syncfs] fd: {}", fd);
     let fd_table = task.files.lock();
     if let Err(errno) = fd_table.check(fd) {
         return errno;
     }
+    drop(fd_table);
+    sync_all(None);
     SUCCESS
 }
 
-pub fn sys_fchmodat() -> isize {
-    0
+pub fn sys_fsync(fd: usize) -> isize {
+    let task = current_task().unwrap();
+
+    info!("[sys_fsync] fd: {}", fd);
+    let fd_table = task.files.lock();
+    let file_descriptor = match fd_table.get_ref(fd) {
+        Ok(file_descriptor) => file_descriptor,
+        Err(errno) => return errno,
+    };
+    match file_descriptor.fsync() {
+        Ok(_) => SUCCESS,
+        Err(errno) => errno,
+    }
+}
+
+/// FAT32 keeps no metadata that could be left behind and lwext4 only flushes whole volumes,
+/// so this is `fsync()`.
+pub fn sys_fdatasync(fd: usize) -> isize {
+    info!("[sys_fdatasync] fd: {}", fd);
+    sys_fsync(fd)
+}
+
+pub fn sys_fchmodat(dirfd: usize, path: *const u8, mode: u32) -> isize {
+    let token = current_user_token();
+    let path = match translated_str(token, path) {
+        Ok(path) => path,
+        Err(errno) => return errno,
+    };
+    info!(
+        "[sys_fchmodat] dirfd: {}, path: {}, mode: {:o}",
+        dirfd as isize, path, mode
+    );
+    match __openat(dirfd, path.as_str(), OpenFlags::O_PATH) {
+        Ok(file_descriptor) => match file_descriptor.chmod(mode) {
+            Ok(_) => SUCCESS,
+            Err(errno) => errno,
+        },
+        Err(errno) => errno,
+    }
+}
+
+pub fn sys_fchmod(fd: usize, mode: u32) -> isize {
+    info!("[sys_fchmod] fd: {}, mode: {:o}", fd, mode);
+    let task = current_task().unwrap();
+    let file_descriptor = match task.files.lock().get_ref(fd) {
+        Ok(file_descriptor) => file_descriptor.clone(),
+        Err(errno) => return errno,
+    };
+    match file_descriptor.chmod(mode) {
+        Ok(_) => SUCCESS,
+        Err(errno) => errno,
+    }
+}
+
+/// An id of -1 is left unchanged
+fn to_owner_id(id: u32) -> Option<u32> {
+    match id {
+        u32::MAX => None,
+        id => Some(id),
+    }
+}
+
+pub fn sys_fchownat(dirfd: usize, path: *const u8, owner: u32, group: u32, flags: u32) -> isize {
+    let token = current_user_token();
+    let path = match translated_str(token, path) {
+        Ok(path) => path,
+        Err(errno) => return errno,
+    };
+    let flags = match FstatatFlags::from_bits(flags) {
+        Some(flags) => flags,
+        None => {
+            warn!("[sys_fchownat] unknown flags");
+            return EINVAL;
+        }
+    };
+    info!(
+        "[sys_fchownat] dirfd: {}, path: {}, owner: {}, group: {}, flags: {:?}",
+        dirfd as isize, path, owner as i32, group as i32, flags
+    );
+    let open_flags = if flags.contains(FstatatFlags::AT_SYMLINK_NOFOLLOW) {
+        NOFOLLOW_FLAGS
+    } else {
+        OpenFlags::O_PATH
+    };
+    match __openat(dirfd, path.as_str(), open_flags) {
+        Ok(file_descriptor) => {
+            match file_descriptor.chown(to_owner_id(owner), to_owner_id(group)) {
+                Ok(_) => SUCCESS,
+                Err(errno) => errno,
+            }
+        }
+        Err(errno) => errno,
+    }
+}
+
+pub fn sys_fchown(fd: usize, owner: u32, group: u32) -> isize {
+    info!(
+        "[sys_fchown] fd: {}, owner: {}, group: {}",
+        fd, owner as i32, group as i32
+    );
+    let task = current_task().unwrap();
+    let file_descriptor = match task.files.lock().get_ref(fd) {
+        Ok(file_descriptor) => file_descriptor.clone(),
+        Err(errno) => return errno,
+    };
+    match file_descriptor.chown(to_owner_id(owner), to_owner_id(group)) {
+        Ok(_) => SUCCESS,
+        Err(errno) => errno,
+    }
+}
+
+/// The file a path based xattr call works on, `follow` is `false` for the `l*` variants
+fn xattr_path_target(path: *const u8, follow: bool) -> Result<FileDescriptor, isize> {
+    let path = translated_str(current_user_token(), path)?;
+    let flags = if follow {
+        OpenFlags::O_PATH
+    } else {
+        NOFOLLOW_FLAGS
+    };
+    __openat(AT_FDCWD, path.as_str(), flags)
+}
+
+fn xattr_fd_target(fd: usize) -> Result<FileDescriptor, isize> {
+    let task = current_task().unwrap();
+    let fd_table = task.files.lock();
+    fd_table
+        .get_ref(fd)
+        .map(|file_descriptor| file_descriptor.clone())
+}
+
+fn do_setxattr(
+    file_descriptor: FileDescriptor,
+    name: *const u8,
+    value: *const u8,
+    size: usize,
+    flags: u32,
+) -> isize {
+    let token = current_user_token();
+    let name = match translated_str(token, name) {
+        Ok(name) => name,
+        Err(errno) => return errno,
+    };
+    let flags = match XattrFlags::from_bits(flags) {
+        Some(flags) => flags,
+        None => return EINVAL,
+    };
+    info!(
+        "[sys_setxattr] name: {}, size: {}, flags: {:?}",
+        name, size, flags
+    );
+    if size > XATTR_SIZE_MAX {
+        return E2BIG;
+    }
+    let mut buf = vec![0u8; size];
+    if size != 0 {
+        match translated_byte_buffer(token, value, size) {
+            Ok(buffer) => UserBuffer::new(buffer).read(&mut buf),
+            Err(errno) => return errno,
+        };
+    }
+    match file_descriptor.set_xattr(name.as_str(), &buf, flags) {
+        Ok(()) => SUCCESS,
+        Err(errno) => errno,
+    }
+}
+
+/// Copy `data` to the user buffer `buf` of `size` bytes, for the get and list calls.
+/// A `size` of 0 only asks for the length.
+fn copy_xattr_out(data: &[u8], buf: *mut u8, size: usize) -> isize {
+    if size == 0 {
+        return data.len() as isize;
+    }
+    if data.len() > size {
+        return ERANGE;
+    }
+    match translated_byte_buffer(current_user_token(), buf, data.len()) {
+        Ok(buffer) => UserBuffer::new(buffer).write(data),
+        Err(errno) => return errno,
+    };
+    data.len() as isize
+}
+
+fn do_getxattr(
+    file_descriptor: FileDescriptor,
+    name: *const u8,
+    value: *mut u8,
+    size: usize,
+) -> isize {
+    let name = match translated_str(current_user_token(), name) {
+        Ok(name) => name,
+        Err(errno) => return errno,
+    };
+    info!("[sys_getxattr] name: {}, size: {}", name, size);
+    match file_descriptor.get_xattr(name.as_str()) {
+        Ok(data) => copy_xattr_out(&data, value, size),
+        Err(errno) => errno,
+    }
+}
+
+/// The names are put one after another, each followed by a null byte
+fn do_listxattr(file_descriptor: FileDescriptor, list: *mut u8, size: usize) -> isize {
+    let names = match file_descriptor.list_xattr() {
+        Ok(names) => names,
+        Err(errno) => return errno,
+    };
+    let mut data = Vec::new();
+    for name in names.iter() {
+        data.extend_from_slice(name.as_bytes());
+        data.push(0);
+    }
+    copy_xattr_out(&data, list, size)
+}
+
+fn do_removexattr(file_descriptor: FileDescriptor, name: *const u8) -> isize {
+    let name = match translated_str(current_user_token(), name) {
+        Ok(name) => name,
+        Err(errno) => return errno,
+    };
+    info!("[sys_removexattr] name: {}", name);
+    match file_descriptor.remove_xattr(name.as_str()) {
+        Ok(()) => SUCCESS,
+        Err(errno) => errno,
+    }
+}
+
+/// `setxattr()`, or `lsetxattr()` if `follow` is `false`
+pub fn sys_setxattr(
+    path: *const u8,
+    name: *const u8,
+    value: *const u8,
+    size: usize,
+    flags: u32,
+    follow: bool,
+) -> isize {
+    match xattr_path_target(path, follow) {
+        Ok(file_descriptor) => do_setxattr(file_descriptor, name, value, size, flags),
+        Err(errno) => errno,
+    }
+}
+
+pub fn sys_fsetxattr(
+    fd: usize,
+    name: *const u8,
+    value: *const u8,
+    size: usize,
+    flags: u32,
+) -> isize {
+    match xattr_fd_target(fd) {
+        Ok(file_descriptor) => do_setxattr(file_descriptor, name, value, size, flags),
+        Err(errno) => errno,
+    }
+}
+
+/// `getxattr()`, or `lgetxattr()` if `follow` is `false`
+pub fn sys_getxattr(
+    path: *const u8,
+    name: *const u8,
+    value: *mut u8,
+    size: usize,
+    follow: bool,
+) -> isize {
+    match xattr_path_target(path, follow) {
+        Ok(file_descriptor) => do_getxattr(file_descriptor, name, value, size),
+        Err(errno) => errno,
+    }
+}
+
+pub fn sys_fgetxattr(fd: usize, name: *const u8, value: *mut u8, size: usize) -> isize {
+    match xattr_fd_target(fd) {
+        Ok(file_descriptor) => do_getxattr(file_descriptor, name, value, size),
+        Err(errno) => errno,
+    }
+}
+
+/// `listxattr()`, or `llistxattr()` if `follow` is `false`
+pub fn sys_listxattr(path: *const u8, list: *mut u8, size: usize, follow: bool) -> isize {
+    match xattr_path_target(path, follow) {
+        Ok(file_descriptor) => do_listxattr(file_descriptor, list, size),
+        Err(errno) => errno,
+    }
+}
+
+pub fn sys_flistxattr(fd: usize, list: *mut u8, size: usize) -> isize {
+    match xattr_fd_target(fd) {
+        Ok(file_descriptor) => do_listxattr(file_descriptor, list, size),
+        Err(errno) => errno,
+    }
+}
+
+/// `removexattr()`, or `lremovexattr()` if `follow` is `false`
+pub fn sys_removexattr(path: *const u8, name: *const u8, follow: bool) -> isize {
+    match xattr_path_target(path, follow) {
+        Ok(file_descriptor) => do_removexattr(file_descriptor, name),
+        Err(errno) => errno,
+    }
+}
+
+pub fn sys_fremovexattr(fd: usize, name: *const u8) -> isize {
+    match xattr_fd_target(fd) {
+        Ok(file_descriptor) => do_removexattr(file_descriptor, name),
+        Err(errno) => errno,
+    }
 }
 
 pub fn sys_chdir(path: *const u8) -> isize {
@@ -808,26 +1153,29 @@ pub fn sys_openat(dirfd: usize, path: *const u8, flags: u32, mode: u32) -> isize
             return EINVAL;
         }
     };
-    let mode = StatMode::from_bits(mode);
     info!(
         "[sys_openat] dirfd: {}, path: {}, flags: {:?}, mode: {:?}",
-        dirfd as isize, path, flags, mode
+        dirfd as isize,
+        path,
+        flags,
+        StatMode::from_bits(mode)
     );
-    let mut fd_table = task.files.lock();
+    let mode = mode & 0o7777 & !task.fs.lock().umask;
     let file_descriptor = match dirfd {
         AT_FDCWD => task.fs.lock().working_inode.as_ref().clone(),
-        fd => match fd_table.get_ref(fd) {
+        fd => match task.files.lock().get_ref(fd) {
             Ok(file_descriptor) => file_descriptor.clone(),
             Err(errno) => return errno,
         },
     };
 
-    let new_file_descriptor = match file_descriptor.open(&path, flags, false) {
+    // The fd table is not held while opening, procfs reads it to list `/proc/<pid>/fd`
+    let new_file_descriptor = match file_descriptor.open_with_mode(&path, flags, mode, false) {
         Ok(file_descriptor) => file_descriptor,
         Err(errno) => return errno,
     };
 
-    let new_fd = match fd_table.insert(new_file_descriptor) {
+    let new_fd = match task.files.lock().insert(new_file_descriptor) {
         Ok(fd) => fd,
         Err(errno) => return errno,
     };
@@ -874,6 +1222,9 @@ pub fn sys_copy_file_range(
         error!("cannot copy files");
         return EBADF;
     }
+    if let Err(errno) = new_file_descriptor.deny_write() {
+        return errno;
+    }
     let mut buf = vec![0u8; len];
     let r = if *in_offset == 0 {
         old_file_descriptor.read(Some(&mut 0usize), &mut buf)
@@ -945,6 +1296,7 @@ pub fn sys_renameat2(
         &oldpath,
         &new_file_descriptor,
         &newpath,
+        flags.contains(LinkatFlags::AT_SYMLINK_FOLLOW),
     ) {
         Ok(_) => SUCCESS,
         Err(errno) => errno,
@@ -993,7 +1345,39 @@ pub fn sys_mkdirat(dirfd: usize, path: *const u8, mode: u32) -> isize {
             }
         }
     };
-    match file_descriptor.mkdir(&path) {
+    let mode = mode & 0o1777 & !task.fs.lock().umask;
+    match file_descriptor.mkdir(&path, mode) {
+        Ok(_) => SUCCESS,
+        Err(errno) => errno,
+    }
+}
+
+pub fn sys_symlinkat(target: *const u8, newdirfd: usize, linkpath: *const u8) -> isize {
+    let task = current_task().unwrap();
+    let token = task.get_user_token();
+    let target = match translated_str(token, target) {
+        Ok(target) => target,
+        Err(errno) => return errno,
+    };
+    let linkpath = match translated_str(token, linkpath) {
+        Ok(path) => path,
+        Err(errno) => return errno,
+    };
+    info!(
+        "[sys_symlinkat] target: {}, newdirfd: {}, linkpath: {}",
+        target, newdirfd as isize, linkpath
+    );
+    let file_descriptor = match newdirfd {
+        AT_FDCWD => task.fs.lock().working_inode.as_ref().clone(),
+        fd => {
+            let fd_table = task.files.lock();
+            match fd_table.get_ref(fd) {
+                Ok(file_descriptor) => file_descriptor.clone(),
+                Err(errno) => return errno,
+            }
+        }
+    };
+    match file_descriptor.symlink(&target, &linkpath) {
         Ok(_) => SUCCESS,
         Err(errno) => errno,
     }
@@ -1006,7 +1390,7 @@ bitflags! {
 }
 
 /// # Warning
-/// Currently we have no hard-link so this syscall will remove file directly.
+/// Only tmpfs has hard links, on other filesystems this syscall removes the file directly.
 pub fn sys_unlinkat(dirfd: usize, path: *const u8, flags: u32) -> isize {
     let task = current_task().unwrap();
     let token = task.get_user_token();
@@ -1043,15 +1427,76 @@ pub fn sys_unlinkat(dirfd: usize, path: *const u8, flags: u32) -> isize {
 }
 
 bitflags! {
-    pub struct UmountFlags: u32 {
-        const MNT_FORCE           =   1;
-        const MNT_DETACH          =   2;
-        const MNT_EXPIRE          =   4;
-        const UMOUNT_NOFOLLOW     =   8;
+    pub struct LinkatFlags: u32 {
+        const AT_SYMLINK_FOLLOW = 0x400;
+    }
+}
+
+pub fn sys_linkat(
+    olddirfd: usize,
+    oldpath: *const u8,
+    newdirfd: usize,
+    newpath: *const u8,
+    flags: u32,
+) -> isize {
+    let task = current_task().unwrap();
+    let token = task.get_user_token();
+    let oldpath = match translated_str(token, oldpath) {
+        Ok(path) => path,
+        Err(errno) => return errno,
+    };
+    let newpath = match translated_str(token, newpath) {
+        Ok(path) => path,
+        Err(errno) => return errno,
+    };
+    let flags = match LinkatFlags::from_bits(flags) {
+        Some(flags) => flags,
+        None => {
+            warn!("[sys_linkat] unknown flags");
+            return EINVAL;
+        }
+    };
+    info!(
+        "[sys_linkat] olddirfd: {}, oldpath: {}, newdirfd: {}, newpath: {}, flags: {:?}",
+        olddirfd as isize, oldpath, newdirfd as isize, newpath, flags
+    );
+
+    let old_file_descriptor = match olddirfd {
+        AT_FDCWD => task.fs.lock().working_inode.as_ref().clone(),
+        fd => {
+            let fd_table = task.files.lock();
+            match fd_table.get_ref(fd) {
+                Ok(file_descriptor) => file_descriptor.clone(),
+                Err(errno) => return errno,
+            }
+        }
+    };
+    let new_file_descriptor = match newdirfd {
+        AT_FDCWD => task.fs.lock().working_inode.as_ref().clone(),
+        fd => {
+            let fd_table = task.files.lock();
+            match fd_table.get_ref(fd) {
+                Ok(file_descriptor) => file_descriptor.clone(),
+                Err(errno) => return errno,
+            }
+        }
+    };
+
+    match FileDescriptor::link(
+        &old_file_descriptor,
+        &oldpath,
+        &new_file_descriptor,
+        &newpath,
+    ) {
+        Ok(_) => SUCCESS,
+        Err(errno) => errno,
     }
 }
 
 pub fn sys_umount2(target: *const u8, flags: u32) -> isize {
+    if !current_cred().is_root() {
+        return EPERM;
+    }
     if target.is_null() {
         return EINVAL;
     }
@@ -1065,41 +1510,10 @@ pub fn sys_umount2(target: *const u8, flags: u32) -> isize {
         None => return EINVAL,
     };
     info!("[sys_umount2] target: {}, flags: {:?}", target, flags);
-    warn!("[sys_umount2] fake implementation!");
-    SUCCESS
-}
-
-bitflags! {
-    pub struct MountFlags: usize {
-        const MS_RDONLY         =   1;
-        const MS_NOSUID         =   2;
-        const MS_NODEV          =   4;
-        const MS_NOEXEC         =   8;
-        const MS_SYNCHRONOUS    =   16;
-        const MS_REMOUNT        =   32;
-        const MS_MANDLOCK       =   64;
-        const MS_DIRSYNC        =   128;
-        const MS_NOATIME        =   1024;
-        const MS_NODIRATIME     =   2048;
-        const MS_BIND           =   4096;
-        const MS_MOVE           =   8192;
-        const MS_REC            =   16384;
-        const MS_SILENT         =   32768;
-        const MS_POSIXACL       =   (1<<16);
-        const MS_UNBINDABLE     =   (1<<17);
-        const MS_PRIVATE        =   (1<<18);
-        const MS_SLAVE          =   (1<<19);
-        const MS_SHARED         =   (1<<20);
-        const MS_RELATIME       =   (1<<21);
-        const MS_KERNMOUNT      =   (1<<22);
-        const MS_I_VERSION      =   (1<<23);
-        const MS_STRICTATIME    =   (1<<24);
-        const MS_LAZYTIME       =   (1<<25);
-        const MS_NOREMOTELOCK   =   (1<<27);
-        const MS_NOSEC          =   (1<<28);
-        const MS_BORN           =   (1<<29);
-        const MS_ACTIVE         =   (1<<30);
-        const MS_NOUSER         =   (1<<31);
+    let working_inode = current_task().unwrap().fs.lock().working_inode.clone();
+    match working_inode.umount(&target, flags) {
+        Ok(_) => SUCCESS,
+        Err(errno) => errno,
     }
 }
 
@@ -1110,6 +1524,9 @@ pub fn sys_mount(
     mountflags: usize,
     data: *const u8,
 ) -> isize {
+    if !current_cred().is_root() {
+        return EPERM;
+    }
     if source.is_null() || target.is_null() || filesystemtype.is_null() {
         return EINVAL;
     }
@@ -1126,14 +1543,26 @@ pub fn sys_mount(
         Ok(filesystemtype) => filesystemtype,
         Err(errno) => return errno,
     };
-    // infallible
-    let mountflags = MountFlags::from_bits(mountflags).unwrap();
+    // the upper bits may hold the obsolete magic number MS_MGC_VAL
+    let mountflags = MountFlags::from_bits_truncate(mountflags);
     info!(
         "[sys_mount] source: {}, target: {}, filesystemtype: {}, mountflags: {:?}, data: {:?}",
         source, target, filesystemtype, mountflags, data
     );
-    warn!("[sys_mount] fake implementation!");
-    SUCCESS
+    // every filesystem we support takes its options as a string
+    let data = if data.is_null() {
+        String::new()
+    } else {
+        match translated_str(token, data) {
+            Ok(data) => data,
+            Err(errno) => return errno,
+        }
+    };
+    let working_inode = current_task().unwrap().fs.lock().working_inode.clone();
+    match working_inode.mount(&source, &target, &filesystemtype, mountflags, &data) {
+        Ok(_) => SUCCESS,
+        Err(errno) => errno,
+    }
 }
 
 bitflags! {
@@ -1172,7 +1601,12 @@ pub fn sys_utimensat(
         dirfd as isize, path, times, flags
     );
 
-    let inode = match __openat(dirfd, &path) {
+    let open_flags = if flags.contains(UtimensatFlags::AT_SYMLINK_NOFOLLOW) {
+        NOFOLLOW_FLAGS
+    } else {
+        OpenFlags::O_PATH
+    };
+    let inode = match __openat(dirfd, &path, open_flags) {
         Ok(inode) => inode,
         Err(errno) => return errno,
     };
@@ -1306,6 +1740,78 @@ pub fn sys_fcntl(fd: usize, cmd: u32, arg: usize) -> isize {
             }
             res
         }
+        command @ (Fcntl_Command::GETLK | Fcntl_Command::OFD_GETLK) => {
+            let file = match fd_table.get_ref(fd) {
+                Ok(file_descriptor) => file_descriptor.file.clone(),
+                Err(errno) => return errno,
+            };
+            drop(fd_table);
+            let token = task.get_user_token();
+            let mut flock = Flock::default();
+            if copy_from_user(token, arg as *const Flock, &mut flock).is_err() {
+                return EFAULT;
+            }
+            let ofd = command == Fcntl_Command::OFD_GETLK;
+            if let Err(errno) = locks::get_range_lock(&file, &mut flock, ofd) {
+                return errno;
+            }
+            if copy_to_user(token, &flock, arg as *mut Flock).is_err() {
+                return EFAULT;
+            }
+            SUCCESS
+        }
+        command @ (Fcntl_Command::SETLK
+        | Fcntl_Command::SETLKW
+        | Fcntl_Command::OFD_SETLK
+        | Fcntl_Command::OFD_SETLKW) => {
+            let file = match fd_table.get_ref(fd) {
+                Ok(file_descriptor) => file_descriptor.file.clone(),
+                Err(errno) => return errno,
+            };
+            // the lock may be waited for
+            drop(fd_table);
+            let mut flock = Flock::default();
+            if copy_from_user(task.get_user_token(), arg as *const Flock, &mut flock).is_err() {
+                return EFAULT;
+            }
+            drop(task);
+            let ofd = matches!(
+                command,
+                Fcntl_Command::OFD_SETLK | Fcntl_Command::OFD_SETLKW
+            );
+            let block = matches!(command, Fcntl_Command::SETLKW | Fcntl_Command::OFD_SETLKW);
+            match locks::set_range_lock(&file, &flock, ofd, block) {
+                Ok(()) => SUCCESS,
+                Err(errno) => errno,
+            }
+        }
+        Fcntl_Command::ADD_SEALS => {
+            let file_descriptor = match fd_table.get_ref(fd) {
+                Ok(file_descriptor) => file_descriptor,
+                Err(errno) => return errno,
+            };
+            if !file_descriptor.writable() {
+                return EPERM;
+            }
+            let seals = match SealFlags::from_bits(arg as u32) {
+                Some(seals) => seals,
+                None => return EINVAL,
+            };
+            match file_descriptor.file.add_seals(seals) {
+                Ok(()) => SUCCESS,
+                Err(errno) => errno,
+            }
+        }
+        Fcntl_Command::GET_SEALS => {
+            let file_descriptor = match fd_table.get_ref(fd) {
+                Ok(file_descriptor) => file_descriptor,
+                Err(errno) => return errno,
+            };
+            match file_descriptor.file.get_seals() {
+                Ok(seals) => seals.bits() as isize,
+                Err(errno) => errno,
+            }
+        }
         command => {
             warn!("[fcntl] Unsupported command: {:?}", command);
             SUCCESS
@@ -1313,6 +1819,47 @@ pub fn sys_fcntl(fd: usize, cmd: u32, arg: usize) -> isize {
     }
 }
 
+pub fn sys_memfd_create(name: *const u8, flags: u32) -> isize {
+    let task = current_task().unwrap();
+    let name = match translated_str(task.get_user_token(), name) {
+        Ok(name) => name,
+        Err(errno) => return errno,
+    };
+    info!("[sys_memfd_create] name: {}, flags: {:#x}", name, flags);
+    let flags = match MemfdFlags::from_bits(flags) {
+        Some(flags) => flags,
+        None => return EINVAL,
+    };
+    let file = match memfd_create(&name, flags) {
+        Ok(file) => file,
+        Err(errno) => return errno,
+    };
+    let mut fd_table = task.files.lock();
+    match fd_table.insert(FileDescriptor::new(
+        flags.contains(MemfdFlags::MFD_CLOEXEC),
+        false,
+        file,
+    )) {
+        Ok(fd) => fd as isize,
+        Err(errno) => errno,
+    }
+}
+
+pub fn sys_flock(fd: usize, operation: u32) -> isize {
+    info!("[sys_flock] fd: {}, operation: {:#x}", fd, operation);
+    let task = current_task().unwrap();
+    let file = match task.files.lock().get_ref(fd) {
+        Ok(file_descriptor) => file_descriptor.file.clone(),
+        Err(errno) => return errno,
+    };
+    // the lock may be waited for
+    drop(task);
+    match locks::flock(&file, operation) {
+        Ok(()) => SUCCESS,
+        Err(errno) => errno,
+    }
+}
+
 pub fn sys_pselect(
     nfds: usize,
     read_fds: *mut FdSet,
@@ -1383,14 +1930,13 @@ pub fn sys_pselect(
 /// umask() sets the calling process's file mode creation mask (umask) to
 /// mask & 0777 (i.e., only the file permission bits of mask are used),
 /// and returns the previous value of the mask.
-/// # WARNING
-/// In current implementation, umask is always 0. This syscall won't do anything.
 pub fn sys_umask(mask: u32) -> isize {
     info!("[sys_umask] mask: {:o}", mask);
-    warn!(
-        "[sys_umask] In current implementation, umask is always 0. This syscall won't do anything."
-    );
-    0
+    let task = current_task().unwrap();
+    let mut lock = task.fs.lock();
+    let old_mask = lock.umask;
+    lock.umask = mask & 0o777;
+    old_mask as isize
 }
 
 bitflags! {
@@ -1432,9 +1978,31 @@ pub fn sys_faccessat2(dirfd: usize, pathname: *const u8, mode: u32, flags: u32)
         dirfd as isize, pathname, mode, flags
     );
 
-    // Do not check user's authority, because user group is not implemented yet.
-    // All existing files can be accessed.
-    match __openat(dirfd, pathname.as_str()) {
+    let open_flags = if flags.contains(FaccessatFlags::AT_SYMLINK_NOFOLLOW) {
+        NOFOLLOW_FLAGS
+    } else {
+        OpenFlags::O_PATH
+    };
+    let file_descriptor = match __openat(dirfd, pathname.as_str(), open_flags) {
+        Ok(file_descriptor) => file_descriptor,
+        Err(errno) => return errno,
+    };
+    if mode.contains(FaccessatMode::W_OK)
+        && file_descriptor
+            .mount_flags()
+            .contains(MountFlags::MS_RDONLY)
+    {
+        return EROFS;
+    }
+    // Like Linux, check with the real ids unless asked otherwise.
+    // The lookup of the path itself still uses the effective ids.
+    let cred = current_cred();
+    let cred = if flags.contains(FaccessatFlags::AT_EACCESS) {
+        cred
+    } else {
+        cred.real()
+    };
+    match file_descriptor.check_access(&cred, Access::from_bits_truncate(mode.bits())) {
         Ok(_) => SUCCESS,
         Err(errno) => errno,
     }
@@ -1456,18 +2024,28 @@ pub fn sys_msync(addr: usize, length: usize, flags: u32) -> isize {
         Some(flags) => flags,
         None => return EINVAL,
     };
-    let task = current_task().unwrap();
-    if !task
-        .vm
-        .lock()
-        .contains_valid_buffer(addr, length, MapPermission::empty())
-    {
-        return ENOMEM;
+    if flags.contains(MsyncFlags::MS_ASYNC | MsyncFlags::MS_SYNC) {
+        return EINVAL;
     }
     info!(
         "[sys_msync] addr: {:X}, length: {:X}, flags: {:?}",
-        addr, flags, flags
+        addr, length, flags
     );
+    let task = current_task().unwrap();
+    let mut vm = task.vm.lock();
+    if !vm.contains_valid_buffer(addr, length, MapPermission::empty()) {
+        return ENOMEM;
+    }
+    let files = vm.msync(addr, length);
+    drop(vm);
+    // with MS_ASYNC the pages are left to the writeback task
+    if flags.contains(MsyncFlags::MS_SYNC) {
+        for file in files {
+            if let Err(errno) = file.fsync() {
+                return errno;
+            }
+        }
+    }
     SUCCESS
 }
 
@@ -1483,3 +2061,66 @@ pub fn sys_ftruncate(fd: usize, length: isize) -> isize {
         Err(errno) => errno,
     }
 }
+
+/// A regular file or a block device, e.g. `/dev/sda2`, can be swapped on.
+#[cfg(feature = "swap")]
+pub fn sys_swapon(path: *const u8, flags: u32) -> isize {
+    if !current_cred().is_root() {
+        return EPERM;
+    }
+    let path = match translated_str(current_user_token(), path) {
+        Ok(path) => path,
+        Err(errno) => return errno,
+    };
+    let prio = flags & SWAP_FLAG_PRIO_MASK;
+    let flags = match SwapFlags::from_bits(flags & !SWAP_FLAG_PRIO_MASK) {
+        Some(flags) => flags,
+        None => return EINVAL,
+    };
+    info!(
+        "[sys_swapon] path: {}, flags: {:?}, prio: {}",
+        path, flags, prio
+    );
+    // read only, an active swap file refuses writers and the second `swapon()` gets `EBUSY`
+    let file_descriptor = match __openat(AT_FDCWD, &path, OpenFlags::O_RDONLY) {
+        Ok(file_descriptor) => file_descriptor,
+        Err(errno) => return errno,
+    };
+    if !file_descriptor.file.is_file() {
+        return EINVAL;
+    }
+    let prio = if flags.contains(SwapFlags::SWAP_FLAG_PREFER) {
+        Some(prio as isize)
+    } else {
+        None
+    };
+    match swap::swapon(file_descriptor.file, prio) {
+        Ok(()) => SUCCESS,
+        Err(errno) => errno,
+    }
+}
+
+#[cfg(feature = "swap")]
+pub fn sys_swapoff(path: *const u8) -> isize {
+    if !current_cred().is_root() {
+        return EPERM;
+    }
+    let path = match translated_str(current_user_token(), path) {
+        Ok(path) => path,
+        Err(errno) => return errno,
+    };
+    info!("[sys_swapoff] path: {}", path);
+    let file_descriptor = match __openat(AT_FDCWD, &path, OpenFlags::O_RDONLY) {
+        Ok(file_descriptor) => file_descriptor,
+        Err(errno) => return errno,
+    };
+    // areas are known by their absolute path
+    let path = match file_descriptor.file.get_dirtree_node() {
+        Some(inode) => inode.get_cwd(),
+        None => return EINVAL,
+    };
+    match swap::swapoff(&path) {
+        Ok(()) => SUCCESS,
+        Err(errno) => errno,
+    }
+}
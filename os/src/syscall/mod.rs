@@ -3,8 +3,12 @@ mod syscall_macro;
 
 pub mod errno;
 pub mod fs;
-// pub mod shm;
+mod msg;
+mod sem;
+mod shm;
 mod process;
+mod ptrace;
+mod sched;
 mod net;
 
 use crate::arch::syscall_id::*;
@@ -12,9 +16,13 @@ use core::convert::TryFrom;
 use fs::*;
 use log::{error, info};
 use process::*;
+use ptrace::*;
 pub use process::CloneFlags;
 use net::*;
-// use shm::*;
+use msg::*;
+use sched::*;
+use sem::*;
+use shm::*;
 pub fn syscall_name(id: usize) -> &'static str {
     match id {
         SYSCALL_DUP => "dup",
@@ -22,17 +30,34 @@ pub fn syscall_name(id: usize) -> &'static str {
         SYSCALL_DUP3 => "dup3",
         SYSCALL_OPEN => "open",
         SYSCALL_GET_TIME => "get_time",
+        SYSCALL_SETXATTR => "setxattr",
+        SYSCALL_LSETXATTR => "lsetxattr",
+        SYSCALL_FSETXATTR => "fsetxattr",
+        SYSCALL_GETXATTR => "getxattr",
+        SYSCALL_LGETXATTR => "lgetxattr",
+        SYSCALL_FGETXATTR => "fgetxattr",
+        SYSCALL_LISTXATTR => "listxattr",
+        SYSCALL_LLISTXATTR => "llistxattr",
+        SYSCALL_FLISTXATTR => "flistxattr",
+        SYSCALL_REMOVEXATTR => "removexattr",
+        SYSCALL_LREMOVEXATTR => "lremovexattr",
+        SYSCALL_FREMOVEXATTR => "fremovexattr",
         SYSCALL_GETCWD => "getcwd",
         SYSCALL_FCNTL => "fcntl",
         SYSCALL_IOCTL => "ioctl",
+        SYSCALL_FLOCK => "flock",
         SYSCALL_MKDIRAT => "mkdirat",
         SYSCALL_UNLINKAT => "unlinkat",
+        SYSCALL_SYMLINKAT => "symlinkat",
         SYSCALL_LINKAT => "linkat",
         SYSCALL_UMOUNT2 => "umount2",
         SYSCALL_MOUNT => "mount",
         SYSCALL_FACCESSAT => "faccessat",
         SYSCALL_CHDIR => "chdir",
+        SYSCALL_FCHMOD => "fchmod",
         SYSCALL_FCHMODAT => "fchmodat",
+        SYSCALL_FCHOWNAT => "fchownat",
+        SYSCALL_FCHOWN => "fchown",
         SYSCALL_OPENAT => "openat",
         SYSCALL_CLOSE => "close",
         SYSCALL_PIPE2 => "pipe2",
@@ -52,9 +77,13 @@ pub fn syscall_name(id: usize) -> &'static str {
         SYSCALL_FSTAT => "fstat",
         SYSCALL_STATFS => "statfs",
         SYSCALL_FTRUNCATE => "ftruncate",
+        SYSCALL_SYNC => "sync",
         SYSCALL_FSYNC => "fsync",
+        SYSCALL_FDATASYNC => "fdatasync",
+        SYSCALL_SYNCFS => "syncfs",
         SYSCALL_UTIMENSAT => "utimensat",
         SYSCALL_EXIT => "exit",
+        SYSCALL_PERSONALITY => "personality",
         SYSCALL_EXIT_GROUP => "exit_GROUP",
         SYSCALL_SET_TID_ADDRESS => "set_tid_address",
         SYSCALL_FUTEX => "futex",
@@ -65,17 +94,40 @@ pub fn syscall_name(id: usize) -> &'static str {
         SYSCALL_SETITIMER => "setitimer",
         SYSCALL_CLOCK_GETTIME => "clock_gettime",
         SYSCALL_SYSLOG => "syslog",
+        SYSCALL_PTRACE => "ptrace",
+        SYSCALL_SCHED_SETPARAM => "sched_setparam",
+        SYSCALL_SCHED_SETSCHEDULER => "sched_setscheduler",
+        SYSCALL_SCHED_GETSCHEDULER => "sched_getscheduler",
+        SYSCALL_SCHED_GETPARAM => "sched_getparam",
+        SYSCALL_SCHED_SETAFFINITY => "sched_setaffinity",
+        SYSCALL_SCHED_GETAFFINITY => "sched_getaffinity",
         SYSCALL_YIELD => "yield",
+        SYSCALL_SCHED_GET_PRIORITY_MAX => "sched_get_priority_max",
+        SYSCALL_SCHED_GET_PRIORITY_MIN => "sched_get_priority_min",
+        SYSCALL_SCHED_RR_GET_INTERVAL => "sched_rr_get_interval",
         SYSCALL_KILL => "kill",
         SYSCALL_TKILL => "tkill",
         SYSCALL_SIGACTION => "sigaction",
         SYSCALL_SIGPROCMASK => "sigprocmask",
         SYSCALL_SIGTIMEDWAIT => "sigtimedwait",
         SYSCALL_SIGRETURN => "sigreturn",
+        SYSCALL_SETPRIORITY => "setpriority",
+        SYSCALL_GETPRIORITY => "getpriority",
+        SYSCALL_SETREGID => "setregid",
+        SYSCALL_SETGID => "setgid",
+        SYSCALL_SETREUID => "setreuid",
+        SYSCALL_SETUID => "setuid",
+        SYSCALL_SETRESUID => "setresuid",
+        SYSCALL_GETRESUID => "getresuid",
+        SYSCALL_SETRESGID => "setresgid",
+        SYSCALL_GETRESGID => "getresgid",
         SYSCALL_TIMES => "times",
         SYSCALL_SETPGID => "setpgid",
         SYSCALL_GETPGID => "getpgid",
+        SYSCALL_GETSID => "getsid",
         SYSCALL_SETSID => "setsid",
+        SYSCALL_GETGROUPS => "getgroups",
+        SYSCALL_SETGROUPS => "setgroups",
         SYSCALL_UNAME => "uname",
         SYSCALL_GETRUSAGE => "getrusage",
         SYSCALL_UMASK => "umask",
@@ -102,11 +154,28 @@ pub fn syscall_name(id: usize) -> &'static str {
         SYSCALL_SBRK => "sbrk",
         SYSCALL_BRK => "brk",
         SYSCALL_MUNMAP => "munmap",
+        SYSCALL_MREMAP => "mremap",
+        SYSCALL_MSGGET => "msgget",
+        SYSCALL_MSGCTL => "msgctl",
+        SYSCALL_MSGRCV => "msgrcv",
+        SYSCALL_MSGSND => "msgsnd",
+        SYSCALL_SEMGET => "semget",
+        SYSCALL_SEMCTL => "semctl",
+        SYSCALL_SEMTIMEDOP => "semtimedop",
+        SYSCALL_SEMOP => "semop",
+        SYSCALL_SHMGET => "shmget",
+        SYSCALL_SHMCTL => "shmctl",
+        SYSCALL_SHMAT => "shmat",
+        SYSCALL_SHMDT => "shmdt",
         SYSCALL_CLONE => "clone",
         SYSCALL_EXECVE => "execve",
         SYSCALL_MMAP => "mmap",
+        SYSCALL_SWAPON => "swapon",
+        SYSCALL_SWAPOFF => "swapoff",
         SYSCALL_MPROTECT => "mprotect",
         SYSCALL_MSYNC => "msync",
+        SYSCALL_MINCORE => "mincore",
+        SYSCALL_MADVISE => "madvise",
         SYSCALL_WAIT4 => "wait4",
         SYSCALL_PRLIMIT => "prlimit",
         SYSCALL_RENAMEAT2 => "renameat2",
@@ -114,6 +183,7 @@ pub fn syscall_name(id: usize) -> &'static str {
         SYSCALL_MEMBARRIER => "membarrier",
         SYSCALL_STATX => "statx",
         SYSCALL_GETRANDOM => "getrandom",
+        SYSCALL_MEMFD_CREATE => "memfd_create",
         // non-standard
         SYSCALL_LS => "ls",
         SYSCALL_SHUTDOWN =>"shutdown",
@@ -162,14 +232,71 @@ pub fn syscall(syscall_id: usize, args: [usize; 6]) -> isize {
         );
     }
     let ret = match syscall_id {
+        SYSCALL_SETXATTR => sys_setxattr(
+            args[0] as *const u8,
+            args[1] as *const u8,
+            args[2] as *const u8,
+            args[3],
+            args[4] as u32,
+            true,
+        ),
+        SYSCALL_LSETXATTR => sys_setxattr(
+            args[0] as *const u8,
+            args[1] as *const u8,
+            args[2] as *const u8,
+            args[3],
+            args[4] as u32,
+            false,
+        ),
+        SYSCALL_FSETXATTR => sys_fsetxattr(
+            args[0],
+            args[1] as *const u8,
+            args[2] as *const u8,
+            args[3],
+            args[4] as u32,
+        ),
+        SYSCALL_GETXATTR => sys_getxattr(
+            args[0] as *const u8,
+            args[1] as *const u8,
+            args[2] as *mut u8,
+            args[3],
+            true,
+        ),
+        SYSCALL_LGETXATTR => sys_getxattr(
+            args[0] as *const u8,
+            args[1] as *const u8,
+            args[2] as *mut u8,
+            args[3],
+            false,
+        ),
+        SYSCALL_FGETXATTR => {
+            sys_fgetxattr(args[0], args[1] as *const u8, args[2] as *mut u8, args[3])
+        }
+        SYSCALL_LISTXATTR => sys_listxattr(args[0] as *const u8, args[1] as *mut u8, args[2], true),
+        SYSCALL_LLISTXATTR => {
+            sys_listxattr(args[0] as *const u8, args[1] as *mut u8, args[2], false)
+        }
+        SYSCALL_FLISTXATTR => sys_flistxattr(args[0], args[1] as *mut u8, args[2]),
+        SYSCALL_REMOVEXATTR => sys_removexattr(args[0] as *const u8, args[1] as *const u8, true),
+        SYSCALL_LREMOVEXATTR => sys_removexattr(args[0] as *const u8, args[1] as *const u8, false),
+        SYSCALL_FREMOVEXATTR => sys_fremovexattr(args[0], args[1] as *const u8),
         SYSCALL_GETCWD => sys_getcwd(args[0], args[1]),
         SYSCALL_DUP => sys_dup(args[0]),
         SYSCALL_DUP2 => sys_dup2(args[0],args[1]),
         SYSCALL_DUP3 => sys_dup3(args[0], args[1], args[2] as u32),
         SYSCALL_FCNTL => sys_fcntl(args[0], args[1] as u32, args[2]),
         SYSCALL_IOCTL => sys_ioctl(args[0], args[1] as u32, args[2]),
+        SYSCALL_FLOCK => sys_flock(args[0], args[1] as u32),
         SYSCALL_MKDIRAT => sys_mkdirat(args[0], args[1] as *const u8, args[2] as u32),
         SYSCALL_UNLINKAT => sys_unlinkat(args[0], args[1] as *const u8, args[2] as u32),
+        SYSCALL_SYMLINKAT => sys_symlinkat(args[0] as *const u8, args[1], args[2] as *const u8),
+        SYSCALL_LINKAT => sys_linkat(
+            args[0],
+            args[1] as *const u8,
+            args[2],
+            args[3] as *const u8,
+            args[4] as u32,
+        ),
         SYSCALL_UMOUNT2 => sys_umount2(args[0] as *const u8, args[1] as u32),
         SYSCALL_MOUNT => sys_mount(
             args[0] as *const u8,
@@ -180,7 +307,16 @@ pub fn syscall(syscall_id: usize, args: [usize; 6]) -> isize {
         ),
         SYSCALL_FACCESSAT => sys_faccessat2(args[0], args[1] as *const u8, args[2] as u32, 0u32),
         SYSCALL_CHDIR => sys_chdir(args[0] as *const u8),
-        SYSCALL_FCHMODAT => sys_fchmodat(),
+        SYSCALL_FCHMOD => sys_fchmod(args[0], args[1] as u32),
+        SYSCALL_FCHMODAT => sys_fchmodat(args[0], args[1] as *const u8, args[2] as u32),
+        SYSCALL_FCHOWNAT => sys_fchownat(
+            args[0],
+            args[1] as *const u8,
+            args[2] as u32,
+            args[3] as u32,
+            args[4] as u32,
+        ),
+        SYSCALL_FCHOWN => sys_fchown(args[0], args[1] as u32, args[2] as u32),
         SYSCALL_OPEN => sys_openat(AT_FDCWD, args[0] as *const u8, args[1] as u32, 0o777u32),
         SYSCALL_OPENAT => sys_openat(
             args[0],
@@ -211,6 +347,8 @@ pub fn syscall(syscall_id: usize, args: [usize; 6]) -> isize {
         SYSCALL_FSTAT => sys_fstat(args[0], args[1] as *mut u8),
         SYSCALL_FTRUNCATE => sys_ftruncate(args[0], args[1] as isize),
         SYSCALL_FSYNC => sys_fsync(args[0]),
+        SYSCALL_FDATASYNC => sys_fdatasync(args[0]),
+        SYSCALL_SYNCFS => sys_syncfs(args[0]),
         SYSCALL_UTIMENSAT => sys_utimensat(
             args[0],
             args[1] as *const u8,
@@ -225,12 +363,40 @@ pub fn syscall(syscall_id: usize, args: [usize; 6]) -> isize {
             args[4],
             args[5],
         ),
-        SYSCALL_SHMGET => sys_return_success(),
-        SYSCALL_SHMCTL => sys_return_success(),
-        SYSCALL_SHMAT => sys_return_success(),
-        SYSCALL_SHMDT => sys_return_success(),
-        SYSCALL_SYNC => sys_return_success(),
+        SYSCALL_MSGGET => sys_msgget(args[0] as i32, args[1] as u32),
+        SYSCALL_MSGCTL => sys_msgctl(
+            args[0],
+            args[1] as u32,
+            args[2] as *mut crate::ipc::msg::MsqidDs,
+        ),
+        SYSCALL_MSGRCV => sys_msgrcv(
+            args[0],
+            args[1] as *mut u8,
+            args[2],
+            args[3] as isize,
+            args[4] as u32,
+        ),
+        SYSCALL_MSGSND => sys_msgsnd(args[0], args[1] as *const u8, args[2], args[3] as u32),
+        SYSCALL_SEMGET => sys_semget(args[0] as i32, args[1] as i32, args[2] as u32),
+        SYSCALL_SEMCTL => sys_semctl(args[0], args[1], args[2] as u32, args[3]),
+        SYSCALL_SEMTIMEDOP => sys_semtimedop(
+            args[0],
+            args[1] as *const crate::ipc::sem::Sembuf,
+            args[2],
+            args[3] as *const crate::timer::TimeSpec,
+        ),
+        SYSCALL_SEMOP => sys_semop(args[0], args[1] as *const crate::ipc::sem::Sembuf, args[2]),
+        SYSCALL_SHMGET => sys_shmget(args[0] as i32, args[1], args[2] as u32),
+        SYSCALL_SHMCTL => sys_shmctl(
+            args[0],
+            args[1] as u32,
+            args[2] as *mut crate::ipc::shm::ShmidDs,
+        ),
+        SYSCALL_SHMAT => sys_shmat(args[0], args[1], args[2] as u32),
+        SYSCALL_SHMDT => sys_shmdt(args[0]),
+        SYSCALL_SYNC => sys_sync(),
         SYSCALL_EXIT => sys_exit(args[0] as u32),
+        SYSCALL_PERSONALITY => sys_personality(args[0] as u32),
         SYSCALL_EXIT_GROUP => sys_exit_group(args[0] as u32),
         SYSCALL_CLOCK_GETTIME => sys_clock_gettime(args[0], args[1] as *mut TimeSpec),
         SYSCALL_CLOCK_NANOSLEEP => sys_clock_nanosleep(args[0],args[1],args[2],args[3]),
@@ -238,11 +404,35 @@ pub fn syscall(syscall_id: usize, args: [usize; 6]) -> isize {
         SYSCALL_TKILL => sys_tkill(args[0], args[1]),
         SYSCALL_TGKILL => sys_tgkill(args[0],args[1],args[2]),
         SYSCALL_SYSLOG => sys_syslog(args[0] as u32, args[1] as *mut u8, args[2] as u32),
+        SYSCALL_PTRACE => sys_ptrace(args[0], args[1], args[2], args[3]),
+        SYSCALL_SCHED_SETPARAM => {
+            sys_sched_setparam(args[0] as isize, args[1] as *const SchedParam)
+        }
+        SYSCALL_SCHED_SETSCHEDULER => sys_sched_setscheduler(
+            args[0] as isize,
+            args[1] as u32,
+            args[2] as *const SchedParam,
+        ),
+        SYSCALL_SCHED_GETSCHEDULER => sys_sched_getscheduler(args[0] as isize),
+        SYSCALL_SCHED_GETPARAM => sys_sched_getparam(args[0] as isize, args[1] as *mut SchedParam),
+        SYSCALL_SCHED_SETAFFINITY => {
+            sys_sched_setaffinity(args[0] as isize, args[1], args[2] as *const u8)
+        }
+        SYSCALL_SCHED_GETAFFINITY => {
+            sys_sched_getaffinity(args[0] as isize, args[1], args[2] as *mut u8)
+        }
         SYSCALL_YIELD => sys_yield(),
+        SYSCALL_SCHED_GET_PRIORITY_MAX => sys_sched_get_priority_max(args[0] as u32),
+        SYSCALL_SCHED_GET_PRIORITY_MIN => sys_sched_get_priority_min(args[0] as u32),
+        SYSCALL_SCHED_RR_GET_INTERVAL => {
+            sys_sched_rr_get_interval(args[0] as isize, args[1] as *mut TimeSpec)
+        }
         SYSCALL_SIGACTION => sys_sigaction(args[0], args[1], args[2]),
         SYSCALL_SIGPROCMASK => sys_sigprocmask(args[0] as u32, args[1], args[2]),
         SYSCALL_SIGTIMEDWAIT => sys_sigtimedwait(args[0], args[1], args[2]),
         SYSCALL_SIGRETURN => sys_sigreturn(),
+        SYSCALL_SETPRIORITY => sys_setpriority(args[0] as u32, args[1], args[2] as i32),
+        SYSCALL_GETPRIORITY => sys_getpriority(args[0] as u32, args[1]),
         SYSCALL_TIMES => sys_times(args[0] as *mut Times),
         SYSCALL_NANOSLEEP => sys_nanosleep(
             args[0] as *const crate::timer::TimeSpec,
@@ -262,6 +452,7 @@ pub fn syscall(syscall_id: usize, args: [usize; 6]) -> isize {
         ),
         SYSCALL_SETPGID => sys_setpgid(args[0], args[1]),
         SYSCALL_GETPGID => sys_getpgid(args[0]),
+        SYSCALL_GETSID => sys_getsid(args[0]),
         SYSCALL_SETSID => sys_setsid(),
         SYSCALL_UNAME => sys_uname(args[0] as *mut u8),
         SYSCALL_GETPID => sys_getpid(),
@@ -314,13 +505,34 @@ pub fn syscall(syscall_id: usize, args: [usize; 6]) -> isize {
         SYSCALL_GETEUID => sys_geteuid(),
         SYSCALL_GETGID => sys_getgid(),
         SYSCALL_GETEGID => sys_getegid(),
+        SYSCALL_SETUID => sys_setuid(args[0] as u32),
+        SYSCALL_SETGID => sys_setgid(args[0] as u32),
+        SYSCALL_SETREUID => sys_setreuid(args[0] as u32, args[1] as u32),
+        SYSCALL_SETREGID => sys_setregid(args[0] as u32, args[1] as u32),
+        SYSCALL_SETRESUID => sys_setresuid(args[0] as u32, args[1] as u32, args[2] as u32),
+        SYSCALL_SETRESGID => sys_setresgid(args[0] as u32, args[1] as u32, args[2] as u32),
+        SYSCALL_GETRESUID => sys_getresuid(
+            args[0] as *mut u32,
+            args[1] as *mut u32,
+            args[2] as *mut u32,
+        ),
+        SYSCALL_GETRESGID => sys_getresgid(
+            args[0] as *mut u32,
+            args[1] as *mut u32,
+            args[2] as *mut u32,
+        ),
+        SYSCALL_GETGROUPS => sys_getgroups(args[0], args[1] as *mut u32),
+        SYSCALL_SETGROUPS => sys_setgroups(args[0], args[1] as *const u32),
         SYSCALL_GETTID => sys_gettid(),
         SYSCALL_SYSINFO => sys_sysinfo(args[0] as *mut Sysinfo),
         SYSCALL_SBRK => sys_sbrk(args[0] as isize),
         SYSCALL_BRK => sys_brk(args[0]),
         SYSCALL_MMAP => sys_mmap(args[0], args[1], args[2], args[3], args[4], args[5]),
         SYSCALL_MUNMAP => sys_munmap(args[0], args[1]),
+        SYSCALL_MREMAP => sys_mremap(args[0], args[1], args[2], args[3] as u32, args[4]),
         SYSCALL_MPROTECT => sys_mprotect(args[0], args[1], args[2]),
+        SYSCALL_MINCORE => sys_mincore(args[0], args[1], args[2] as *mut u8),
+        SYSCALL_MADVISE => sys_madvise(args[0], args[1], args[2]),
         SYSCALL_PSELECT6 => sys_pselect(
             args[0],
             args[1] as *mut FdSet,
@@ -410,6 +622,11 @@ pub fn syscall(syscall_id: usize, args: [usize; 6]) -> isize {
             args[1] as usize,
             args[2] as u32
         ),
+        SYSCALL_MEMFD_CREATE => sys_memfd_create(args[0] as *const u8, args[1] as u32),
+        #[cfg(feature = "swap")]
+        SYSCALL_SWAPON => sys_swapon(args[0] as *const u8, args[1] as u32),
+        #[cfg(feature = "swap")]
+        SYSCALL_SWAPOFF => sys_swapoff(args[0] as *const u8),
         SYSCALL_SHUTDOWN => sys_shutdown(),
         _ => {
             error!(
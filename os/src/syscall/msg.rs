@@ -0,0 +1,102 @@
+use crate::ipc::{
+    msg::{self, MsgInfo, MsqidDs, MSGMAX, MSG_INFO},
+    IPC_64, IPC_INFO, IPC_RMID, IPC_SET, IPC_STAT,
+};
+use crate::mm::{copy_from_user, copy_from_user_array, copy_to_user, copy_to_user_array};
+use crate::syscall::errno::*;
+use crate::task::current_user_token;
+use alloc::vec;
+use log::info;
+
+/// `struct msgbuf` starts with the type, the text follows it.
+const MTYPE_SIZE: usize = core::mem::size_of::<isize>();
+
+pub fn sys_msgget(key: i32, msgflg: u32) -> isize {
+    info!("[sys_msgget] key: {}, msgflg: {:#o}", key, msgflg);
+    match msg::get(key, msgflg) {
+        Ok(msqid) => msqid as isize,
+        Err(errno) => errno,
+    }
+}
+
+pub fn sys_msgsnd(msqid: usize, msgp: *const u8, msgsz: usize, msgflg: u32) -> isize {
+    info!(
+        "[sys_msgsnd] msqid: {}, msgp: {:?}, msgsz: {}, msgflg: {:#o}",
+        msqid, msgp, msgsz, msgflg
+    );
+    if msgsz > MSGMAX {
+        return EINVAL;
+    }
+    let token = current_user_token();
+    let mut mtype = 0isize;
+    if let Err(errno) = copy_from_user(token, msgp as *const isize, &mut mtype) {
+        return errno;
+    }
+    let mut text = vec![0u8; msgsz];
+    if let Err(errno) = copy_from_user_array(
+        token,
+        msgp.wrapping_add(MTYPE_SIZE),
+        text.as_mut_ptr(),
+        msgsz,
+    ) {
+        return errno;
+    }
+    match msg::send(msqid, mtype, text, msgflg) {
+        Ok(()) => SUCCESS,
+        Err(errno) => errno,
+    }
+}
+
+pub fn sys_msgrcv(msqid: usize, msgp: *mut u8, msgsz: usize, msgtyp: isize, msgflg: u32) -> isize {
+    info!(
+        "[sys_msgrcv] msqid: {}, msgp: {:?}, msgsz: {}, msgtyp: {}, msgflg: {:#o}",
+        msqid, msgp, msgsz, msgtyp, msgflg
+    );
+    if (msgsz as isize) < 0 {
+        return EINVAL;
+    }
+    let (mtype, text) = match msg::receive(msqid, msgtyp, msgsz, msgflg) {
+        Ok(message) => message,
+        Err(errno) => return errno,
+    };
+    // the message is gone from the queue even if it can't be stored, like on Linux
+    let token = current_user_token();
+    if let Err(errno) = copy_to_user(token, &mtype, msgp as *mut isize) {
+        return errno;
+    }
+    match copy_to_user_array(
+        token,
+        text.as_ptr(),
+        msgp.wrapping_add(MTYPE_SIZE),
+        text.len(),
+    ) {
+        Ok(()) => text.len() as isize,
+        Err(errno) => errno,
+    }
+}
+
+pub fn sys_msgctl(msqid: usize, cmd: u32, buf: *mut MsqidDs) -> isize {
+    info!(
+        "[sys_msgctl] msqid: {}, cmd: {:#x}, buf: {:?}",
+        msqid, cmd, buf
+    );
+    let token = current_user_token();
+    let result = match cmd & !IPC_64 {
+        IPC_STAT => msg::stat(msqid).and_then(|msqid_ds| copy_to_user(token, &msqid_ds, buf)),
+        IPC_SET => {
+            let mut msqid_ds = MsqidDs::default();
+            copy_from_user(token, buf, &mut msqid_ds).and_then(|()| msg::set(msqid, &msqid_ds))
+        }
+        IPC_RMID => msg::remove(msqid),
+        IPC_INFO | MSG_INFO => copy_to_user(
+            token,
+            &msg::info(cmd & !IPC_64 == MSG_INFO),
+            buf as *mut MsgInfo,
+        ),
+        _ => Err(EINVAL),
+    };
+    match result {
+        Ok(()) => SUCCESS,
+        Err(errno) => errno,
+    }
+}
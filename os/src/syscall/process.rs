@@ -1,19 +1,22 @@
 use crate::arch::{MachineContext, TrapContext};
 use crate::config::{PAGE_SIZE, SYSTEM_TASK_LIMIT, USER_STACK_SIZE};
-use crate::fs::OpenFlags;
+use crate::fs::directory_tree::sync_all;
+use crate::fs::{MountFlags, OpenFlags, StatMode};
 use crate::mm::{
-    copy_from_user, copy_to_user, copy_to_user_string, get_from_user, translated_byte_buffer,
-    translated_ref, translated_refmut, translated_str, try_get_from_user, MapFlags, MapPermission,
-    UserBuffer,
+    copy_from_user, copy_from_user_array, copy_to_user, copy_to_user_array, copy_to_user_string,
+    get_from_user, translated_byte_buffer, translated_ref, translated_refmut, translated_str,
+    try_get_from_user, Advice, MapFlags, MapPermission, MremapFlags, UserBuffer,
+    PER_CLEAR_ON_SETID,
 };
 use crate::show_frame_consumption;
 use crate::syscall::errno::*;
 use crate::task::threads::{do_futex_wait, FutexCmd};
 use crate::task::{
-    add_task, block_current_and_run_next, current_task, current_user_token,
-    exit_current_and_run_next, exit_group_and_run_next, find_task_by_pid, find_task_by_tgid,
-    procs_count, signal::*, suspend_current_and_run_next, threads, wait_with_timeout,
-    wake_interruptible, Rusage, TaskStatus,
+    add_task, all_tasks, block_current_and_run_next, current_cred, current_task,
+    current_user_token, exit_current_and_run_next, exit_group_and_run_next, find_task_by_pid,
+    find_task_by_tgid, pgrp_in_session, processes, procs_count, ptrace, signal::*,
+    suspend_current_and_run_next, threads, wait_with_timeout, Access, Credentials, JobReport,
+    Rusage, TaskControlBlock, INITPROC, NGROUPS_MAX,
 };
 use crate::timer::{get_time_ms, get_time_sec, ITimerVal, TimeSpec, TimeVal, TimeZone, Times};
 use alloc::boxed::Box;
@@ -21,10 +24,12 @@ use alloc::string::{String, ToString};
 use alloc::sync::Arc;
 use alloc::vec::Vec;
 use core::mem::size_of;
+use core::sync::atomic::Ordering;
 use log::{debug, error, info, trace, warn};
 use num_enum::FromPrimitive;
 use crate::arch::shutdown;
 pub fn sys_shutdown() -> isize {
+    sync_all(None);
     shutdown()
 }
 pub fn sys_exit(exit_code: u32) -> ! {
@@ -86,6 +91,36 @@ pub fn sys_yield() -> isize {
     SUCCESS
 }
 
+/// Send `signal` to `task` on behalf of a process with `cred`.
+/// `SIGCONT` may be sent to any process of the same session, as a shell does with `fg` and `bg`.
+fn kill_task(cred: &Credentials, task: Arc<TaskControlBlock>, signal: Signals) -> isize {
+    let same_session = signal == Signals::SIGCONT && {
+        let sid = current_task().unwrap().acquire_inner_lock().sid;
+        let target_sid = task.acquire_inner_lock().sid;
+        sid == target_sid
+    };
+    if !same_session && !cred.may_signal(&task.cred.lock()) {
+        return EPERM;
+    }
+    send_signal(task, signal);
+    SUCCESS
+}
+
+/// Send `signal` to every process for which `target(task, pgid)` holds.
+/// Like Linux, this succeeds if any process was signaled.
+fn kill_processes(signal: Signals, target: impl Fn(&TaskControlBlock, usize) -> bool) -> isize {
+    let cred = current_cred();
+    let mut result = ESRCH;
+    for task in processes(target) {
+        match kill_task(&cred, task, signal) {
+            SUCCESS => result = SUCCESS,
+            errno if result != SUCCESS => result = errno,
+            _ => {}
+        }
+    }
+    result
+}
+
 pub fn sys_kill(pid: usize, sig: usize) -> isize {
     let signal = match Signals::from_signum(sig) {
         Ok(signal) => signal,
@@ -95,32 +130,27 @@ pub fn sys_kill(pid: usize, sig: usize) -> isize {
     if pid == 10 {
         return SUCCESS;
     }
+    let pid = pid as isize;
     if pid > 0 {
         // [Warning] in current implementation,
         // signal will be sent to an arbitrary task with target `pid` (`tgid` more precisely).
         // But manual also require that the target task should not mask this signal.
-        if let Some(task) = find_task_by_tgid(pid) {
-            if !signal.is_empty() {
-                let mut inner = task.acquire_inner_lock();
-                inner.add_signal(signal);
-                // wake up target process if it is sleeping
-                if inner.task_status == TaskStatus::Interruptible {
-                    inner.task_status = TaskStatus::Ready;
-                    drop(inner);
-                    wake_interruptible(task);
-                }
-            }
-            SUCCESS
-        } else {
-            ESRCH
+        match find_task_by_tgid(pid as usize) {
+            Some(task) => kill_task(&current_cred(), task, signal),
+            None => ESRCH,
         }
     } else if pid == 0 {
-        todo!()
-    } else if (pid as isize) == -1 {
-        todo!()
+        let pgid = current_task().unwrap().getpgid();
+        kill_processes(signal, |_, task_pgid| task_pgid == pgid)
+    } else if pid == -1 {
+        // every process but init and the caller
+        let tgid = current_task().unwrap().tgid;
+        kill_processes(signal, |task, _| {
+            task.tgid != tgid && task.tgid != INITPROC.tgid
+        })
     } else {
-        // (pid as isize) < -1
-        todo!()
+        let pgid = -pid as usize;
+        kill_processes(signal, |_, task_pgid| task_pgid == pgid)
     }
 }
 
@@ -129,28 +159,12 @@ pub fn sys_tgkill(tgid: usize, tid: usize, sig: usize) -> isize {
         Ok(signal) => signal,
         Err(_) => return EINVAL,
     };
-    if tid > 0 {
-        if let Some(task) = find_task_by_tgid(tgid) {
-            if !signal.is_empty() {
-                let mut inner = task.acquire_inner_lock();
-                inner.add_signal(signal);
-                // wake up target process if it is sleeping
-                if inner.task_status == TaskStatus::Interruptible {
-                    inner.task_status = TaskStatus::Ready;
-                    drop(inner);
-                    wake_interruptible(task);
-                }
-            }
-            SUCCESS
-        } else {
-            ESRCH
-        }
-    } else if tid == 0 {
-        todo!()
-    } else if (tid as isize) == -1 {
-        todo!()
-    } else {
-        todo!()
+    if (tgid as isize) <= 0 || (tid as isize) <= 0 {
+        return EINVAL;
+    }
+    match find_task_by_pid(tid) {
+        Some(task) if task.tgid == tgid => kill_task(&current_cred(), task, signal),
+        _ => ESRCH,
     }
 }
 
@@ -159,29 +173,12 @@ pub fn sys_tkill(tid: usize, sig: usize) -> isize {
         Ok(signal) => signal,
         Err(_) => return EINVAL,
     };
-    if tid > 0 {
-        if let Some(task) = find_task_by_pid(tid) {
-            if !signal.is_empty() {
-                let mut inner = task.acquire_inner_lock();
-                inner.add_signal(signal);
-                // wake up target process if it is sleeping
-                if inner.task_status == TaskStatus::Interruptible {
-                    inner.task_status = TaskStatus::Ready;
-                    drop(inner);
-                    wake_interruptible(task);
-                }
-            }
-            SUCCESS
-        } else {
-            ESRCH
-        }
-    } else if tid == 0 {
-        todo!()
-    } else if (tid as isize) == -1 {
-        todo!()
-    } else {
-        // (pid as isize) < -1
-        todo!()
+    if (tid as isize) <= 0 {
+        return EINVAL;
+    }
+    match find_task_by_pid(tid) {
+        Some(task) => kill_task(&current_cred(), task, signal),
+        None => ESRCH,
     }
 }
 
@@ -200,14 +197,19 @@ pub fn sys_nanosleep(req: *const TimeSpec, rem: *mut TimeSpec) -> isize {
     wait_with_timeout(Arc::downgrade(&task), end);
     drop(task);
 
-    block_current_and_run_next();
-    let task = current_task().unwrap();
-    let inner = task.acquire_inner_lock();
-    let now = TimeSpec::now();
-    // this is a little different with manual (do not consider sigmask)
-    // but now we have to compromise
-    if inner.sigpending.is_empty() {
-        assert!(end <= now);
+    // a wakeup meant for another sleep may end this one early, the timeout is still queued then
+    let (sigpending, now) = loop {
+        block_current_and_run_next();
+        let task = current_task().unwrap();
+        let inner = task.acquire_inner_lock();
+        let now = TimeSpec::now();
+        // this is a little different with manual (do not consider sigmask)
+        // but now we have to compromise
+        if !inner.sigpending.is_empty() || end <= now {
+            break (inner.sigpending, now);
+        }
+    };
+    if sigpending.is_empty() {
         if !rem.is_null() {
             copy_to_user(token, &TimeSpec::new(), rem).unwrap();
         }
@@ -308,54 +310,234 @@ pub fn sys_getppid() -> isize {
 }
 
 pub fn sys_getuid() -> isize {
-    0 // root user
+    current_task().unwrap().cred.lock().uid as isize
 }
 
 pub fn sys_geteuid() -> isize {
-    0 // root user
+    current_task().unwrap().cred.lock().euid as isize
 }
 
 pub fn sys_getgid() -> isize {
-    0 // root group
+    current_task().unwrap().cred.lock().gid as isize
 }
 
 pub fn sys_getegid() -> isize {
-    0 // root group
+    current_task().unwrap().cred.lock().egid as isize
+}
+
+pub fn sys_setuid(uid: u32) -> isize {
+    info!("[sys_setuid] uid: {}", uid);
+    match current_task().unwrap().cred.lock().setuid(uid) {
+        Ok(_) => SUCCESS,
+        Err(errno) => errno,
+    }
+}
+
+pub fn sys_setgid(gid: u32) -> isize {
+    info!("[sys_setgid] gid: {}", gid);
+    match current_task().unwrap().cred.lock().setgid(gid) {
+        Ok(_) => SUCCESS,
+        Err(errno) => errno,
+    }
+}
+
+pub fn sys_setreuid(ruid: u32, euid: u32) -> isize {
+    info!(
+        "[sys_setreuid] ruid: {}, euid: {}",
+        ruid as i32, euid as i32
+    );
+    match current_task().unwrap().cred.lock().setreuid(ruid, euid) {
+        Ok(_) => SUCCESS,
+        Err(errno) => errno,
+    }
+}
+
+pub fn sys_setregid(rgid: u32, egid: u32) -> isize {
+    info!(
+        "[sys_setregid] rgid: {}, egid: {}",
+        rgid as i32, egid as i32
+    );
+    match current_task().unwrap().cred.lock().setregid(rgid, egid) {
+        Ok(_) => SUCCESS,
+        Err(errno) => errno,
+    }
+}
+
+pub fn sys_setresuid(ruid: u32, euid: u32, suid: u32) -> isize {
+    info!(
+        "[sys_setresuid] ruid: {}, euid: {}, suid: {}",
+        ruid as i32, euid as i32, suid as i32
+    );
+    match current_task()
+        .unwrap()
+        .cred
+        .lock()
+        .setresuid(ruid, euid, suid)
+    {
+        Ok(_) => SUCCESS,
+        Err(errno) => errno,
+    }
+}
+
+pub fn sys_setresgid(rgid: u32, egid: u32, sgid: u32) -> isize {
+    info!(
+        "[sys_setresgid] rgid: {}, egid: {}, sgid: {}",
+        rgid as i32, egid as i32, sgid as i32
+    );
+    match current_task()
+        .unwrap()
+        .cred
+        .lock()
+        .setresgid(rgid, egid, sgid)
+    {
+        Ok(_) => SUCCESS,
+        Err(errno) => errno,
+    }
+}
+
+pub fn sys_getresuid(ruid: *mut u32, euid: *mut u32, suid: *mut u32) -> isize {
+    let task = current_task().unwrap();
+    let token = task.get_user_token();
+    let cred = task.cred.lock().clone();
+    for (id, ptr) in [(cred.uid, ruid), (cred.euid, euid), (cred.suid, suid)] {
+        if let Err(errno) = copy_to_user(token, &id, ptr) {
+            return errno;
+        }
+    }
+    SUCCESS
+}
+
+pub fn sys_getresgid(rgid: *mut u32, egid: *mut u32, sgid: *mut u32) -> isize {
+    let task = current_task().unwrap();
+    let token = task.get_user_token();
+    let cred = task.cred.lock().clone();
+    for (id, ptr) in [(cred.gid, rgid), (cred.egid, egid), (cred.sgid, sgid)] {
+        if let Err(errno) = copy_to_user(token, &id, ptr) {
+            return errno;
+        }
+    }
+    SUCCESS
+}
+
+/// Returns the number of supplementary groups, only counting them if `size` is 0
+pub fn sys_getgroups(size: usize, list: *mut u32) -> isize {
+    let task = current_task().unwrap();
+    let token = task.get_user_token();
+    let groups = task.cred.lock().groups.clone();
+    if size == 0 || groups.is_empty() {
+        return groups.len() as isize;
+    }
+    if size < groups.len() {
+        return EINVAL;
+    }
+    match copy_to_user_array(token, groups.as_ptr(), list, groups.len()) {
+        Ok(_) => groups.len() as isize,
+        Err(errno) => errno,
+    }
+}
+
+pub fn sys_setgroups(size: usize, list: *const u32) -> isize {
+    info!("[sys_setgroups] size: {}", size);
+    if size > NGROUPS_MAX {
+        return EINVAL;
+    }
+    let task = current_task().unwrap();
+    let token = task.get_user_token();
+    let mut groups = Vec::with_capacity(size);
+    groups.resize(size, 0u32);
+    if size > 0 {
+        if let Err(errno) = copy_from_user_array(token, list, groups.as_mut_ptr(), size) {
+            return errno;
+        }
+    }
+    match task.cred.lock().setgroups(groups) {
+        Ok(_) => SUCCESS,
+        Err(errno) => errno,
+    }
 }
 
-// Warning, we don't support this syscall in fact, task.setpgid() won't take effect for some reason
-// So it just pretend to do this work.
-// Fortunately, that won't make difference when we just try to run busybox sh so far.
+/// Move process `pid`, the caller or one of its children, into group `pgid` of the same session.
+/// 0 stands for the caller as `pid` and for `pid` itself as `pgid`.
 pub fn sys_setpgid(pid: usize, pgid: usize) -> isize {
-    /* An attempt.*/
-    let task = crate::task::find_task_by_tgid(pid);
-    match task {
-        Some(task) => task.setpgid(pgid),
-        None => ESRCH,
+    let caller = current_task().unwrap();
+    let pid = if pid == 0 { caller.tgid } else { pid };
+    let pgid = if pgid == 0 { pid } else { pgid };
+    if (pgid as isize) < 0 {
+        return EINVAL;
     }
+    let task = match find_task_by_tgid(pid) {
+        Some(task) => task,
+        None => return ESRCH,
+    };
+    let is_child = task
+        .acquire_inner_lock()
+        .parent
+        .as_ref()
+        .map_or(false, |parent| {
+            parent
+                .upgrade()
+                .map_or(false, |parent| parent.tgid == caller.tgid)
+        });
+    if task.tgid != caller.tgid && !is_child {
+        return ESRCH;
+    }
+    let sid = caller.acquire_inner_lock().sid;
+    let target_sid = task.acquire_inner_lock().sid;
+    // a session leader stays in its group
+    if target_sid == task.tgid || target_sid != sid {
+        return EPERM;
+    }
+    if pgid != pid && !pgrp_in_session(pgid, sid) {
+        return EPERM;
+    }
+    for thread in all_tasks().iter().filter(|thread| thread.tgid == task.tgid) {
+        thread.acquire_inner_lock().pgid = pgid;
+    }
+    SUCCESS
 }
 
 pub fn sys_getpgid(pid: usize) -> isize {
-    /* An attempt.*/
+    if pid == 0 {
+        return current_task().unwrap().getpgid() as isize;
+    }
     let task = crate::task::find_task_by_tgid(pid);
     match task {
         Some(task) => task.getpgid() as isize,
         None => ESRCH,
     }
 }
+
 /// creates a new session if the calling process is not a process group leader.
-/// The calling process is the leader of the new session
-/// 当前进程脱离父进程，从父进程的子进程列表中移除当前进程，当前进程的父进程设置为空。
+/// The calling process is the leader of the new session and of a new group, without a controlling terminal.
 pub fn sys_setsid() -> isize {
     let task = current_task().unwrap();
-    if let Some(parent) = task.acquire_inner_lock().parent.as_ref().unwrap().upgrade() {
-        parent
-            .acquire_inner_lock()
-            .children
-            .retain(|x| x.tid != task.tid);
+    let tgid = task.tgid;
+    drop(task);
+    let tasks = all_tasks();
+    if tasks
+        .iter()
+        .any(|task| task.acquire_inner_lock().pgid == tgid)
+    {
+        return EPERM;
+    }
+    for thread in tasks.iter().filter(|thread| thread.tgid == tgid) {
+        let mut inner = thread.acquire_inner_lock();
+        inner.sid = tgid;
+        inner.pgid = tgid;
+    }
+    tgid as isize
+}
+
+pub fn sys_getsid(pid: usize) -> isize {
+    let task = if pid == 0 {
+        current_task()
+    } else {
+        find_task_by_tgid(pid)
+    };
+    match task {
+        Some(task) => task.acquire_inner_lock().sid as isize,
+        None => ESRCH,
     }
-    task.acquire_inner_lock().parent = None;
-    SUCCESS
 }
 
 // For user, tid is pid in kernel
@@ -363,6 +545,18 @@ pub fn sys_gettid() -> isize {
     current_task().unwrap().pid.0 as isize
 }
 
+/// Returns the previous persona, `0xffffffff` only queries it.
+/// Only `ADDR_NO_RANDOMIZE` changes anything here.
+pub fn sys_personality(persona: u32) -> isize {
+    let task = current_task().unwrap();
+    let mut inner = task.acquire_inner_lock();
+    let old = inner.personality;
+    if persona != 0xffffffff {
+        inner.personality = persona;
+    }
+    old as isize
+}
+
 #[derive(Clone, Copy, Debug)]
 #[repr(C)]
 pub struct Sysinfo {
@@ -609,18 +803,36 @@ pub fn sys_execve(
         envp_vec,
         envp_vec.len()
     );
-    let working_inode = &task.fs.lock().working_inode;
+    // not kept locked, other threads may need it to exit
+    let working_inode = task.fs.lock().working_inode.clone();
 
-    match working_inode.open(&path, OpenFlags::O_RDONLY, false) {
+    // execution needs the x bit only, not the r bit
+    match working_inode.open(&path, OpenFlags::O_PATH, false) {
         Ok(file) => {
+            if file.mount_flags().contains(MountFlags::MS_NOEXEC) || !file.file.is_file() {
+                return EACCES;
+            }
+            if let Err(errno) = file.check_access(&current_cred(), Access::EXEC) {
+                return errno;
+            }
             if file.get_size() < 4 {
                 return ENOEXEC;
             }
             let mut magic_number = Box::<[u8; 4]>::new([0; 4]);
             // this operation may be expensive... I'm not sure
             file.read(Some(&mut 0usize), magic_number.as_mut_slice());
+            // the set-user-ID and set-group-ID bits are ignored on scripts, as Linux does
+            let mut setid_stat = None;
             let elf = match magic_number.as_slice() {
-                b"\x7fELF" => file,
+                b"\x7fELF" => {
+                    // nor does a traced program get them, its tracer could take them over
+                    if !file.mount_flags().contains(MountFlags::MS_NOSUID)
+                        && ptrace::tracer(&task).is_none()
+                    {
+                        setid_stat = Some(file.get_stat());
+                    }
+                    file
+                }
                 b"#!" => {
                     let shell_file = working_inode
                         .open(DEFAULT_SHELL, OpenFlags::O_RDONLY, false)
@@ -632,12 +844,35 @@ pub fn sys_execve(
             };
 
             let task = current_task().unwrap();
+            if let Some(stat) = &setid_stat {
+                // the caller mustn't pick the layout of a program running with other privileges
+                if stat.get_mode() & (StatMode::S_ISUID | StatMode::S_ISGID).bits() != 0 {
+                    task.acquire_inner_lock().personality &= !PER_CLEAR_ON_SETID;
+                }
+            }
             show_frame_consumption! {
                 "load_elf";
                 if let Err(errno) = task.load_elf(elf, &argv_vec, &envp_vec) {
                     return errno;
                 };
             }
+            let mut cred = task.cred.lock();
+            if let Some(stat) = setid_stat {
+                let mode = stat.get_mode();
+                if mode & StatMode::S_ISUID.bits() != 0 {
+                    cred.euid = stat.get_uid();
+                }
+                // without group execute permission, the bit marks mandatory locking instead
+                if mode & (StatMode::S_ISGID | StatMode::S_IXGRP).bits()
+                    == (StatMode::S_ISGID | StatMode::S_IXGRP).bits()
+                {
+                    cred.egid = stat.get_gid();
+                }
+            }
+            cred.suid = cred.euid;
+            cred.sgid = cred.egid;
+            drop(cred);
+            ptrace::exec_stop();
             // should return 0 in success
             SUCCESS
         }
@@ -652,8 +887,40 @@ bitflags! {
         const WEXITED    = 4;
         const WCONTINUED = 8;
         const WNOWAIT    = 0x1000000;
+        /// `__WNOTHREAD`, `__WALL` and `__WCLONE`, accepted and ignored
+        const WNOTHREAD  = 0x20000000;
+        const WALL       = 0x40000000;
+        const WCLONE     = 0x80000000;
     }
 }
+/// A child process which stopped or continued, as `option` asks, and the change.
+/// The change is collected unless `WNOWAIT` is given.
+fn wait_job_report(
+    children: &Vec<Arc<TaskControlBlock>>,
+    wanted: &impl Fn(&Arc<TaskControlBlock>) -> bool,
+    option: WaitOption,
+) -> Option<(usize, JobReport)> {
+    for child in children.iter() {
+        // threads of the child are children too, its main thread reports for all
+        if child.pid.0 != child.tgid || !wanted(child) {
+            continue;
+        }
+        let mut job = child.job.lock();
+        let report = match job.report {
+            Some(report @ JobReport::Stopped(_)) if option.contains(WaitOption::WSTOPPED) => report,
+            Some(report @ JobReport::Continued) if option.contains(WaitOption::WCONTINUED) => {
+                report
+            }
+            _ => continue,
+        };
+        if !option.contains(WaitOption::WNOWAIT) {
+            job.report = None;
+        }
+        return Some((child.getpid(), report));
+    }
+    None
+}
+
 /// If there is not a child process whose pid is same as given, return -1.
 /// Else if there is a child process but it is still running, return -2.
 pub fn sys_wait4(pid: isize, status: *mut u32, option: u32, _ru: *mut Rusage) -> isize {
@@ -661,42 +928,50 @@ pub fn sys_wait4(pid: isize, status: *mut u32, option: u32, _ru: *mut Rusage) ->
     info!("[sys_wait4] pid: {}, option: {:?}", pid, option);
     let task = current_task().unwrap();
     let token = task.get_user_token();
+    let pgid = task.getpgid();
+    // 0 and below -1 wait for a process group
+    let wanted = |p: &Arc<TaskControlBlock>| match pid {
+        -1 => true,
+        0 => p.getpgid() == pgid,
+        pid if pid < -1 => p.getpgid() == -pid as usize,
+        pid => pid as usize == p.getpid(),
+    };
     loop {
+        // tracees are looked up first, `TRACEES` mustn't be locked under an inner lock
+        let tracees: Vec<_> = ptrace::tracees(task.tgid)
+            .into_iter()
+            .filter(|t| wanted(t) || pid > 0 && pid as usize == t.pid.0)
+            .collect();
         // find a child process
 
         // ---- hold current PCB lock
         let mut inner = task.acquire_inner_lock();
-        if inner
-            .children
-            .iter()
-            .find(|p| pid == -1 || pid as usize == p.getpid())
-            .is_none()
-        {
+        if inner.children.iter().find(|p| wanted(p)).is_none() && tracees.is_empty() {
             return ECHILD;
             // ---- release current PCB lock
         }
-        inner
-            .children
-            .iter()
-            .filter(|p| pid == -1 || pid as usize == p.getpid())
-            .for_each(|p| {
-                trace!(
-                    "[sys_wait4] found child pid: {}, status: {:?}",
-                    p.pid.0,
-                    p.acquire_inner_lock().task_status
-                )
-            });
+        inner.children.iter().filter(|p| wanted(p)).for_each(|p| {
+            trace!(
+                "[sys_wait4] found child pid: {}, status: {:?}",
+                p.pid.0,
+                p.acquire_inner_lock().task_status
+            )
+        });
         let pair = inner.children.iter().enumerate().find(|(_, p)| {
             // ++++ temporarily hold child PCB lock
-            p.acquire_inner_lock().is_zombie() && (pid == -1 || pid as usize == p.getpid())
+            wanted(p) && p.acquire_inner_lock().is_zombie()
             // ++++ release child PCB lock
         });
         if let Some((idx, _)) = pair {
-            // drop last TCB of child
+            // the child may still be switching out of another core
+            if inner.children[idx].on_cpu.load(Ordering::Acquire) {
+                drop(inner);
+                suspend_current_and_run_next();
+                continue;
+            }
+            // drop last TCB of child, another core may still hold it for a moment, e.g. to read `/proc`
             let child = inner.children.remove(idx);
             trace!("[wait4] release zombie task, pid: {}", child.pid.0);
-            // confirm that child will be deallocated after being removed from children list
-            assert_eq!(Arc::strong_count(&child), 1);
             // if main thread exit
             if child.pid.0 == child.tgid {
                 let found_pid = child.getpid();
@@ -712,6 +987,27 @@ pub fn sys_wait4(pid: isize, status: *mut u32, option: u32, _ru: *mut Rusage) ->
                 }
                 return found_pid as isize;
             }
+        } else if let Some((found_pid, report)) = wait_job_report(&inner.children, &wanted, option)
+        {
+            drop(inner);
+            if !status.is_null() {
+                match translated_refmut(token, status) {
+                    Ok(word) => *word = report.status(),
+                    Err(errno) => return errno,
+                };
+            }
+            return found_pid as isize;
+        } else if let Some((found_pid, status_word)) =
+            ptrace::wait_report(&tracees, option.contains(WaitOption::WNOWAIT))
+        {
+            drop(inner);
+            if !status.is_null() {
+                match translated_refmut(token, status) {
+                    Ok(word) => *word = status_word,
+                    Err(errno) => return errno,
+                };
+            }
+            return found_pid as isize;
         } else {
             drop(inner);
             if option.contains(WaitOption::WNOHANG) {
@@ -1026,6 +1322,59 @@ pub fn sys_mprotect(addr: usize, len: usize, prot: usize) -> isize {
     }
 }
 
+pub fn sys_mremap(
+    old_address: usize,
+    old_size: usize,
+    new_size: usize,
+    flags: u32,
+    new_address: usize,
+) -> isize {
+    let flags = match MremapFlags::from_bits(flags) {
+        Some(flags) => flags,
+        None => return EINVAL,
+    };
+    info!(
+        "[sys_mremap] old_address: {:X}, old_size: {:X}, new_size: {:X}, flags: {:?}, new_address: {:X}",
+        old_address, old_size, new_size, flags, new_address
+    );
+    let task = current_task().unwrap();
+    let result = task
+        .vm
+        .lock()
+        .mremap(old_address, old_size, new_size, flags, new_address);
+    match result {
+        Ok(address) => address as isize,
+        Err(errno) => errno,
+    }
+}
+
+pub fn sys_madvise(addr: usize, len: usize, advice: usize) -> isize {
+    let advice = Advice::from_primitive(advice);
+    info!(
+        "[sys_madvise] addr: {:X}, len: {:X}, advice: {:?}",
+        addr, len, advice
+    );
+    let task = current_task().unwrap();
+    let result = task.vm.lock().madvise(addr, len, advice);
+    match result {
+        Ok(_) => SUCCESS,
+        Err(errno) => errno,
+    }
+}
+
+pub fn sys_mincore(addr: usize, len: usize, vec: *mut u8) -> isize {
+    let task = current_task().unwrap();
+    let resident = match task.vm.lock().mincore(addr, len) {
+        Ok(resident) => resident,
+        Err(errno) => return errno,
+    };
+    let token = task.get_user_token();
+    match copy_to_user_array(token, resident.as_ptr(), vec, resident.len()) {
+        Ok(()) => SUCCESS,
+        Err(errno) => errno,
+    }
+}
+
 pub fn sys_clock_gettime(clk_id: usize, tp: *mut TimeSpec) -> isize {
     if !tp.is_null() {
         let token = current_user_token();
@@ -1071,10 +1420,6 @@ pub fn sys_sigtimedwait(set: usize, info: usize, timeout: usize) -> isize {
     )
 }
 
-pub fn sys_return_success() -> isize{
-    SUCCESS
-}
-
 pub fn sys_sigreturn() -> isize {
     // mark not processing signal handler
     let task = current_task().unwrap();
@@ -0,0 +1,256 @@
+use crate::arch::{single_step_supported, UserRegs};
+use crate::mm::{copy_from_user, copy_from_user_array, copy_to_user, copy_to_user_array};
+use crate::syscall::errno::*;
+use crate::task::ptrace::{self, PtraceOptions};
+use crate::task::{
+    current_cred, current_task, current_user_token, find_task_by_pid, send_signal, SigInfo,
+    Signals, TaskControlBlock, INITPROC,
+};
+use alloc::sync::Arc;
+use core::mem::size_of;
+use log::info;
+use num_enum::FromPrimitive;
+
+#[allow(non_camel_case_types)]
+#[derive(Debug, Eq, PartialEq, FromPrimitive)]
+#[repr(usize)]
+pub enum PtraceRequest {
+    TRACEME = 0,
+    PEEKTEXT = 1,
+    PEEKDATA = 2,
+    POKETEXT = 4,
+    POKEDATA = 5,
+    CONT = 7,
+    KILL = 8,
+    SINGLESTEP = 9,
+    GETREGS = 12,
+    SETREGS = 13,
+    ATTACH = 16,
+    DETACH = 17,
+    SYSCALL = 24,
+    SETOPTIONS = 0x4200,
+    GETSIGINFO = 0x4202,
+    GETREGSET = 0x4204,
+    SETREGSET = 0x4205,
+    SEIZE = 0x4206,
+    #[num_enum(default)]
+    ILLEAGAL,
+}
+
+/// The only register set of `PTRACE_GETREGSET` and `PTRACE_SETREGSET`, the general registers
+const NT_PRSTATUS: usize = 1;
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct IoVec {
+    iov_base: *mut u8,
+    iov_len: usize,
+}
+
+/// The task `pid` stands for, if the caller may start tracing it
+fn attach_target(pid: usize) -> Result<Arc<TaskControlBlock>, isize> {
+    let task = find_task_by_pid(pid).ok_or(ESRCH)?;
+    if task.tgid == current_task().unwrap().tgid || task.tgid == INITPROC.tgid {
+        return Err(EPERM);
+    }
+    if !current_cred().may_trace(&task.cred.lock()) {
+        return Err(EPERM);
+    }
+    if task.acquire_inner_lock().is_zombie() {
+        return Err(ESRCH);
+    }
+    Ok(task)
+}
+
+/// The task `pid` stands for, which the caller must trace. Most requests also need it to be stopped.
+fn traced_target(pid: usize, stopped: bool) -> Result<Arc<TaskControlBlock>, isize> {
+    let task = find_task_by_pid(pid).ok_or(ESRCH)?;
+    let tracer = ptrace::tracer(&task).ok_or(ESRCH)?;
+    if !Arc::ptr_eq(&tracer, &current_task().unwrap()) {
+        return Err(ESRCH);
+    }
+    if stopped && task.ptrace.lock().stopped.is_none() {
+        return Err(ESRCH);
+    }
+    Ok(task)
+}
+
+pub fn sys_ptrace(request: usize, pid: usize, addr: usize, data: usize) -> isize {
+    let request = PtraceRequest::from_primitive(request);
+    info!(
+        "[sys_ptrace] request: {:?}, pid: {}, addr: {:X}, data: {:X}",
+        request, pid, addr, data
+    );
+    let token = current_user_token();
+    match request {
+        PtraceRequest::TRACEME => {
+            let task = current_task().unwrap();
+            let parent = task
+                .acquire_inner_lock()
+                .parent
+                .as_ref()
+                .and_then(|parent| parent.upgrade());
+            let parent = match parent {
+                Some(parent) => parent,
+                None => return EPERM,
+            };
+            match ptrace::attach(&task, &parent, PtraceOptions::empty()) {
+                Ok(()) => SUCCESS,
+                Err(errno) => errno,
+            }
+        }
+        PtraceRequest::ATTACH | PtraceRequest::SEIZE => {
+            let options = if request == PtraceRequest::SEIZE {
+                match PtraceOptions::from_bits(data as u32) {
+                    Some(options) => options,
+                    None => return EINVAL,
+                }
+            } else {
+                PtraceOptions::empty()
+            };
+            let task = match attach_target(pid) {
+                Ok(task) => task,
+                Err(errno) => return errno,
+            };
+            if let Err(errno) = ptrace::attach(&task, &current_task().unwrap(), options) {
+                return errno;
+            }
+            // a seized tracee keeps running
+            if request == PtraceRequest::ATTACH {
+                send_signal(task, Signals::SIGSTOP);
+            }
+            SUCCESS
+        }
+        PtraceRequest::KILL => {
+            let task = match traced_target(pid, false) {
+                Ok(task) => task,
+                Err(errno) => return errno,
+            };
+            send_signal(task, Signals::SIGKILL);
+            SUCCESS
+        }
+        request => {
+            let task = match traced_target(pid, true) {
+                Ok(task) => task,
+                Err(errno) => return errno,
+            };
+            ptrace_stopped(task, request, token, addr, data)
+        }
+    }
+}
+
+/// The requests on a tracee stopped for the caller
+fn ptrace_stopped(
+    task: Arc<TaskControlBlock>,
+    request: PtraceRequest,
+    token: usize,
+    addr: usize,
+    data: usize,
+) -> isize {
+    match request {
+        PtraceRequest::PEEKTEXT | PtraceRequest::PEEKDATA => {
+            let mut word = [0u8; size_of::<usize>()];
+            if task
+                .vm
+                .lock()
+                .access_remote(addr, &mut word, false)
+                .is_err()
+            {
+                return EIO;
+            }
+            let word = usize::from_ne_bytes(word);
+            match copy_to_user(token, &word, data as *mut usize) {
+                Ok(_) => SUCCESS,
+                Err(errno) => errno,
+            }
+        }
+        PtraceRequest::POKETEXT | PtraceRequest::POKEDATA => {
+            let mut word = data.to_ne_bytes();
+            match task.vm.lock().access_remote(addr, &mut word, true) {
+                Ok(_) => SUCCESS,
+                Err(_) => EIO,
+            }
+        }
+        PtraceRequest::GETREGS => {
+            let regs = task.acquire_inner_lock().get_trap_cx().user_regs();
+            match copy_to_user(token, &regs, data as *mut UserRegs) {
+                Ok(_) => SUCCESS,
+                Err(errno) => errno,
+            }
+        }
+        PtraceRequest::SETREGS => {
+            let mut regs = task.acquire_inner_lock().get_trap_cx().user_regs();
+            if let Err(errno) = copy_from_user(token, data as *const UserRegs, &mut regs) {
+                return errno;
+            }
+            task.acquire_inner_lock().get_trap_cx().set_user_regs(&regs);
+            SUCCESS
+        }
+        PtraceRequest::GETREGSET | PtraceRequest::SETREGSET => {
+            if addr != NT_PRSTATUS {
+                return EINVAL;
+            }
+            let mut iov = IoVec {
+                iov_base: core::ptr::null_mut(),
+                iov_len: 0,
+            };
+            if let Err(errno) = copy_from_user(token, data as *const IoVec, &mut iov) {
+                return errno;
+            }
+            // like Linux, a short buffer gets the first registers only
+            let len = iov.iov_len.min(size_of::<UserRegs>());
+            let mut regs = task.acquire_inner_lock().get_trap_cx().user_regs();
+            let bytes = &mut regs as *mut UserRegs as *mut u8;
+            let result = if request == PtraceRequest::GETREGSET {
+                copy_to_user_array(token, bytes, iov.iov_base, len)
+            } else {
+                copy_from_user_array(token, iov.iov_base, bytes, len)
+            };
+            if let Err(errno) = result {
+                return errno;
+            }
+            if request == PtraceRequest::SETREGSET {
+                task.acquire_inner_lock().get_trap_cx().set_user_regs(&regs);
+            }
+            iov.iov_len = len;
+            match copy_to_user(token, &iov, data as *mut IoVec) {
+                Ok(_) => SUCCESS,
+                Err(errno) => errno,
+            }
+        }
+        PtraceRequest::GETSIGINFO => {
+            let signum = task.ptrace.lock().stopped.unwrap_or(0);
+            let info = SigInfo::new(signum, 0, 0);
+            match copy_to_user(token, &info, data as *mut SigInfo) {
+                Ok(_) => SUCCESS,
+                Err(errno) => errno,
+            }
+        }
+        PtraceRequest::SETOPTIONS => match PtraceOptions::from_bits(data as u32) {
+            Some(options) => {
+                task.ptrace.lock().options = options;
+                SUCCESS
+            }
+            None => EINVAL,
+        },
+        PtraceRequest::CONT | PtraceRequest::SYSCALL | PtraceRequest::SINGLESTEP => {
+            if Signals::from_signum(data).is_err() {
+                return EIO;
+            }
+            let single_step = request == PtraceRequest::SINGLESTEP;
+            if single_step && !single_step_supported() {
+                return EIO;
+            }
+            ptrace::resume(&task, data, request == PtraceRequest::SYSCALL, single_step);
+            SUCCESS
+        }
+        PtraceRequest::DETACH => {
+            if Signals::from_signum(data).is_err() {
+                return EIO;
+            }
+            ptrace::detach(&task, data);
+            SUCCESS
+        }
+        _ => EIO,
+    }
+}
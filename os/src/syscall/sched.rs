@@ -0,0 +1,295 @@
+use crate::arch::{cpu_id, online_cpus, TICKS_PER_SEC};
+use crate::mm::{copy_from_user_array, copy_to_user, copy_to_user_array, get_from_user};
+use crate::syscall::errno::*;
+use crate::task::{
+    all_tasks, current_cred, current_task, current_user_token, find_task_by_pid, requeue_task,
+    suspend_current_and_run_next, Credentials, SchedPolicy, TaskControlBlock, MAX_NICE,
+    MAX_RT_PRIO, MIN_NICE, RR_TIMESLICE, SCHED_RESET_ON_FORK,
+};
+use crate::timer::TimeSpec;
+use alloc::sync::Arc;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::mem::size_of;
+use log::info;
+
+const PRIO_PROCESS: u32 = 0;
+const PRIO_PGRP: u32 = 1;
+const PRIO_USER: u32 = 2;
+
+/// Size of the CPU masks of the affinity syscalls, one `unsigned long` like Linux with few CPUs
+const CPU_MASK_SIZE: usize = size_of::<usize>();
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub struct SchedParam {
+    sched_priority: i32,
+}
+
+/// The task `pid` stands for, 0 is the caller. Like Linux, `pid` is a thread id.
+fn find_target(pid: isize) -> Result<Arc<TaskControlBlock>, isize> {
+    if pid < 0 {
+        return Err(EINVAL);
+    }
+    let task = if pid == 0 {
+        current_task()
+    } else {
+        find_task_by_pid(pid as usize)
+    };
+    task.ok_or(ESRCH)
+}
+
+/// Whether a process with `cred` may change how `task` is scheduled, like `check_same_owner()` of Linux
+fn may_change(cred: &Credentials, task: &TaskControlBlock) -> bool {
+    let target = task.cred.lock();
+    cred.is_root() || cred.euid == target.euid || cred.euid == target.uid
+}
+
+/// The live tasks `which` and `who` of `setpriority()` stand for
+fn prio_targets(which: u32, who: usize) -> Result<Vec<Arc<TaskControlBlock>>, isize> {
+    let tasks = match which {
+        PRIO_PROCESS => find_target(who as isize).map_or(Vec::new(), |task| vec![task]),
+        PRIO_PGRP => {
+            let pgid = if who == 0 {
+                current_task().unwrap().getpgid()
+            } else {
+                who
+            };
+            all_tasks()
+                .into_iter()
+                .filter(|task| task.getpgid() == pgid)
+                .collect()
+        }
+        PRIO_USER => {
+            let uid = if who == 0 {
+                current_cred().uid
+            } else {
+                who as u32
+            };
+            all_tasks()
+                .into_iter()
+                .filter(|task| task.cred.lock().uid == uid)
+                .collect()
+        }
+        _ => return Err(EINVAL),
+    };
+    Ok(tasks
+        .into_iter()
+        .filter(|task| !task.acquire_inner_lock().is_zombie())
+        .collect())
+}
+
+/// Set the nice value of every task `which` and `who` stand for, `nice()` of libc calls this too.
+/// Only root lowers a nice value.
+pub fn sys_setpriority(which: u32, who: usize, niceval: i32) -> isize {
+    info!(
+        "[sys_setpriority] which: {}, who: {}, niceval: {}",
+        which, who, niceval
+    );
+    let targets = match prio_targets(which, who) {
+        Ok(targets) => targets,
+        Err(errno) => return errno,
+    };
+    let nice = niceval.max(MIN_NICE).min(MAX_NICE);
+    let cred = current_cred();
+    let mut result = ESRCH;
+    for task in targets {
+        if !may_change(&cred, &task) {
+            result = EPERM;
+            continue;
+        }
+        if nice < task.sched.lock().nice && !cred.is_root() {
+            result = EACCES;
+            continue;
+        }
+        task.sched.lock().nice = nice;
+        requeue_task(&task);
+        if result == ESRCH {
+            result = SUCCESS;
+        }
+    }
+    result
+}
+
+/// Returns the lowest nice value of the tasks `which` and `who` stand for as `20 - nice`,
+/// so that the result is never negative.
+pub fn sys_getpriority(which: u32, who: usize) -> isize {
+    let targets = match prio_targets(which, who) {
+        Ok(targets) => targets,
+        Err(errno) => return errno,
+    };
+    match targets.iter().map(|task| task.sched.lock().nice).min() {
+        Some(nice) => 20 - nice as isize,
+        None => ESRCH,
+    }
+}
+
+/// Check `policy` and the priority in `param` and apply them to task `pid`.
+/// Only root chooses a real-time policy.
+fn set_scheduler(pid: isize, policy: Option<u32>, param: *const SchedParam) -> isize {
+    let param = match get_from_user(current_user_token(), param) {
+        Ok(param) => param,
+        Err(errno) => return errno,
+    };
+    let task = match find_target(pid) {
+        Ok(task) => task,
+        Err(errno) => return errno,
+    };
+    let (policy, reset_on_fork) = match policy {
+        Some(policy) => match SchedPolicy::from_raw(policy & !SCHED_RESET_ON_FORK) {
+            Some(sched_policy) => (sched_policy, policy & SCHED_RESET_ON_FORK != 0),
+            None => return EINVAL,
+        },
+        // `sched_setparam()` keeps the policy
+        None => {
+            let entity = task.sched.lock();
+            (entity.policy, entity.reset_on_fork)
+        }
+    };
+    let valid = if policy.is_realtime() {
+        param.sched_priority >= 1 && param.sched_priority <= MAX_RT_PRIO as i32
+    } else {
+        param.sched_priority == 0
+    };
+    if !valid {
+        return EINVAL;
+    }
+    let cred = current_cred();
+    if !may_change(&cred, &task) || (policy.is_realtime() && !cred.is_root()) {
+        return EPERM;
+    }
+    {
+        let mut entity = task.sched.lock();
+        entity.set_policy(policy, param.sched_priority as u32);
+        entity.reset_on_fork = reset_on_fork;
+    }
+    requeue_task(&task);
+    SUCCESS
+}
+
+pub fn sys_sched_setscheduler(pid: isize, policy: u32, param: *const SchedParam) -> isize {
+    info!(
+        "[sys_sched_setscheduler] pid: {}, policy: {:#x}",
+        pid, policy
+    );
+    set_scheduler(pid, Some(policy), param)
+}
+
+pub fn sys_sched_setparam(pid: isize, param: *const SchedParam) -> isize {
+    set_scheduler(pid, None, param)
+}
+
+pub fn sys_sched_getscheduler(pid: isize) -> isize {
+    match find_target(pid) {
+        Ok(task) => {
+            let entity = task.sched.lock();
+            let reset_on_fork = if entity.reset_on_fork {
+                SCHED_RESET_ON_FORK
+            } else {
+                0
+            };
+            (entity.policy.raw() | reset_on_fork) as isize
+        }
+        Err(errno) => errno,
+    }
+}
+
+pub fn sys_sched_getparam(pid: isize, param: *mut SchedParam) -> isize {
+    let task = match find_target(pid) {
+        Ok(task) => task,
+        Err(errno) => return errno,
+    };
+    let sched_param = SchedParam {
+        sched_priority: task.sched.lock().rt_priority as i32,
+    };
+    match copy_to_user(current_user_token(), &sched_param, param) {
+        Ok(_) => SUCCESS,
+        Err(errno) => errno,
+    }
+}
+
+pub fn sys_sched_get_priority_max(policy: u32) -> isize {
+    match SchedPolicy::from_raw(policy) {
+        Some(policy) if policy.is_realtime() => MAX_RT_PRIO as isize,
+        Some(_) => 0,
+        None => EINVAL,
+    }
+}
+
+pub fn sys_sched_get_priority_min(policy: u32) -> isize {
+    match SchedPolicy::from_raw(policy) {
+        Some(policy) if policy.is_realtime() => 1,
+        Some(_) => 0,
+        None => EINVAL,
+    }
+}
+
+/// The time slice of `SCHED_RR`, 0 for `SCHED_FIFO` which has none
+pub fn sys_sched_rr_get_interval(pid: isize, interval: *mut TimeSpec) -> isize {
+    let task = match find_target(pid) {
+        Ok(task) => task,
+        Err(errno) => return errno,
+    };
+    let timeslice = match task.sched.lock().policy {
+        SchedPolicy::Fifo => TimeSpec::new(),
+        _ => TimeSpec::from_ms(RR_TIMESLICE * 1000 / TICKS_PER_SEC),
+    };
+    match copy_to_user(current_user_token(), &timeslice, interval) {
+        Ok(_) => SUCCESS,
+        Err(errno) => errno,
+    }
+}
+
+/// Restrict task `pid` to the online cores of `mask`, of which the bytes past `CPU_MASK_SIZE` are ignored.
+/// The caller moves at once if it may no longer run on its core, another task once it is preempted.
+pub fn sys_sched_setaffinity(pid: isize, len: usize, mask: *const u8) -> isize {
+    let mut bytes = [0u8; CPU_MASK_SIZE];
+    let len = len.min(CPU_MASK_SIZE);
+    if len > 0 {
+        if let Err(errno) =
+            copy_from_user_array(current_user_token(), mask, bytes.as_mut_ptr(), len)
+        {
+            return errno;
+        }
+    }
+    let task = match find_target(pid) {
+        Ok(task) => task,
+        Err(errno) => return errno,
+    };
+    if !may_change(&current_cred(), &task) {
+        return EPERM;
+    }
+    let cpus_allowed = usize::from_le_bytes(bytes) & online_cpus();
+    info!(
+        "[sys_sched_setaffinity] pid: {}, cpus_allowed: {:#x}",
+        pid, cpus_allowed
+    );
+    if cpus_allowed == 0 {
+        return EINVAL;
+    }
+    task.sched.lock().cpus_allowed = cpus_allowed;
+    requeue_task(&task);
+    let current = current_task().unwrap();
+    if Arc::ptr_eq(&task, &current) && cpus_allowed & 1 << cpu_id() == 0 {
+        drop(task);
+        drop(current);
+        suspend_current_and_run_next();
+    }
+    SUCCESS
+}
+
+/// Returns the size of the mask stored, which `len` must hold.
+pub fn sys_sched_getaffinity(pid: isize, len: usize, mask: *mut u8) -> isize {
+    if len < CPU_MASK_SIZE || len % size_of::<usize>() != 0 {
+        return EINVAL;
+    }
+    let task = match find_target(pid) {
+        Ok(task) => task,
+        Err(errno) => return errno,
+    };
+    let bytes = (task.sched.lock().cpus_allowed & online_cpus()).to_le_bytes();
+    match copy_to_user_array(current_user_token(), bytes.as_ptr(), mask, CPU_MASK_SIZE) {
+        Ok(_) => CPU_MASK_SIZE as isize,
+        Err(errno) => errno,
+    }
+}
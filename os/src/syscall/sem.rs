@@ -0,0 +1,108 @@
+use crate::ipc::{
+    sem::{
+        self, Sembuf, SemidDs, GETALL, GETNCNT, GETPID, GETVAL, GETZCNT, SEMOPM, SEM_INFO, SETALL,
+        SETVAL,
+    },
+    IPC_64, IPC_INFO, IPC_RMID, IPC_SET, IPC_STAT,
+};
+use crate::mm::{
+    copy_from_user, copy_from_user_array, copy_to_user, copy_to_user_array, try_get_from_user,
+};
+use crate::syscall::errno::*;
+use crate::task::{current_task, current_user_token};
+use crate::timer::{TimeSpec, NSEC_PER_SEC};
+use alloc::vec;
+use log::info;
+
+pub fn sys_semget(key: i32, nsems: i32, semflg: u32) -> isize {
+    info!(
+        "[sys_semget] key: {}, nsems: {}, semflg: {:#o}",
+        key, nsems, semflg
+    );
+    if nsems < 0 {
+        return EINVAL;
+    }
+    match sem::get(key, nsems as usize, semflg) {
+        Ok(semid) => semid as isize,
+        Err(errno) => errno,
+    }
+}
+
+pub fn sys_semtimedop(
+    semid: usize,
+    sops: *const Sembuf,
+    nsops: usize,
+    timeout: *const TimeSpec,
+) -> isize {
+    info!(
+        "[sys_semtimedop] semid: {}, sops: {:?}, nsops: {}, timeout: {:?}",
+        semid, sops, nsops, timeout
+    );
+    if nsops == 0 {
+        return EINVAL;
+    }
+    if nsops > SEMOPM {
+        return E2BIG;
+    }
+    let token = current_user_token();
+    let mut buf = vec![Sembuf::default(); nsops];
+    if let Err(errno) = copy_from_user_array(token, sops, buf.as_mut_ptr(), nsops) {
+        return errno;
+    }
+    let deadline = match try_get_from_user(token, timeout) {
+        Ok(Some(timeout)) if timeout.tv_nsec >= NSEC_PER_SEC => return EINVAL,
+        Ok(timeout) => timeout.map(|timeout| timeout + TimeSpec::now()),
+        Err(errno) => return errno,
+    };
+    let undo = current_task().unwrap().sem_undo.id();
+    match sem::op(semid, &buf, deadline, undo) {
+        Ok(()) => SUCCESS,
+        Err(errno) => errno,
+    }
+}
+
+pub fn sys_semop(semid: usize, sops: *const Sembuf, nsops: usize) -> isize {
+    sys_semtimedop(semid, sops, nsops, core::ptr::null())
+}
+
+/// `arg` is `union semun`: a value for `SETVAL`, a pointer for the others.
+pub fn sys_semctl(semid: usize, semnum: usize, cmd: u32, arg: usize) -> isize {
+    info!(
+        "[sys_semctl] semid: {}, semnum: {}, cmd: {:#x}, arg: {:#x}",
+        semid, semnum, cmd, arg
+    );
+    let cmd = cmd & !IPC_64;
+    if let GETVAL | GETPID | GETNCNT | GETZCNT = cmd {
+        return match sem::get_one(semid, semnum, cmd) {
+            Ok(val) => val,
+            Err(errno) => errno,
+        };
+    }
+    let token = current_user_token();
+    let result = match cmd {
+        IPC_STAT => sem::stat(semid)
+            .and_then(|semid_ds| copy_to_user(token, &semid_ds, arg as *mut SemidDs)),
+        IPC_SET => {
+            let mut semid_ds = SemidDs::default();
+            copy_from_user(token, arg as *const SemidDs, &mut semid_ds)
+                .and_then(|()| sem::set(semid, &semid_ds.sem_perm))
+        }
+        IPC_RMID => sem::remove(semid),
+        IPC_INFO | SEM_INFO => {
+            copy_to_user(token, &sem::info(cmd == SEM_INFO), arg as *mut sem::SemInfo)
+        }
+        GETALL => sem::get_all(semid)
+            .and_then(|vals| copy_to_user_array(token, vals.as_ptr(), arg as *mut u16, vals.len())),
+        SETVAL => sem::set_one(semid, semnum, arg as i32),
+        SETALL => sem::nsems(semid).and_then(|nsems| {
+            let mut vals = vec![0u16; nsems];
+            copy_from_user_array(token, arg as *const u16, vals.as_mut_ptr(), nsems)?;
+            sem::set_all(semid, &vals)
+        }),
+        _ => Err(EINVAL),
+    };
+    match result {
+        Ok(()) => SUCCESS,
+        Err(errno) => errno,
+    }
+}
@@ -0,0 +1,88 @@
+use crate::ipc::{
+    shm::{self, ShmidDs, SHMLBA, SHM_LOCK, SHM_REMAP, SHM_RND, SHM_UNLOCK},
+    IPC_64, IPC_INFO, IPC_RMID, IPC_SET, IPC_STAT,
+};
+use crate::mm::{copy_from_user, copy_to_user};
+use crate::syscall::errno::*;
+use crate::task::{current_task, current_user_token};
+use log::info;
+
+pub fn sys_shmget(key: i32, size: usize, shmflg: u32) -> isize {
+    info!(
+        "[sys_shmget] key: {}, size: {:#x}, shmflg: {:#o}",
+        key, size, shmflg
+    );
+    match shm::get(key, size, shmflg) {
+        Ok(shmid) => shmid as isize,
+        Err(errno) => errno,
+    }
+}
+
+pub fn sys_shmat(shmid: usize, shmaddr: usize, shmflg: u32) -> isize {
+    info!(
+        "[sys_shmat] shmid: {}, shmaddr: {:#x}, shmflg: {:#o}",
+        shmid, shmaddr, shmflg
+    );
+    let shmaddr = if shmflg & SHM_RND != 0 {
+        shmaddr & !(SHMLBA - 1)
+    } else if shmaddr & (SHMLBA - 1) != 0 {
+        return EINVAL;
+    } else {
+        shmaddr
+    };
+    // SHM_REMAP asks to replace what is mapped at `shmaddr`, there has to be one
+    if shmaddr == 0 && shmflg & SHM_REMAP != 0 {
+        return EINVAL;
+    }
+    let (frames, map_perm, attachment) = match shm::attach(shmid, shmflg) {
+        Ok(attach) => attach,
+        Err(errno) => return errno,
+    };
+    let task = current_task().unwrap();
+    let result = task.vm.lock().attach_shm(
+        shmaddr,
+        shmflg & SHM_REMAP != 0,
+        map_perm,
+        frames,
+        attachment,
+    );
+    match result {
+        Ok(addr) => addr as isize,
+        Err(errno) => errno,
+    }
+}
+
+pub fn sys_shmdt(shmaddr: usize) -> isize {
+    info!("[sys_shmdt] shmaddr: {:#x}", shmaddr);
+    let task = current_task().unwrap();
+    let result = task.vm.lock().detach_shm(shmaddr);
+    match result {
+        Ok(()) => SUCCESS,
+        Err(errno) => errno,
+    }
+}
+
+pub fn sys_shmctl(shmid: usize, cmd: u32, buf: *mut ShmidDs) -> isize {
+    info!(
+        "[sys_shmctl] shmid: {}, cmd: {:#x}, buf: {:?}",
+        shmid, cmd, buf
+    );
+    let token = current_user_token();
+    let result = match cmd & !IPC_64 {
+        IPC_STAT => shm::stat(shmid).and_then(|shmid_ds| copy_to_user(token, &shmid_ds, buf)),
+        IPC_SET => {
+            let mut shmid_ds = ShmidDs::default();
+            copy_from_user(token, buf, &mut shmid_ds)
+                .and_then(|()| shm::set(shmid, &shmid_ds.shm_perm))
+        }
+        IPC_RMID => shm::remove(shmid),
+        IPC_INFO => copy_to_user(token, &shm::info(), buf.cast()),
+        SHM_LOCK => shm::lock(shmid, true),
+        SHM_UNLOCK => shm::lock(shmid, false),
+        _ => Err(EINVAL),
+    };
+    match result {
+        Ok(()) => SUCCESS,
+        Err(errno) => errno,
+    }
+}
@@ -0,0 +1,221 @@
+use super::current_task;
+use crate::fs::{Stat, StatMode};
+use crate::syscall::errno::*;
+use alloc::vec::Vec;
+
+/// Linux caps the supplementary groups at `NGROUPS_MAX`
+pub const NGROUPS_MAX: usize = 65536;
+
+bitflags! {
+    /// What is asked of an inode, same values as `R_OK`, `W_OK` and `X_OK`
+    pub struct Access: u32 {
+        const READ  = 4;
+        const WRITE = 2;
+        const EXEC  = 1;
+    }
+}
+
+/// Identity of a process, shared by its threads.
+/// There are no capabilities, uid 0 is allowed everything.
+#[derive(Clone, Debug)]
+pub struct Credentials {
+    pub uid: u32,
+    pub euid: u32,
+    pub suid: u32,
+    pub gid: u32,
+    pub egid: u32,
+    pub sgid: u32,
+    /// Supplementary groups
+    pub groups: Vec<u32>,
+}
+
+impl Credentials {
+    pub fn root() -> Self {
+        Self {
+            uid: 0,
+            euid: 0,
+            suid: 0,
+            gid: 0,
+            egid: 0,
+            sgid: 0,
+            groups: Vec::new(),
+        }
+    }
+    pub fn is_root(&self) -> bool {
+        self.euid == 0
+    }
+    /// Whether `gid` is the effective group or one of the supplementary groups
+    pub fn in_group(&self, gid: u32) -> bool {
+        self.egid == gid || self.groups.contains(&gid)
+    }
+    /// The credentials `access()` checks with, the real ids take the place of the effective ones.
+    pub fn real(&self) -> Self {
+        Self {
+            euid: self.uid,
+            egid: self.gid,
+            ..self.clone()
+        }
+    }
+    /// Check `access` against the owner and mode in `stat`.
+    /// Root may do anything, except executing a file nobody may execute.
+    pub fn may_access(&self, stat: &Stat, access: Access) -> bool {
+        let mode = stat.get_mode();
+        if self.is_root() {
+            return !access.contains(Access::EXEC)
+                || mode & StatMode::S_IFMT.bits() == StatMode::S_IFDIR.bits()
+                || mode & 0o111 != 0;
+        }
+        let granted = if self.euid == stat.get_uid() {
+            mode >> 6
+        } else if self.in_group(stat.get_gid()) {
+            mode >> 3
+        } else {
+            mode
+        } & 0o7;
+        granted & access.bits() == access.bits()
+    }
+    /// Whether a process with these credentials may send a signal to one with `target`,
+    /// the real or effective user must be the real or saved user of the target.
+    pub fn may_signal(&self, target: &Credentials) -> bool {
+        self.is_root()
+            || [self.uid, self.euid]
+                .iter()
+                .any(|uid| *uid == target.uid || *uid == target.suid)
+    }
+    /// Whether a process with these credentials may trace one with `target`, like `ptrace_may_access()` of Linux:
+    /// every user and group id of the target must be the real one of the tracer,
+    /// so that nobody traces a set-user-ID program.
+    pub fn may_trace(&self, target: &Credentials) -> bool {
+        self.is_root()
+            || [target.uid, target.euid, target.suid]
+                .iter()
+                .all(|uid| *uid == self.uid)
+                && [target.gid, target.egid, target.sgid]
+                    .iter()
+                    .all(|gid| *gid == self.gid)
+    }
+    /// `setuid()`, root sets all three ids, others may only set the effective one to the real or saved one.
+    pub fn setuid(&mut self, uid: u32) -> Result<(), isize> {
+        if self.is_root() {
+            self.uid = uid;
+            self.euid = uid;
+            self.suid = uid;
+        } else if uid == self.uid || uid == self.suid {
+            self.euid = uid;
+        } else {
+            return Err(EPERM);
+        }
+        Ok(())
+    }
+    pub fn setgid(&mut self, gid: u32) -> Result<(), isize> {
+        if self.is_root() {
+            self.gid = gid;
+            self.egid = gid;
+            self.sgid = gid;
+        } else if gid == self.gid || gid == self.sgid {
+            self.egid = gid;
+        } else {
+            return Err(EPERM);
+        }
+        Ok(())
+    }
+    /// `setreuid()`, `u32::MAX` (-1) leaves an id unchanged.
+    /// The saved id follows the effective one when the real id is set or the effective one changes away from it.
+    pub fn setreuid(&mut self, ruid: u32, euid: u32) -> Result<(), isize> {
+        let old_uid = self.uid;
+        if !self.is_root() {
+            if ruid != u32::MAX && ruid != self.uid && ruid != self.euid {
+                return Err(EPERM);
+            }
+            if euid != u32::MAX && euid != self.uid && euid != self.euid && euid != self.suid {
+                return Err(EPERM);
+            }
+        }
+        if ruid != u32::MAX {
+            self.uid = ruid;
+        }
+        if euid != u32::MAX {
+            self.euid = euid;
+        }
+        if ruid != u32::MAX || (euid != u32::MAX && euid != old_uid) {
+            self.suid = self.euid;
+        }
+        Ok(())
+    }
+    pub fn setregid(&mut self, rgid: u32, egid: u32) -> Result<(), isize> {
+        let old_gid = self.gid;
+        if !self.is_root() {
+            if rgid != u32::MAX && rgid != self.gid && rgid != self.egid {
+                return Err(EPERM);
+            }
+            if egid != u32::MAX && egid != self.gid && egid != self.egid && egid != self.sgid {
+                return Err(EPERM);
+            }
+        }
+        if rgid != u32::MAX {
+            self.gid = rgid;
+        }
+        if egid != u32::MAX {
+            self.egid = egid;
+        }
+        if rgid != u32::MAX || (egid != u32::MAX && egid != old_gid) {
+            self.sgid = self.egid;
+        }
+        Ok(())
+    }
+    /// `setresuid()`, `u32::MAX` (-1) leaves an id unchanged.
+    /// Unprivileged callers may only pick among the current real, effective and saved ids.
+    pub fn setresuid(&mut self, ruid: u32, euid: u32, suid: u32) -> Result<(), isize> {
+        let allowed =
+            |id: u32| id == u32::MAX || id == self.uid || id == self.euid || id == self.suid;
+        if !self.is_root() && !(allowed(ruid) && allowed(euid) && allowed(suid)) {
+            return Err(EPERM);
+        }
+        if ruid != u32::MAX {
+            self.uid = ruid;
+        }
+        if euid != u32::MAX {
+            self.euid = euid;
+        }
+        if suid != u32::MAX {
+            self.suid = suid;
+        }
+        Ok(())
+    }
+    pub fn setresgid(&mut self, rgid: u32, egid: u32, sgid: u32) -> Result<(), isize> {
+        let allowed =
+            |id: u32| id == u32::MAX || id == self.gid || id == self.egid || id == self.sgid;
+        if !self.is_root() && !(allowed(rgid) && allowed(egid) && allowed(sgid)) {
+            return Err(EPERM);
+        }
+        if rgid != u32::MAX {
+            self.gid = rgid;
+        }
+        if egid != u32::MAX {
+            self.egid = egid;
+        }
+        if sgid != u32::MAX {
+            self.sgid = sgid;
+        }
+        Ok(())
+    }
+    pub fn setgroups(&mut self, groups: Vec<u32>) -> Result<(), isize> {
+        if !self.is_root() {
+            return Err(EPERM);
+        }
+        if groups.len() > NGROUPS_MAX {
+            return Err(EINVAL);
+        }
+        self.groups = groups;
+        Ok(())
+    }
+}
+
+/// Credentials of the current task.
+/// Kernel work done before the first task runs, such as opening `/dev/tty` for initproc, acts as root.
+pub fn current_cred() -> Credentials {
+    match current_task() {
+        Some(task) => task.cred.lock().clone(),
+        None => Credentials::root(),
+    }
+}
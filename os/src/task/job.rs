@@ -0,0 +1,203 @@
+//! Job control: stopping and continuing processes, process groups and sessions.
+//!
+//! Like Linux, a stop signal stops every thread of a process, each on its way back to user mode,
+//! and the parent hears of it once, through `SIGCHLD` and `wait4()`.
+//! Only `SIGCONT` continues a stopped process, `SIGKILL` still kills it.
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+
+use super::{
+    all_tasks, current_task, send_signal, stop_current_and_run_next, wake_interruptible,
+    SigActionFlags, Signals, TaskControlBlock, TaskStatus, INITPROC,
+};
+
+/// The signals whose default action is to stop the process
+pub const STOP_SIGNALS: Signals = Signals::from_bits_truncate(
+    Signals::SIGSTOP.bits()
+        | Signals::SIGTSTP.bits()
+        | Signals::SIGTTIN.bits()
+        | Signals::SIGTTOU.bits(),
+);
+
+/// A change of state the parent may collect with `wait4()`
+#[derive(Clone, Copy, Debug)]
+pub enum JobReport {
+    Stopped(usize),
+    Continued,
+}
+
+impl JobReport {
+    /// The status word `wait4()` stores
+    pub fn status(&self) -> u32 {
+        match self {
+            JobReport::Stopped(signum) => (*signum as u32) << 8 | 0x7f,
+            JobReport::Continued => 0xffff,
+        }
+    }
+}
+
+/// Stop state of a process, shared by its threads
+#[derive(Default)]
+pub struct JobStatus {
+    /// The signal which stopped the process, while it is stopped
+    pub stopped: Option<usize>,
+    /// The last change the parent hasn't collected
+    pub report: Option<JobReport>,
+}
+
+/// One live task of every process for which `target(task, pgid)` holds, the leader if it is alive
+pub fn processes(target: impl Fn(&TaskControlBlock, usize) -> bool) -> Vec<Arc<TaskControlBlock>> {
+    let mut processes: Vec<Arc<TaskControlBlock>> = Vec::new();
+    // ordered by pid, so the leader comes first
+    for task in all_tasks() {
+        let (zombie, pgid) = {
+            let inner = task.acquire_inner_lock();
+            (inner.is_zombie(), inner.pgid)
+        };
+        if zombie || !target(&task, pgid) || processes.iter().any(|p| p.tgid == task.tgid) {
+            continue;
+        }
+        processes.push(task);
+    }
+    processes
+}
+
+/// The live threads of process `tgid`
+fn threads(tgid: usize) -> Vec<Arc<TaskControlBlock>> {
+    all_tasks()
+        .into_iter()
+        .filter(|task| task.tgid == tgid && !task.acquire_inner_lock().is_zombie())
+        .collect()
+}
+
+/// Send `signal` to every process of group `pgid`, as the terminal does.
+pub fn kill_pgrp(pgid: usize, signal: Signals) {
+    for task in processes(|_, task_pgid| task_pgid == pgid) {
+        send_signal(task, signal);
+    }
+}
+
+/// Whether some process of session `sid` is in group `pgid`
+pub fn pgrp_in_session(pgid: usize, sid: usize) -> bool {
+    all_tasks().iter().any(|task| {
+        let inner = task.acquire_inner_lock();
+        !inner.is_zombie() && inner.pgid == pgid && inner.sid == sid
+    })
+}
+
+/// Whether no process of group `pgid` has a parent in another group of the same session,
+/// a shell which could continue it. Like Linux, initproc doesn't count.
+pub fn is_orphaned_pgrp(pgid: usize) -> bool {
+    for task in all_tasks() {
+        let (parent, sid) = {
+            let inner = task.acquire_inner_lock();
+            if inner.is_zombie() || inner.pgid != pgid {
+                continue;
+            }
+            (inner.parent.clone(), inner.sid)
+        };
+        if let Some(parent) = parent.and_then(|parent| parent.upgrade()) {
+            if Arc::ptr_eq(&parent, &INITPROC) {
+                continue;
+            }
+            let inner = parent.acquire_inner_lock();
+            if inner.pgid != pgid && inner.sid == sid {
+                return false;
+            }
+        }
+    }
+    true
+}
+
+/// Tell the parent of the process of `task` that it stopped or continued,
+/// unless it asked not to be told with `SA_NOCLDSTOP`.
+fn notify_parent(task: &TaskControlBlock) {
+    let parent = task
+        .acquire_inner_lock()
+        .parent
+        .as_ref()
+        .and_then(|parent| parent.upgrade());
+    let parent = match parent {
+        Some(parent) => parent,
+        None => return,
+    };
+    let signum = Signals::SIGCHLD.to_signum().unwrap();
+    let nocldstop = parent.sighand.lock()[signum - 1]
+        .as_ref()
+        .map_or(false, |act| {
+            act.flags.contains(SigActionFlags::SA_NOCLDSTOP)
+        });
+    if !nocldstop {
+        send_signal(parent, Signals::SIGCHLD);
+    }
+}
+
+/// Job control done when `signal` is sent to the process of `task`, like `prepare_signal()` of Linux.
+/// A stop signal discards a pending `SIGCONT` and the other way round,
+/// and `SIGCONT` continues a stopped process whether it is caught, ignored or blocked.
+pub fn prepare_signal(task: &TaskControlBlock, signal: Signals) {
+    if signal.intersects(STOP_SIGNALS) {
+        for thread in threads(task.tgid) {
+            thread
+                .acquire_inner_lock()
+                .sigpending
+                .remove(Signals::SIGCONT);
+        }
+    } else if signal == Signals::SIGCONT {
+        let threads = threads(task.tgid);
+        for thread in threads.iter() {
+            thread.acquire_inner_lock().sigpending.remove(STOP_SIGNALS);
+        }
+        {
+            let mut job = task.job.lock();
+            if job.stopped.take().is_none() {
+                return;
+            }
+            job.report = Some(JobReport::Continued);
+        }
+        for thread in threads {
+            let mut inner = thread.acquire_inner_lock();
+            if inner.task_status == TaskStatus::Stopped {
+                inner.task_status = TaskStatus::Ready;
+                drop(inner);
+                wake_interruptible(thread);
+            }
+        }
+        notify_parent(task);
+    }
+}
+
+/// Stop the process of `task`, the current task, on `signum`.
+pub fn do_group_stop(task: &TaskControlBlock, signum: usize) {
+    {
+        let mut job = task.job.lock();
+        // another thread stopped it first
+        if job.stopped.is_some() {
+            return;
+        }
+        job.stopped = Some(signum);
+        job.report = Some(JobReport::Stopped(signum));
+    }
+    notify_parent(task);
+}
+
+/// Keep the current task stopped while its process is, unless it is being killed.
+pub fn stop_while_stopped() {
+    loop {
+        let task = current_task().unwrap();
+        if task.job.lock().stopped.is_none() {
+            return;
+        }
+        if task
+            .acquire_inner_lock()
+            .sigpending
+            .contains(Signals::SIGKILL)
+        {
+            return;
+        }
+        drop(task);
+        stop_current_and_run_next(TaskStatus::Stopped, |task| {
+            task.job.lock().stopped.is_some()
+        });
+    }
+}
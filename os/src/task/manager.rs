@@ -1,93 +1,101 @@
 use core::cmp::Ordering;
 
 #[cfg(feature = "oom_handler")]
-use crate::config::SYSTEM_TASK_LIMIT;
-#[cfg(feature = "oom_handler")]
-use alloc::vec::Vec;
+use crate::mm::{MemorySet, PageTableImpl};
 
+use crate::arch::board::CPU_NUM;
+use crate::arch::{cpu_id, online_cpus};
 use crate::timer::TimeSpec;
 
+use super::processor::running_tasks;
+use super::sched::{default_scheduler, Scheduler};
 use super::{current_task, TaskControlBlock};
+use alloc::boxed::Box;
 use alloc::collections::{BinaryHeap, VecDeque};
 use alloc::sync::{Arc, Weak};
+use alloc::vec::Vec;
+use core::sync::atomic;
 use lazy_static::*;
 use spin::Mutex;
 
-#[cfg(feature = "oom_handler")]
-pub struct ActiveTracker {
-    bitmap: Vec<u64>,
-}
-
-#[cfg(feature = "oom_handler")]
-#[allow(unused)]
-impl ActiveTracker {
-    pub const DEFAULT_SIZE: usize = SYSTEM_TASK_LIMIT;
-    pub fn new() -> Self {
-        let len = (Self::DEFAULT_SIZE + 63) / 64;
-        let mut bitmap = Vec::with_capacity(len);
-        bitmap.resize(len, 0);
-        Self { bitmap }
-    }
-    pub fn check_active(&self, pid: usize) -> bool {
-        (self.bitmap[pid / 64] & (1 << (pid % 64))) != 0
-    }
-    pub fn check_inactive(&self, pid: usize) -> bool {
-        (self.bitmap[pid / 64] & (1 << (pid % 64))) == 0
-    }
-    pub fn mark_active(&mut self, pid: usize) {
-        self.bitmap[pid / 64] |= 1 << (pid % 64)
-    }
-    pub fn mark_inactive(&mut self, pid: usize) {
-        self.bitmap[pid / 64] &= !(1 << (pid % 64))
-    }
-}
-
-#[cfg(feature = "oom_handler")]
-pub struct TaskManager {
-    pub ready_queue: VecDeque<Arc<TaskControlBlock>>,
-    pub interruptible_queue: VecDeque<Arc<TaskControlBlock>>,
-    pub active_tracker: ActiveTracker,
-}
-
-#[cfg(not(feature = "oom_handler"))]
 pub struct TaskManager {
-    pub ready_queue: VecDeque<Arc<TaskControlBlock>>,
+    /// The ready tasks of each core, in the order its scheduler picks
+    pub ready_queues: Vec<Box<dyn Scheduler>>,
     pub interruptible_queue: VecDeque<Arc<TaskControlBlock>>,
 }
 
-/// A simple FIFO scheduler.
 impl TaskManager {
-    #[cfg(feature = "oom_handler")]
     pub fn new() -> Self {
         Self {
-            ready_queue: VecDeque::new(),
+            ready_queues: (0..CPU_NUM).map(|_| default_scheduler()).collect(),
             interruptible_queue: VecDeque::new(),
-            active_tracker: ActiveTracker::new(),
         }
     }
-    #[cfg(not(feature = "oom_handler"))]
-    pub fn new() -> Self {
-        Self {
-            ready_queue: VecDeque::new(),
-            interruptible_queue: VecDeque::new(),
-        }
+    /// The ready tasks of every core
+    pub fn ready_tasks(&self) -> impl Iterator<Item = &Arc<TaskControlBlock>> {
+        self.ready_queues.iter().flat_map(|queue| queue.iter())
+    }
+    /// The core to queue `task` on, the least loaded of those it may run on, preferring the one it ran on last.
+    fn select_cpu(&self, task: &TaskControlBlock) -> usize {
+        let (cpus_allowed, last) = {
+            let entity = task.sched.lock();
+            (entity.cpus_allowed, entity.cpu)
+        };
+        let online = online_cpus();
+        // the cores it is allowed on may never have come up
+        let allowed = match cpus_allowed & online {
+            0 => online,
+            allowed => allowed,
+        };
+        (0..CPU_NUM)
+            .filter(|cpu| allowed & 1 << cpu != 0)
+            .min_by_key(|cpu| (self.ready_queues[*cpu].len(), *cpu != last))
+            .unwrap()
     }
     pub fn add(&mut self, task: Arc<TaskControlBlock>) {
-        self.ready_queue.push_back(task);
-    }
-    #[cfg(feature = "oom_handler")]
-    pub fn fetch(&mut self) -> Option<Arc<TaskControlBlock>> {
-        match self.ready_queue.pop_front() {
-            Some(task) => {
-                self.active_tracker.mark_active(task.pid.0);
-                Some(task)
-            }
-            None => None,
+        let cpu = self.select_cpu(&task);
+        self.ready_queues[cpu].add(task);
+    }
+    /// Queue the task which ran last on `cpu`, on `cpu` again unless it may no longer run there.
+    pub fn put_prev(&mut self, task: Arc<TaskControlBlock>, yielded: bool, cpu: usize) {
+        if task.sched.lock().cpus_allowed & 1 << cpu != 0 {
+            self.ready_queues[cpu].put_prev(task, yielded);
+        } else {
+            self.add(task);
         }
     }
-    #[cfg(not(feature = "oom_handler"))]
-    pub fn fetch(&mut self) -> Option<Arc<TaskControlBlock>> {
-        self.ready_queue.pop_front()
+    /// Take the task `cpu` runs next, balancing the queues first.
+    pub fn fetch(&mut self, cpu: usize) -> Option<Arc<TaskControlBlock>> {
+        self.balance(cpu);
+        let task = self.ready_queues[cpu].fetch()?;
+        task.sched.lock().cpu = cpu;
+        task.on_cpu.store(true, atomic::Ordering::Release);
+        Some(task)
+    }
+    /// Pull a task from the busiest queue to the one of `cpu`,
+    /// when the latter is empty or the former has at least two more tasks.
+    /// The task pulled is the last to run of those allowed on `cpu`.
+    fn balance(&mut self, cpu: usize) {
+        let len = self.ready_queues[cpu].len();
+        let (busiest, busiest_len) = self
+            .ready_queues
+            .iter()
+            .map(|queue| queue.len())
+            .enumerate()
+            .max_by_key(|(_, len)| *len)
+            .unwrap();
+        if busiest == cpu || busiest_len == 0 || (len > 0 && busiest_len < len + 2) {
+            return;
+        }
+        let task = self.ready_queues[busiest]
+            .iter()
+            .filter(|task| task.sched.lock().cpus_allowed & 1 << cpu != 0)
+            .last()
+            .cloned();
+        if let Some(task) = task {
+            self.ready_queues[busiest].retain(&|queued| !Arc::ptr_eq(queued, &task));
+            self.ready_queues[cpu].add(task);
+        }
     }
     pub fn add_interruptible(&mut self, task: Arc<TaskControlBlock>) {
         self.interruptible_queue.push_back(task);
@@ -97,21 +105,22 @@ impl TaskManager {
             .retain(|task_in_queue| Arc::as_ptr(task_in_queue) != Arc::as_ptr(task));
     }
     pub fn find_by_pid(&self, pid: usize) -> Option<Arc<TaskControlBlock>> {
-        self.ready_queue
-            .iter()
+        self.ready_tasks()
             .chain(self.interruptible_queue.iter())
             .find(|task| task.pid.0 == pid)
             .cloned()
     }
     pub fn find_by_tgid(&self, tgid: usize) -> Option<Arc<TaskControlBlock>> {
-        self.ready_queue
-            .iter()
+        self.ready_tasks()
             .chain(self.interruptible_queue.iter())
             .find(|task| task.tgid == tgid)
             .cloned()
     }
     pub fn ready_count(&self) -> u16 {
-        self.ready_queue.len() as u16
+        self.ready_queues
+            .iter()
+            .map(|queue| queue.len())
+            .sum::<usize>() as u16
     }
     pub fn interruptible_count(&self) -> u16 {
         self.interruptible_queue.len() as u16
@@ -130,6 +139,7 @@ impl TaskManager {
     }
     /// This function will drop `task` from `interruptible_queue` and push it into `ready_queue`.
     /// The `task` will be scheduled if everything goes well. Returns `Err()` if `task` is already waken.
+    /// A task still switching out of a core is left to `finish_switch()`, which queues it by its status.
     /// # Attention
     /// This function **won't** change `task_status`, you should change it manully to keep consistency.
     pub fn try_wake_interruptible(
@@ -137,6 +147,9 @@ impl TaskManager {
         task: Arc<TaskControlBlock>,
     ) -> Result<(), WaitQueueError> {
         self.drop_interruptible(&task);
+        if task.on_cpu.load(atomic::Ordering::Acquire) {
+            return Ok(());
+        }
         if self.find_by_pid(task.pid.0).is_none() {
             self.add(task);
             Ok(())
@@ -147,7 +160,7 @@ impl TaskManager {
     #[allow(unused)]
     // debug use only
     pub fn show_ready(&self) {
-        self.ready_queue.iter().for_each(|task| {
+        self.ready_tasks().for_each(|task| {
             log::error!("[show_ready] pid: {}", task.pid.0);
         })
     }
@@ -168,67 +181,50 @@ pub fn add_task(task: Arc<TaskControlBlock>) {
     TASK_MANAGER.lock().add(task);
 }
 
-pub fn fetch_task() -> Option<Arc<TaskControlBlock>> {
-    TASK_MANAGER.lock().fetch()
+/// Whether the current task should be preempted, called at every timer tick.
+pub fn scheduler_tick() -> bool {
+    let task = current_task().unwrap();
+    TASK_MANAGER.lock().ready_queues[cpu_id()].tick(&task)
 }
 
-/// Try to clean all tasks' memory space until `req` pages are released.
-#[cfg(feature = "oom_handler")]
-pub fn do_oom(req: usize) -> Result<(), ()> {
+/// Queue `task` again if it is ready, after its policy or priority changed.
+pub fn requeue_task(task: &Arc<TaskControlBlock>) {
     let mut manager = TASK_MANAGER.lock();
-    let mut cleaned = Vec::with_capacity(16);
-    let mut total_released = 0;
-    for task in manager
-        .interruptible_queue
-        .iter()
-        .filter(|task| manager.active_tracker.check_active(task.pid.0))
+    if manager
+        .ready_tasks()
+        .any(|queued| Arc::ptr_eq(queued, task))
     {
-        let released = task.vm.lock().do_deep_clean();
-        log::warn!("deep clean on task: {}, released: {}", task.tgid, released);
-        cleaned.push(task.pid.0);
-        total_released += released;
-        if total_released >= req {
-            while let Some(pid) = cleaned.pop() {
-                manager.active_tracker.mark_inactive(pid)
-            }
-            return Ok(());
-        };
+        for queue in manager.ready_queues.iter_mut() {
+            queue.retain(&|queued| !Arc::ptr_eq(queued, task));
+        }
+        manager.add(task.clone());
     }
+}
+
+/// The memory sets of the queued tasks, each once, for page reclaim.
+/// Sleeping tasks come first, then the ready ones, those to run last first.
+#[cfg(feature = "oom_handler")]
+pub fn memory_sets() -> Vec<Arc<Mutex<MemorySet<PageTableImpl>>>> {
+    let manager = TASK_MANAGER.lock();
+    let mut memory_sets: Vec<Arc<Mutex<MemorySet<PageTableImpl>>>> = Vec::new();
+    let ready: Vec<&Arc<TaskControlBlock>> = manager.ready_tasks().collect();
     for task in manager
-        .ready_queue
+        .interruptible_queue
         .iter()
-        .rev()
-        .filter(|task| manager.active_tracker.check_active(task.pid.0))
+        .chain(ready.into_iter().rev())
     {
-        let released = task.vm.lock().do_shallow_clean();
-        log::warn!(
-            "shallow clean on task: {}, released: {}",
-            task.tgid,
-            released
-        );
-        cleaned.push(task.pid.0);
-        total_released += released;
-        if total_released >= req {
-            while let Some(pid) = cleaned.pop() {
-                manager.active_tracker.mark_inactive(pid)
-            }
-            return Ok(());
-        };
+        if !memory_sets.iter().any(|vm| Arc::ptr_eq(vm, &task.vm)) {
+            memory_sets.push(task.vm.clone());
+        }
     }
-    Err(())
-}
-
-#[cfg(not(feature = "oom_handler"))]
-#[allow(unused)]
-pub fn do_oom() {
-    // do nothing
+    memory_sets
 }
 
 /// This function add a `task` to `interruptible_queue`,
 /// but won't take it out from `ready_queue`.
 /// So you should make sure that the `task` won't be presented in `ready_queue`.
 /// In common cases, a `task` will be dropped from `ready_queue` when it is scheduled,
-/// and you can use `take_current_task()` to acquire the ownership of current `task`.
+/// and `finish_switch()` calls this for a task which switched out to sleep.
 /// # Attention
 /// You should find a place to save `Arc<TaskControlBlock>` of the task, or you would
 /// be unable to use `wake_interruptible()` to wake it up in the future.
@@ -248,22 +244,20 @@ pub fn wake_interruptible(task: Arc<TaskControlBlock>) {
 /// # Warning
 /// `pid` here is unique, user will regard it as `tid`
 pub fn find_task_by_pid(pid: usize) -> Option<Arc<TaskControlBlock>> {
-    let task = current_task().unwrap();
-    if task.pid.0 == pid {
-        Some(task)
-    } else {
-        TASK_MANAGER.lock().find_by_pid(pid)
-    }
+    let manager = TASK_MANAGER.lock();
+    running_tasks()
+        .into_iter()
+        .find(|task| task.pid.0 == pid)
+        .or_else(|| manager.find_by_pid(pid))
 }
 
 /// Return arbitrary task with `tgid`.
 pub fn find_task_by_tgid(tgid: usize) -> Option<Arc<TaskControlBlock>> {
-    let task = current_task().unwrap();
-    if task.tgid == tgid {
-        Some(task)
-    } else {
-        TASK_MANAGER.lock().find_by_tgid(tgid)
-    }
+    let manager = TASK_MANAGER.lock();
+    running_tasks()
+        .into_iter()
+        .find(|task| task.tgid == tgid)
+        .or_else(|| manager.find_by_tgid(tgid))
 }
 
 pub fn procs_count() -> u16 {
@@ -330,6 +324,15 @@ impl WaitQueue {
                         super::TaskStatus::Interruptible => {
                             inner.task_status = super::task::TaskStatus::Ready
                         }
+                        // it is on its way to sleep on another core, see `block_current_and_run_next()`
+                        super::TaskStatus::Running if task.is_running_elsewhere() => {
+                            inner.wakeup_pending = true;
+                            cnt += 1;
+                            if cnt == limit {
+                                break;
+                            }
+                            continue;
+                        }
                         // for `Ready` or `Running`, we don't need to do wake,
                         // for `Zombie`, we will mess up the process management if we do wake...
                         _ => continue,
@@ -412,6 +415,10 @@ impl TimeoutWaitQueue {
                             super::TaskStatus::Interruptible => {
                                 inner.task_status = super::task::TaskStatus::Ready
                             }
+                            super::TaskStatus::Running if task.is_running_elsewhere() => {
+                                inner.wakeup_pending = true;
+                                continue;
+                            }
                             // for `Ready` or `Running`, we don't need to do wake,
                             // for `Zombie`, we will mess up the process management if we do wake...
                             _ => continue,
@@ -1,41 +1,60 @@
 mod context;
+mod cred;
 mod elf;
+mod job;
 mod manager;
 mod pid;
 mod processor;
+pub mod ptrace;
+mod sched;
 pub mod signal;
 mod task;
 pub mod threads;
 
-use crate::arch::__switch;
+use crate::arch::{__switch, kick_cpu};
 use crate::{
-    fs::{OpenFlags, ROOT_FD},
+    fs::{dev::tty::TTY, locks, OpenFlags, ROOT_FD},
     mm::translated_refmut,
 };
-use alloc::{collections::VecDeque, sync::Arc};
+use alloc::{
+    collections::{BTreeMap, VecDeque},
+    sync::Arc,
+    vec,
+    vec::Vec,
+};
 pub use context::TaskContext;
+use core::sync::atomic::Ordering;
+pub use cred::{current_cred, Access, Credentials, NGROUPS_MAX};
 pub use elf::{load_elf_interp, AuxvEntry, AuxvType, ELFInfo};
+pub use job::{is_orphaned_pgrp, kill_pgrp, pgrp_in_session, processes, JobReport};
 use lazy_static::*;
 use log::warn;
-use manager::fetch_task;
+#[cfg(feature = "oom_handler")]
+pub use manager::memory_sets;
 pub use manager::{
-    add_task, do_oom, do_wake_expired, find_task_by_pid, find_task_by_tgid, procs_count,
-    sleep_interruptible, wait_with_timeout, wake_interruptible,
+    add_task, do_wake_expired, find_task_by_pid, find_task_by_tgid, procs_count, requeue_task,
+    scheduler_tick, sleep_interruptible, wait_with_timeout, wake_interruptible, WaitQueue,
 };
 // pub use pid::RecycleAllocator;
 pub use pid::{
     pid_alloc, trap_cx_bottom_from_tid, ustack_bottom_from_tid, KernelStackImpl, PidHandle,
 };
 pub use processor::{
-    current_task, current_trap_cx, current_user_token, run_tasks, schedule, take_current_task,
+    current_task, current_trap_cx, current_user_token, run_tasks, running_tasks, schedule,
 };
+pub use sched::{SchedPolicy, MAX_NICE, MAX_RT_PRIO, MIN_NICE, RR_TIMESLICE, SCHED_RESET_ON_FORK};
 pub use signal::*;
-pub use task::{RobustList, Rusage, TaskControlBlock, TaskStatus};
+pub use task::{
+    ExecArgs, RobustList, Rusage, TaskControlBlock, TaskStatus, OOM_SCORE_ADJ_MAX,
+    OOM_SCORE_ADJ_MIN,
+};
 
-use self::processor::PROCESSOR;
+use self::processor::this_processor;
+use self::task::TaskControlBlockInner;
+use spin::MutexGuard;
 #[allow(unused)]
 pub fn try_yield() {
-    let lock = PROCESSOR.lock();
+    let lock = this_processor().lock();
     let mut do_suspend = false;
     if !lock.is_vacant() {
         do_suspend = true;
@@ -45,62 +64,88 @@ pub fn try_yield() {
         suspend_current_and_run_next()
     }
 }
+/// Give up the CPU, like `sched_yield()`.
 pub fn suspend_current_and_run_next() {
+    requeue_current_and_run_next(true);
+}
+
+/// Called at a timer tick, unlike `suspend_current_and_run_next()`
+/// a real-time task keeps its place ahead of the others of its priority.
+pub fn preempt_current_and_run_next() {
+    requeue_current_and_run_next(false);
+}
+
+fn requeue_current_and_run_next(yielded: bool) {
+    // There must be an application running.
+    let task = current_task().unwrap();
+    // Change status to Ready
+    task.acquire_inner_lock().task_status = TaskStatus::Ready;
+    drop(task);
+    // jump to scheduling cycle, which pushes it back to ready queue.
+    schedule(yielded);
+}
+
+pub fn block_current_and_run_next() {
     // There must be an application running.
-    let task = take_current_task().unwrap();
+    let task = current_task().unwrap();
 
     // ---- hold current PCB lock
     let mut task_inner = task.acquire_inner_lock();
-    let task_cx_ptr = &mut task_inner.task_cx as *mut TaskContext;
-    // Change status to Ready
-    task_inner.task_status = TaskStatus::Ready;
+    // woken up from another core before it got here
+    if task_inner.wakeup_pending {
+        task_inner.wakeup_pending = false;
+        return;
+    }
+    // Change status to Interruptible
+    task_inner.task_status = TaskStatus::Interruptible;
     drop(task_inner);
     // ---- release current PCB lock
+    drop(task);
 
-    // push back to ready queue.
-    add_task(task);
-    // jump to scheduling cycle
-    schedule(task_cx_ptr);
+    // jump to scheduling cycle, which pushes it to interruptible queue, so that it won't be scheduled.
+    schedule(false);
 }
 
-pub fn block_current_and_run_next() {
+/// Like `block_current_and_run_next()`, but the task sleeps as `status`, `Stopped` or `Traced`,
+/// which `SIGKILL` and whoever makes `stopped()` false wake it up from.
+pub fn stop_current_and_run_next(status: TaskStatus, stopped: impl Fn(&TaskControlBlock) -> bool) {
     // There must be an application running.
-    let task = take_current_task().unwrap();
+    let task = current_task().unwrap();
 
     // ---- hold current PCB lock
     let mut task_inner = task.acquire_inner_lock();
-    let task_cx_ptr = &mut task_inner.task_cx as *mut TaskContext;
-    // Change status to Interruptible
-    task_inner.task_status = TaskStatus::Interruptible;
+    if task_inner.sigpending.contains(Signals::SIGKILL) {
+        return;
+    }
+    task_inner.task_status = status;
     drop(task_inner);
     // ---- release current PCB lock
 
-    // push to interruptible queue of scheduler, so that it won't be scheduled.
-    sleep_interruptible(task);
-    // jump to scheduling cycle
-    schedule(task_cx_ptr);
+    // it may have been continued from another core since the caller checked
+    if !stopped(&task) {
+        task.acquire_inner_lock().task_status = TaskStatus::Running;
+        return;
+    }
+    drop(task);
+    schedule(false);
+}
+
+/// Wake up `task`, whose inner is `inner`, if it is sleeping,
+/// or have its next sleep return at once if it runs on another core.
+fn wake_up(task: &Arc<TaskControlBlock>, mut inner: MutexGuard<TaskControlBlockInner>) {
+    if inner.task_status == TaskStatus::Interruptible {
+        inner.task_status = TaskStatus::Ready;
+        drop(inner);
+        // push back to ready queue.
+        wake_interruptible(task.clone());
+    } else if inner.task_status == TaskStatus::Running && task.is_running_elsewhere() {
+        inner.wakeup_pending = true;
+    }
 }
 
 pub fn do_exit(task: Arc<TaskControlBlock>, exit_code: u32) {
     // **** hold current PCB lock
     let mut inner = task.acquire_inner_lock();
-    if !task.exit_signal.is_empty() {
-        if let Some(parent) = inner.parent.as_ref(){
-            let parent_task = parent.upgrade().unwrap(); // this will acquire inner of current task
-            let mut parent_inner = parent_task.acquire_inner_lock();
-            parent_inner.add_signal(task.exit_signal);
-    
-            if parent_inner.task_status == TaskStatus::Interruptible {
-                // wake up parent if parent is waiting.
-                parent_inner.task_status = TaskStatus::Ready;
-                drop(parent_inner);
-                // push back to ready queue.
-                wake_interruptible(parent_task);
-            }
-        } else {
-            warn!("[do_exit] parent is None");
-        }
-    }
     log::trace!(
         "[do_exit] Trying to exit pid {} with {}",
         task.pid.0,
@@ -110,30 +155,19 @@ pub fn do_exit(task: Arc<TaskControlBlock>, exit_code: u32) {
     inner.task_status = TaskStatus::Zombie;
     // Record exit code
     inner.exit_code = exit_code;
+    let parent = inner.parent.as_ref().and_then(|parent| parent.upgrade());
+    let children = core::mem::take(&mut inner.children);
+    let clear_child_tid = inner.clear_child_tid;
+    drop(inner);
+    // **** release current PCB lock, the wake up below locks the task manager
 
-    // move children to initproc
-    if !inner.children.is_empty() {
-        let mut initproc_inner = INITPROC.acquire_inner_lock();
-        while let Some(child) = inner.children.pop() {
-            child.acquire_inner_lock().parent = Some(Arc::downgrade(&INITPROC));
-            initproc_inner.children.push(child);
-        }
-        if initproc_inner.task_status == TaskStatus::Interruptible {
-            // wake up initproc if initproc is waiting.
-            initproc_inner.task_status = TaskStatus::Ready;
-            // push back to ready queue.
-            wake_interruptible(INITPROC.clone());
-        }
-    }
-
-    inner.children.clear();
-    if inner.clear_child_tid != 0 {
+    if clear_child_tid != 0 {
         log::debug!(
             "[do_exit] do futex wake on clear_child_tid: {:X}",
-            inner.clear_child_tid
+            clear_child_tid
         );
         //let phys_ref =
-        match translated_refmut(task.get_user_token(), inner.clear_child_tid as *mut u32) {
+        match translated_refmut(task.get_user_token(), clear_child_tid as *mut u32) {
             Ok(phys_ref) => {
                 *phys_ref = 0;
                 task.futex.lock().wake(phys_ref as *const u32 as usize, 1);
@@ -141,6 +175,7 @@ pub fn do_exit(task: Arc<TaskControlBlock>, exit_code: u32) {
             Err(_) => log::warn!("invalid clear_child_tid"),
         };
     }
+    let inner = task.acquire_inner_lock();
     // deallocate user resource (trap context and user stack)
     task.vm.lock().dealloc_user_res(task.tid);
     // deallocate whole user space in advance, or if its parent do not call wait,
@@ -148,42 +183,79 @@ pub fn do_exit(task: Arc<TaskControlBlock>, exit_code: u32) {
     if Arc::strong_count(&task.vm) == 1 {
         task.vm.lock().recycle_data_pages();
     }
+    let session_leader = task.pid.0 == inner.sid;
     drop(inner);
     // **** release current PCB lock
+
+    ptrace::exit_ptrace(&task, parent.as_ref(), exit_code);
+    // the parent and the children are locked after the task, which they may be locking now
+    if !task.exit_signal.is_empty() {
+        if let Some(parent) = parent {
+            let mut parent_inner = parent.acquire_inner_lock();
+            parent_inner.add_signal(task.exit_signal);
+            // wake up parent if parent is waiting.
+            wake_up(&parent, parent_inner);
+        } else {
+            warn!("[do_exit] parent is None");
+        }
+    }
+    // move children to initproc
+    if !children.is_empty() {
+        for child in children.iter() {
+            child.acquire_inner_lock().parent = Some(Arc::downgrade(&INITPROC));
+        }
+        let mut initproc_inner = INITPROC.acquire_inner_lock();
+        initproc_inner.children.extend(children);
+        // wake up initproc if initproc is waiting.
+        wake_up(&INITPROC, initproc_inner);
+    }
+    if session_leader {
+        TTY.disassociate(task.pid.0);
+    }
+    // the files stay open until the task is reaped, but the locks of the process are released
+    // by its last thread, threads exiting together on several cores are counted exactly once
+    if task.live_threads.fetch_sub(1, Ordering::AcqRel) == 1 {
+        for file_descriptor in task.files.lock().iter().flatten() {
+            locks::release_on_close(task.tgid, &file_descriptor.file);
+        }
+        task.sem_undo.apply(task.tgid);
+    }
     // drop task manually to maintain rc correctly
     log::info!("[do_exit] Pid {} exited with {}", task.pid.0, exit_code);
 }
 
 pub fn exit_current_and_run_next(exit_code: u32) -> ! {
-    // take from Processor
-    let task = take_current_task().unwrap();
+    let task = current_task().unwrap();
     do_exit(task, exit_code);
-    // we do not have to save task context
-    let mut _unused = TaskContext::zero_init();
-    schedule(&mut _unused as *mut _);
+    // the idle loop drops the zombie from the Processor
+    schedule(false);
     panic!("Unreachable");
 }
 
+/// Kill a thread running on another core when its process exits, with `exit_code` for the process.
+fn zap_thread(task: &Arc<TaskControlBlock>, exit_code: u32) {
+    task.acquire_inner_lock().group_exit = Some(exit_code);
+    send_signal(task.clone(), Signals::SIGKILL);
+    kick_cpu(task.sched.lock().cpu);
+}
+
 pub fn exit_group_and_run_next(exit_code: u32) -> ! {
-    // exit current, take from Processor
-    let task = take_current_task().unwrap();
+    let task = current_task().unwrap();
+    // another thread exits the process, which kills this one
+    let group_exit = task.acquire_inner_lock().group_exit;
+    if let Some(exit_code) = group_exit {
+        drop(task);
+        exit_current_and_run_next(exit_code);
+    }
     let tgid = task.tgid;
     do_exit(task, exit_code);
 
     let mut exit_list = VecDeque::new();
 
     let mut manager = manager::TASK_MANAGER.lock();
-    let mut remain = manager.ready_queue.len();
-    while let Some(task) = manager.ready_queue.pop_front() {
-        if task.tgid == tgid {
-            exit_list.push_back(task);
-        } else {
-            manager.ready_queue.push_back(task);
-        }
-        remain -= 1;
-        if remain == 0 {
-            break;
-        }
+    for queue in manager.ready_queues.iter_mut() {
+        exit_list.extend(queue.iter().filter(|task| task.tgid == tgid).cloned());
+        queue.retain(&|task| task.tgid != tgid);
     }
     let mut remain = manager.interruptible_queue.len();
     while let Some(task) = manager.interruptible_queue.pop_front() {
@@ -197,14 +269,21 @@ pub fn exit_group_and_run_next(exit_code: u32) -> ! {
             break;
         }
     }
+    // those on other cores exit on their own
+    let running: Vec<Arc<TaskControlBlock>> = running_tasks()
+        .into_iter()
+        .filter(|task| task.tgid == tgid && !task.acquire_inner_lock().is_zombie())
+        .collect();
     drop(manager);
 
     for task in exit_list.into_iter() {
         do_exit(task, exit_code);
     }
-    // we do not have to save task context
-    let mut _unused = TaskContext::zero_init();
-    schedule(&mut _unused as *mut _);
+    for task in running.iter() {
+        zap_thread(task, exit_code);
+    }
+    // the idle loop drops the zombie from the Processor
+    schedule(false);
     panic!("Unreachable");
 }
 
@@ -216,5 +295,33 @@ lazy_static! {
 }
 
 pub fn add_initproc() {
+    // like the console of a login session, the terminal controls the session of initproc
+    let pgid = INITPROC.acquire_inner_lock().pgid;
+    TTY.set_controlling(pgid, pgid);
     add_task(INITPROC.clone());
 }
+
+/// Every task in the system, zombies included, ordered by pid.
+/// Tasks are found by walking the process tree from `INITPROC`,
+/// threads cloned by a task without parent are only found in the queues.
+pub fn all_tasks() -> Vec<Arc<TaskControlBlock>> {
+    let mut tasks = BTreeMap::new();
+    let mut stack = vec![INITPROC.clone()];
+    let manager = manager::TASK_MANAGER.lock();
+    stack.extend(running_tasks());
+    stack.extend(
+        manager
+            .ready_tasks()
+            .chain(manager.interruptible_queue.iter())
+            .cloned(),
+    );
+    drop(manager);
+    while let Some(task) = stack.pop() {
+        if tasks.contains_key(&task.pid.0) {
+            continue;
+        }
+        stack.extend(task.acquire_inner_lock().children.iter().cloned());
+        tasks.insert(task.pid.0, task);
+    }
+    tasks.into_values().collect()
+}
@@ -1,13 +1,25 @@
-use super::{__switch, do_wake_expired};
-use super::{fetch_task, TaskStatus};
+use super::manager::TASK_MANAGER;
+use super::{__switch, do_wake_expired, TaskStatus};
 use super::{TaskContext, TaskControlBlock};
-use crate::arch::TrapContext;
+use crate::arch::board::CPU_NUM;
+use crate::arch::{cpu_id, TrapContext};
+use crate::fs::dev::tty::TTY;
+use crate::fs::writeback::writeback_tick;
+#[cfg(feature = "oom_handler")]
+use crate::mm::kswapd_tick;
+use crate::timer::get_time_ns;
 use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::hint::spin_loop;
+use core::sync::atomic::Ordering;
 use lazy_static::*;
 use spin::Mutex;
 
 pub struct Processor {
     current: Option<Arc<TaskControlBlock>>,
+    /// The task which just switched to the idle loop, and whether it yielded,
+    /// queued by `finish_switch()` once its context is saved
+    prev: Option<(Arc<TaskControlBlock>, bool)>,
     idle_task_cx: TaskContext,
 }
 
@@ -15,15 +27,13 @@ impl Processor {
     pub fn new() -> Self {
         Self {
             current: None,
+            prev: None,
             idle_task_cx: TaskContext::zero_init(),
         }
     }
     fn get_idle_task_cx_ptr(&mut self) -> *mut TaskContext {
         &mut self.idle_task_cx as *mut _
     }
-    pub fn take_current(&mut self) -> Option<Arc<TaskControlBlock>> {
-        self.current.take()
-    }
     pub fn current(&self) -> Option<Arc<TaskControlBlock>> {
         self.current.as_ref().map(Arc::clone)
     }
@@ -33,13 +43,29 @@ impl Processor {
 }
 
 lazy_static! {
-    pub static ref PROCESSOR: Mutex<Processor> = Mutex::new(Processor::new());
+    /// One per core, indexed by `cpu_id()`
+    pub static ref PROCESSORS: Vec<Mutex<Processor>> =
+        (0..CPU_NUM).map(|_| Mutex::new(Processor::new())).collect();
+}
+
+/// The `Processor` of this core
+pub fn this_processor() -> &'static Mutex<Processor> {
+    &PROCESSORS[cpu_id()]
 }
 
 pub fn run_tasks() {
+    let cpu = cpu_id();
     loop {
-        let mut processor = PROCESSOR.lock();
-        if let Some(task) = fetch_task() {
+        // the flushes may take long, so they are done between two tasks rather than in a trap,
+        // and by the boot core alone
+        if cpu == 0 {
+            writeback_tick();
+            #[cfg(feature = "oom_handler")]
+            kswapd_tick();
+        }
+        let mut manager = TASK_MANAGER.lock();
+        if let Some(task) = manager.fetch(cpu) {
+            let mut processor = PROCESSORS[cpu].lock();
             let idle_task_cx_ptr = processor.get_idle_task_cx_ptr();
             // access coming task TCB exclusively
             let next_task_cx_ptr = {
@@ -47,26 +73,53 @@ pub fn run_tasks() {
                 task_inner.task_status = TaskStatus::Running;
                 &task_inner.task_cx as *const TaskContext
             };
+            task.sched.lock().start(get_time_ns() as u64);
             processor.current = Some(task);
             // release processor manually
             drop(processor);
+            drop(manager);
             unsafe {
                 __switch(idle_task_cx_ptr, next_task_cx_ptr);
             }
+            finish_switch(cpu);
         } else {
-            drop(processor);
+            drop(manager);
             // we have no ready tasks, try to wake some...
             do_wake_expired();
+            if cpu == 0 {
+                TTY.poll();
+            } else {
+                spin_loop();
+            }
         }
     }
 }
 
-pub fn take_current_task() -> Option<Arc<TaskControlBlock>> {
-    PROCESSOR.lock().take_current()
+/// Queue the task which just left this core according to its status.
+/// Its context is saved now, so another core may run it.
+fn finish_switch(cpu: usize) {
+    let mut manager = TASK_MANAGER.lock();
+    let (task, yielded) = match PROCESSORS[cpu].lock().prev.take() {
+        Some(prev) => prev,
+        None => return,
+    };
+    let status = task.acquire_inner_lock().task_status;
+    task.on_cpu.store(false, Ordering::Release);
+    match status {
+        TaskStatus::Ready => manager.put_prev(task, yielded, cpu),
+        TaskStatus::Interruptible | TaskStatus::Stopped | TaskStatus::Traced => {
+            manager.add_interruptible(task)
+        }
+        // a zombie is dropped here, or by its parent, after the lock is released
+        _ => {
+            drop(manager);
+            drop(task);
+        }
+    }
 }
 
 pub fn current_task() -> Option<Arc<TaskControlBlock>> {
-    PROCESSOR.lock().current()
+    this_processor().lock().current()
 }
 
 pub fn current_user_token() -> usize {
@@ -77,9 +130,33 @@ pub fn current_trap_cx() -> &'static mut TrapContext {
     current_task().unwrap().acquire_inner_lock().get_trap_cx()
 }
 
-pub fn schedule(switched_task_cx_ptr: *mut TaskContext) {
-    let idle_task_cx_ptr = PROCESSOR.lock().get_idle_task_cx_ptr();
+/// The tasks on a core, running or just switched out
+pub fn running_tasks() -> Vec<Arc<TaskControlBlock>> {
+    let mut tasks = Vec::new();
+    for processor in PROCESSORS.iter() {
+        let processor = processor.lock();
+        tasks.extend(processor.current());
+        tasks.extend(processor.prev.as_ref().map(|(task, _)| task.clone()));
+    }
+    tasks
+}
+
+/// Switch from the current task to the idle loop, which queues it again according to its status,
+/// as `Ready` after the others if `yielded`.
+pub fn schedule(yielded: bool) {
+    let task_cx_ptr = {
+        let task = current_task().unwrap();
+        let mut task_inner = task.acquire_inner_lock();
+        &mut task_inner.task_cx as *mut TaskContext
+    };
+    let mut processor = this_processor().lock();
+    let task = processor.current.take().unwrap();
+    // charge it for the time it ran before it is queued again
+    task.sched.lock().account(get_time_ns() as u64);
+    processor.prev = Some((task, yielded));
+    let idle_task_cx_ptr = processor.get_idle_task_cx_ptr();
+    drop(processor);
     unsafe {
-        __switch(switched_task_cx_ptr, idle_task_cx_ptr);
+        __switch(task_cx_ptr, idle_task_cx_ptr);
     }
 }
@@ -0,0 +1,288 @@
+//! Process tracing with `ptrace()`, for debuggers and `strace`.
+//!
+//! Like Linux, tracing is per thread. A tracee stops for its tracer before a signal other than `SIGKILL`
+//! is delivered, and as the tracer asks, at system call entry and exit, after `execve()` or after one instruction.
+//! The tracer hears of each stop through `SIGCHLD` and `wait4()`, looks at and changes the stopped tracee,
+//! then resumes it, maybe with a signal.
+use alloc::sync::{Arc, Weak};
+use alloc::vec::Vec;
+use lazy_static::*;
+use spin::Mutex;
+
+use super::{
+    current_task, send_signal, stop_current_and_run_next, wake_interruptible, Signals,
+    TaskControlBlock, TaskStatus,
+};
+use crate::syscall::errno::*;
+
+bitflags! {
+    /// Set with `PTRACE_SETOPTIONS` or `PTRACE_SEIZE`
+    #[derive(Default)]
+    pub struct PtraceOptions: u32 {
+        /// Report system call stops as `SIGTRAP | 0x80`
+        const TRACESYSGOOD = 1;
+        /// Stop with `PTRACE_EVENT_EXEC` after `execve()`, instead of sending `SIGTRAP`
+        const TRACEEXEC    = 1 << 4;
+        /// Kill the tracee when the tracer exits
+        const EXITKILL     = 1 << 20;
+    }
+}
+
+const PTRACE_EVENT_EXEC: u32 = 4;
+
+/// Tracing state of a task
+#[derive(Default)]
+pub struct PtraceStatus {
+    /// The thread tracing the task
+    pub tracer: Option<Weak<TaskControlBlock>>,
+    pub options: PtraceOptions,
+    /// The signal of the stop the task is in, `SIGTRAP` for other stops than signal-delivery ones
+    pub stopped: Option<usize>,
+    /// The stop, or exit, `wait4()` of the tracer hasn't collected, as a status word
+    pub report: Option<u32>,
+    /// The signal the tracer resumed the task with, 0 for none
+    resume_signal: usize,
+    /// Stop at the next system call entry or exit
+    syscall: bool,
+    /// Stop after the next instruction
+    single_step: bool,
+}
+
+lazy_static! {
+    /// Every traced task, `wait4()` of a tracer looks for its tracees here.
+    /// The `ptrace` of a task is locked after it, and nothing is locked after that.
+    static ref TRACEES: Mutex<Vec<Arc<TaskControlBlock>>> = Mutex::new(Vec::new());
+}
+
+/// The tracer of `task`, if it is traced
+pub fn tracer(task: &TaskControlBlock) -> Option<Arc<TaskControlBlock>> {
+    task.ptrace
+        .lock()
+        .tracer
+        .as_ref()
+        .and_then(|tracer| tracer.upgrade())
+}
+
+fn is_traced_by(task: &TaskControlBlock, tracer: &Arc<TaskControlBlock>) -> bool {
+    task.ptrace
+        .lock()
+        .tracer
+        .as_ref()
+        .map_or(false, |task_tracer| {
+            task_tracer.as_ptr() == Arc::as_ptr(tracer)
+        })
+}
+
+pub fn is_single_stepping(task: &TaskControlBlock) -> bool {
+    task.ptrace.lock().single_step
+}
+
+/// The tasks traced by a thread of process `tgid`
+pub fn tracees(tgid: usize) -> Vec<Arc<TaskControlBlock>> {
+    TRACEES
+        .lock()
+        .iter()
+        .filter(|task| tracer(task).map_or(false, |tracer| tracer.tgid == tgid))
+        .cloned()
+        .collect()
+}
+
+/// Have `tracer` trace `tracee` with `options`, which must not be traced yet.
+pub fn attach(
+    tracee: &Arc<TaskControlBlock>,
+    tracer: &Arc<TaskControlBlock>,
+    options: PtraceOptions,
+) -> Result<(), isize> {
+    let mut tracees = TRACEES.lock();
+    {
+        let mut ptrace = tracee.ptrace.lock();
+        if ptrace.tracer.is_some() {
+            return Err(EPERM);
+        }
+        *ptrace = PtraceStatus {
+            tracer: Some(Arc::downgrade(tracer)),
+            options,
+            ..PtraceStatus::default()
+        };
+    }
+    tracees.push(tracee.clone());
+    Ok(())
+}
+
+/// Resume `tracee` with `signum`, 0 for none, if it is stopped for its tracer.
+/// It stops again at the next system call entry or exit with `syscall`, after the next instruction with `single_step`.
+pub fn resume(tracee: &Arc<TaskControlBlock>, signum: usize, syscall: bool, single_step: bool) {
+    {
+        let mut ptrace = tracee.ptrace.lock();
+        ptrace.resume_signal = signum;
+        ptrace.syscall = syscall;
+        ptrace.single_step = single_step;
+        ptrace.stopped = None;
+        ptrace.report = None;
+    }
+    let mut inner = tracee.acquire_inner_lock();
+    if inner.task_status == TaskStatus::Traced {
+        inner.task_status = TaskStatus::Ready;
+        drop(inner);
+        wake_interruptible(tracee.clone());
+    }
+}
+
+/// Stop tracing `tracee`, and resume it with `signum` if it is stopped.
+pub fn detach(tracee: &Arc<TaskControlBlock>, signum: usize) {
+    TRACEES.lock().retain(|task| !Arc::ptr_eq(task, tracee));
+    {
+        let mut ptrace = tracee.ptrace.lock();
+        ptrace.tracer = None;
+        ptrace.options = PtraceOptions::empty();
+    }
+    resume(tracee, signum, false, false);
+}
+
+/// Stop the current task, `task`, for its tracer, telling it `status`.
+/// Returns the signal the tracer resumed it with, 0 for none, or `None` if it isn't traced.
+fn ptrace_stop(task: &Arc<TaskControlBlock>, signum: usize, status: u32) -> Option<usize> {
+    let tracer = {
+        let mut ptrace = task.ptrace.lock();
+        let tracer = ptrace.tracer.as_ref().and_then(|tracer| tracer.upgrade())?;
+        ptrace.stopped = Some(signum);
+        ptrace.report = Some(status << 8 | 0x7f);
+        ptrace.resume_signal = 0;
+        tracer
+    };
+    send_signal(tracer, Signals::SIGCHLD);
+    // `SIGKILL` ends the stop, the tracer may also have gone
+    loop {
+        if task
+            .acquire_inner_lock()
+            .sigpending
+            .contains(Signals::SIGKILL)
+        {
+            break;
+        }
+        if task.ptrace.lock().stopped.is_none() {
+            break;
+        }
+        stop_current_and_run_next(TaskStatus::Traced, |task| {
+            task.ptrace.lock().stopped.is_some()
+        });
+    }
+    let mut ptrace = task.ptrace.lock();
+    ptrace.stopped = None;
+    ptrace.report = None;
+    Some(core::mem::take(&mut ptrace.resume_signal))
+}
+
+/// Send the current task the signal its tracer resumed it with from a stop other than a signal-delivery one.
+fn send_resume_signal(task: Arc<TaskControlBlock>, signum: Option<usize>) {
+    if let Some(Ok(signal)) = signum
+        .filter(|signum| *signum != 0)
+        .map(Signals::from_signum)
+    {
+        send_signal(task, signal);
+    }
+}
+
+/// A signal-delivery stop, as the current task, `task`, is about to handle `signum`.
+/// Returns the signal to handle instead, as the tracer picked it, 0 for none, or `None` if it isn't traced.
+pub fn signal_stop(task: &Arc<TaskControlBlock>, signum: usize) -> Option<usize> {
+    ptrace_stop(task, signum, signum as u32)
+}
+
+/// A system call stop, at entry or exit, if the tracer asked for one with `PTRACE_SYSCALL`.
+pub fn syscall_stop() {
+    let task = current_task().unwrap();
+    let sysgood = {
+        let ptrace = task.ptrace.lock();
+        if !ptrace.syscall {
+            return;
+        }
+        ptrace.options.contains(PtraceOptions::TRACESYSGOOD)
+    };
+    let signum = Signals::SIGTRAP.to_signum().unwrap();
+    let status = if sysgood { signum | 0x80 } else { signum };
+    let resume_signal = ptrace_stop(&task, signum, status as u32);
+    send_resume_signal(task, resume_signal);
+}
+
+/// Let the tracer of the current task know that `execve()` succeeded,
+/// with a `PTRACE_EVENT_EXEC` stop if it asked for one, or else with a `SIGTRAP`.
+pub fn exec_stop() {
+    let task = current_task().unwrap();
+    let options = match tracer(&task) {
+        Some(_) => task.ptrace.lock().options,
+        None => return,
+    };
+    let signum = Signals::SIGTRAP.to_signum().unwrap();
+    if options.contains(PtraceOptions::TRACEEXEC) {
+        let status = signum as u32 | PTRACE_EVENT_EXEC << 8;
+        let resume_signal = ptrace_stop(&task, signum, status);
+        send_resume_signal(task, resume_signal);
+    } else {
+        send_signal(task, Signals::SIGTRAP);
+    }
+}
+
+/// Called as `task`, whose parent is `parent`, exits with `exit_code`: its tracees are let go,
+/// or killed with `PTRACE_O_EXITKILL`, and its tracer hears of the exit, unless it is the parent.
+pub fn exit_ptrace(
+    task: &Arc<TaskControlBlock>,
+    parent: Option<&Arc<TaskControlBlock>>,
+    exit_code: u32,
+) {
+    let traced: Vec<Arc<TaskControlBlock>> = TRACEES
+        .lock()
+        .iter()
+        .filter(|tracee| is_traced_by(tracee, task))
+        .cloned()
+        .collect();
+    for tracee in traced {
+        let exitkill = tracee
+            .ptrace
+            .lock()
+            .options
+            .contains(PtraceOptions::EXITKILL);
+        detach(&tracee, 0);
+        if exitkill {
+            send_signal(tracee, Signals::SIGKILL);
+        }
+    }
+    let tracer = match tracer(task) {
+        Some(tracer) => tracer,
+        None => return,
+    };
+    if parent.map_or(false, |parent| parent.tgid == tracer.tgid) {
+        detach(task, 0);
+        return;
+    }
+    {
+        let mut ptrace = task.ptrace.lock();
+        ptrace.stopped = None;
+        ptrace.report = Some(exit_code);
+    }
+    send_signal(tracer, Signals::SIGCHLD);
+}
+
+/// A stop or exit of one of `tracees` not collected yet, the tid and the status word.
+/// It is left for the next call with `nowait`, an exited tracee is let go once collected.
+pub fn wait_report(tracees: &[Arc<TaskControlBlock>], nowait: bool) -> Option<(usize, u32)> {
+    for tracee in tracees.iter() {
+        let (status, exited) = {
+            let mut ptrace = tracee.ptrace.lock();
+            let status = match ptrace.report {
+                Some(status) => status,
+                None => continue,
+            };
+            if !nowait {
+                ptrace.report = None;
+            }
+            (status, ptrace.stopped.is_none())
+        };
+        if exited && !nowait {
+            TRACEES.lock().retain(|task| !Arc::ptr_eq(task, tracee));
+            *tracee.ptrace.lock() = PtraceStatus::default();
+        }
+        return Some((tracee.pid.0, status));
+    }
+    None
+}
@@ -0,0 +1,346 @@
+//! Scheduling policies and the run queues implementing them.
+//!
+//! Like Linux, the real-time tasks (`SCHED_FIFO` and `SCHED_RR`) run before all others,
+//! highest priority first, and the others share the CPU in proportion to the weight of their nice value,
+//! the one with the least virtual runtime running next.
+//! `TaskManager` keeps the ready tasks of each core in a `Scheduler`,
+//! the `sched_fifo` feature puts back the plain FIFO queue to compare the two.
+use alloc::boxed::Box;
+use alloc::collections::{BTreeMap, VecDeque};
+use alloc::sync::Arc;
+
+use super::TaskControlBlock;
+use crate::arch::TICKS_PER_SEC;
+
+pub const SCHED_OTHER: u32 = 0;
+pub const SCHED_FIFO: u32 = 1;
+pub const SCHED_RR: u32 = 2;
+pub const SCHED_BATCH: u32 = 3;
+pub const SCHED_IDLE: u32 = 5;
+/// Or'ed into the policy, children don't inherit a real-time policy or a negative nice value
+pub const SCHED_RESET_ON_FORK: u32 = 0x40000000;
+
+pub const MIN_NICE: i32 = -20;
+pub const MAX_NICE: i32 = 19;
+/// Priorities of the real-time policies go from 1 to this
+pub const MAX_RT_PRIO: u32 = 99;
+
+/// The time slice of `SCHED_RR`, 100 ms like Linux
+pub const RR_TIMESLICE: usize = TICKS_PER_SEC / 10;
+
+const NICE_0_WEIGHT: u64 = 1024;
+const WEIGHT_IDLEPRIO: u64 = 3;
+/// `sched_prio_to_weight` of Linux, one nice level is about 10% of CPU time
+const PRIO_TO_WEIGHT: [u64; 40] = [
+    /* -20 */ 88761, 71755, 56483, 46273, 36291, //
+    /* -15 */ 29154, 23254, 18705, 14949, 11916, //
+    /* -10 */ 9548, 7620, 6100, 4904, 3906, //
+    /*  -5 */ 3121, 2501, 1991, 1586, 1277, //
+    /*   0 */ 1024, 820, 655, 526, 423, //
+    /*   5 */ 335, 272, 215, 172, 137, //
+    /*  10 */ 110, 87, 70, 56, 45, //
+    /*  15 */ 36, 29, 23, 18, 15, //
+];
+/// How far behind the least virtual runtime a waking task is put at most, in ns,
+/// so that sleeping doesn't save up CPU time
+const SLEEPER_CREDIT: u64 = 3_000_000;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum SchedPolicy {
+    Other,
+    Fifo,
+    RoundRobin,
+    Batch,
+    Idle,
+}
+
+impl SchedPolicy {
+    pub fn from_raw(policy: u32) -> Option<Self> {
+        match policy {
+            SCHED_OTHER => Some(SchedPolicy::Other),
+            SCHED_FIFO => Some(SchedPolicy::Fifo),
+            SCHED_RR => Some(SchedPolicy::RoundRobin),
+            SCHED_BATCH => Some(SchedPolicy::Batch),
+            SCHED_IDLE => Some(SchedPolicy::Idle),
+            _ => None,
+        }
+    }
+    pub fn raw(&self) -> u32 {
+        match self {
+            SchedPolicy::Other => SCHED_OTHER,
+            SchedPolicy::Fifo => SCHED_FIFO,
+            SchedPolicy::RoundRobin => SCHED_RR,
+            SchedPolicy::Batch => SCHED_BATCH,
+            SchedPolicy::Idle => SCHED_IDLE,
+        }
+    }
+    pub fn is_realtime(&self) -> bool {
+        matches!(self, SchedPolicy::Fifo | SchedPolicy::RoundRobin)
+    }
+}
+
+/// Scheduling state of a task, inherited on clone
+#[derive(Clone, Debug)]
+pub struct SchedEntity {
+    pub policy: SchedPolicy,
+    /// From `MIN_NICE` to `MAX_NICE`, only weighs the policies which aren't real-time
+    pub nice: i32,
+    /// From 1 to `MAX_RT_PRIO` for the real-time policies, 0 for the others
+    pub rt_priority: u32,
+    pub reset_on_fork: bool,
+    /// The cores the task may run on, bit `cpu` for core `cpu`
+    pub cpus_allowed: usize,
+    /// The core the task runs on, or ran on last
+    pub cpu: usize,
+    /// Time run, in ns, scaled by `NICE_0_WEIGHT` over the weight
+    vruntime: u64,
+    /// When the task was last put on the CPU, in ns
+    exec_start: u64,
+    /// Ticks left of the time slice of `SCHED_RR`
+    time_slice: usize,
+}
+
+impl SchedEntity {
+    pub fn new() -> Self {
+        Self {
+            policy: SchedPolicy::Other,
+            nice: 0,
+            rt_priority: 0,
+            reset_on_fork: false,
+            cpus_allowed: usize::MAX,
+            cpu: 0,
+            vruntime: 0,
+            exec_start: 0,
+            time_slice: RR_TIMESLICE,
+        }
+    }
+    /// The entity of a child, which starts from the virtual runtime of its parent
+    /// so that forking earns no CPU time.
+    pub fn fork(&self) -> Self {
+        let mut child = self.clone();
+        if child.reset_on_fork {
+            if child.policy.is_realtime() {
+                child.set_policy(SchedPolicy::Other, 0);
+            }
+            child.nice = child.nice.max(0);
+            child.reset_on_fork = false;
+        }
+        child.time_slice = RR_TIMESLICE;
+        child
+    }
+    pub fn set_policy(&mut self, policy: SchedPolicy, rt_priority: u32) {
+        self.policy = policy;
+        self.rt_priority = rt_priority;
+        self.time_slice = RR_TIMESLICE;
+    }
+    fn weight(&self) -> u64 {
+        match self.policy {
+            SchedPolicy::Idle => WEIGHT_IDLEPRIO,
+            _ => PRIO_TO_WEIGHT[(self.nice - MIN_NICE) as usize],
+        }
+    }
+    /// The priority shown in `/proc/<pid>/stat`, negative for the real-time policies
+    pub fn prio(&self) -> isize {
+        if self.policy.is_realtime() {
+            -1 - self.rt_priority as isize
+        } else {
+            20 + self.nice as isize
+        }
+    }
+    /// Called when the task is put on the CPU at `now`.
+    pub fn start(&mut self, now: u64) {
+        self.exec_start = now;
+    }
+    /// Charge the task for the time it ran until `now`.
+    pub fn account(&mut self, now: u64) {
+        let delta = now.saturating_sub(self.exec_start);
+        self.exec_start = now;
+        if !self.policy.is_realtime() {
+            self.vruntime += delta * NICE_0_WEIGHT / self.weight();
+        }
+    }
+}
+
+/// A run queue, deciding which ready task runs next
+pub trait Scheduler: Send {
+    /// Queue a task which became ready, either new or woken up.
+    fn add(&mut self, task: Arc<TaskControlBlock>);
+    /// Queue the task which ran last, `yielded` if it gave up the CPU rather than being preempted.
+    fn put_prev(&mut self, task: Arc<TaskControlBlock>, yielded: bool) {
+        let _ = yielded;
+        self.add(task);
+    }
+    /// Take the task to run next.
+    fn fetch(&mut self) -> Option<Arc<TaskControlBlock>>;
+    /// Whether the running `task` should be preempted, called at every timer tick.
+    fn tick(&mut self, task: &TaskControlBlock) -> bool {
+        let _ = task;
+        true
+    }
+    fn retain(&mut self, f: &dyn Fn(&Arc<TaskControlBlock>) -> bool);
+    /// The queued tasks, in the order they would run
+    fn iter(&self) -> Box<dyn Iterator<Item = &Arc<TaskControlBlock>> + '_>;
+    fn len(&self) -> usize;
+}
+
+/// Every task in turn, whatever its policy, the scheduler before `FairScheduler`
+pub struct FifoScheduler {
+    queue: VecDeque<Arc<TaskControlBlock>>,
+}
+
+impl FifoScheduler {
+    pub fn new() -> Self {
+        Self {
+            queue: VecDeque::new(),
+        }
+    }
+}
+
+impl Scheduler for FifoScheduler {
+    fn add(&mut self, task: Arc<TaskControlBlock>) {
+        self.queue.push_back(task);
+    }
+    fn fetch(&mut self) -> Option<Arc<TaskControlBlock>> {
+        self.queue.pop_front()
+    }
+    fn retain(&mut self, f: &dyn Fn(&Arc<TaskControlBlock>) -> bool) {
+        self.queue.retain(|task| f(task));
+    }
+    fn iter(&self) -> Box<dyn Iterator<Item = &Arc<TaskControlBlock>> + '_> {
+        Box::new(self.queue.iter())
+    }
+    fn len(&self) -> usize {
+        self.queue.len()
+    }
+}
+
+/// Real-time tasks by priority, then the others by virtual runtime, like the CFS of Linux
+pub struct FairScheduler {
+    /// Real-time tasks by priority, in FIFO order within a priority
+    rt: BTreeMap<u32, VecDeque<Arc<TaskControlBlock>>>,
+    /// The other tasks by virtual runtime, ties broken by arrival
+    fair: BTreeMap<(u64, u64), Arc<TaskControlBlock>>,
+    /// Virtual runtime of the last task fetched, it never decreases
+    min_vruntime: u64,
+    arrivals: u64,
+}
+
+impl FairScheduler {
+    pub fn new() -> Self {
+        Self {
+            rt: BTreeMap::new(),
+            fair: BTreeMap::new(),
+            min_vruntime: 0,
+            arrivals: 0,
+        }
+    }
+    fn enqueue_fair(&mut self, task: Arc<TaskControlBlock>, vruntime: u64) {
+        self.arrivals += 1;
+        self.fair.insert((vruntime, self.arrivals), task);
+    }
+    /// Whether a real-time task above `rt_priority` is queued
+    fn rt_above(&self, rt_priority: u32) -> bool {
+        self.rt
+            .keys()
+            .next_back()
+            .map_or(false, |highest| *highest > rt_priority)
+    }
+}
+
+impl Scheduler for FairScheduler {
+    fn add(&mut self, task: Arc<TaskControlBlock>) {
+        let mut entity = task.sched.lock();
+        if entity.policy.is_realtime() {
+            let rt_priority = entity.rt_priority;
+            drop(entity);
+            self.rt.entry(rt_priority).or_default().push_back(task);
+        } else {
+            let floor = self.min_vruntime.saturating_sub(SLEEPER_CREDIT);
+            entity.vruntime = entity.vruntime.max(floor);
+            let vruntime = entity.vruntime;
+            drop(entity);
+            self.enqueue_fair(task, vruntime);
+        }
+    }
+    /// A preempted real-time task stays at the head of its priority, unless its time slice ran out.
+    /// A task which yields goes behind the others of its priority,
+    /// or behind all others if it isn't real-time, like `sched_compat_yield` of Linux.
+    fn put_prev(&mut self, task: Arc<TaskControlBlock>, yielded: bool) {
+        let mut entity = task.sched.lock();
+        if entity.policy.is_realtime() {
+            let expired = entity.policy == SchedPolicy::RoundRobin && entity.time_slice == 0;
+            if expired {
+                entity.time_slice = RR_TIMESLICE;
+            }
+            let rt_priority = entity.rt_priority;
+            drop(entity);
+            let queue = self.rt.entry(rt_priority).or_default();
+            if yielded || expired {
+                queue.push_back(task);
+            } else {
+                queue.push_front(task);
+            }
+        } else {
+            if yielded {
+                if let Some((last, _)) = self.fair.keys().next_back() {
+                    entity.vruntime = entity.vruntime.max(*last);
+                }
+            }
+            let vruntime = entity.vruntime;
+            drop(entity);
+            self.enqueue_fair(task, vruntime);
+        }
+    }
+    fn fetch(&mut self) -> Option<Arc<TaskControlBlock>> {
+        if let Some(rt_priority) = self.rt.keys().next_back().cloned() {
+            let queue = self.rt.get_mut(&rt_priority).unwrap();
+            let task = queue.pop_front();
+            if queue.is_empty() {
+                self.rt.remove(&rt_priority);
+            }
+            return task;
+        }
+        let key = *self.fair.keys().next()?;
+        self.min_vruntime = self.min_vruntime.max(key.0);
+        self.fair.remove(&key)
+    }
+    fn tick(&mut self, task: &TaskControlBlock) -> bool {
+        let mut entity = task.sched.lock();
+        match entity.policy {
+            SchedPolicy::Fifo => self.rt_above(entity.rt_priority),
+            SchedPolicy::RoundRobin => {
+                entity.time_slice = entity.time_slice.saturating_sub(1);
+                entity.time_slice == 0 || self.rt_above(entity.rt_priority)
+            }
+            _ => !self.rt.is_empty() || !self.fair.is_empty(),
+        }
+    }
+    fn retain(&mut self, f: &dyn Fn(&Arc<TaskControlBlock>) -> bool) {
+        for queue in self.rt.values_mut() {
+            queue.retain(|task| f(task));
+        }
+        self.rt.retain(|_, queue| !queue.is_empty());
+        self.fair.retain(|_, task| f(task));
+    }
+    fn iter(&self) -> Box<dyn Iterator<Item = &Arc<TaskControlBlock>> + '_> {
+        Box::new(
+            self.rt
+                .values()
+                .rev()
+                .flat_map(|queue| queue.iter())
+                .chain(self.fair.values()),
+        )
+    }
+    fn len(&self) -> usize {
+        self.rt.values().map(|queue| queue.len()).sum::<usize>() + self.fair.len()
+    }
+}
+
+/// The scheduler `TaskManager` starts with
+pub fn default_scheduler() -> Box<dyn Scheduler> {
+    if cfg!(feature = "sched_fifo") {
+        Box::new(FifoScheduler::new())
+    } else {
+        Box::new(FairScheduler::new())
+    }
+}
@@ -7,7 +7,7 @@ use core::fmt::{self, Debug, Formatter};
 use core::mem::size_of;
 use log::{debug, error, trace, warn};
 
-use crate::arch::get_bad_addr;
+use crate::arch::{get_bad_addr, kick_cpu};
 use crate::mm::{
     copy_from_user, copy_to_user, translated_ref, translated_refmut, try_get_from_user,
 };
@@ -17,7 +17,9 @@ use crate::task::{block_current_and_run_next, exit_current_and_run_next, exit_gr
 use crate::timer::TimeSpec;
 use crate::{config::*, signal_type};
 
-use super::current_task;
+use super::job::{do_group_stop, is_orphaned_pgrp, prepare_signal, stop_while_stopped};
+use super::ptrace::{signal_stop, tracer};
+use super::{current_task, wake_interruptible, TaskControlBlock, TaskStatus};
 
 bitflags! {
     /// Signal
@@ -228,6 +230,9 @@ impl SigAction {
             mask: Signals::empty(),
         }
     }
+    pub fn ignored(&self) -> bool {
+        self.handler == SigHandler::SIG_IGN
+    }
 }
 
 impl Debug for SigAction {
@@ -277,8 +282,8 @@ pub fn sigaction(signum: usize, act: *const SigAction, oldact: *mut SigAction) -
                     return EFAULT;
                 }
                 sigact.mask.remove(Signals::CAN_NOT_BE_MASKED);
-                if !(sigact.handler == SigHandler::SIG_DFL || sigact.handler == SigHandler::SIG_IGN)
-                {
+                // `SIG_IGN` is kept, `execve()` and the stop signals must tell it from `SIG_DFL`
+                if sigact.handler != SigHandler::SIG_DFL {
                     task.sighand.lock()[signum - 1] = Some(Box::new(sigact));
                 } else {
                     task.sighand.lock()[signum - 1] = None;
@@ -316,7 +321,40 @@ impl SignalStack {
     }
 }
 
+/// Queue `signal` on `task` and wake it up if it is sleeping, the empty signal does nothing.
+/// A stopped or traced task is only woken up to be killed, `SIGCONT` continues its process beforehand.
+pub fn send_signal(task: Arc<TaskControlBlock>, signal: Signals) {
+    if signal.is_empty() {
+        return;
+    }
+    prepare_signal(&task, signal);
+    let mut inner = task.acquire_inner_lock();
+    inner.add_signal(signal);
+    if inner.task_status == TaskStatus::Interruptible
+        || matches!(inner.task_status, TaskStatus::Stopped | TaskStatus::Traced)
+            && signal == Signals::SIGKILL
+    {
+        inner.task_status = TaskStatus::Ready;
+        drop(inner);
+        wake_interruptible(task);
+    } else if inner.task_status == TaskStatus::Running && task.is_running_elsewhere() {
+        // it sees the signal on its way back to user mode, or instead of going to sleep
+        inner.wakeup_pending = true;
+        drop(inner);
+        kick_cpu(task.sched.lock().cpu);
+    }
+}
+
+/// Run the syscall the signal interrupted again, as no handler runs for the signal.
+fn restart_syscall(trap_cx: &mut TrapContext) {
+    if get_exception_cause().is_syscall() && trap_cx.gp.a0 == ERESTART as usize {
+        trap_cx.gp.pc -= 4;
+        trap_cx.gp.a0 = trap_cx.origin_a0;
+    }
+}
+
 pub fn do_signal() {
+    stop_while_stopped();
     let task = current_task().unwrap();
     let mut inner = task.acquire_inner_lock();
     while let Some(signum) = inner.sigpending.difference(inner.sigmask).peek_front() {
@@ -328,7 +366,36 @@ pub fn do_signal() {
             inner.sigpending,
             inner.sigmask
         );
+        // the tracer sees the signal first, and may replace or discard it
+        let (signum, signal) = if signal != Signals::SIGKILL && tracer(&task).is_some() {
+            drop(inner);
+            let resume_signal = signal_stop(&task, signum);
+            inner = task.acquire_inner_lock();
+            match resume_signal {
+                Some(0) => continue,
+                Some(signum) => {
+                    let signal = Signals::from_bits_truncate(1 << (signum - 1));
+                    // a blocked one is handled once it is unblocked, like Linux
+                    if inner.sigmask.contains(signal) {
+                        inner.add_signal(signal);
+                        continue;
+                    }
+                    (signum, signal)
+                }
+                // the tracer went away meanwhile
+                None => (signum, signal),
+            }
+        } else {
+            (signum, signal)
+        };
         let mut sighand = task.sighand.lock();
+        if sighand[signum - 1]
+            .as_ref()
+            .map_or(false, |act| act.ignored())
+        {
+            trace!("[do_signal] Ignore {:?}", signal);
+            continue;
+        }
         // user-defined handler
         if let Some(act) = &sighand[signum - 1] {
             let trap_cx = inner.get_trap_cx();
@@ -351,7 +418,7 @@ pub fn do_signal() {
             let siginfo_addr = (ucontext_addr - size_of::<SigInfo>()) & !0x7;
             // check if we have enough space on user stack
             let sig_sp = siginfo_addr;
-            let sig_size = sig_sp.checked_sub(task.ustack_base - USER_STACK_SIZE);
+            let sig_size = sig_sp.checked_sub(inner.ustack_base - USER_STACK_SIZE);
             if let Some(sig_size) = sig_size {
                 let token = task.get_user_token();
                 // In this case, signal hander have three parameters
@@ -472,16 +539,19 @@ pub fn do_signal() {
                     trace!("[do_signal] Ignore {:?}", signal);
                     continue;
                 }
-                // stop (or we should say block) current process
-                Signals::SIGTSTP | Signals::SIGTTIN | Signals::SIGTTOU => {
+                // stop current process, the syscall runs again once it is continued
+                Signals::SIGSTOP | Signals::SIGTSTP | Signals::SIGTTIN | Signals::SIGTTOU => {
+                    let pgid = inner.pgid;
+                    restart_syscall(inner.get_trap_cx());
                     drop(inner);
                     drop(sighand);
+                    // nobody could continue an orphaned process group stopped by the terminal
+                    if signal == Signals::SIGSTOP || !is_orphaned_pgrp(pgid) {
+                        do_group_stop(&task, signum);
+                    }
                     drop(task);
-                    block_current_and_run_next();
-                    // because this loop require `inner`, and we have `drop(inner)` above, so `break` is compulsory
-                    // this would cause some signals won't be handled immediately when this process resumes
-                    // but it doesn't matter, maybe
-                    break;
+                    // the signals left are handled once the process is continued
+                    return do_signal();
                 }
                 // for all other signals, we should terminate current process
                 _ => {
@@ -494,6 +564,8 @@ pub fn do_signal() {
             }
         }
     }
+    // every signal was ignored
+    restart_syscall(inner.get_trap_cx());
 }
 
 bitflags! {
@@ -638,8 +710,15 @@ pub fn sigtimedwait(set: *const Signals, info: *mut SigInfo, timeout: *const Tim
     wait_with_timeout(Arc::downgrade(&task), start + timeout);
     drop(task);
 
-    block_current_and_run_next();
-    let task = current_task().unwrap();
+    // a wakeup meant for another sleep may end this one early, the timeout is still queued then
+    let task = loop {
+        block_current_and_run_next();
+        let task = current_task().unwrap();
+        let interrupted = !task.acquire_inner_lock().sigpending.is_empty();
+        if interrupted || start + timeout <= TimeSpec::now() {
+            break task;
+        }
+    };
     let inner = task.acquire_inner_lock();
     // interrupted by signal(s)
     if !inner.sigpending.is_empty() {
@@ -659,7 +738,6 @@ pub fn sigtimedwait(set: *const Signals, info: *mut SigInfo, timeout: *const Tim
         }
     // reach timeout
     } else {
-        assert!(start + timeout <= TimeSpec::now());
         EAGAIN
     }
 }
@@ -1,15 +1,20 @@
 use super::manager::TASK_MANAGER;
 use super::pid::{kstack_alloc, RecycleAllocator};
+use super::sched::SchedEntity;
+use super::cred::Credentials;
+use super::job::JobStatus;
+use super::ptrace::PtraceStatus;
 use super::signal::*;
 use super::threads::Futex;
+use super::processor::running_tasks;
 use super::trap_cx_bottom_from_tid;
-use super::ustack_bottom_from_tid;
 use super::TaskContext;
-use super::{pid_alloc, KernelStackImpl, PidHandle};
+use super::{pid_alloc, suspend_current_and_run_next, zap_thread, KernelStackImpl, PidHandle};
 use crate::arch::TrapImpl;
-use crate::arch::{trap_handler, TrapContext};
+use crate::arch::{cpu_id, trap_handler, TrapContext};
 use crate::config::MMAP_BASE;
-use crate::fs::{FdTable, FileDescriptor, OpenFlags, ROOT_FD};
+use crate::fs::{locks, FdTable, FileDescriptor, OpenFlags, ROOT_FD};
+use crate::ipc::sem::SemUndoList;
 use crate::mm::{MemorySet, PageTableImpl, PhysPageNum, VirtAddr, KERNEL_SPACE};
 use crate::net::SocketTable;
 use crate::syscall::CloneFlags;
@@ -19,12 +24,31 @@ use alloc::string::String;
 use alloc::sync::{Arc, Weak};
 use alloc::vec::Vec;
 use core::fmt::{self, Debug, Formatter};
+use core::sync::atomic::{AtomicBool, AtomicIsize, AtomicUsize, Ordering};
 use log::trace;
 use spin::{Mutex, MutexGuard};
 
+/// Range of `/proc/<pid>/oom_score_adj`, the minimum keeps the process from the OOM killer
+pub const OOM_SCORE_ADJ_MIN: isize = -1000;
+pub const OOM_SCORE_ADJ_MAX: isize = 1000;
+
+/// Held while `execve()` reads the program through the window at `MMAP_BASE` of the kernel space,
+/// which every core shares
+static ELF_WINDOW: Mutex<()> = Mutex::new(());
+
 #[derive(Clone)]
 pub struct FsStatus {
     pub working_inode: Arc<FileDescriptor>,
+    /// Permission bits cleared from the mode of new files
+    pub umask: u32,
+}
+
+/// Arguments and environment of the last `execve()`,
+/// shown in `/proc/<pid>/cmdline` and `/proc/<pid>/environ`.
+#[derive(Clone, Default)]
+pub struct ExecArgs {
+    pub argv: Vec<String>,
+    pub envp: Vec<String>,
 }
 
 pub struct TaskControlBlock {
@@ -33,19 +57,36 @@ pub struct TaskControlBlock {
     pub tid: usize,
     pub tgid: usize,
     pub kstack: KernelStackImpl,
-    pub ustack_base: usize,
     pub exit_signal: Signals,
+    /// When the task was created
+    pub start_time: TimeVal,
     // mutable
     inner: Mutex<TaskControlBlockInner>,
     // shareable and mutable
     pub exe: Arc<Mutex<FileDescriptor>>,
+    pub exec_args: Arc<Mutex<ExecArgs>>,
     pub tid_allocator: Arc<Mutex<RecycleAllocator>>,
     pub files: Arc<Mutex<FdTable>>,
     pub socket_table : Arc<Mutex<SocketTable>>,
     pub fs: Arc<Mutex<FsStatus>>,
+    pub cred: Arc<Mutex<Credentials>>,
     pub vm: Arc<Mutex<MemorySet<PageTableImpl>>>,
     pub sighand: Arc<Mutex<Vec<Option<Box<SigAction>>>>>,
     pub futex: Arc<Mutex<Futex>>,
+    /// `SEM_UNDO` adjustments, reverted when the last task sharing them exits
+    pub sem_undo: Arc<SemUndoList>,
+    /// Added to the OOM badness of the process, shared by its threads and inherited on fork
+    pub oom_score_adj: Arc<AtomicIsize>,
+    /// Whether the process is stopped, shared by its threads
+    pub job: Arc<Mutex<JobStatus>>,
+    /// Threads of the process which haven't exited yet, the last one to exit releases what the process holds
+    pub live_threads: Arc<AtomicUsize>,
+    /// Whether the task is traced, and how
+    pub ptrace: Mutex<PtraceStatus>,
+    /// Policy, priority and runtime, kept out of `inner` so that the run queue never locks it
+    pub sched: Mutex<SchedEntity>,
+    /// Set while a core runs the task, until its context is saved
+    pub on_cpu: AtomicBool,
 }
 
 pub struct TaskControlBlockInner {
@@ -62,9 +103,19 @@ pub struct TaskControlBlockInner {
     pub heap_bottom: usize,
     pub heap_pt: usize,
     pub pgid: usize,
+    /// Session, the pid of its leader
+    pub sid: usize,
     pub rusage: Rusage,
     pub clock: ProcClock,
     pub timer: [ITimerVal; 3],
+    /// Highest address of the user stack, moved by `execve()` when the layout is randomized
+    pub ustack_base: usize,
+    /// Set by `personality()`, inherited on fork and across `execve()`
+    pub personality: u32,
+    /// A wakeup which came while the task ran on another core, before it went to sleep
+    pub wakeup_pending: bool,
+    /// The exit code of the process, once another thread called `exit_group()`
+    pub group_exit: Option<u32>,
 }
 
 #[derive(Clone, Copy, Debug)]
@@ -225,14 +276,14 @@ impl TaskControlBlock {
         trap_cx_bottom_from_tid(self.tid)
     }
     pub fn ustack_bottom_va(&self) -> usize {
-        ustack_bottom_from_tid(self.tid)
+        self.vm.lock().ustack_bottom(self.tid)
     }
     /// !!!!!!!!!!!!!!!!WARNING!!!!!!!!!!!!!!!!!!!!!
     /// Currently used for initproc loading only. bin_path must be used changed if used elsewhere.
     pub fn new(elf: FileDescriptor) -> Self {
         let elf_data = elf.map_to_kernel_space(MMAP_BASE);
         // memory_set with elf program headers/trampoline
-        let (mut memory_set, user_heap, elf_info) = MemorySet::from_elf(elf_data).unwrap();
+        let (mut memory_set, user_heap, elf_info) = MemorySet::from_elf(elf_data, 0).unwrap();
         crate::mm::KERNEL_SPACE
             .lock()
             .remove_area_with_start_vpn(VirtAddr::from(MMAP_BASE).floor())
@@ -252,14 +303,16 @@ impl TaskControlBlock {
             .translate(VirtAddr::from(trap_cx_bottom_from_tid(tid)).into())
             .unwrap();
         log::trace!("[TCB::new]trap_cx_ppn{:?}", trap_cx_ppn);
+        let ustack_base = memory_set.ustack_bottom(tid);
         let task_control_block = Self {
             pid: pid_handle,
             tid,
             tgid,
             kstack,
-            ustack_base: ustack_bottom_from_tid(tid),
             exit_signal: Signals::empty(),
+            start_time: TimeVal::now(),
             exe: Arc::new(Mutex::new(elf)),
+            exec_args: Arc::new(Mutex::new(ExecArgs::default())),
             tid_allocator,
             files: Arc::new(Mutex::new(FdTable::new({
                 let mut vec = Vec::with_capacity(144);
@@ -278,7 +331,9 @@ impl TaskControlBlock {
                         .open(".", OpenFlags::O_RDONLY | OpenFlags::O_DIRECTORY, true)
                         .unwrap(),
                 ),
+                umask: 0o022,
             })),
+            cred: Arc::new(Mutex::new(Credentials::root())),
             vm: Arc::new(Mutex::new(memory_set)),
             sighand: Arc::new(Mutex::new({
                 let mut vec = Vec::with_capacity(64);
@@ -286,6 +341,13 @@ impl TaskControlBlock {
                 vec
             })),
             futex: Arc::new(Mutex::new(Futex::new())),
+            sem_undo: Arc::new(SemUndoList::new()),
+            oom_score_adj: Arc::new(AtomicIsize::new(0)),
+            job: Arc::new(Mutex::new(JobStatus::default())),
+            live_threads: Arc::new(AtomicUsize::new(1)),
+            ptrace: Mutex::new(PtraceStatus::default()),
+            sched: Mutex::new(SchedEntity::new()),
+            on_cpu: AtomicBool::new(false),
             inner: Mutex::new(TaskControlBlockInner {
                 sigmask: Signals::empty(),
                 sigpending: Signals::empty(),
@@ -300,16 +362,21 @@ impl TaskControlBlock {
                 heap_bottom: user_heap,
                 heap_pt: user_heap,
                 pgid,
+                sid: pgid,
                 rusage: Rusage::new(),
                 clock: ProcClock::new(),
                 timer: [ITimerVal::new(); 3],
+                ustack_base,
+                personality: 0,
+                wakeup_pending: false,
+                group_exit: None,
             }),
         };
         // prepare TrapContext in user space
         let trap_cx = task_control_block.acquire_inner_lock().get_trap_cx();
         *trap_cx = TrapContext::app_init_context(
             elf_info.entry,
-            ustack_bottom_from_tid(tid),
+            ustack_base,
             KERNEL_SPACE.lock().token(),
             kstack_top,
             trap_handler as usize,
@@ -324,18 +391,25 @@ impl TaskControlBlock {
         argv_vec: &Vec<String>,
         envp_vec: &Vec<String>,
     ) -> Result<(), isize> {
+        let elf_window = ELF_WINDOW.lock();
         let elf_data = elf.map_to_kernel_space(MMAP_BASE);
         // memory_set with elf program headers/trampoline/trap context/user stack
-        let (mut memory_set, program_break, elf_info) = MemorySet::from_elf(elf_data)?;
+        let personality = self.acquire_inner_lock().personality;
+        let (mut memory_set, program_break, elf_info) = MemorySet::from_elf(elf_data, personality)?;
         log::trace!("[load_elf] ELF file mapped");
         // remove elf area
         crate::mm::KERNEL_SPACE
             .lock()
             .remove_area_with_start_vpn(VirtAddr::from(MMAP_BASE).floor())
             .unwrap();
+        drop(elf_window);
+        // the other threads run in the memory set about to be replaced
+        if self.tid_allocator.lock().get_allocated() > 1 {
+            self.kill_other_threads();
+        }
         memory_set.alloc_user_res(self.tid, true);
-        let user_sp =
-            memory_set.create_elf_tables(self.ustack_bottom_va(), argv_vec, envp_vec, &elf_info);
+        let ustack_base = memory_set.ustack_bottom(self.tid);
+        let user_sp = memory_set.create_elf_tables(ustack_base, argv_vec, envp_vec, &elf_info);
         log::trace!("[load_elf] user sp after pushing parameters: {:X}", user_sp);
         // initialize trap_cx
         let trap_cx = TrapContext::app_init_context(
@@ -363,12 +437,18 @@ impl TaskControlBlock {
         // update heap pointers
         inner.heap_bottom = program_break;
         inner.heap_pt = program_break;
+        inner.ustack_base = ustack_base;
         // track the change of ELF file
         *self.exe.lock() = elf;
+        *self.exec_args.lock() = ExecArgs {
+            argv: argv_vec.clone(),
+            envp: envp_vec.clone(),
+        };
         // flush cloexec fd
         self.files.lock().iter_mut().for_each(|fd| match fd {
             Some(file) => {
                 if file.get_cloexec() {
+                    locks::release_on_close(self.tgid, &file.file);
                     *fd = None;
                 }
             }
@@ -376,22 +456,14 @@ impl TaskControlBlock {
         });
         // substitute memory_set
         *self.vm.lock() = memory_set;
-        // flush signal handler
+        // flush signal handler, the ignored signals stay ignored
         for sigact in self.sighand.lock().iter_mut() {
-            *sigact = None;
+            if !sigact.as_ref().map_or(false, |act| act.ignored()) {
+                *sigact = None;
+            }
         }
         // flush futex
         self.futex.lock().clear();
-        if self.tid_allocator.lock().get_allocated() > 1 {
-            let mut manager = TASK_MANAGER.lock();
-            // destory all other threads
-            manager
-                .ready_queue
-                .retain(|task| (*task).tgid != (*self).tgid);
-            manager
-                .interruptible_queue
-                .retain(|task| (*task).tgid != (*self).tgid);
-        };
         Ok(())
         // **** release current PCB lock
     }
@@ -437,19 +509,26 @@ impl TaskControlBlock {
             .lock()
             .translate(VirtAddr::from(trap_cx_bottom_from_tid(tid)).into())
             .unwrap();
+        let ustack_base = memory_set.lock().ustack_bottom(tid);
 
         let task_control_block = Arc::new(TaskControlBlock {
             pid: pid_handle,
             tid,
             tgid,
             kstack,
-            ustack_base: if !stack.is_null() {
-                stack as usize
+            exit_signal,
+            start_time: TimeVal::now(),
+            // like Linux, the executable belongs to the address space
+            exe: if flags.contains(CloneFlags::CLONE_VM) {
+                self.exe.clone()
             } else {
-                ustack_bottom_from_tid(tid)
+                Arc::new(Mutex::new(self.exe.lock().clone()))
+            },
+            exec_args: if flags.contains(CloneFlags::CLONE_VM) {
+                self.exec_args.clone()
+            } else {
+                Arc::new(Mutex::new(self.exec_args.lock().clone()))
             },
-            exit_signal,
-            exe: self.exe.clone(),
             tid_allocator,
             files: if flags.contains(CloneFlags::CLONE_FILES) {
                 self.files.clone()
@@ -462,6 +541,12 @@ impl TaskControlBlock {
             } else {
                 Arc::new(Mutex::new(self.fs.lock().clone()))
             },
+            // threads of a process share one identity, as POSIX asks of `setuid()` and friends
+            cred: if flags.contains(CloneFlags::CLONE_THREAD) {
+                self.cred.clone()
+            } else {
+                Arc::new(Mutex::new(self.cred.lock().clone()))
+            },
             vm: memory_set,
             sighand: if flags.contains(CloneFlags::CLONE_SIGHAND) {
                 self.sighand.clone()
@@ -474,11 +559,40 @@ impl TaskControlBlock {
                 // maybe should do clone here?
                 Arc::new(Mutex::new(Futex::new()))
             },
+            sem_undo: if flags.contains(CloneFlags::CLONE_SYSVSEM) {
+                if !flags.contains(CloneFlags::CLONE_THREAD) {
+                    self.sem_undo.add_process();
+                }
+                self.sem_undo.clone()
+            } else {
+                Arc::new(SemUndoList::new())
+            },
+            oom_score_adj: if flags.contains(CloneFlags::CLONE_THREAD) {
+                self.oom_score_adj.clone()
+            } else {
+                Arc::new(AtomicIsize::new(self.oom_score_adj.load(Ordering::Relaxed)))
+            },
+            job: if flags.contains(CloneFlags::CLONE_THREAD) {
+                self.job.clone()
+            } else {
+                Arc::new(Mutex::new(JobStatus::default()))
+            },
+            live_threads: if flags.contains(CloneFlags::CLONE_THREAD) {
+                self.live_threads.fetch_add(1, Ordering::Relaxed);
+                self.live_threads.clone()
+            } else {
+                Arc::new(AtomicUsize::new(1))
+            },
+            ptrace: Mutex::new(PtraceStatus::default()),
+            sched: Mutex::new(self.sched.lock().fork()),
+            on_cpu: AtomicBool::new(false),
             inner: Mutex::new(TaskControlBlockInner {
                 // inherited
                 pgid: parent_inner.pgid,
+                sid: parent_inner.sid,
                 heap_bottom: parent_inner.heap_bottom,
                 heap_pt: parent_inner.heap_pt,
+                personality: parent_inner.personality,
                 // clone
                 sigpending: parent_inner.sigpending.clone(),
                 // new
@@ -491,6 +605,11 @@ impl TaskControlBlock {
                 sigmask: Signals::empty(),
                 // compute
                 trap_cx_ppn,
+                ustack_base: if !stack.is_null() {
+                    stack as usize
+                } else {
+                    ustack_base
+                },
                 task_cx: TaskContext::goto_trap_return(kstack_top),
                 parent: if flags.contains(CloneFlags::CLONE_PARENT)
                     | flags.contains(CloneFlags::CLONE_THREAD)
@@ -502,6 +621,8 @@ impl TaskControlBlock {
                 // constants
                 task_status: TaskStatus::Ready,
                 exit_code: 0,
+                wakeup_pending: false,
+                group_exit: None,
             }),
         });
         // add child
@@ -537,18 +658,42 @@ impl TaskControlBlock {
         task_control_block
         // ---- release parent PCB lock
     }
+    /// Destroy the other threads of the process for `execve()`.
+    /// The queued ones are dropped, those running on other cores are killed and waited for.
+    fn kill_other_threads(&self) {
+        loop {
+            let mut manager = TASK_MANAGER.lock();
+            for queue in manager.ready_queues.iter_mut() {
+                queue.retain(&|task| task.tgid != self.tgid);
+            }
+            manager
+                .interruptible_queue
+                .retain(|task| task.tgid != self.tgid);
+            let running: Vec<Arc<TaskControlBlock>> = running_tasks()
+                .into_iter()
+                .filter(|task| {
+                    task.tgid == self.tgid
+                        && task.pid.0 != self.pid.0
+                        && !task.acquire_inner_lock().is_zombie()
+                })
+                .collect();
+            drop(manager);
+            if running.is_empty() {
+                return;
+            }
+            for task in running {
+                zap_thread(&task, Signals::SIGKILL.to_signum().unwrap() as u32);
+            }
+            suspend_current_and_run_next();
+        }
+    }
+    /// Whether the task runs on a core other than this one
+    pub fn is_running_elsewhere(&self) -> bool {
+        self.on_cpu.load(Ordering::Acquire) && self.sched.lock().cpu != cpu_id()
+    }
     pub fn getpid(&self) -> usize {
         self.pid.0
     }
-    pub fn setpgid(&self, pgid: usize) -> isize {
-        if (pgid as isize) < 0 {
-            return -1;
-        }
-        let mut inner = self.acquire_inner_lock();
-        inner.pgid = pgid;
-        0
-        //Temporarily suspend. Because the type of 'self' is 'Arc', which can't be borrow as mutable.
-    }
     pub fn getpgid(&self) -> usize {
         let inner = self.acquire_inner_lock();
         inner.pgid
@@ -570,4 +715,8 @@ pub enum TaskStatus {
     Running,
     Zombie,
     Interruptible,
+    /// Stopped by a signal, only `SIGCONT` or `SIGKILL` wake it up
+    Stopped,
+    /// Stopped for its tracer, only the tracer or `SIGKILL` wake it up
+    Traced,
 }
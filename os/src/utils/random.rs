@@ -1,6 +1,7 @@
+use core::sync::atomic::{AtomicU64, Ordering};
 use rand_core::RngCore;
 
-use crate::timer::get_time_ms;
+use crate::timer::{get_time, get_time_ms};
 
 pub struct Rng {
     pub seed: usize,
@@ -45,3 +46,17 @@ impl Rng {
 }
 
 pub static mut RNG: Rng = Rng { seed: BIGPRIME };
+
+static STATE: AtomicU64 = AtomicU64::new(BIGPRIME as u64);
+
+/// 64 random bits, for address space layout randomization and `AT_RANDOM`.
+/// The state moves on by the cycle counter on every call and is mixed by splitmix64.
+pub fn get_random_u64() -> u64 {
+    let increment = 0x9e3779b97f4a7c15u64.wrapping_add(get_time() as u64);
+    let mut z = STATE
+        .fetch_add(increment, Ordering::Relaxed)
+        .wrapping_add(increment);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+    z ^ (z >> 31)
+}